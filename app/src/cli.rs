@@ -0,0 +1,127 @@
+// Command-line options. Anything given here overrides the config file.
+
+use anyhow::{anyhow, Result};
+use config::Config;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: ring0 [options] [-- <program> [args...]]
+
+Options:
+  -e, --command <program> [args...]
+                              Run <program> instead of the configured shell.
+                              Everything after it is passed to the program.
+      --cols <n>              Initial width in character cells.
+      --rows <n>              Initial height in character cells.
+      --font-size <size>      Font size in points.
+      --profile <name>        Start the first tab from the profile <name>.
+      --config <path>         Read settings from <path> instead of the
+                              default config file.
+      --working-directory <path>
+                              Start the shell in <path>.
+  -h, --help                  Show this help.
+";
+
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    // Program followed by its arguments; empty when not given.
+    pub command: Vec<String>,
+    pub cols: Option<u32>,
+    pub rows: Option<u32>,
+    pub font_size: Option<f32>,
+    pub profile: Option<String>,
+    pub config: Option<PathBuf>,
+    pub working_directory: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl CliArgs {
+    // Options take their value as the next argument or after `=`.
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Self> {
+        let mut cli = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = arg
+                .into_string()
+                .map_err(|arg| anyhow!("argument {arg:?} is not valid Unicode"))?;
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = |name: &str| -> Result<String> {
+                match inline.clone() {
+                    Some(value) => Ok(value),
+                    None => args
+                        .next()
+                        .and_then(|value| value.into_string().ok())
+                        .ok_or_else(|| anyhow!("{name} needs a value")),
+                }
+            };
+            match name {
+                "-h" | "--help" => cli.help = true,
+                "--cols" => cli.cols = Some(parse_cells(name, &value(name)?)?),
+                "--rows" => cli.rows = Some(parse_cells(name, &value(name)?)?),
+                "--font-size" => {
+                    let text = value(name)?;
+                    let size = text
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|size| size.is_finite() && *size > 0.0)
+                        .ok_or_else(|| anyhow!("{name} expects a size in points, got {text:?}"))?;
+                    cli.font_size = Some(size);
+                }
+                "--profile" => cli.profile = Some(value(name)?),
+                "--config" => cli.config = Some(PathBuf::from(value(name)?)),
+                "--working-directory" => {
+                    cli.working_directory = Some(PathBuf::from(value(name)?));
+                }
+                "-e" | "--command" | "--" => {
+                    if let Some(program) = inline {
+                        cli.command.push(program);
+                    }
+                    for arg in args.by_ref() {
+                        let arg = arg
+                            .into_string()
+                            .map_err(|arg| anyhow!("argument {arg:?} is not valid Unicode"))?;
+                        cli.command.push(arg);
+                    }
+                    if cli.command.is_empty() {
+                        return Err(anyhow!("{name} needs a program to run"));
+                    }
+                }
+                _ => return Err(anyhow!("unknown option {arg:?}")),
+            }
+        }
+        Ok(cli)
+    }
+
+    // A command given here wins over a profile given here, and either one
+    // over the shell and profile from the config.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(profile) = &self.profile {
+            config.default_profile = Some(profile.clone());
+        }
+        if let Some((program, args)) = self.command.split_first() {
+            config.shell = Some(program.clone());
+            config.args = args.to_vec();
+            config.default_profile = None;
+        }
+        if let Some(cols) = self.cols {
+            config.window.cols = Some(cols);
+        }
+        if let Some(rows) = self.rows {
+            config.window.rows = Some(rows);
+        }
+        if let Some(size) = self.font_size {
+            config.font.size = Some(size);
+        }
+    }
+}
+
+fn parse_cells(name: &str, text: &str) -> Result<u32> {
+    text.parse::<u32>()
+        .ok()
+        .filter(|cells| *cells > 0)
+        .ok_or_else(|| anyhow!("{name} expects a number of cells, got {text:?}"))
+}
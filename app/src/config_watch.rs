@@ -0,0 +1,56 @@
+// Notices edits to the config file by polling its modification time, so
+// they can be applied without restarting.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ConfigWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+impl ConfigWatch {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            modified: modified_time(&path),
+            path,
+            checked_at: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn next_check(&self) -> Instant {
+        self.checked_at + CHECK_INTERVAL
+    }
+
+    // True once for each change, checked at most once a second. A file
+    // that goes missing isn't a change; it is read again once it is back.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_check() {
+            return false;
+        }
+        self.checked_at = now;
+        let Some(modified) = modified_time(&self.path) else {
+            return false;
+        };
+        if self.modified == Some(modified) {
+            return false;
+        }
+        self.modified = Some(modified);
+        true
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
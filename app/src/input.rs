@@ -0,0 +1,406 @@
+// Byte sequences sent to the pty for keys that don't just type text, as an
+// xterm-compatible terminal sends them. Alt acts as meta and prefixes ESC.
+
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+const ESC: u8 = 0x1b;
+pub const DEL: u8 = 0x7f;
+pub const BS: u8 = 0x08;
+
+#[derive(Debug, Copy, Clone)]
+pub struct KeyModes {
+    // DECCKM: the arrows, Home and End are sent as ESC O rather than ESC [.
+    pub application_cursor: bool,
+    // What Backspace sends, DEL or BS.
+    pub backspace: u8,
+}
+
+// None leaves the key to the text it typed, which is how plain and shifted
+// characters are sent, as well as dead keys and AltGr.
+pub fn encode_key(key: &Key, mods: ModifiersState, modes: KeyModes) -> Option<Vec<u8>> {
+    match key {
+        Key::Named(key) => encode_named_key(*key, mods, modes),
+        Key::Character(text) => encode_character(text, mods),
+        _ => None,
+    }
+}
+
+fn encode_named_key(key: NamedKey, mods: ModifiersState, modes: KeyModes) -> Option<Vec<u8>> {
+    let modifier = modifier_param(mods);
+    if let Some(number) = function_key_number(key) {
+        return Some(encode_function_key(number, modifier));
+    }
+    // Modified cursor keys always use the ESC [1;<mod> form, whatever
+    // DECCKM says.
+    let cursor = |final_byte: u8| match modifier {
+        Some(modifier) => format!("\x1b[1;{modifier}{}", final_byte as char).into_bytes(),
+        None if modes.application_cursor => vec![ESC, b'O', final_byte],
+        None => vec![ESC, b'[', final_byte],
+    };
+    let tilde = |code: u8| match modifier {
+        Some(modifier) => format!("\x1b[{code};{modifier}~").into_bytes(),
+        None => format!("\x1b[{code}~").into_bytes(),
+    };
+    let meta = |bytes: &[u8]| {
+        let mut out = Vec::with_capacity(bytes.len() + 1);
+        if mods.alt_key() {
+            out.push(ESC);
+        }
+        out.extend_from_slice(bytes);
+        out
+    };
+    let bytes = match key {
+        NamedKey::ArrowUp => cursor(b'A'),
+        NamedKey::ArrowDown => cursor(b'B'),
+        NamedKey::ArrowRight => cursor(b'C'),
+        NamedKey::ArrowLeft => cursor(b'D'),
+        NamedKey::Home => cursor(b'H'),
+        NamedKey::End => cursor(b'F'),
+        NamedKey::Insert => tilde(2),
+        NamedKey::Delete => tilde(3),
+        NamedKey::PageUp => tilde(5),
+        NamedKey::PageDown => tilde(6),
+        NamedKey::Enter => meta(b"\r"),
+        // Ctrl+Backspace sends whichever of DEL and BS Backspace doesn't,
+        // which shells bind to deleting a word.
+        NamedKey::Backspace if mods.control_key() => {
+            meta(&[if modes.backspace == DEL { BS } else { DEL }])
+        }
+        NamedKey::Backspace => meta(&[modes.backspace]),
+        NamedKey::Tab if mods.shift_key() => b"\x1b[Z".to_vec(),
+        NamedKey::Tab => meta(b"\t"),
+        NamedKey::Escape => meta(&[ESC]),
+        NamedKey::Space if mods.control_key() => meta(&[0]),
+        NamedKey::Space if mods.alt_key() => meta(b" "),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+fn encode_character(text: &str, mods: ModifiersState) -> Option<Vec<u8>> {
+    let mut chars = text.chars();
+    let (Some(ch), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    let (ctrl, alt) = (mods.control_key(), mods.alt_key());
+    // Windows reports AltGr as Ctrl+Alt. Anything but a letter under both
+    // is taken to be a character AltGr typed, and is left to the text.
+    if ctrl && alt && !ch.is_ascii_alphabetic() {
+        return None;
+    }
+    if ctrl {
+        let code = control_code(ch)?;
+        return Some(if alt { vec![ESC, code] } else { vec![code] });
+    }
+    if alt {
+        let mut bytes = vec![ESC];
+        bytes.extend_from_slice(text.as_bytes());
+        return Some(bytes);
+    }
+    None
+}
+
+// The C0 code Ctrl sends with `ch`, following xterm for the digits.
+fn control_code(ch: char) -> Option<u8> {
+    let code = match ch {
+        'a'..='z' => ch as u8 - b'a' + 1,
+        'A'..='Z' => ch as u8 - b'A' + 1,
+        '@' | ' ' | '2' => 0,
+        '[' | '3' => ESC,
+        '\\' | '4' => 0x1c,
+        ']' | '5' => 0x1d,
+        '^' | '6' => 0x1e,
+        '_' | '/' | '7' => 0x1f,
+        '?' | '8' => DEL,
+        _ => return None,
+    };
+    Some(code)
+}
+
+fn function_key_number(key: NamedKey) -> Option<u8> {
+    let number = match key {
+        NamedKey::F1 => 1,
+        NamedKey::F2 => 2,
+        NamedKey::F3 => 3,
+        NamedKey::F4 => 4,
+        NamedKey::F5 => 5,
+        NamedKey::F6 => 6,
+        NamedKey::F7 => 7,
+        NamedKey::F8 => 8,
+        NamedKey::F9 => 9,
+        NamedKey::F10 => 10,
+        NamedKey::F11 => 11,
+        NamedKey::F12 => 12,
+        _ => return None,
+    };
+    Some(number)
+}
+
+// F1-F4 are ESC O P-S, or ESC [1;<mod> P-S with modifiers. The rest use
+// their VT220 codes, which skip 16 and 22.
+fn encode_function_key(number: u8, modifier: Option<u8>) -> Vec<u8> {
+    const CODES: [u8; 8] = [15, 17, 18, 19, 20, 21, 23, 24];
+    if number <= 4 {
+        let final_byte = b'P' + number - 1;
+        return match modifier {
+            Some(modifier) => format!("\x1b[1;{modifier}{}", final_byte as char).into_bytes(),
+            None => vec![ESC, b'O', final_byte],
+        };
+    }
+    let code = CODES[number as usize - 5];
+    match modifier {
+        Some(modifier) => format!("\x1b[{code};{modifier}~").into_bytes(),
+        None => format!("\x1b[{code}~").into_bytes(),
+    }
+}
+
+// The xterm modifier parameter, 1 plus a bit per modifier held, or None
+// when no modifier is held.
+fn modifier_param(mods: ModifiersState) -> Option<u8> {
+    let mut bits = 0;
+    if mods.shift_key() {
+        bits |= 1;
+    }
+    if mods.alt_key() {
+        bits |= 2;
+    }
+    if mods.control_key() {
+        bits |= 4;
+    }
+    (bits != 0).then_some(1 + bits)
+}
+
+// A button press or release reported to an application tracking the mouse.
+// `button` is 0 for left, 1 for middle and 2 for right, and the cell is
+// zero-based. The legacy encoding can't describe cells past column 222, so
+// those clicks are dropped unless SGR reports were asked for.
+pub fn encode_mouse_button(
+    button: u8,
+    pressed: bool,
+    col: u16,
+    row: u16,
+    mods: ModifiersState,
+    sgr: bool,
+) -> Option<Vec<u8>> {
+    let mut code = button;
+    if mods.shift_key() {
+        code |= 4;
+    }
+    if mods.alt_key() {
+        code |= 8;
+    }
+    if mods.control_key() {
+        code |= 16;
+    }
+    let (col, row) = (col as u32 + 1, row as u32 + 1);
+    if sgr {
+        let action = if pressed { 'M' } else { 'm' };
+        return Some(format!("\x1b[<{code};{col};{row}{action}").into_bytes());
+    }
+    // Releases don't say which button was let go.
+    if !pressed {
+        code = (code & !3) | 3;
+    }
+    let encode = |value: u32| u8::try_from(value + 32).ok();
+    Some(vec![
+        ESC,
+        b'[',
+        b'M',
+        encode(code as u32)?,
+        encode(col)?,
+        encode(row)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: ModifiersState = ModifiersState::empty();
+    const SHIFT: ModifiersState = ModifiersState::SHIFT;
+    const CTRL: ModifiersState = ModifiersState::CONTROL;
+    const ALT: ModifiersState = ModifiersState::ALT;
+
+    const NORMAL: KeyModes = KeyModes {
+        application_cursor: false,
+        backspace: DEL,
+    };
+    const APPLICATION: KeyModes = KeyModes {
+        application_cursor: true,
+        backspace: DEL,
+    };
+
+    fn named(key: NamedKey, mods: ModifiersState, modes: KeyModes) -> Option<Vec<u8>> {
+        encode_key(&Key::Named(key), mods, modes)
+    }
+
+    fn check(cases: &[(NamedKey, ModifiersState, KeyModes, &str)]) {
+        for (key, mods, modes, expected) in cases {
+            assert_eq!(
+                named(*key, *mods, *modes).as_deref(),
+                Some(expected.as_bytes()),
+                "{key:?} with {mods:?}, application cursor {}",
+                modes.application_cursor
+            );
+        }
+    }
+
+    #[test]
+    fn navigation_keys() {
+        check(&[
+            (NamedKey::ArrowUp, NONE, NORMAL, "\x1b[A"),
+            (NamedKey::ArrowDown, NONE, NORMAL, "\x1b[B"),
+            (NamedKey::ArrowRight, NONE, NORMAL, "\x1b[C"),
+            (NamedKey::ArrowLeft, NONE, NORMAL, "\x1b[D"),
+            (NamedKey::Home, NONE, NORMAL, "\x1b[H"),
+            (NamedKey::End, NONE, NORMAL, "\x1b[F"),
+            (NamedKey::Insert, NONE, NORMAL, "\x1b[2~"),
+            (NamedKey::Delete, NONE, NORMAL, "\x1b[3~"),
+            (NamedKey::PageUp, NONE, NORMAL, "\x1b[5~"),
+            (NamedKey::PageDown, NONE, NORMAL, "\x1b[6~"),
+            (NamedKey::ArrowUp, NONE, APPLICATION, "\x1bOA"),
+            (NamedKey::ArrowLeft, NONE, APPLICATION, "\x1bOD"),
+            (NamedKey::Home, NONE, APPLICATION, "\x1bOH"),
+            (NamedKey::End, NONE, APPLICATION, "\x1bOF"),
+            (NamedKey::PageUp, NONE, APPLICATION, "\x1b[5~"),
+            (NamedKey::Delete, NONE, APPLICATION, "\x1b[3~"),
+            (NamedKey::ArrowUp, SHIFT, NORMAL, "\x1b[1;2A"),
+            (NamedKey::ArrowLeft, CTRL, NORMAL, "\x1b[1;5D"),
+            (NamedKey::ArrowRight, CTRL, NORMAL, "\x1b[1;5C"),
+            (NamedKey::Home, CTRL, NORMAL, "\x1b[1;5H"),
+            (NamedKey::End, SHIFT, NORMAL, "\x1b[1;2F"),
+            (NamedKey::Delete, CTRL, NORMAL, "\x1b[3;5~"),
+            (NamedKey::PageUp, SHIFT, NORMAL, "\x1b[5;2~"),
+            (NamedKey::PageDown, ALT, NORMAL, "\x1b[6;3~"),
+        ]);
+    }
+
+    #[test]
+    fn backspace_and_delete_are_distinct() {
+        let bs = KeyModes {
+            backspace: BS,
+            ..NORMAL
+        };
+        check(&[
+            (NamedKey::Backspace, NONE, NORMAL, "\x7f"),
+            (NamedKey::Backspace, NONE, bs, "\x08"),
+            (NamedKey::Backspace, CTRL, NORMAL, "\x08"),
+            (NamedKey::Backspace, CTRL, bs, "\x7f"),
+            (NamedKey::Backspace, ALT, NORMAL, "\x1b\x7f"),
+            (NamedKey::Delete, NONE, bs, "\x1b[3~"),
+        ]);
+    }
+
+    #[test]
+    fn function_keys() {
+        check(&[
+            (NamedKey::F1, NONE, NORMAL, "\x1bOP"),
+            (NamedKey::F2, NONE, NORMAL, "\x1bOQ"),
+            (NamedKey::F3, NONE, NORMAL, "\x1bOR"),
+            (NamedKey::F4, NONE, NORMAL, "\x1bOS"),
+            (NamedKey::F5, NONE, NORMAL, "\x1b[15~"),
+            (NamedKey::F6, NONE, NORMAL, "\x1b[17~"),
+            (NamedKey::F7, NONE, NORMAL, "\x1b[18~"),
+            (NamedKey::F8, NONE, NORMAL, "\x1b[19~"),
+            (NamedKey::F9, NONE, NORMAL, "\x1b[20~"),
+            (NamedKey::F10, NONE, NORMAL, "\x1b[21~"),
+            (NamedKey::F11, NONE, NORMAL, "\x1b[23~"),
+            (NamedKey::F12, NONE, NORMAL, "\x1b[24~"),
+            (NamedKey::F1, CTRL, NORMAL, "\x1b[1;5P"),
+            (NamedKey::F4, SHIFT, NORMAL, "\x1b[1;2S"),
+            (NamedKey::F5, CTRL, NORMAL, "\x1b[15;5~"),
+            (NamedKey::F12, ALT, NORMAL, "\x1b[24;3~"),
+            // DECCKM only changes the cursor keys.
+            (NamedKey::F1, NONE, APPLICATION, "\x1bOP"),
+            (NamedKey::F10, NONE, APPLICATION, "\x1b[21~"),
+        ]);
+    }
+
+    // Every combination of Shift, Alt and Ctrl on the cursor and editing
+    // keys, in both cursor key modes.
+    #[test]
+    fn modifier_matrix() {
+        let cursor_keys = [
+            (NamedKey::ArrowUp, 'A'),
+            (NamedKey::ArrowDown, 'B'),
+            (NamedKey::ArrowRight, 'C'),
+            (NamedKey::ArrowLeft, 'D'),
+            (NamedKey::Home, 'H'),
+            (NamedKey::End, 'F'),
+        ];
+        let tilde_keys = [
+            (NamedKey::Insert, 2),
+            (NamedKey::Delete, 3),
+            (NamedKey::PageUp, 5),
+            (NamedKey::PageDown, 6),
+        ];
+        for bits in 1..8u8 {
+            let mut mods = NONE;
+            mods.set(SHIFT, bits & 1 != 0);
+            mods.set(ALT, bits & 2 != 0);
+            mods.set(CTRL, bits & 4 != 0);
+            let param = 1 + bits;
+            for modes in [NORMAL, APPLICATION] {
+                for (key, final_byte) in cursor_keys {
+                    let expected = format!("\x1b[1;{param}{final_byte}");
+                    check(&[(key, mods, modes, &expected)]);
+                }
+                for (key, code) in tilde_keys {
+                    let expected = format!("\x1b[{code};{param}~");
+                    check(&[(key, mods, modes, &expected)]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alt_prefixes_escape() {
+        check(&[
+            (NamedKey::Enter, NONE, NORMAL, "\r"),
+            (NamedKey::Enter, ALT, NORMAL, "\x1b\r"),
+            (NamedKey::Tab, NONE, NORMAL, "\t"),
+            (NamedKey::Tab, ALT, NORMAL, "\x1b\t"),
+            (NamedKey::Tab, SHIFT, NORMAL, "\x1b[Z"),
+            (NamedKey::Escape, NONE, NORMAL, "\x1b"),
+            (NamedKey::Escape, ALT, NORMAL, "\x1b\x1b"),
+            (NamedKey::Space, CTRL, NORMAL, "\0"),
+            (NamedKey::Space, ALT, NORMAL, "\x1b "),
+        ]);
+        assert_eq!(named(NamedKey::Space, NONE, NORMAL), None);
+        assert_eq!(named(NamedKey::Space, SHIFT, NORMAL), None);
+    }
+
+    #[test]
+    fn characters_with_modifiers() {
+        let character = |text: &str, mods| encode_key(&Key::Character(text.into()), mods, NORMAL);
+        let cases: &[(&str, ModifiersState, Option<&[u8]>)] = &[
+            // Plain and shifted characters are sent as the text they type.
+            ("a", NONE, None),
+            ("A", SHIFT, None),
+            ("a", CTRL, Some(b"\x01")),
+            ("C", CTRL | SHIFT, Some(b"\x03")),
+            ("z", CTRL, Some(b"\x1a")),
+            ("[", CTRL, Some(b"\x1b")),
+            ("2", CTRL, Some(b"\0")),
+            ("/", CTRL, Some(b"\x1f")),
+            ("8", CTRL, Some(b"\x7f")),
+            ("b", ALT, Some(b"\x1bb")),
+            ("B", ALT | SHIFT, Some(b"\x1bB")),
+            ("f", CTRL | ALT, Some(b"\x1b\x06")),
+            // AltGr arrives as Ctrl+Alt and types the character itself.
+            ("@", CTRL | ALT, None),
+            ("\u{20ac}", CTRL | ALT, None),
+            // Ctrl has no code for most punctuation.
+            (",", CTRL, None),
+            // Dead keys and IME output are more than one character.
+            ("ab", ALT, None),
+        ];
+        for (text, mods, expected) in cases {
+            assert_eq!(
+                character(text, *mods).as_deref(),
+                *expected,
+                "{text:?} with {mods:?}"
+            );
+        }
+    }
+}
@@ -1,65 +1,175 @@
 #![windows_subsystem = "windows"]
 
+mod cli;
+mod config_watch;
+mod input;
+mod profile;
+mod session;
+mod tray;
+
 use anyhow::{anyhow, Context, Result};
-use pty::{Pty, PtyReader, PtySize, PtyWriter};
+use cli::CliArgs;
+use config::{CloseOnExit, Config, DropQuoting, KeyBinding, RightClick, WindowState};
+use config_watch::ConfigWatch;
+#[cfg(windows)]
+use ico::{IconDir, IconDirEntry, IconImage, ResourceType};
+use input::KeyModes;
+use profile::Profile;
+use pty::{CtrlEvent, Pty, PtyBuilder, PtyError, PtySize};
+#[cfg(windows)]
+use raw_window_handle::{HasWindowHandle, RawWindowHandle};
 use render::{
-    CursorPosition, FontSpec, RenderError, RenderGrid, RenderSize, Renderer, CELL_HEIGHT,
-    CELL_WIDTH, DEFAULT_FONT_SIZE, PADDING_X, PADDING_Y,
+    CellMetrics, CursorBlink, CursorPosition, FontSpec, FrameCapture, GridLayout, GridSelection,
+    Notice, PostEffect, Preedit, RenderError, RenderGrid, RenderSize, Renderer, RowSource,
+    ScrollIndicator, TabBar, TextAntialias, Theme, DEFAULT_FONT_SIZE, MAX_CELL_SCALE,
+    MAX_CONTRAST_RATIO, MAX_FONT_SIZE, MIN_CELL_SCALE, MIN_FONT_SIZE, PADDING_X, PADDING_Y,
+};
+use screen::{
+    Cell, CursorShape, MouseTracking, Rgb, Screen, ScreenSize, Selection, SelectionPoint, UrlMatch,
+    DEFAULT_SCROLLBACK_LINES,
 };
-use screen::{Screen, ScreenSize};
-use std::path::PathBuf;
-use std::sync::mpsc::{self, Receiver, Sender};
+use session::Session;
 use std::io::Cursor;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
 use std::thread;
-use std::time::{Duration, Instant};
-use std::{env, fs};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
 use tracing::{error, info, warn};
-use vt::VtParser;
-use winit::event::{ElementState, Event, WindowEvent};
-use winit::event_loop::{ControlFlow, EventLoop};
-use winit::keyboard::{Key, ModifiersState, NamedKey};
+use tray::{Tray, TrayEvent};
+use winit::event::{ElementState, Event, Ime, KeyEvent, MouseButton, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopProxy};
+use winit::keyboard::{Key, KeyCode, ModifiersState, NamedKey, PhysicalKey};
 #[cfg(windows)]
 use winit::platform::windows::{IconExtWindows, WindowBuilderExtWindows, WindowExtWindows};
 use winit::window::WindowBuilder;
-#[cfg(windows)]
-use raw_window_handle::{HasWindowHandle, RawWindowHandle};
-#[cfg(windows)]
-use ico::{IconDir, IconDirEntry, IconImage, ResourceType};
 
 const CASCADIA_DOWNLOAD_URLS: &[&str] = &[
     "https://raw.githubusercontent.com/BENZOOgataga/RING0/main/install/Cascadia_Code.zip",
     "https://github.com/BENZOOgataga/RING0/raw/main/install/Cascadia_Code.zip",
 ];
 const CASCADIA_ZIP_PATH: &str = "static/CascadiaCode-Regular.ttf";
-const DEFAULT_SHELL_COMMAND: &str =
-    "powershell.exe -NoLogo -NoProfile -NoExit -Command \"Remove-Module PSReadLine -ErrorAction SilentlyContinue\"";
+const DEFAULT_COLS: u32 = 120;
+const DEFAULT_ROWS: u32 = 30;
+const DEFAULT_OPACITY: f32 = 1.0;
+const MIN_OPACITY: f32 = 0.3;
+const OPACITY_STEP: f32 = 0.05;
+const DEFAULT_MAX_FPS: u32 = 120;
+const DEFAULT_TITLE_TEMPLATE: &str = "{title} — RING0";
+// Appended to the title while the window stays on top.
+const PINNED_TITLE_MARK: &str = " \u{1F4CC}";
+const ALWAYS_ON_TOP_KEY: KeyBinding = KeyBinding {
+    ctrl: true,
+    shift: true,
+    alt: false,
+    key: 'a',
+};
+// Some prompts set the title every time they are drawn.
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(200);
+// A program printing a stream of BEL characters shouldn't strobe.
+const BELL_MIN_INTERVAL: Duration = Duration::from_millis(250);
+// A hidden window is never redrawn, so nothing else wakes the event loop to
+// keep reading the shells.
+const TRAY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const TEXT_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+const SCROLLBAR_FADE_IN: Duration = Duration::from_millis(120);
+const SCROLLBAR_HOLD: Duration = Duration::from_secs(1);
+const SCROLLBAR_FADE_OUT: Duration = Duration::from_millis(300);
+const NOTICE_HOLD: Duration = Duration::from_secs(1);
+const NOTICE_FADE_OUT: Duration = Duration::from_millis(300);
+const SMOOTH_SCROLL_DURATION: Duration = Duration::from_millis(80);
+const BRACKETED_PASTE_START: &[u8] = b"\x1b[200~";
+const BRACKETED_PASTE_END: &[u8] = b"\x1b[201~";
 
 struct AppState {
     window: winit::window::Window,
     renderer: Renderer<'static>,
-    pty: Option<Pty>,
-    pty_writer: Option<PtyWriter>,
-    pty_rx: Option<Receiver<PtyMessage>>,
-    vt_parser: VtParser,
-    screen: Screen,
-    render_cells: Vec<char>,
-    pty_closed: bool,
-    last_status_check: Instant,
-    exit_checks_failed: u8,
-    cursor_visible: bool,
-    last_cursor_toggle: Instant,
+    // One per tab; never empty.
+    sessions: Vec<Session>,
+    active: usize,
+    text_blink_on: bool,
+    last_text_blink_toggle: Instant,
+    // Last title given to the window, to skip redundant updates.
+    window_title: String,
+    title_pending: bool,
+    title_updated_at: Instant,
+    last_bell: Option<Instant>,
+    // Last known position and size of the restored window.
+    window_layout: Option<WindowState>,
+    focused: bool,
+    scroll_fade: Option<ScrollFade>,
+    scroll_animation: Option<ScrollAnimation>,
     font_prompt: bool,
     font_download_rx: Option<Receiver<FontDownloadMessage>>,
     font_download_in_progress: bool,
     modifiers: ModifiersState,
-    input_len: usize,
-    input_buffer: String,
+    mouse_position: Option<(f64, f64)>,
+    // Where the left button went down, while it is held.
+    selection_anchor: Option<SelectionPoint>,
+    // The link under the mouse while Ctrl is held.
+    hovered_link: Option<UrlMatch>,
+    // Text the input method is composing. It is only drawn, never sent.
+    preedit: Option<ImePreedit>,
+    // Where the candidate window was last placed, in window pixels.
+    ime_cursor_area: Option<(u32, u32)>,
+    // Files dropped since the last turn of the event loop. A drop of several
+    // files arrives as one event per file.
+    dropped_paths: Vec<PathBuf>,
+    // Closes the window at the end of the event-loop turn.
     exit_requested: bool,
+    always_on_top: bool,
+    // Picked with the opacity hotkeys, and remembered with the window layout.
+    chosen_opacity: Option<f32>,
+    // Shown briefly at the top of the window after a setting changes.
+    notice: Option<NoticeBadge>,
+    config: Config,
+    // Reapplied over the config file whenever it is reloaded.
+    cli: CliArgs,
+    config_watch: Option<ConfigWatch>,
+    // Present while the window is hidden in the notification area.
+    tray: Option<Tray>,
+    // Wakes the event loop when the tray icon is clicked.
+    event_proxy: EventLoopProxy<()>,
+    // Built-in and configured, in the order Ctrl+Shift+1 to 9 open them.
+    profiles: Vec<Profile>,
+    // Run in the first tab and by Ctrl+Shift+T.
+    default_profile: Profile,
+    // The configured theme, for tabs whose profile has none of its own.
+    theme: Theme,
+    // Font size the config asks for, restored by Ctrl+0.
+    font_size: f32,
+    // Config problems, printed above the shell's output once it starts.
+    config_notices: Vec<String>,
+}
+
+#[derive(Copy, Clone)]
+struct ScrollFade {
+    shown: Instant,
+    last_activity: Instant,
+}
+
+struct NoticeBadge {
+    text: String,
+    shown: Instant,
 }
 
-enum PtyMessage {
-    Data(Vec<u8>),
-    Closed,
+#[derive(Copy, Clone)]
+struct ScrollAnimation {
+    from: f32,
+    started: Instant,
+}
+
+// Lends the renderer the visible rows straight from the screen.
+struct ScreenRows<'a> {
+    screen: &'a Screen,
+    scroll_offset: usize,
+}
+
+impl RowSource for ScreenRows<'_> {
+    fn row(&self, index: usize, _cols: usize) -> Option<&[Cell]> {
+        self.screen.view_row(self.scroll_offset, index)
+    }
 }
 
 enum FontDownloadMessage {
@@ -67,13 +177,20 @@ enum FontDownloadMessage {
 }
 
 impl AppState {
-    async fn new(window: winit::window::Window) -> Result<Self> {
+    async fn new(
+        window: winit::window::Window,
+        cli: CliArgs,
+        config_path: Option<PathBuf>,
+        config: Config,
+        mut config_notices: Vec<String>,
+        saved_layout: Option<WindowState>,
+        event_proxy: EventLoopProxy<()>,
+    ) -> Result<Self> {
         let size = window.inner_size();
         let render_size = RenderSize {
             width: size.width.max(1),
             height: size.height.max(1),
         };
-        let screen_size = screen_size_from_pixels(size);
 
         let instance = wgpu::Instance::default();
         let surface = instance
@@ -97,17 +214,24 @@ impl AppState {
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::empty(),
+                    // Only used for subpixel text; without it the renderer
+                    // falls back to grayscale.
+                    required_features: adapter.features() & wgpu::Features::DUAL_SOURCE_BLENDING,
                     required_limits: wgpu::Limits::default(),
                 },
                 None,
             )
             .await
             .context("request wgpu device")?;
+        info!(
+            "surface formats offered: {:?}",
+            surface.get_capabilities(&adapter).formats
+        );
 
-        let font_load = load_font_bytes().context("load font data")?;
+        let font_load = configured_font(&config, &mut config_notices)?;
         info!("font source: {:?}", font_load.source);
-        let renderer = Renderer::new(
+        let font_size = configured_font_size(&config, &mut config_notices);
+        let mut renderer = Renderer::new(
             surface,
             &adapter,
             device,
@@ -115,39 +239,120 @@ impl AppState {
             render_size,
             FontSpec {
                 bytes: font_load.bytes,
-                size: DEFAULT_FONT_SIZE,
+                size: font_size,
+                collection_index: font_load.collection_index,
+                weight: configured_font_weight(&config, &mut config_notices),
             },
         )
         .context("initialize renderer")?;
-
-        let screen = Screen::new(screen_size).context("initialize screen")?;
+        info!("surface format: {:?}", renderer.surface_format());
+        let (padding_x, padding_y) = configured_padding(&config);
+        renderer.set_padding(padding_x, padding_y);
+        renderer
+            .set_scale_factor(window.scale_factor())
+            .context("apply display scale")?;
+        let line_height =
+            configured_cell_scale(config.font.line_height, "Line height", &mut config_notices);
+        renderer
+            .set_line_height_scale(line_height)
+            .context("apply line height")?;
+        let cell_width =
+            configured_cell_scale(config.font.cell_width, "Cell width", &mut config_notices);
+        renderer
+            .set_cell_width_scale(cell_width)
+            .context("apply cell width")?;
+
+        // A restored window keeps its saved size.
+        let cell = renderer.cell_metrics();
+        if cell != CellMetrics::default() && saved_layout.is_none() {
+            if let Some(actual) = window.request_inner_size(default_window_size(cell, &config)) {
+                renderer
+                    .resize(RenderSize {
+                        width: actual.width.max(1),
+                        height: actual.height.max(1),
+                    })
+                    .context("resize renderer")?;
+            }
+        }
+        let (profiles, default_profile) = configured_profiles(&config, &cli, &mut config_notices);
+        let theme = configured_theme(&config.theme, &mut config_notices);
+        // The config wins over the remembered state when it says either way.
+        let always_on_top = config
+            .window
+            .always_on_top
+            .or(saved_layout.as_ref().map(|layout| layout.always_on_top))
+            .unwrap_or(false);
+        let chosen_opacity = saved_layout.as_ref().and_then(|layout| layout.opacity);
         let mut state = Self {
             window,
             renderer,
-            pty: None,
-            pty_writer: None,
-            pty_rx: None,
-            vt_parser: VtParser::new(),
-            screen,
-            render_cells: Vec::new(),
-            pty_closed: false,
-            last_status_check: Instant::now(),
-            exit_checks_failed: 0,
-            cursor_visible: true,
-            last_cursor_toggle: Instant::now(),
+            sessions: Vec::new(),
+            active: 0,
+            text_blink_on: true,
+            last_text_blink_toggle: Instant::now(),
+            window_title: "RING0".to_string(),
+            title_pending: false,
+            title_updated_at: Instant::now(),
+            last_bell: None,
+            window_layout: saved_layout,
+            focused: true,
+            scroll_fade: None,
+            scroll_animation: None,
             font_prompt: font_load.source == FontSource::Fallback,
             font_download_rx: None,
             font_download_in_progress: false,
             modifiers: ModifiersState::default(),
-            input_len: 0,
-            input_buffer: String::new(),
+            mouse_position: None,
+            selection_anchor: None,
+            hovered_link: None,
+            preedit: None,
+            ime_cursor_area: None,
+            dropped_paths: Vec::new(),
             exit_requested: false,
+            always_on_top: false,
+            chosen_opacity,
+            notice: None,
+            config,
+            cli,
+            config_watch: config_path.map(ConfigWatch::new),
+            tray: None,
+            event_proxy,
+            profiles,
+            default_profile: default_profile.clone(),
+            theme: theme.clone(),
+            font_size,
+            config_notices,
         };
+        let session = state.create_session(default_profile)?;
+        state.sessions.push(session);
+        if always_on_top {
+            state.set_always_on_top(true);
+        }
+        state.apply_theme(theme);
+        let opacity = configured_opacity(
+            &state.config,
+            state.chosen_opacity,
+            &mut state.config_notices,
+        );
+        state.set_opacity(opacity);
+        state
+            .renderer
+            .set_max_fps(configured_max_fps(&state.config));
+        state
+            .renderer
+            .set_text_blink(state.config.text_blink.unwrap_or(true));
+        state.apply_cursor_blink();
+        state
+            .renderer
+            .set_text_antialias(configured_text_antialias(&state.config));
+        let minimum_contrast =
+            configured_minimum_contrast(&state.config, &mut state.config_notices);
+        state.renderer.set_minimum_contrast(minimum_contrast);
 
         if state.font_prompt {
             state.show_font_prompt();
         } else {
-            state.start_pty()?;
+            state.start_pty_or_report();
         }
 
         Ok(state)
@@ -165,128 +370,238 @@ impl AppState {
             warn!("renderer resize failed: {err}");
         }
 
-        let screen_size = screen_size_from_pixels(new_size);
-        if screen_size != self.screen.size() {
-            if let Err(err) = self.screen.resize(screen_size) {
-                warn!("screen resize failed: {err}");
-            }
-            if let Some(pty) = self.pty.as_mut() {
-                if let Err(err) = pty.resize(PtySize {
-                    cols: screen_size.cols,
-                    rows: screen_size.rows,
-                }) {
-                    warn!("pty resize failed: {err}");
-                }
-            }
+        let screen_size = screen_size_from_layout(self.renderer.grid_layout());
+        let pty_size = self.pty_size(screen_size);
+        for session in &mut self.sessions {
+            session.resize(screen_size, pty_size);
         }
     }
 
-    fn handle_input_text(&mut self, text: &str) {
-        if self.pty_closed {
-            return;
+    fn session(&self) -> &Session {
+        &self.sessions[self.active]
+    }
+
+    fn session_mut(&mut self) -> &mut Session {
+        &mut self.sessions[self.active]
+    }
+
+    // A session sized to the current grid, with the screen set up the way
+    // the config and theme ask.
+    fn create_session(&mut self, profile: Profile) -> Result<Session> {
+        let theme = profile
+            .theme
+            .as_ref()
+            .map(|theme| configured_theme(theme, &mut self.config_notices));
+        let mut session = Session::new(
+            screen_size_from_layout(self.renderer.grid_layout()),
+            profile,
+        )?;
+        session.theme = theme;
+        let palette = session.theme.as_ref().unwrap_or(&self.theme).palette();
+        session.screen.set_base_palette(palette);
+        configure_session(&self.config, &mut session);
+        Ok(session)
+    }
+
+    fn new_tab(&mut self) {
+        self.open_tab(self.default_profile.clone());
+    }
+
+    // Ctrl+Shift+N: the active tab's profile in a new tab, started where its
+    // shell is now, or where the profile starts when that isn't known.
+    fn duplicate_session(&mut self) {
+        let profile = &self.session().profile;
+        let profile = self
+            .session()
+            .current_dir()
+            .and_then(|dir| profile.starting_in(&dir))
+            .unwrap_or_else(|| profile.clone());
+        self.open_tab(profile);
+    }
+
+    // Ctrl+Shift+1 to 9 open the profile at that position.
+    fn open_profile(&mut self, index: usize) {
+        if let Some(profile) = self.profiles.get(index) {
+            self.open_tab(profile.clone());
         }
+    }
+
+    // Opens a tab running the profile and switches to it.
+    fn open_tab(&mut self, profile: Profile) {
         if self.font_prompt {
-            self.handle_font_prompt_input(text);
             return;
         }
-        let mut filtered = String::new();
-        for ch in text.chars() {
-            if ch.is_control() {
-                continue;
+        let session = match self.create_session(profile) {
+            Ok(session) => session,
+            Err(err) => {
+                warn!("new tab failed: {err:#}");
+                return;
             }
-            filtered.push(ch);
+        };
+        self.sessions.push(session);
+        self.active = self.sessions.len() - 1;
+        // The tab bar may have just appeared and shrunk the grid, so the
+        // shell starts at the final size.
+        self.session_switched();
+        self.start_pty_or_report();
+        self.update_tab_bar();
+    }
+
+    // Closing the last session closes the window.
+    fn close_session(&mut self, index: usize) {
+        if self.sessions.len() == 1 {
+            self.exit_requested = true;
+            return;
         }
-        if !filtered.is_empty() {
-            self.input_len = self.input_len.saturating_add(filtered.chars().count());
-            self.input_buffer.push_str(&filtered);
-            self.send_input_bytes(filtered.as_bytes());
+        self.sessions[index].shutdown();
+        self.sessions.remove(index);
+        if self.active > index || self.active == self.sessions.len() {
+            self.active -= 1;
         }
+        self.session_switched();
     }
 
-    fn handle_special_key(&mut self, key: NamedKey) {
-        if self.pty_closed {
-            return;
+    fn close_requested_sessions(&mut self) {
+        while let Some(index) = self
+            .sessions
+            .iter()
+            .position(|session| session.exit_requested)
+        {
+            self.close_session(index);
+            if self.exit_requested {
+                return;
+            }
         }
-        if self.font_prompt {
+    }
+
+    fn cycle_tab(&mut self, forward: bool) {
+        let count = self.sessions.len();
+        if count < 2 {
             return;
         }
-        let bytes: Option<&[u8]> = match key {
-            NamedKey::Enter => {
-                if self.input_buffer.trim().eq_ignore_ascii_case("exit") {
-                    self.exit_requested = true;
-                    self.pty_closed = true;
-                }
-                self.input_len = 0;
-                self.input_buffer.clear();
-                Some(b"\r".as_slice())
-            }
-            NamedKey::Backspace => {
-                if self.input_len > 0 {
-                    self.input_len -= 1;
-                    self.input_buffer.pop();
-                    Some(&[0x08u8] as &[u8])
-                } else {
-                    None
-                }
-            }
-            NamedKey::Escape => Some(&[0x1Bu8] as &[u8]),
-            NamedKey::Tab => Some(&[0x09u8] as &[u8]),
-            _ => None,
+        self.active = match forward {
+            true => (self.active + 1) % count,
+            false => (self.active + count - 1) % count,
         };
+        self.session_switched();
+    }
 
-        if let Some(bytes) = bytes {
-            self.send_input_bytes(bytes);
+    fn session_switched(&mut self) {
+        self.update_renderer_theme();
+        self.selection_anchor = None;
+        self.hovered_link = None;
+        self.scroll_animation = None;
+        self.scroll_fade = None;
+        self.update_window_title();
+        self.update_tab_bar();
+        self.window.request_redraw();
+    }
+
+    // Shown only while there is more than one tab. The bar takes its height
+    // from the grid, so the sessions are resized when it comes or goes.
+    fn update_tab_bar(&mut self) {
+        let tabs = (self.sessions.len() > 1).then(|| TabBar {
+            titles: self
+                .sessions
+                .iter()
+                .map(|session| session.title().to_string())
+                .collect(),
+            active: self.active,
+        });
+        if self.renderer.tab_bar() == tabs.as_ref() {
+            return;
         }
+        let moved = self.renderer.tab_bar().is_some() != tabs.is_some();
+        self.renderer.set_tab_bar(tabs);
+        if moved {
+            self.resize(self.window.inner_size());
+        }
+        self.window.request_redraw();
     }
 
-    fn drain_pty(&mut self) {
-        let mut events = Vec::new();
-        if let Some(rx) = self.pty_rx.as_ref() {
-            while let Ok(message) = rx.try_recv() {
-                match message {
-                    PtyMessage::Data(bytes) => {
-                        self.vt_parser.advance(&bytes, &mut events);
-                        if !events.is_empty() {
-                            self.screen.apply_events(&events);
-                            events.clear();
-                        }
-                    }
-                    PtyMessage::Closed => {
-                        self.pty_closed = true;
-                        self.exit_checks_failed = 0;
-                        info!("pty closed; stopping input");
-                    }
-                }
+    // Background sessions are drained as well, so their output isn't lost
+    // and their exit is noticed.
+    fn drain_sessions(&mut self) {
+        for (index, session) in self.sessions.iter_mut().enumerate() {
+            session.drain_pty();
+            if session.drain_pty_exit() && index == self.active {
+                self.window.request_redraw();
             }
         }
     }
 
-    fn check_pty_status(&mut self) {
-        if self.pty_closed {
-            return;
+    fn shutdown_sessions(&mut self) {
+        for session in &mut self.sessions {
+            session.shutdown();
+        }
+    }
+
+    fn pty_size(&self, size: ScreenSize) -> PtySize {
+        let cell = self.renderer.cell_metrics();
+        let pixels =
+            |cells: u16, cell: u32| u16::try_from(u32::from(cells) * cell).unwrap_or(u16::MAX);
+        PtySize {
+            cols: size.cols,
+            rows: size.rows,
+            pixel_width: pixels(size.cols, cell.width),
+            pixel_height: pixels(size.rows, cell.height),
         }
-        let Some(pty) = self.pty.as_ref() else {
+    }
+
+    fn handle_input_text(&mut self, text: &str) {
+        if self.session().pty_closed {
             return;
-        };
-        if self.last_status_check.elapsed() < Duration::from_millis(500) {
+        }
+        if self.font_prompt {
+            self.handle_font_prompt_input(text);
             return;
         }
-        self.last_status_check = Instant::now();
-        match pty.is_running() {
-            Ok(true) => {
-                self.exit_checks_failed = 0;
+        let mut filtered = String::new();
+        for ch in text.chars() {
+            if ch.is_control() {
+                continue;
             }
-            Ok(false) => {
-                self.exit_checks_failed = self.exit_checks_failed.saturating_add(1);
-                if self.exit_checks_failed >= 2 {
-                    self.pty_closed = true;
-                    info!("pty no longer running; exiting");
+            filtered.push(ch);
+        }
+        if !filtered.is_empty() {
+            self.session_mut().input_buffer.push_str(&filtered);
+            self.send_input_bytes(filtered.as_bytes());
+        }
+    }
+
+    // Sends the encoding of a key that doesn't just type text. Returns
+    // false when the key is left to the text it produced.
+    fn handle_key(&mut self, key: &Key) -> bool {
+        if self.session().pty_closed || self.font_prompt {
+            return false;
+        }
+        let modes = KeyModes {
+            application_cursor: self.session().screen.application_cursor_keys(),
+            backspace: match self.config.backspace {
+                Some(config::Backspace::Del) | None => input::DEL,
+                Some(config::Backspace::Bs) => input::BS,
+            },
+        };
+        let Some(bytes) = input::encode_key(key, self.modifiers, modes) else {
+            return false;
+        };
+        let session = self.session_mut();
+        match key {
+            Key::Named(NamedKey::Enter) => {
+                if session.input_buffer.trim().eq_ignore_ascii_case("exit") {
+                    session.exit_requested = true;
+                    session.pty_closed = true;
                 }
+                session.input_buffer.clear();
             }
-            Err(err) => {
-                warn!("pty status check failed: {err}");
+            Key::Named(NamedKey::Backspace) => {
+                session.input_buffer.pop();
             }
+            _ if bytes == [0x03] => session.input_buffer.clear(),
+            _ => {}
         }
+        self.send_input_bytes(&bytes);
+        true
     }
 
     fn drain_font_download(&mut self) {
@@ -314,12 +629,7 @@ impl AppState {
                     return;
                 }
                 self.font_prompt = false;
-                if let Err(err) = self.start_pty() {
-                    warn!("pty start failed: {err}");
-                    self.show_system_message(&format!(
-                        "Failed to start shell: {err}\r\nClose the window to exit.\r\n"
-                    ));
-                }
+                self.start_pty_or_report();
             }
             FontDownloadMessage::Completed(Err(err)) => {
                 self.show_font_download_error(&err);
@@ -350,12 +660,7 @@ impl AppState {
             Some(true) => self.begin_font_download(),
             Some(false) => {
                 self.font_prompt = false;
-                if let Err(err) = self.start_pty() {
-                    warn!("pty start failed: {err}");
-                    self.show_system_message(&format!(
-                        "Failed to start shell: {err}\r\nClose the window to exit.\r\n"
-                    ));
-                }
+                self.start_pty_or_report();
             }
             None => {}
         }
@@ -370,12 +675,37 @@ impl AppState {
         self.font_download_rx = Some(spawn_font_download());
     }
 
+    // The window stays open when the shell can't start, so the reason is
+    // shown in it, with a hint for the failures a user can fix.
+    fn start_pty_or_report(&mut self) {
+        let Err(err) = self.start_pty() else {
+            // Shows the shell's name until it sets a title.
+            self.update_window_title();
+            return;
+        };
+        warn!("pty start failed: {err:#}");
+        let hint = match err.downcast_ref::<PtyError>() {
+            Some(PtyError::CommandNotFound { .. }) => {
+                "Check that the shell is installed and on PATH.\r\n"
+            }
+            Some(PtyError::AccessDenied { .. }) => {
+                "Check that the shell executable can be run by this user.\r\n"
+            }
+            Some(PtyError::ElevationRequired { .. }) => {
+                "Start RING0 as administrator to run this shell.\r\n"
+            }
+            _ => "",
+        };
+        self.show_system_message(&format!(
+            "Failed to start shell: {err:#}\r\n{hint}Close the window to exit.\r\n"
+        ));
+    }
+
     fn show_system_message(&mut self, text: &str) {
-        self.screen.clear();
-        self.screen.scroll_to_bottom();
-        let mut events = Vec::new();
-        self.vt_parser.advance(text.as_bytes(), &mut events);
-        self.screen.apply_events(&events);
+        let session = self.session_mut();
+        session.screen.clear();
+        session.screen.scroll_to_bottom();
+        session.write_text(text);
     }
 
     fn show_font_prompt(&mut self) {
@@ -398,12 +728,18 @@ Press Y to retry or N to continue with the fallback font.\r\n"
     }
 
     fn apply_downloaded_font(&mut self, bytes: Vec<u8>) -> Result<()> {
-        self.renderer
+        let metrics_changed = self
+            .renderer
             .set_font(FontSpec {
                 bytes: bytes.clone(),
-                size: DEFAULT_FONT_SIZE,
+                size: self.renderer.font_size(),
+                collection_index: 0,
+                weight: configured_font_weight(&self.config, &mut self.config_notices),
             })
             .context("update renderer font")?;
+        if metrics_changed {
+            self.resize(self.window.inner_size());
+        }
         info!("font source: {:?}", FontSource::Cascadia);
         if let Some(path) = font_cache_path()? {
             if let Some(parent) = path.parent() {
@@ -414,72 +750,788 @@ Press Y to retry or N to continue with the fallback font.\r\n"
         Ok(())
     }
 
-    fn start_pty(&mut self) -> Result<()> {
-        let size = self.screen.size();
-        let pty = Pty::spawn(
-            DEFAULT_SHELL_COMMAND,
-            PtySize {
-                cols: size.cols,
-                rows: size.rows,
-            },
-        )
-        .context("spawn pty")?;
-        let reader = pty.reader().context("clone pty reader")?;
-        let writer = pty.writer().context("clone pty writer")?;
-        let rx = spawn_pty_reader(reader);
+    // The active tab's profile. Its environment can override TERM.
+    fn shell(&self) -> PtyBuilder {
+        let profile = &self.session().profile;
+        // The VT parser handles 256-colour and 24-bit SGR colours.
+        let mut shell = Pty::builder(profile.command.as_str())
+            .args(profile.args.iter().cloned())
+            .env("TERM", "xterm-256color")
+            .env("COLORTERM", "truecolor");
+        for (key, value) in &profile.env {
+            shell = shell.env(key, value);
+        }
+        if let Some(dir) = &profile.cwd {
+            shell = shell.current_dir(dir);
+        }
+        shell
+    }
 
+    fn start_pty(&mut self) -> Result<()> {
+        let shell = self.shell();
+        let pty_size = self.pty_size(self.session().screen.size());
+        self.session_mut().start(shell, pty_size)?;
         self.font_prompt = false;
-        self.pty = Some(pty);
-        self.pty_writer = Some(writer);
-        self.pty_rx = Some(rx);
-        self.pty_closed = false;
-        self.last_status_check = Instant::now();
-        self.exit_checks_failed = 0;
-        self.input_len = 0;
-        self.input_buffer.clear();
-        self.exit_requested = false;
-        self.screen.clear();
-        self.screen.scroll_to_bottom();
+        self.show_config_notices();
         Ok(())
     }
 
+    // A fresh shell in the active tab, started the same way as the first.
+    fn restart_session(&mut self) {
+        if self.font_prompt {
+            return;
+        }
+        let shell = self.shell();
+        let pty_size = self.pty_size(self.session().screen.size());
+        let keep_scrollback = self.config.keep_scrollback_on_restart.unwrap_or(false);
+        self.scroll_animation = None;
+        self.selection_anchor = None;
+        if let Err(err) = self.session_mut().restart(shell, pty_size, keep_scrollback) {
+            warn!("pty restart failed: {err:#}");
+            self.session_mut()
+                .write_text(&format!("Failed to restart shell: {err:#}\r\n"));
+        }
+        self.update_window_title();
+        self.update_tab_bar();
+        self.window.request_redraw();
+    }
+
+    // Applies the config file again after it changed. The shell settings
+    // only take effect in tabs opened afterwards, and the window size only
+    // at the next start.
+    fn reload_config(&mut self) {
+        let Some(watch) = self.config_watch.as_mut() else {
+            return;
+        };
+        if !watch.poll() {
+            return;
+        }
+        let path = watch.path().to_path_buf();
+        let mut config = match Config::load(&path) {
+            Ok(loaded) => {
+                let notices = unknown_key_notices(&path, &loaded.unknown_keys);
+                self.config_notices.extend(notices);
+                loaded.config
+            }
+            // A half-finished edit shouldn't undo the settings in use.
+            Err(err) => {
+                warn!("config not reloaded: {err}");
+                self.config_notices
+                    .push(format!("{err}\nKeeping the previous settings."));
+                self.print_config_notices();
+                return;
+            }
+        };
+        self.cli.apply(&mut config);
+        let old = std::mem::replace(&mut self.config, config);
+        info!("config reloaded from {}", path.display());
+
+        if self.config.theme != old.theme {
+            let theme = configured_theme(&self.config.theme, &mut self.config_notices);
+            self.apply_theme(theme);
+        }
+        if (&self.config.shell, &self.config.args, &self.config.profiles)
+            != (&old.shell, &old.args, &old.profiles)
+            || self.config.default_profile != old.default_profile
+        {
+            let (profiles, default_profile) =
+                configured_profiles(&self.config, &self.cli, &mut self.config_notices);
+            self.profiles = profiles;
+            self.default_profile = default_profile;
+            info!("shell settings changed; open tabs keep their shell until restarted");
+        }
+        if (&self.config.font.family, self.config.font.weight)
+            != (&old.font.family, old.font.weight)
+        {
+            self.reload_font();
+        }
+        if self.config.font.size != old.font.size {
+            self.font_size = configured_font_size(&self.config, &mut self.config_notices);
+            self.set_font_size(self.font_size);
+        }
+        if (self.config.font.line_height, self.config.font.cell_width)
+            != (old.font.line_height, old.font.cell_width)
+        {
+            self.apply_cell_scales();
+        }
+        if self.config.padding != old.padding {
+            let (padding_x, padding_y) = configured_padding(&self.config);
+            self.renderer.set_padding(padding_x, padding_y);
+            self.resize(self.window.inner_size());
+        }
+        if self.config.font.antialias != old.font.antialias {
+            self.renderer
+                .set_text_antialias(configured_text_antialias(&self.config));
+        }
+        if self.config.minimum_contrast != old.minimum_contrast {
+            let ratio = configured_minimum_contrast(&self.config, &mut self.config_notices);
+            self.renderer.set_minimum_contrast(ratio);
+        }
+        if self.config.max_fps != old.max_fps {
+            self.renderer.set_max_fps(configured_max_fps(&self.config));
+        }
+        if self.config.text_blink != old.text_blink {
+            self.renderer
+                .set_text_blink(self.config.text_blink.unwrap_or(true));
+        }
+        if (self.config.cursor.blink, self.config.cursor.blink_interval)
+            != (old.cursor.blink, old.cursor.blink_interval)
+        {
+            self.apply_cursor_blink();
+        }
+        // Editing the opacity in the file replaces the one picked with the
+        // hotkeys.
+        if self.config.window.opacity != old.window.opacity {
+            self.chosen_opacity = None;
+            let opacity = configured_opacity(&self.config, None, &mut self.config_notices);
+            self.set_opacity(opacity);
+        }
+        if self.config.window.always_on_top != old.window.always_on_top {
+            if let Some(always_on_top) = self.config.window.always_on_top {
+                self.set_always_on_top(always_on_top);
+            }
+        }
+        for session in &mut self.sessions {
+            configure_session(&self.config, session);
+        }
+        self.update_window_title();
+        self.window.request_redraw();
+        self.print_config_notices();
+    }
+
+    fn reload_font(&mut self) {
+        let font_load = match configured_font(&self.config, &mut self.config_notices) {
+            Ok(font_load) => font_load,
+            Err(err) => {
+                warn!("font not reloaded: {err:#}");
+                return;
+            }
+        };
+        info!("font source: {:?}", font_load.source);
+        match self.renderer.set_font(FontSpec {
+            bytes: font_load.bytes,
+            size: self.renderer.font_size(),
+            collection_index: font_load.collection_index,
+            weight: configured_font_weight(&self.config, &mut self.config_notices),
+        }) {
+            Ok(true) => self.resize(self.window.inner_size()),
+            Ok(false) => {}
+            Err(err) => warn!("font change failed: {err}"),
+        }
+    }
+
+    // Changes the cell size the same way a font size change does.
+    fn apply_cell_scales(&mut self) {
+        let font = &self.config.font;
+        let notices = &mut self.config_notices;
+        let line_height = configured_cell_scale(font.line_height, "Line height", notices);
+        let cell_width = configured_cell_scale(font.cell_width, "Cell width", notices);
+        let at_bottom = !self.session().screen.is_scrolled();
+        let result = self
+            .renderer
+            .set_line_height_scale(line_height)
+            .and_then(|_| self.renderer.set_cell_width_scale(cell_width));
+        if let Err(err) = result {
+            warn!("cell size change failed: {err}");
+            return;
+        }
+        self.resize(self.window.inner_size());
+        self.scroll_animation = None;
+        if at_bottom {
+            self.session_mut().screen.scroll_to_bottom();
+        }
+    }
+
+    fn apply_cursor_blink(&mut self) {
+        let mut cursor_blink = CursorBlink::default();
+        if let Some(enabled) = self.config.cursor.blink {
+            cursor_blink.enabled = enabled;
+        }
+        match self.config.cursor.blink_interval {
+            Some(0) => self
+                .config_notices
+                .push("Cursor blink interval must be above 0 ms, using the default.".to_string()),
+            Some(ms) => cursor_blink.interval = Duration::from_millis(ms),
+            None => {}
+        }
+        self.renderer.set_cursor_blink(cursor_blink);
+    }
+
+    // Notices that come up while the shell runs are printed on a line of
+    // their own below its output.
+    fn print_config_notices(&mut self) {
+        if self.config_notices.is_empty() {
+            return;
+        }
+        self.session_mut().write_text("\x1b[0m\r\n");
+        self.show_config_notices();
+        self.session_mut().screen.scroll_to_bottom();
+    }
+
+    // Printed without clearing, so the shell's output follows below.
+    fn show_config_notices(&mut self) {
+        if self.config_notices.is_empty() {
+            return;
+        }
+        let mut text = String::new();
+        for notice in self.config_notices.drain(..) {
+            text.push_str(&notice.replace('\n', "\r\n"));
+            text.push_str("\r\n");
+        }
+        self.session_mut().write_text(&text);
+    }
+
     fn send_input_bytes(&mut self, bytes: &[u8]) {
-        self.screen.scroll_to_bottom();
-        if let Some(writer) = self.pty_writer.as_mut() {
-            if let Err(err) = writer.write_all(bytes) {
-                warn!("pty write failed: {err}");
+        if self.config.scroll_on_input.unwrap_or(true) {
+            if self.session().screen.is_scrolled() {
+                self.note_scroll();
             }
+            self.scroll_animation = None;
+            self.session_mut().screen.scroll_to_bottom();
+        }
+        let session = self.session_mut();
+        let had_selection = session.screen.selection().is_some();
+        if had_selection {
+            session.screen.clear_selection();
+        }
+        session.pending_input.extend_from_slice(bytes);
+        session.flush_input();
+        if had_selection {
+            self.window.request_redraw();
         }
     }
 
-    fn render(&mut self) {
-        self.drain_pty();
-        if self.pty_closed {
+    // The cell under a window pixel, clamped into the grid so a drag past
+    // its edge extends the selection to the nearest cell.
+    fn selection_point_at(&self, x: f64, y: f64) -> Option<SelectionPoint> {
+        let layout = self.renderer.grid_layout();
+        let screen = &self.session().screen;
+        let size = screen.size();
+        let cols = layout.cols.min(size.cols as u32);
+        let rows = layout.rows.min(size.rows as u32);
+        if cols == 0 || rows == 0 {
+            return None;
+        }
+        let left = layout.origin_x as f64;
+        let top = layout.origin_y as f64;
+        let right = left + (cols * layout.cell.width) as f64 - 1.0;
+        let bottom = top + (rows * layout.cell.height) as f64 - 1.0;
+        let cell = layout.cell_at(x.clamp(left, right), y.clamp(top, bottom))?;
+        let offset = screen.scroll_position().offset;
+        Some(SelectionPoint {
+            line: screen.view_line(offset, cell.row as usize),
+            col: cell.col,
+        })
+    }
+
+    // The cell under a window pixel, if there is one.
+    fn cell_point_at(&self, x: f64, y: f64) -> Option<SelectionPoint> {
+        let screen = &self.session().screen;
+        let cell = self.renderer.grid_layout().cell_at(x, y)?;
+        if cell.col >= screen.size().cols || cell.row >= screen.size().rows {
+            return None;
+        }
+        let offset = screen.scroll_position().offset;
+        Some(SelectionPoint {
+            line: screen.view_line(offset, cell.row as usize),
+            col: cell.col,
+        })
+    }
+
+    // Links are only live while Ctrl is held, so plain clicks keep
+    // selecting text.
+    fn update_hovered_link(&mut self) {
+        let link = match self.mouse_position {
+            Some((x, y)) if self.modifiers.control_key() && self.selection_anchor.is_none() => self
+                .cell_point_at(x, y)
+                .and_then(|point| self.session().screen.url_at(point)),
+            _ => None,
+        };
+        if link == self.hovered_link {
+            return;
+        }
+        self.window.set_cursor_icon(match link {
+            Some(_) => winit::window::CursorIcon::Pointer,
+            None => winit::window::CursorIcon::Default,
+        });
+        self.hovered_link = link;
+        self.window.request_redraw();
+    }
+
+    fn mouse_moved(&mut self, x: f64, y: f64) {
+        self.mouse_position = Some((x, y));
+        self.update_hovered_link();
+        let Some(anchor) = self.selection_anchor else {
+            return;
+        };
+        let Some(head) = self.selection_point_at(x, y) else {
+            return;
+        };
+        // Nothing is selected until the drag leaves the starting cell.
+        let screen = &mut self.session_mut().screen;
+        let current = screen.selection();
+        if (current.is_none() && head == anchor) || current == Some(Selection { anchor, head }) {
+            return;
+        }
+        screen.select(anchor, head);
+        self.window.request_redraw();
+    }
+
+    // A press starts a new selection and drops the old one, so a plain
+    // click clears it; the release leaves the selection in place for copy.
+    fn left_button(&mut self, pressed: bool) {
+        if !pressed {
+            self.selection_anchor = None;
+            return;
+        }
+        if let Some(link) = self.hovered_link.take() {
+            open_link(&link.url);
+            self.update_hovered_link();
+            return;
+        }
+        let screen = &mut self.session_mut().screen;
+        if screen.selection().is_some() {
+            screen.clear_selection();
+            self.window.request_redraw();
+        }
+        self.selection_anchor = self
+            .mouse_position
+            .and_then(|(x, y)| self.selection_point_at(x, y));
+    }
+
+    fn right_button(&mut self, pressed: bool) {
+        if self.report_mouse_button(2, pressed) || !pressed {
+            return;
+        }
+        let has_selection = self.session().screen.selection().is_some();
+        match self.config.right_click.unwrap_or(RightClick::Paste) {
+            RightClick::Paste => self.paste_clipboard(),
+            RightClick::Smart if has_selection => {
+                self.copy_selection();
+                self.session_mut().screen.clear_selection();
+                self.window.request_redraw();
+            }
+            RightClick::Smart => self.paste_clipboard(),
+            RightClick::Menu => match show_context_menu(&self.window, has_selection) {
+                Some(MenuAction::Copy) => self.copy_selection(),
+                Some(MenuAction::Paste) => self.paste_clipboard(),
+                Some(MenuAction::SelectAll) => {
+                    self.session_mut().screen.select_all();
+                    self.window.request_redraw();
+                }
+                Some(MenuAction::ClearScrollback) => {
+                    self.scroll_animation = None;
+                    self.session_mut().screen.clear_scrollback();
+                    self.window.request_redraw();
+                }
+                None => {}
+            },
+        }
+    }
+
+    // Applications tracking the mouse get the click instead, unless Shift
+    // is held, as in xterm. Returns true when the click was sent.
+    fn report_mouse_button(&mut self, button: u8, pressed: bool) -> bool {
+        let screen = &self.session().screen;
+        if screen.mouse_tracking() == MouseTracking::Off
+            || self.modifiers.shift_key()
+            || self.session().pty_closed
+        {
+            return false;
+        }
+        let Some((x, y)) = self.mouse_position else {
+            return false;
+        };
+        let Some(cell) = self.renderer.grid_layout().cell_at(x, y) else {
+            return false;
+        };
+        let sgr = screen.sgr_mouse();
+        if let Some(bytes) =
+            input::encode_mouse_button(button, pressed, cell.col, cell.row, self.modifiers, sgr)
+        {
+            let session = self.session_mut();
+            session.pending_input.extend_from_slice(&bytes);
+            session.flush_input();
+        }
+        true
+    }
+
+    fn copy_selection(&mut self) {
+        let Some(text) = self.session().screen.selection_text() else {
+            return;
+        };
+        if let Err(err) = set_clipboard_text(&self.window, &text) {
+            warn!("copy to clipboard failed: {err:#}");
+        }
+    }
+
+    // When off, Ctrl+C and Ctrl+V always go to the shell, and only
+    // Ctrl+Shift+C and Ctrl+Shift+V use the clipboard.
+    fn ctrl_c_copies(&self) -> bool {
+        self.config.ctrl_c_copies.unwrap_or(true)
+    }
+
+    fn paste_clipboard(&mut self) {
+        if self.session().pty_closed || self.font_prompt {
+            return;
+        }
+        match clipboard_text() {
+            Ok(Some(text)) => self.paste_text(&text),
+            Ok(None) => {}
+            Err(err) => warn!("read clipboard failed: {err:#}"),
+        }
+    }
+
+    fn paste_text(&mut self, text: &str) {
+        let text = sanitize_paste(text);
+        if text.is_empty() {
             return;
         }
+        let session = self.session_mut();
+        let last_line = match text.rfind('\r') {
+            Some(index) => {
+                session.input_buffer.clear();
+                &text[index + 1..]
+            }
+            None => text.as_str(),
+        };
+        session.input_buffer.push_str(last_line);
+
+        let bracketed = session.screen.bracketed_paste();
+        let mut bytes = Vec::with_capacity(text.len() + 12);
+        if bracketed {
+            bytes.extend_from_slice(BRACKETED_PASTE_START);
+        }
+        bytes.extend_from_slice(text.as_bytes());
+        if bracketed {
+            bytes.extend_from_slice(BRACKETED_PASTE_END);
+        }
+        self.send_input_bytes(&bytes);
+    }
+
+    fn handle_ime(&mut self, ime: Ime) {
+        match ime {
+            Ime::Enabled => {
+                self.ime_cursor_area = None;
+                self.update_ime_cursor_area();
+            }
+            Ime::Preedit(text, _) if text.is_empty() => self.preedit = None,
+            Ime::Preedit(text, cursor) => {
+                if self.preedit.is_none() && self.config.scroll_on_input.unwrap_or(true) {
+                    self.scroll_animation = None;
+                    self.session_mut().screen.scroll_to_bottom();
+                }
+                // The range is the part being converted, with the caret at
+                // its end.
+                let highlights = cursor
+                    .filter(|(start, end)| start != end)
+                    .map(|(start, end)| std::iter::once(start..end).collect())
+                    .unwrap_or_default();
+                self.preedit = Some(ImePreedit {
+                    text,
+                    cursor: cursor.map(|(_, end)| end),
+                    highlights,
+                });
+            }
+            Ime::Commit(text) => {
+                self.preedit = None;
+                self.handle_input_text(&text);
+            }
+            Ime::Disabled => self.preedit = None,
+        }
+        self.window.request_redraw();
+    }
 
-        self.screen.render_chars(&mut self.render_cells);
+    // Escape drops the composition here; turning the input method off and
+    // on again makes it forget the text as well.
+    fn cancel_preedit(&mut self) {
+        self.preedit = None;
+        self.window.set_ime_allowed(false);
+        self.window.set_ime_allowed(true);
+        self.window.request_redraw();
+    }
 
-        let cursor = if self.pty_closed || self.screen.is_scrolled() {
+    // Keeps the candidate window next to the terminal cursor.
+    fn update_ime_cursor_area(&mut self) {
+        let layout = self.renderer.grid_layout();
+        let cursor = self.session().screen.cursor();
+        let area = (
+            layout.origin_x + cursor.col as u32 * layout.cell.width,
+            layout.origin_y + cursor.row as u32 * layout.cell.height,
+        );
+        if self.ime_cursor_area == Some(area) {
+            return;
+        }
+        self.ime_cursor_area = Some(area);
+        self.window.set_ime_cursor_area(
+            winit::dpi::PhysicalPosition::new(area.0, area.1),
+            winit::dpi::PhysicalSize::new(layout.cell.width, layout.cell.height),
+        );
+    }
+
+    // Typed like a paste, separated by spaces, so the shell sees one
+    // argument per file.
+    fn paste_dropped_paths(&mut self) {
+        if self.dropped_paths.is_empty() {
+            return;
+        }
+        let quoting = self.config.drop_quoting.unwrap_or(DropQuoting::Plain);
+        let text = self
+            .dropped_paths
+            .drain(..)
+            .map(|path| quote_path(&path.to_string_lossy(), quoting))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if self.session().pty_closed || self.font_prompt {
+            return;
+        }
+        self.paste_text(&text);
+    }
+
+    fn zoom(&mut self, delta: f32) {
+        self.set_font_size(self.renderer.font_size() + delta);
+    }
+
+    // The grid is reflowed to the new cell size; a view that was following
+    // the output stays at the bottom.
+    fn set_font_size(&mut self, size: f32) {
+        let at_bottom = !self.session().screen.is_scrolled();
+        match self.renderer.set_font_size(size) {
+            Ok(_) => {
+                self.resize(self.window.inner_size());
+                self.scroll_animation = None;
+                if at_bottom {
+                    self.session_mut().screen.scroll_to_bottom();
+                }
+                self.window.request_redraw();
+            }
+            Err(err) => warn!("font size change failed: {err}"),
+        }
+    }
+
+    fn set_scale_factor(&mut self, scale: f64) {
+        match self.renderer.set_scale_factor(scale) {
+            Ok(_) => {
+                self.resize(self.window.inner_size());
+                self.window.request_redraw();
+            }
+            Err(err) => warn!("display scale {scale} not applied: {err}"),
+        }
+    }
+
+    // Tabs whose profile has a theme of its own keep it.
+    fn apply_theme(&mut self, theme: Theme) {
+        for session in &mut self.sessions {
+            let palette = session.theme.as_ref().unwrap_or(&theme).palette();
+            session.screen.set_base_palette(palette);
+        }
+        self.theme = theme;
+        self.update_renderer_theme();
+        self.window.request_redraw();
+    }
+
+    // The renderer draws with the active tab's theme.
+    fn update_renderer_theme(&mut self) {
+        let theme = self.session().theme.as_ref().unwrap_or(&self.theme);
+        if self.renderer.theme() != theme {
+            self.renderer.set_theme(theme.clone());
+        }
+    }
+
+    fn note_scroll(&mut self) {
+        let now = Instant::now();
+        let shown = match self.scroll_fade {
+            Some(fade) if self.scrollbar_opacity() > 0.0 => fade.shown,
+            _ => now,
+        };
+        self.scroll_fade = Some(ScrollFade {
+            shown,
+            last_activity: now,
+        });
+    }
+
+    fn animate_scroll(&mut self, previous_offset: usize) {
+        if !self.config.smooth_scroll.unwrap_or(true) {
+            return;
+        }
+        let from = self
+            .animated_scroll_offset()
+            .unwrap_or(previous_offset as f32);
+        self.scroll_animation = Some(ScrollAnimation {
+            from,
+            started: Instant::now(),
+        });
+    }
+
+    fn animated_scroll_offset(&self) -> Option<f32> {
+        let animation = self.scroll_animation?;
+        let progress =
+            animation.started.elapsed().as_secs_f32() / SMOOTH_SCROLL_DURATION.as_secs_f32();
+        if progress >= 1.0 {
+            return None;
+        }
+        let eased = 1.0 - (1.0 - progress).powi(3);
+        let target = self.session().screen.scroll_position().offset as f32;
+        Some(target + (animation.from - target) * (1.0 - eased))
+    }
+
+    fn scrollbar_opacity(&self) -> f32 {
+        let Some(fade) = self.scroll_fade else {
+            return 0.0;
+        };
+        let fade_in = fade.shown.elapsed().as_secs_f32() / SCROLLBAR_FADE_IN.as_secs_f32();
+        let idle = fade.last_activity.elapsed().saturating_sub(SCROLLBAR_HOLD);
+        let fade_out = 1.0 - idle.as_secs_f32() / SCROLLBAR_FADE_OUT.as_secs_f32();
+        fade_in.min(1.0) * fade_out.max(0.0)
+    }
+
+    fn save_screenshot(&mut self) {
+        let result = self
+            .renderer
+            .capture_frame()
+            .map_err(anyhow::Error::from)
+            .and_then(|frame| {
+                let path = screenshot_path()?.ok_or_else(|| anyhow!("LOCALAPPDATA is not set"))?;
+                write_png(&path, &frame)?;
+                Ok(path)
+            });
+        match result {
+            Ok(path) => info!("saved screenshot to {}", path.display()),
+            Err(err) => warn!("screenshot failed: {err:#}"),
+        }
+    }
+
+    fn set_opacity(&mut self, opacity: f32) {
+        if let Err(err) = self.renderer.set_opacity(opacity) {
+            warn!("background opacity {opacity} not applied, rendering opaque: {err}");
+        }
+        self.window.request_redraw();
+    }
+
+    // In 5% steps, from 30% up to opaque.
+    fn adjust_opacity(&mut self, steps: i32) {
+        if !self.renderer.supports_transparency() {
+            self.show_notice("transparency unavailable");
+            return;
+        }
+        let current = (self.renderer.opacity() / OPACITY_STEP).round() as i32;
+        let opacity = ((current + steps) as f32 * OPACITY_STEP).clamp(MIN_OPACITY, 1.0);
+        self.chosen_opacity = Some(opacity);
+        self.set_opacity(opacity);
+        self.show_notice(format!("opacity {}%", (opacity * 100.0).round()));
+    }
+
+    fn show_notice(&mut self, text: impl Into<String>) {
+        self.notice = Some(NoticeBadge {
+            text: text.into(),
+            shown: Instant::now(),
+        });
+        self.window.request_redraw();
+    }
+
+    fn notice_opacity(&self) -> f32 {
+        let Some(notice) = self.notice.as_ref() else {
+            return 0.0;
+        };
+        let idle = notice.shown.elapsed().saturating_sub(NOTICE_HOLD);
+        (1.0 - idle.as_secs_f32() / NOTICE_FADE_OUT.as_secs_f32()).max(0.0)
+    }
+
+    // Cycles off -> scanlines -> CRT -> off.
+    fn cycle_post_effect(&mut self) {
+        let next = match self.renderer.post_effect() {
+            None => Some(PostEffect::Scanlines),
+            Some(PostEffect::Scanlines) => Some(PostEffect::Crt),
+            Some(_) => None,
+        };
+        if let Err(err) = self.renderer.set_post_effect(next) {
+            warn!("post effect not applied: {err}");
+        }
+        self.window.request_redraw();
+    }
+
+    fn render(&mut self) {
+        self.session_mut().drain_pty();
+
+        let animated_offset = self.animated_scroll_offset();
+        let scrollbar_opacity = self.scrollbar_opacity();
+        let notice_opacity = self.notice_opacity();
+        if notice_opacity == 0.0 {
+            self.notice = None;
+        }
+        let session = &self.sessions[self.active];
+        let screen = &session.screen;
+        let screen_rows = screen.size().rows;
+        let (top_offset, rows, scroll_pixel_offset) = match animated_offset {
+            Some(offset) => {
+                let top = offset.ceil();
+                let cell_height = self.renderer.cell_metrics().height as f32;
+                (top as usize, screen_rows + 1, (top - offset) * cell_height)
+            }
+            None => (screen.scroll_position().offset, screen_rows, 0.0),
+        };
+
+        let cursor = if session.pty_closed || screen.is_scrolled() || animated_offset.is_some() {
             None
         } else {
-            let cursor = self.screen.cursor();
+            let cursor = screen.cursor();
             Some(CursorPosition {
                 col: cursor.col,
                 row: cursor.row,
             })
         };
 
+        let cursor_state = screen.cursor_state();
+        let top_line = screen.view_line(top_offset, 0);
+        let selection = screen
+            .selection()
+            .and_then(|selection| grid_selection(selection, top_line, rows, screen.size().cols));
+        let link = self.hovered_link.as_ref().and_then(|link| {
+            let selection = Selection {
+                anchor: link.start,
+                head: link.end,
+            };
+            grid_selection(selection, top_line, rows, screen.size().cols)
+        });
         let grid = RenderGrid {
-            cols: self.screen.size().cols,
-            rows: self.screen.size().rows,
-            cells: &self.render_cells,
+            cols: screen.size().cols,
+            rows,
+            cells: &ScreenRows {
+                screen,
+                scroll_offset: top_offset,
+            },
+            palette: screen.palette(),
             cursor,
-            cursor_visible: self.cursor_visible,
+            cursor_visible: cursor_state.visible,
+            cursor_shape: cursor_state.style,
+            cursor_blinking: cursor_state.blink,
+            scroll_indicator: Some(ScrollIndicator {
+                position: screen.scroll_position(),
+                opacity: scrollbar_opacity,
+                new_output: session.new_output_below,
+            }),
+            scroll_pixel_offset,
+            blink_on: self.text_blink_on,
+            focused: self.focused,
+            preedit: self.preedit.as_ref().map(|preedit| Preedit {
+                text: &preedit.text,
+                cursor: preedit.cursor,
+                highlights: &preedit.highlights,
+            }),
+            selection,
+            link,
+            notice: self.notice.as_ref().map(|notice| Notice {
+                text: &notice.text,
+                opacity: notice_opacity,
+            }),
         };
 
-        match self.renderer.render(&grid) {
-            Ok(()) => {}
+        let result = self.renderer.render(&grid);
+        if animated_offset.is_none() {
+            self.scroll_animation = None;
+        }
+        match result {
+            Ok(_) => {}
             Err(RenderError::Surface(wgpu::SurfaceError::Lost)) => {
                 if let Err(err) = self.renderer.resize(self.renderer_size()) {
                     warn!("surface lost; resize failed: {err}");
@@ -506,62 +1558,581 @@ Press Y to retry or N to continue with the fallback font.\r\n"
         }
     }
 
-    fn update_cursor_blink(&mut self) {
-        if self.pty_closed {
-            self.cursor_visible = false;
+    // Title changes are applied at most every TITLE_UPDATE_INTERVAL; the
+    // latest one wins.
+    fn update_title(&mut self) {
+        for session in &mut self.sessions {
+            self.title_pending |= session.screen.take_title_changed();
+        }
+        if !self.title_pending || self.title_updated_at.elapsed() < TITLE_UPDATE_INTERVAL {
             return;
         }
-        if self.last_cursor_toggle.elapsed() >= Duration::from_millis(600) {
-            self.cursor_visible = !self.cursor_visible;
-            self.last_cursor_toggle = Instant::now();
+        self.title_pending = false;
+        self.title_updated_at = Instant::now();
+        self.update_window_title();
+        self.update_tab_bar();
+    }
+
+    // Any session can ring, but only the visible one flashes. Bells closer
+    // together than BELL_MIN_INTERVAL are dropped.
+    fn ring_bells(&mut self) {
+        let mut rung = false;
+        let mut active_rung = false;
+        for (index, session) in self.sessions.iter_mut().enumerate() {
+            if session.screen.take_bell() {
+                rung = true;
+                active_rung |= index == self.active;
+            }
+        }
+        if !rung
+            || self
+                .last_bell
+                .is_some_and(|at| at.elapsed() < BELL_MIN_INTERVAL)
+        {
+            return;
+        }
+        self.last_bell = Some(Instant::now());
+        let bell = &self.config.bell;
+        if bell.audible.unwrap_or(false) {
+            beep();
+        }
+        if bell.visual.unwrap_or(false) && active_rung {
+            self.renderer.flash_bell();
+            self.window.request_redraw();
+        }
+        if bell.taskbar.unwrap_or(true) && !self.focused {
+            match self.tray.as_mut() {
+                // A hidden window has no taskbar button to flash.
+                Some(tray) => tray.set_attention(true),
+                None => self
+                    .window
+                    .request_user_attention(Some(winit::window::UserAttentionType::Critical)),
+            }
+        }
+    }
+
+    // Only the restored window is remembered, so a window closed maximized
+    // or fullscreen still comes back to its old size when restored.
+    fn track_window_layout(&mut self) {
+        let window = &self.window;
+        if window.is_maximized()
+            || window.fullscreen().is_some()
+            || window.is_minimized() == Some(true)
+        {
+            return;
+        }
+        let Ok(position) = window.outer_position() else {
+            return;
+        };
+        let size = window.inner_size();
+        self.window_layout = Some(WindowState {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            maximized: false,
+            monitor: window.current_monitor().and_then(|monitor| monitor.name()),
+            always_on_top: self.always_on_top,
+            opacity: self.chosen_opacity,
+        });
+    }
+
+    fn save_window_layout(&mut self) {
+        if !self.config.window.remember_layout.unwrap_or(true) {
+            return;
+        }
+        self.track_window_layout();
+        let (Some(mut layout), Some(path)) =
+            (self.window_layout.clone(), WindowState::default_path())
+        else {
+            return;
+        };
+        layout.maximized = self.window.is_maximized();
+        layout.always_on_top = self.always_on_top;
+        layout.opacity = self.chosen_opacity;
+        match layout.save(&path) {
+            Ok(()) => info!("window layout saved to {}", path.display()),
+            Err(err) => warn!("window layout not saved: {err}"),
+        }
+    }
+
+    fn set_always_on_top(&mut self, always_on_top: bool) {
+        self.always_on_top = always_on_top;
+        self.window.set_window_level(match always_on_top {
+            true => winit::window::WindowLevel::AlwaysOnTop,
+            false => winit::window::WindowLevel::Normal,
+        });
+        self.update_window_title();
+    }
+
+    fn always_on_top_key(&self) -> KeyBinding {
+        self.config.keys.always_on_top.unwrap_or(ALWAYS_ON_TOP_KEY)
+    }
+
+    // Hidden rather than left minimized, so the window leaves the taskbar
+    // until the tray icon brings it back.
+    fn hide_if_minimized(&mut self) {
+        if !self.config.window.minimize_to_tray.unwrap_or(false)
+            || self.tray.is_some()
+            || self.window.is_minimized() != Some(true)
+        {
+            return;
+        }
+        match Tray::new(self.event_proxy.clone(), &self.window_title) {
+            Ok(tray) => {
+                self.tray = Some(tray);
+                self.window.set_visible(false);
+            }
+            Err(err) => warn!("tray icon not shown: {err:#}"),
+        }
+    }
+
+    fn restore_from_tray(&mut self) {
+        self.tray = None;
+        self.window.set_visible(true);
+        self.window.set_minimized(false);
+        self.window.focus_window();
+        self.window.request_redraw();
+    }
+
+    fn handle_tray_events(&mut self) {
+        let events = self
+            .tray
+            .as_mut()
+            .map(Tray::take_events)
+            .unwrap_or_default();
+        for event in events {
+            match event {
+                TrayEvent::Show => self.restore_from_tray(),
+                TrayEvent::NewWindow => open_new_window(self.cli.config.as_deref()),
+                TrayEvent::Quit => self.exit_requested = true,
+            }
+        }
+    }
+
+    // Dropping the tray removes its icon, which would otherwise linger in
+    // the notification area until the mouse passes over it.
+    fn prepare_exit(&mut self) {
+        self.save_window_layout();
+        self.shutdown_sessions();
+        self.tray = None;
+    }
+
+    fn title_deadline(&self) -> Option<Instant> {
+        self.title_pending
+            .then(|| self.title_updated_at + TITLE_UPDATE_INTERVAL)
+    }
+
+    fn update_window_title(&mut self) {
+        let mut title = match self.session().title() {
+            "" => "RING0".to_string(),
+            title => expand_title_template(
+                self.config
+                    .window
+                    .title
+                    .as_deref()
+                    .unwrap_or(DEFAULT_TITLE_TEMPLATE),
+                title,
+            ),
+        };
+        if self.always_on_top {
+            title.push_str(PINNED_TITLE_MARK);
+        }
+        if title != self.window_title {
+            self.window.set_title(&title);
+            self.window_title = title;
+        }
+    }
+
+    fn update_text_blink(&mut self) {
+        if !self.session().screen.has_blinking_cells() {
+            self.text_blink_on = true;
+            return;
+        }
+        if self.last_text_blink_toggle.elapsed() >= TEXT_BLINK_INTERVAL {
+            self.text_blink_on = !self.text_blink_on;
+            self.last_text_blink_toggle = Instant::now();
         }
     }
 }
 
-fn spawn_pty_reader(reader: PtyReader) -> Receiver<PtyMessage> {
-    let (tx, rx) = mpsc::channel();
-    spawn_reader_thread(tx, reader);
-    rx
+// `{title}` is replaced by the shell's title; any other text, including
+// placeholders RING0 doesn't know, is kept as written. An empty template
+// shows the title alone rather than a blank title bar.
+fn expand_title_template(template: &str, title: &str) -> String {
+    if template.is_empty() {
+        return title.to_string();
+    }
+    template.replace("{title}", title)
 }
 
-fn spawn_reader_thread(tx: Sender<PtyMessage>, mut reader: PtyReader) {
-    thread::spawn(move || {
-        let mut buffer = [0u8; 4096];
-        loop {
-            match reader.read(&mut buffer) {
-                Ok(0) => {
-                    let _ = tx.send(PtyMessage::Closed);
-                    break;
-                }
-                Ok(n) => {
-                    if tx.send(PtyMessage::Data(buffer[..n].to_vec())).is_err() {
-                        break;
-                    }
-                }
-                Err(err) => {
-                    warn!("pty read failed: {err}");
-                    let _ = tx.send(PtyMessage::Closed);
-                    break;
-                }
-            }
+// Clips a selection on absolute lines to the frame whose first row shows
+// `top_line`.
+fn grid_selection(
+    selection: Selection,
+    top_line: usize,
+    rows: u16,
+    cols: u16,
+) -> Option<GridSelection> {
+    let (start, end) = selection.ordered();
+    let bottom_line = top_line + rows.max(1) as usize - 1;
+    if end.line < top_line || start.line > bottom_line {
+        return None;
+    }
+    let start = match start.line.checked_sub(top_line) {
+        Some(row) => CursorPosition {
+            col: start.col,
+            row: row as u16,
+        },
+        None => CursorPosition { col: 0, row: 0 },
+    };
+    let end = if end.line > bottom_line {
+        CursorPosition {
+            col: cols.saturating_sub(1),
+            row: rows.saturating_sub(1),
+        }
+    } else {
+        CursorPosition {
+            col: end.col,
+            row: (end.line - top_line) as u16,
+        }
+    };
+    Some(GridSelection { start, end })
+}
+
+fn screen_size_from_layout(layout: GridLayout) -> ScreenSize {
+    ScreenSize {
+        cols: layout.cols.max(1) as u16,
+        rows: layout.rows.max(1) as u16,
+    }
+}
+
+// The opacity last picked with the hotkeys wins over the config file.
+fn configured_opacity(config: &Config, chosen: Option<f32>, notices: &mut Vec<String>) -> f32 {
+    if let Some(opacity) = chosen.filter(|opacity| (MIN_OPACITY..=1.0).contains(opacity)) {
+        return opacity;
+    }
+    match config.window.opacity {
+        Some(opacity) if (0.0..=1.0).contains(&opacity) => opacity,
+        Some(opacity) => {
+            notices.push(format!(
+                "Opacity {opacity} is outside 0-1, using {DEFAULT_OPACITY}."
+            ));
+            DEFAULT_OPACITY
+        }
+        None => DEFAULT_OPACITY,
+    }
+}
+
+fn configured_minimum_contrast(config: &Config, notices: &mut Vec<String>) -> Option<f32> {
+    match config.minimum_contrast {
+        Some(ratio) if (1.0..=MAX_CONTRAST_RATIO).contains(&ratio) => Some(ratio),
+        Some(ratio) => {
+            notices.push(format!(
+                "Minimum contrast {ratio} is outside 1-{MAX_CONTRAST_RATIO}, leaving colours as they are."
+            ));
+            None
+        }
+        None => None,
+    }
+}
+
+// 0 removes the cap.
+fn configured_max_fps(config: &Config) -> Option<u32> {
+    match config.max_fps {
+        Some(0) => None,
+        Some(fps) => Some(fps),
+        None => Some(DEFAULT_MAX_FPS),
+    }
+}
+
+fn configured_font_weight(config: &Config, notices: &mut Vec<String>) -> Option<u16> {
+    match config.font.weight {
+        Some(weight) if (1..=1000).contains(&weight) => Some(weight),
+        Some(weight) => {
+            notices.push(format!(
+                "Font weight {weight} is outside 1-1000, using the font's own."
+            ));
+            None
+        }
+        None => None,
+    }
+}
+
+fn configured_cell_scale(scale: Option<f32>, name: &str, notices: &mut Vec<String>) -> f32 {
+    match scale {
+        Some(scale) if (MIN_CELL_SCALE..=MAX_CELL_SCALE).contains(&scale) => scale,
+        Some(scale) => {
+            notices.push(format!(
+                "{name} {scale} is outside {MIN_CELL_SCALE}-{MAX_CELL_SCALE}, using 1."
+            ));
+            1.0
+        }
+        None => 1.0,
+    }
+}
+
+fn configured_text_antialias(config: &Config) -> TextAntialias {
+    match config.font.antialias {
+        Some(config::Antialias::Grayscale) | None => TextAntialias::Grayscale,
+        Some(config::Antialias::Subpixel) => TextAntialias::Subpixel,
+        Some(config::Antialias::None) => TextAntialias::None,
+    }
+}
+
+fn default_window_size(cell: CellMetrics, config: &Config) -> winit::dpi::PhysicalSize<u32> {
+    let cols = config.window.cols.unwrap_or(DEFAULT_COLS).max(1);
+    let rows = config.window.rows.unwrap_or(DEFAULT_ROWS).max(1);
+    let (padding_x, padding_y) = configured_padding(config);
+    winit::dpi::PhysicalSize::new(
+        cell.width * cols + padding_x * 2,
+        cell.height * rows + padding_y * 2,
+    )
+}
+
+fn configured_padding(config: &Config) -> (u32, u32) {
+    config
+        .padding
+        .map_or((PADDING_X, PADDING_Y), |padding| (padding, padding))
+}
+
+// A built-in theme picked by name, with any colours the config sets on top.
+fn configured_theme(config: &config::ThemeConfig, notices: &mut Vec<String>) -> Theme {
+    let mut theme = match config
+        .name
+        .as_deref()
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        None | Some("dark") => Theme::dark(),
+        Some("light") => Theme::light(),
+        Some("gruvbox") => Theme::gruvbox(),
+        Some(name) => {
+            notices.push(format!(
+                "Unknown theme {name:?}, expected dark, light or gruvbox."
+            ));
+            Theme::default()
+        }
+    };
+    let rgb = |color: config::Color| Rgb {
+        r: color.r,
+        g: color.g,
+        b: color.b,
+    };
+    if let Some(color) = config.foreground {
+        theme.foreground = rgb(color);
+    }
+    if let Some(color) = config.background {
+        theme.background = rgb(color);
+    }
+    match config.cursor {
+        Some(config::CursorColor::Auto) => theme.cursor = None,
+        Some(config::CursorColor::Color(color)) => theme.cursor = Some(rgb(color)),
+        None => {}
+    }
+    if let Some(color) = config.selection {
+        theme.selection = rgb(color);
+    }
+    if let Some(ansi) = config.ansi {
+        theme.ansi = ansi.map(rgb);
+    }
+    match config.cursor_thickness {
+        Some(thickness) if thickness > 0.0 && thickness.is_finite() => {
+            theme.cursor_thickness = thickness;
+        }
+        Some(thickness) => notices.push(format!(
+            "Cursor thickness {thickness} must be above 0, using {}.",
+            theme.cursor_thickness
+        )),
+        None => {}
+    }
+    theme
+}
+
+// Letters match in either case, since Shift makes them upper case.
+fn binding_matches(binding: KeyBinding, modifiers: ModifiersState, event: &KeyEvent) -> bool {
+    let held = (
+        modifiers.control_key(),
+        modifiers.shift_key(),
+        modifiers.alt_key(),
+    );
+    if held != (binding.ctrl, binding.shift, binding.alt) {
+        return false;
+    }
+    let Key::Character(text) = &event.logical_key else {
+        return false;
+    };
+    text.chars()
+        .next()
+        .is_some_and(|ch| ch.to_ascii_lowercase() == binding.key)
+}
+
+// Ctrl+Shift+1 to 9, on the top row or the number pad.
+fn profile_key_index(key: PhysicalKey) -> Option<usize> {
+    let PhysicalKey::Code(code) = key else {
+        return None;
+    };
+    let index = match code {
+        KeyCode::Digit1 | KeyCode::Numpad1 => 0,
+        KeyCode::Digit2 | KeyCode::Numpad2 => 1,
+        KeyCode::Digit3 | KeyCode::Numpad3 => 2,
+        KeyCode::Digit4 | KeyCode::Numpad4 => 3,
+        KeyCode::Digit5 | KeyCode::Numpad5 => 4,
+        KeyCode::Digit6 | KeyCode::Numpad6 => 5,
+        KeyCode::Digit7 | KeyCode::Numpad7 => 6,
+        KeyCode::Digit8 | KeyCode::Numpad8 => 7,
+        KeyCode::Digit9 | KeyCode::Numpad9 => 8,
+        _ => return None,
+    };
+    Some(index)
+}
+
+// Problems are returned as notices rather than failing startup, and the
+// defaults are used for anything that could not be read. Only the default
+// path is created when missing; a path given with --config must exist.
+fn load_config(path: Option<&Path>) -> (Config, Vec<String>) {
+    let loaded = match path {
+        Some(path) => Config::load(path).map(|loaded| (path.to_path_buf(), loaded)),
+        None => {
+            let Some(path) = Config::default_path() else {
+                warn!("LOCALAPPDATA is not set; using the default config");
+                return (Config::default(), Vec::new());
+            };
+            Config::load_or_create(&path).map(|loaded| (path, loaded))
+        }
+    };
+    match loaded {
+        Ok((path, loaded)) => {
+            let notices = unknown_key_notices(&path, &loaded.unknown_keys);
+            (loaded.config, notices)
+        }
+        Err(err) => {
+            warn!("config not loaded: {err}");
+            (
+                Config::default(),
+                vec![format!("{err}\nUsing the default settings.")],
+            )
+        }
+    }
+}
+
+fn unknown_key_notices(path: &Path, keys: &[String]) -> Vec<String> {
+    keys.iter()
+        .map(|key| {
+            warn!("ignoring unknown config key {key}");
+            format!("Ignoring unknown key `{key}` in {}.", path.display())
+        })
+        .collect()
+}
+
+// The settings a session takes from the config, applied when it is created
+// and again when the config is reloaded.
+fn configure_session(config: &Config, session: &mut Session) {
+    let screen = &mut session.screen;
+    screen.set_scrollback_limit(config.scrollback_lines.unwrap_or(DEFAULT_SCROLLBACK_LINES));
+    screen.set_default_cursor_style(match config.cursor.style {
+        Some(config::CursorStyle::Block) => CursorShape::Block,
+        Some(config::CursorStyle::Bar) | None => CursorShape::Bar,
+        Some(config::CursorStyle::Underline) => CursorShape::Underline,
+    });
+    session.scroll_on_output = config.scroll_on_output.unwrap_or(false);
+    session.close_on_exit = config.close_on_exit.unwrap_or(CloseOnExit::Auto);
+}
+
+// The profiles from the config, with the command line's working directory
+// in place of their own.
+fn configured_profiles(
+    config: &Config,
+    cli: &CliArgs,
+    notices: &mut Vec<String>,
+) -> (Vec<Profile>, Profile) {
+    let mut profiles = profile::profiles(config, notices);
+    let mut default_profile = profile::default_profile(config, &profiles, notices);
+    if let Some(dir) = &cli.working_directory {
+        for profile in profiles.iter_mut().chain([&mut default_profile]) {
+            profile.cwd = Some(dir.clone());
+        }
+    }
+    (profiles, default_profile)
+}
+
+fn configured_font(config: &Config, notices: &mut Vec<String>) -> Result<FontLoad> {
+    let Some(family) = config.font.family.as_deref() else {
+        return load_font_bytes().context("load font data");
+    };
+    match configured_font_bytes(family) {
+        Some(bytes) => Ok(FontLoad {
+            bytes,
+            collection_index: 0,
+            source: FontSource::Configured,
+        }),
+        None => {
+            notices.push(format!(
+                "Font {family:?} not found, using the default font."
+            ));
+            load_font_bytes().context("load font data")
         }
-    });
+    }
 }
 
-fn screen_size_from_pixels(size: winit::dpi::PhysicalSize<u32>) -> ScreenSize {
-    let usable_width = size.width.saturating_sub(PADDING_X * 2);
-    let usable_height = size.height.saturating_sub(PADDING_Y * 2);
-    let cols = (usable_width / CELL_WIDTH).max(1) as u16;
-    let rows = (usable_height / CELL_HEIGHT).max(1) as u16;
-    ScreenSize { cols, rows }
+fn configured_font_size(config: &Config, notices: &mut Vec<String>) -> f32 {
+    match config.font.size {
+        Some(size) if (MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&size) => size,
+        Some(size) => {
+            notices.push(format!(
+                "Font size {size} is outside {MIN_FONT_SIZE}-{MAX_FONT_SIZE}, using {DEFAULT_FONT_SIZE}."
+            ));
+            DEFAULT_FONT_SIZE
+        }
+        None => DEFAULT_FONT_SIZE,
+    }
 }
 
-fn control_code_for_char(ch: char) -> Option<u8> {
-    let ch = ch.to_ascii_uppercase();
-    if ('A'..='Z').contains(&ch) {
-        Some((ch as u8) - b'A' + 1)
-    } else {
-        None
+// Line breaks become the CR that Enter sends, and other control characters
+// except tab are dropped so pasted text can't carry escape sequences or end
+// a bracketed paste early.
+fn sanitize_paste(text: &str) -> String {
+    text.replace("\r\n", "\r")
+        .chars()
+        .filter_map(|ch| match ch {
+            '\n' => Some('\r'),
+            '\r' | '\t' => Some(ch),
+            ch if ch.is_control() => None,
+            ch => Some(ch),
+        })
+        .collect()
+}
+
+// Characters that make cmd split or reinterpret an unquoted path.
+const CMD_SPECIAL_CHARS: &[char] = &[
+    '&', '(', ')', '[', ']', '{', '}', '^', '=', ';', '!', '\'', '+', ',', '`', '~',
+];
+// PowerShell also expands variables and treats a few more as syntax.
+const POWERSHELL_SPECIAL_CHARS: &[char] = &['$', '@', '#', '<', '>', '|'];
+
+fn quote_path(path: &str, quoting: DropQuoting) -> String {
+    let needs_quotes = path.chars().any(|ch| {
+        ch.is_whitespace()
+            || CMD_SPECIAL_CHARS.contains(&ch)
+            || (quoting == DropQuoting::PowerShell && POWERSHELL_SPECIAL_CHARS.contains(&ch))
+    });
+    if !needs_quotes {
+        return path.to_string();
+    }
+    match quoting {
+        DropQuoting::Plain => format!("\"{path}\""),
+        DropQuoting::PowerShell => {
+            let mut quoted = String::with_capacity(path.len() + 2);
+            quoted.push('"');
+            for ch in path.chars() {
+                if matches!(ch, '`' | '$' | '"') {
+                    quoted.push('`');
+                }
+                quoted.push(ch);
+            }
+            quoted.push('"');
+            quoted
+        }
     }
 }
 
@@ -610,11 +2181,31 @@ fn extract_cascadia_from_zip(zip_bytes: Vec<u8>) -> Result<Vec<u8>, String> {
     Ok(out)
 }
 
+// A path to a font file, or a family name matched against file names in the
+// system and per-user font folders: "Cascadia Code" finds CascadiaCode.ttf.
+fn configured_font_bytes(family: &str) -> Option<Vec<u8>> {
+    let path = Path::new(family);
+    if path.is_file() {
+        return fs::read(path).ok();
+    }
+    let stem: String = family.chars().filter(|ch| !ch.is_whitespace()).collect();
+    let mut dirs = vec![PathBuf::from(r"C:\Windows\Fonts")];
+    if let Some(base) = env::var_os("LOCALAPPDATA") {
+        dirs.push(PathBuf::from(base).join(r"Microsoft\Windows\Fonts"));
+    }
+    dirs.iter().find_map(|dir| {
+        ["ttf", "otf", "ttc"]
+            .iter()
+            .find_map(|ext| fs::read(dir.join(format!("{stem}.{ext}"))).ok())
+    })
+}
+
 fn load_font_bytes() -> Result<FontLoad> {
     if let Some(path) = font_cache_path()? {
         if let Ok(bytes) = fs::read(&path) {
             return Ok(FontLoad {
                 bytes,
+                collection_index: 0,
                 source: FontSource::Cascadia,
             });
         }
@@ -623,11 +2214,13 @@ fn load_font_bytes() -> Result<FontLoad> {
     let cascadia = [
         r"C:\Windows\Fonts\CascadiaCode.ttf",
         r"C:\Windows\Fonts\CascadiaCodePL.ttf",
+        r"C:\Windows\Fonts\CascadiaCode.ttc",
     ];
     for path in cascadia {
         if let Ok(bytes) = fs::read(path) {
             return Ok(FontLoad {
                 bytes,
+                collection_index: 0,
                 source: FontSource::Cascadia,
             });
         }
@@ -641,6 +2234,7 @@ fn load_font_bytes() -> Result<FontLoad> {
         if let Ok(bytes) = fs::read(path) {
             return Ok(FontLoad {
                 bytes,
+                collection_index: 0,
                 source: FontSource::Fallback,
             });
         }
@@ -653,13 +2247,51 @@ fn load_font_bytes() -> Result<FontLoad> {
 
 struct FontLoad {
     bytes: Vec<u8>,
+    collection_index: u32,
     source: FontSource,
 }
 
+struct ImePreedit {
+    text: String,
+    cursor: Option<usize>,
+    highlights: Vec<Range<usize>>,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum FontSource {
     Cascadia,
     Fallback,
+    Configured,
+}
+
+// The saved layout, as long as the monitor it was on is still connected and
+// the top edge of the window, where it is dragged from, is still on it.
+fn restorable_window_state(event_loop: &EventLoop<()>) -> Option<WindowState> {
+    let path = WindowState::default_path()?;
+    let layout = match WindowState::load(&path) {
+        Ok(layout) => layout?,
+        Err(err) => {
+            warn!("window layout not restored: {err}");
+            return None;
+        }
+    };
+    if layout.width == 0 || layout.height == 0 {
+        return None;
+    }
+    let x = layout.x.saturating_add((layout.width / 2) as i32);
+    let y = layout.y;
+    let on_screen = event_loop.available_monitors().any(|monitor| {
+        let position = monitor.position();
+        let size = monitor.size();
+        (layout.monitor.is_none() || monitor.name() == layout.monitor)
+            && (position.x..position.x.saturating_add(size.width as i32)).contains(&x)
+            && (position.y..position.y.saturating_add(size.height as i32)).contains(&y)
+    });
+    if !on_screen {
+        info!("saved window position is off screen; using the default");
+        return None;
+    }
+    Some(layout)
 }
 
 fn font_cache_path() -> Result<Option<PathBuf>> {
@@ -673,23 +2305,91 @@ fn font_cache_path() -> Result<Option<PathBuf>> {
     ))
 }
 
+fn screenshot_path() -> Result<Option<PathBuf>> {
+    let base = match env::var("LOCALAPPDATA").ok() {
+        Some(base) => PathBuf::from(base),
+        None => return Ok(None),
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system clock before unix epoch")?
+        .as_millis();
+    Ok(Some(
+        base.join("RING0")
+            .join("screenshots")
+            .join(format!("ring0-{timestamp}.png")),
+    ))
+}
+
+fn write_png(path: &Path, frame: &FrameCapture) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create screenshot directory")?;
+    }
+    let file = fs::File::create(path).context("create screenshot file")?;
+    let mut encoder = png::Encoder::new(io::BufWriter::new(file), frame.width, frame.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().context("write png header")?;
+    writer
+        .write_image_data(&frame.rgba)
+        .context("write png data")?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     tracing_subscriber::fmt().with_target(false).init();
+    let cli = match CliArgs::parse(env::args_os().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            show_usage(&format!("ring0: {err}\n\n{}", cli::USAGE), true);
+            return Err(err);
+        }
+    };
+    if cli.help {
+        show_usage(cli::USAGE, false);
+        return Ok(());
+    }
+    let (mut config, config_notices) = load_config(cli.config.as_deref());
+    cli.apply(&mut config);
+    let config_path = cli.config.clone().or_else(Config::default_path);
+
+    // Checked before any window exists, since without a pty there is
+    // nothing to show in it.
+    if !Pty::is_supported() {
+        let message = "RING0 requires Windows 10 1809 or later.";
+        error!("{message}");
+        #[cfg(windows)]
+        show_error_box(message);
+        return Err(anyhow!(message));
+    }
 
     #[cfg(windows)]
     set_app_user_model_id();
 
     let event_loop = EventLoop::new().context("create event loop")?;
-    let default_width = CELL_WIDTH * 120 + PADDING_X * 2;
-    let default_height = CELL_HEIGHT * 30 + PADDING_Y * 2;
     let mut window_builder = WindowBuilder::new()
         .with_title("RING0")
-        .with_inner_size(winit::dpi::PhysicalSize::new(default_width, default_height));
+        .with_transparent(true)
+        .with_inner_size(default_window_size(CellMetrics::default(), &config));
+    // A size given on the command line wins over the remembered one.
+    let saved_layout = match config.window.remember_layout.unwrap_or(true)
+        && cli.cols.is_none()
+        && cli.rows.is_none()
+    {
+        true => restorable_window_state(&event_loop),
+        false => None,
+    };
+    if let Some(layout) = saved_layout.as_ref() {
+        window_builder = window_builder
+            .with_position(winit::dpi::PhysicalPosition::new(layout.x, layout.y))
+            .with_inner_size(winit::dpi::PhysicalSize::new(layout.width, layout.height))
+            .with_maximized(layout.maximized);
+    }
     let window_icon = build_terminal_icon(32);
     #[cfg(windows)]
     let taskbar_icon = load_taskbar_icon();
     #[cfg(not(windows))]
-    let taskbar_icon: Option<TaskbarIcon> = None;
+    let _taskbar_icon: Option<TaskbarIcon> = None;
     if let Some(icon) = window_icon.as_ref() {
         window_builder = window_builder.with_window_icon(Some(icon.clone()));
     }
@@ -697,19 +2397,26 @@ fn main() -> Result<()> {
     if let Some(taskbar) = taskbar_icon.as_ref() {
         window_builder = window_builder.with_taskbar_icon(Some(taskbar.icon.clone()));
     }
-    let window = window_builder
-        .build(&event_loop)
-        .context("create window")?;
+    let window = window_builder.build(&event_loop).context("create window")?;
     if let Some(icon) = window_icon {
         window.set_window_icon(Some(icon));
     }
+    window.set_ime_allowed(true);
     #[cfg(windows)]
     if let Some(taskbar) = taskbar_icon {
         window.set_taskbar_icon(Some(taskbar.icon.clone()));
         apply_taskbar_icon_from_file(&window, &taskbar.path);
     }
 
-    let mut state = pollster::block_on(AppState::new(window))?;
+    let mut state = pollster::block_on(AppState::new(
+        window,
+        cli,
+        config_path,
+        config,
+        config_notices,
+        saved_layout,
+        event_loop.create_proxy(),
+    ))?;
 
     event_loop.run(move |event, target| {
         target.set_control_flow(ControlFlow::Wait);
@@ -717,43 +2424,214 @@ fn main() -> Result<()> {
             Event::WindowEvent { event, window_id } if window_id == state.window.id() => {
                 match event {
                     WindowEvent::CloseRequested => {
+                        state.prepare_exit();
                         target.exit();
                     }
                     WindowEvent::Resized(size) => {
                         state.resize(size);
+                        state.track_window_layout();
+                        state.hide_if_minimized();
+                    }
+                    WindowEvent::Moved(_) => {
+                        state.track_window_layout();
+                    }
+                    WindowEvent::Ime(ime) => {
+                        state.handle_ime(ime);
+                    }
+                    WindowEvent::DroppedFile(path) => {
+                        state.dropped_paths.push(path);
                     }
                     WindowEvent::KeyboardInput { event, .. } => {
+                        // Once the shell has exited, keys only close the tab;
+                        // shortcuts such as scrolling keep working.
+                        if state.session().exit_notice_shown
+                            && event.state == ElementState::Pressed
+                            && matches!(
+                                event.logical_key,
+                                Key::Named(NamedKey::Enter | NamedKey::Escape)
+                            )
+                        {
+                            state.session_mut().exit_requested = true;
+                            return;
+                        }
+                        // Keys belong to the input method while it composes.
+                        if state.preedit.is_some() {
+                            if event.state == ElementState::Pressed
+                                && event.logical_key == Key::Named(NamedKey::Escape)
+                            {
+                                state.cancel_preedit();
+                            }
+                            return;
+                        }
                         if event.state == ElementState::Pressed {
+                            if binding_matches(state.always_on_top_key(), state.modifiers, &event) {
+                                state.set_always_on_top(!state.always_on_top);
+                                return;
+                            }
+                            if state.modifiers.shift_key()
+                                && event.logical_key == Key::Named(NamedKey::Insert)
+                            {
+                                state.paste_clipboard();
+                                return;
+                            }
                             if state.modifiers.control_key() {
-                                if let Key::Character(ch) = &event.logical_key {
+                                if event.logical_key == Key::Named(NamedKey::Tab) {
+                                    state.cycle_tab(!state.modifiers.shift_key());
+                                    return;
+                                }
+                                if state.modifiers.shift_key() {
+                                    let opacity_step = match &event.logical_key {
+                                        Key::Named(NamedKey::PageUp) => Some(1),
+                                        Key::Named(NamedKey::PageDown) => Some(-1),
+                                        _ => None,
+                                    };
+                                    if let Some(step) = opacity_step {
+                                        state.adjust_opacity(step);
+                                        return;
+                                    }
+                                    let screen = &mut state.session_mut().screen;
+                                    let previous = screen.scroll_position().offset;
+                                    let moved = match &event.logical_key {
+                                        Key::Named(NamedKey::ArrowUp) => {
+                                            Some(screen.scroll_to_previous_prompt())
+                                        }
+                                        Key::Named(NamedKey::ArrowDown) => {
+                                            Some(screen.scroll_to_next_prompt())
+                                        }
+                                        _ => None,
+                                    };
+                                    if moved == Some(true) {
+                                        state.animate_scroll(previous);
+                                        state.note_scroll();
+                                        state.window.request_redraw();
+                                    }
+                                    if moved.is_some() {
+                                        return;
+                                    }
+                                }
+                                // Ctrl+Break has no input byte, so it is sent as
+                                // a real console control event.
+                                if event.physical_key == PhysicalKey::Code(KeyCode::Pause) {
+                                    state.session_mut().send_ctrl_event(CtrlEvent::Break);
+                                    return;
+                                }
+                                // Matched by key position, since Shift changes
+                                // the character a digit key types.
+                                if state.modifiers.shift_key() && !state.modifiers.alt_key() {
+                                    if let Some(index) = profile_key_index(event.physical_key) {
+                                        state.open_profile(index);
+                                        return;
+                                    }
+                                }
+                                // Ctrl+Alt is also AltGr, which types characters
+                                // rather than triggering shortcuts.
+                                if let (Key::Character(ch), false) =
+                                    (&event.logical_key, state.modifiers.alt_key())
+                                {
                                     let mut chars = ch.chars();
                                     if let Some(ch) = chars.next() {
                                         match ch.to_ascii_lowercase() {
-                                            'c' | 'v' => {
+                                            'c' if state.modifiers.shift_key() => {
+                                                state.copy_selection();
                                                 return;
                                             }
-                                            _ => {}
-                                        }
-                                        if let Some(code) = control_code_for_char(ch) {
-                                            if code == 0x03 {
-                                                state.input_len = 0;
+                                            // Without a selection, Ctrl+C goes
+                                            // to the shell as an interrupt.
+                                            'c' if state.ctrl_c_copies()
+                                                && state.session().screen.selection().is_some() =>
+                                            {
+                                                state.copy_selection();
+                                                state.session_mut().screen.clear_selection();
+                                                state.window.request_redraw();
+                                                return;
+                                            }
+                                            'n' if state.modifiers.shift_key() => {
+                                                state.duplicate_session();
+                                                return;
+                                            }
+                                            'r' if state.modifiers.shift_key() => {
+                                                state.restart_session();
+                                                return;
+                                            }
+                                            't' if state.modifiers.shift_key() => {
+                                                state.new_tab();
+                                                return;
+                                            }
+                                            'w' if state.modifiers.shift_key() => {
+                                                state.close_session(state.active);
+                                                return;
+                                            }
+                                            'v' if state.modifiers.shift_key()
+                                                || state.ctrl_c_copies() =>
+                                            {
+                                                state.paste_clipboard();
+                                                return;
+                                            }
+                                            's' if state.modifiers.shift_key() => {
+                                                state.save_screenshot();
+                                                return;
+                                            }
+                                            'e' if state.modifiers.shift_key() => {
+                                                state.cycle_post_effect();
+                                                return;
+                                            }
+                                            '=' | '+' => {
+                                                state.zoom(1.0);
+                                                return;
+                                            }
+                                            '-' => {
+                                                state.zoom(-1.0);
+                                                return;
+                                            }
+                                            '0' => {
+                                                state.set_font_size(state.font_size);
+                                                return;
                                             }
-                                            state.send_input_bytes(&[code]);
+                                            _ => {}
                                         }
                                     }
                                 }
-                                return;
                             }
-                            if let Key::Named(key) = event.logical_key {
-                                state.handle_special_key(key);
+                            if state.handle_key(&event.logical_key) {
+                                return;
                             }
                         }
                         if let Some(text) = event.text.as_ref() {
                             state.handle_input_text(text);
                         }
                     }
+                    WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                        state.set_scale_factor(scale_factor);
+                    }
+                    WindowEvent::Focused(focused) => {
+                        state.focused = focused;
+                        state.renderer.reset_cursor_blink();
+                        state.window.request_redraw();
+                    }
                     WindowEvent::ModifiersChanged(modifiers) => {
                         state.modifiers = modifiers.state();
+                        state.update_hovered_link();
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        state.mouse_moved(position.x, position.y);
+                    }
+                    WindowEvent::CursorLeft { .. } => {
+                        state.mouse_position = None;
+                        state.update_hovered_link();
+                    }
+                    WindowEvent::MouseInput {
+                        state: button_state,
+                        button: MouseButton::Left,
+                        ..
+                    } => {
+                        state.left_button(button_state == ElementState::Pressed);
+                    }
+                    WindowEvent::MouseInput {
+                        state: button_state,
+                        button: MouseButton::Right,
+                        ..
+                    } => {
+                        state.right_button(button_state == ElementState::Pressed);
                     }
                     WindowEvent::MouseWheel { delta, .. } => {
                         let lines = match delta {
@@ -768,7 +2646,19 @@ fn main() -> Result<()> {
                                 }
                             }
                         };
-                        if lines != 0 && state.screen.scroll_view(lines) {
+                        let previous = state.session().screen.scroll_position().offset;
+                        // Ctrl+Shift changes the opacity, Ctrl alone the
+                        // font size, and the wheel by itself scrolls.
+                        if lines != 0
+                            && state.modifiers.control_key()
+                            && state.modifiers.shift_key()
+                        {
+                            state.adjust_opacity(lines.signum());
+                        } else if lines != 0 && state.modifiers.control_key() {
+                            state.zoom(lines.signum() as f32);
+                        } else if lines != 0 && state.session_mut().screen.scroll_view(lines) {
+                            state.animate_scroll(previous);
+                            state.note_scroll();
                             state.window.request_redraw();
                         }
                     }
@@ -779,18 +2669,56 @@ fn main() -> Result<()> {
                 }
             }
             Event::AboutToWait => {
-                state.check_pty_status();
+                for session in &mut state.sessions {
+                    session.flush_input();
+                }
+                state.paste_dropped_paths();
+                state.drain_sessions();
+                state.close_requested_sessions();
                 state.drain_font_download();
-                state.update_cursor_blink();
+                state.update_text_blink();
+                state.update_title();
+                state.ring_bells();
+                state.update_ime_cursor_area();
+                state.reload_config();
+                state.handle_tray_events();
                 if state.exit_requested {
+                    state.prepare_exit();
                     target.exit();
                     return;
                 }
-                if state.pty_closed {
-                    target.exit();
-                    return;
+                match state.renderer.next_frame_at() {
+                    Some(next) if next > Instant::now() => {
+                        let wake = state
+                            .renderer
+                            .next_blink_deadline()
+                            .map_or(next, |blink| blink.min(next));
+                        target.set_control_flow(ControlFlow::WaitUntil(wake));
+                    }
+                    _ => state.window.request_redraw(),
+                }
+                if state
+                    .sessions
+                    .iter()
+                    .any(|session| !session.pending_input.is_empty())
+                {
+                    target.set_control_flow(ControlFlow::Poll);
+                }
+                let config_check = state.config_watch.as_ref().map(ConfigWatch::next_check);
+                let tray_poll = state
+                    .tray
+                    .as_ref()
+                    .map(|_| Instant::now() + TRAY_POLL_INTERVAL);
+                for deadline in [state.title_deadline(), config_check, tray_poll]
+                    .into_iter()
+                    .flatten()
+                {
+                    match target.control_flow() {
+                        ControlFlow::Poll => {}
+                        ControlFlow::WaitUntil(wake) if wake <= deadline => {}
+                        _ => target.set_control_flow(ControlFlow::WaitUntil(deadline)),
+                    }
                 }
-                state.window.request_redraw();
             }
             _ => {}
         }
@@ -799,6 +2727,20 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+// Another RING0 process, reading the same config file as this one.
+fn open_new_window(config: Option<&Path>) {
+    let spawned = env::current_exe().and_then(|exe| {
+        let mut command = std::process::Command::new(exe);
+        if let Some(path) = config {
+            command.arg("--config").arg(path);
+        }
+        command.spawn()
+    });
+    if let Err(err) = spawned {
+        warn!("new window not opened: {err}");
+    }
+}
+
 #[cfg(windows)]
 fn set_app_user_model_id() {
     use std::ffi::OsStr;
@@ -812,6 +2754,349 @@ fn set_app_user_model_id() {
     }
 }
 
+// The binary has no console of its own, so the text goes to the console it
+// was started from, or into a message box when there is none.
+#[cfg(windows)]
+fn show_usage(text: &str, error: bool) {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{MB_ICONERROR, MB_ICONINFORMATION};
+
+    if unsafe { AttachConsole(ATTACH_PARENT_PROCESS) } != 0 {
+        // The parent shell has already printed its prompt by now.
+        if error {
+            eprint!("\n{text}");
+        } else {
+            print!("\n{text}");
+        }
+        return;
+    }
+    let icon = if error {
+        MB_ICONERROR
+    } else {
+        MB_ICONINFORMATION
+    };
+    show_message_box(text, icon);
+}
+
+#[cfg(not(windows))]
+fn show_usage(text: &str, error: bool) {
+    if error {
+        eprint!("{text}");
+    } else {
+        print!("{text}");
+    }
+}
+
+#[cfg(windows)]
+fn show_error_box(message: &str) {
+    use windows_sys::Win32::UI::WindowsAndMessaging::MB_ICONERROR;
+
+    show_message_box(message, MB_ICONERROR);
+}
+
+#[cfg(windows)]
+fn show_message_box(
+    message: &str,
+    icon: windows_sys::Win32::UI::WindowsAndMessaging::MESSAGEBOX_STYLE,
+) {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{MessageBoxW, MB_OK};
+
+    let wide = |text: &str| -> Vec<u16> {
+        OsStr::new(text)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let text = wide(message);
+    let caption = wide("RING0");
+    unsafe {
+        MessageBoxW(0, text.as_ptr(), caption.as_ptr(), MB_OK | icon);
+    }
+}
+
+#[cfg(windows)]
+fn beep() {
+    use windows_sys::Win32::System::Diagnostics::Debug::MessageBeep;
+    use windows_sys::Win32::UI::WindowsAndMessaging::MB_OK;
+
+    unsafe {
+        MessageBeep(MB_OK);
+    }
+}
+
+#[cfg(not(windows))]
+fn beep() {}
+
+// Only the Windows menu offers more than Paste.
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MenuAction {
+    Copy,
+    Paste,
+    SelectAll,
+    ClearScrollback,
+}
+
+// Opens at the mouse pointer and returns once an item is picked or the menu
+// is dismissed.
+#[cfg(windows)]
+fn show_context_menu(window: &winit::window::Window, can_copy: bool) -> Option<MenuAction> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::POINT;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        AppendMenuW, CreatePopupMenu, DestroyMenu, GetCursorPos, TrackPopupMenu, MF_GRAYED,
+        MF_SEPARATOR, MF_STRING, TPM_RETURNCMD, TPM_RIGHTBUTTON,
+    };
+
+    const ITEMS: [(MenuAction, &str); 4] = [
+        (MenuAction::Copy, "Copy"),
+        (MenuAction::Paste, "Paste"),
+        (MenuAction::SelectAll, "Select All"),
+        (MenuAction::ClearScrollback, "Clear Scrollback"),
+    ];
+
+    let handle = window.window_handle().ok()?;
+    let RawWindowHandle::Win32(handle) = handle.as_raw() else {
+        return None;
+    };
+    let hwnd = handle.hwnd.get();
+    let wide = |text: &str| -> Vec<u16> {
+        OsStr::new(text)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    unsafe {
+        let menu = CreatePopupMenu();
+        if menu == 0 {
+            return None;
+        }
+        // Item ids start at 1, since 0 means the menu was dismissed.
+        for (index, (action, label)) in ITEMS.iter().enumerate() {
+            if *action == MenuAction::SelectAll {
+                AppendMenuW(menu, MF_SEPARATOR, 0, std::ptr::null());
+            }
+            let flags = match *action == MenuAction::Copy && !can_copy {
+                true => MF_STRING | MF_GRAYED,
+                false => MF_STRING,
+            };
+            let label = wide(label);
+            AppendMenuW(menu, flags, index + 1, label.as_ptr());
+        }
+        let mut point = POINT { x: 0, y: 0 };
+        GetCursorPos(&mut point);
+        let picked = TrackPopupMenu(
+            menu,
+            TPM_RETURNCMD | TPM_RIGHTBUTTON,
+            point.x,
+            point.y,
+            0,
+            hwnd,
+            std::ptr::null(),
+        );
+        DestroyMenu(menu);
+        let index = usize::try_from(picked).ok()?.checked_sub(1)?;
+        ITEMS.get(index).map(|(action, _)| *action)
+    }
+}
+
+// Without a native menu the right button pastes.
+#[cfg(not(windows))]
+fn show_context_menu(_window: &winit::window::Window, _can_copy: bool) -> Option<MenuAction> {
+    Some(MenuAction::Paste)
+}
+
+// Anything but a web page could run or reveal something local, so it is
+// confirmed first.
+fn open_link(url: &str) {
+    if !is_web_link(url) && !confirm_open_link(url) {
+        return;
+    }
+    info!("opening {url}");
+    if let Err(err) = open_url(url) {
+        warn!("opening {url} failed: {err:#}");
+    }
+}
+
+// Schemes are case-insensitive, so HTTPS://example.com is a web link too.
+fn is_web_link(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once("://") else {
+        return false;
+    };
+    scheme.eq_ignore_ascii_case("https") || scheme.eq_ignore_ascii_case("http")
+}
+
+#[cfg(windows)]
+fn confirm_open_link(url: &str) -> bool {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        MessageBoxW, IDYES, MB_ICONWARNING, MB_YESNO,
+    };
+
+    let wide = |text: &str| -> Vec<u16> {
+        OsStr::new(text)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let text = wide(&format!("Open this link?\n\n{url}"));
+    let caption = wide("RING0");
+    unsafe {
+        MessageBoxW(
+            0,
+            text.as_ptr(),
+            caption.as_ptr(),
+            MB_YESNO | MB_ICONWARNING,
+        ) == IDYES
+    }
+}
+
+#[cfg(not(windows))]
+fn confirm_open_link(url: &str) -> bool {
+    warn!("not opening {url}: only web links are opened on this platform");
+    false
+}
+
+#[cfg(windows)]
+fn open_url(url: &str) -> Result<()> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::UI::Shell::ShellExecuteW;
+    use windows_sys::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    let wide = |text: &str| -> Vec<u16> {
+        OsStr::new(text)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let operation = wide("open");
+    let file = wide(url);
+    let result = unsafe {
+        ShellExecuteW(
+            0,
+            operation.as_ptr(),
+            file.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+    // Values of 32 and below are error codes.
+    if result <= 32 {
+        return Err(anyhow!("ShellExecuteW failed with code {result}"));
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn open_url(url: &str) -> Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    let mut child = std::process::Command::new(opener)
+        .arg(url)
+        .spawn()
+        .with_context(|| format!("run {opener}"))?;
+    // The opener hands the URL off and exits; waiting for it keeps it from
+    // lingering as a zombie.
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(windows)]
+fn clipboard_text() -> Result<Option<String>> {
+    use windows_sys::Win32::System::DataExchange::{
+        CloseClipboard, GetClipboardData, OpenClipboard,
+    };
+    use windows_sys::Win32::System::Memory::{GlobalLock, GlobalUnlock};
+    use windows_sys::Win32::System::Ole::CF_UNICODETEXT;
+
+    unsafe {
+        if OpenClipboard(0) == 0 {
+            return Err(io::Error::last_os_error()).context("open clipboard");
+        }
+        let handle = GetClipboardData(CF_UNICODETEXT as u32);
+        let mut text = None;
+        if handle != 0 {
+            let data = GlobalLock(handle) as *const u16;
+            if !data.is_null() {
+                let len = (0..).take_while(|&index| *data.add(index) != 0).count();
+                let wide = std::slice::from_raw_parts(data, len);
+                text = Some(String::from_utf16_lossy(wide));
+                let _ = GlobalUnlock(handle);
+            }
+        }
+        let _ = CloseClipboard();
+        Ok(text)
+    }
+}
+
+#[cfg(not(windows))]
+fn clipboard_text() -> Result<Option<String>> {
+    Err(anyhow!("the clipboard is only supported on Windows"))
+}
+
+#[cfg(windows)]
+fn set_clipboard_text(window: &winit::window::Window, text: &str) -> Result<()> {
+    use windows_sys::Win32::System::DataExchange::{
+        CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData,
+    };
+    use windows_sys::Win32::System::Memory::{
+        GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE,
+    };
+    use windows_sys::Win32::System::Ole::CF_UNICODETEXT;
+
+    // Opened without an owner window, EmptyClipboard leaves the clipboard
+    // unowned and SetClipboardData fails.
+    let handle = window.window_handle().context("get window handle")?;
+    let RawWindowHandle::Win32(handle) = handle.as_raw() else {
+        return Err(anyhow!("not a Win32 window"));
+    };
+    let wide: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, std::mem::size_of_val(wide.as_slice()));
+        if memory == 0 {
+            return Err(io::Error::last_os_error()).context("allocate clipboard text");
+        }
+        let data = GlobalLock(memory) as *mut u16;
+        if data.is_null() {
+            let err = io::Error::last_os_error();
+            GlobalFree(memory);
+            return Err(err).context("lock clipboard text");
+        }
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), data, wide.len());
+        let _ = GlobalUnlock(memory);
+
+        if OpenClipboard(handle.hwnd.get()) == 0 {
+            let err = io::Error::last_os_error();
+            GlobalFree(memory);
+            return Err(err).context("open clipboard");
+        }
+        let _ = EmptyClipboard();
+        // On success the clipboard owns the memory.
+        let stored = SetClipboardData(CF_UNICODETEXT as u32, memory) != 0;
+        let err = io::Error::last_os_error();
+        let _ = CloseClipboard();
+        if !stored {
+            GlobalFree(memory);
+            return Err(err).context("set clipboard text");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn set_clipboard_text(_window: &winit::window::Window, _text: &str) -> Result<()> {
+    Err(anyhow!("the clipboard is only supported on Windows"))
+}
+
 fn build_terminal_icon(size: u32) -> Option<winit::window::Icon> {
     let rgba = make_terminal_icon_rgba(size, size);
     winit::window::Icon::from_rgba(rgba, size, size).ok()
@@ -890,8 +3175,7 @@ fn write_taskbar_icon(path: &PathBuf) -> Result<()> {
     for size in sizes {
         let rgba = make_terminal_icon_rgba(size, size);
         let image = IconImage::from_rgba_data(size, size, rgba);
-        let entry = IconDirEntry::encode_as_bmp(&image)
-            .context("encode taskbar icon bmp")?;
+        let entry = IconDirEntry::encode_as_bmp(&image).context("encode taskbar icon bmp")?;
         icon_dir.add_entry(entry);
     }
     let mut file = fs::File::create(path).context("create taskbar icon file")?;
@@ -912,7 +3196,15 @@ fn make_terminal_icon_rgba(width: u32, height: u32) -> Vec<u8> {
     let accent_size = (height / 10).max(4);
     let accent_y = (bar_height / 2).saturating_sub(accent_size / 2);
     let accent_gap = accent_size + (accent_size / 2).max(2);
-    fill_rect(&mut buffer, width, accent_size, accent_y, accent_size, accent_size, accent);
+    fill_rect(
+        &mut buffer,
+        width,
+        accent_size,
+        accent_y,
+        accent_size,
+        accent_size,
+        accent,
+    );
     fill_rect(
         &mut buffer,
         width,
@@ -1007,3 +3299,41 @@ const GLYPH_GT: [&str; 7] = [
 const GLYPH_UNDERSCORE: [&str; 7] = [
     "00000", "00000", "00000", "00000", "00000", "11111", "00000",
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn web_links_are_matched_in_any_case() {
+        assert!(is_web_link("https://example.com"));
+        assert!(is_web_link("HTTPS://EXAMPLE.COM/path"));
+        assert!(is_web_link("Http://example.com"));
+        assert!(!is_web_link("file:///etc/passwd"));
+        assert!(!is_web_link("FILE://host/share"));
+        assert!(!is_web_link("mailto:someone@example.com"));
+        assert!(!is_web_link("httpsx://example.com"));
+        assert!(!is_web_link("example.com"));
+    }
+
+    #[test]
+    fn title_templates_are_expanded() {
+        let cases = [
+            ("{title} — RING0", "vim", "vim — RING0"),
+            ("{title}", "~/src", "~/src"),
+            ("[{title}] {title}", "top", "[top] top"),
+            ("RING0", "vim", "RING0"),
+            ("{cwd} {title} {TITLE}", "vim", "{cwd} vim {TITLE}"),
+            ("{title", "vim", "{title"),
+            ("{title} — RING0", "{title}", "{title} — RING0"),
+            ("", "vim", "vim"),
+        ];
+        for (template, title, expected) in cases {
+            assert_eq!(
+                expand_title_template(template, title),
+                expected,
+                "{template:?} with {title:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Named ways to start a shell. The built-in profiles come first, followed by
+// any the config adds; Ctrl+Shift+1 to 9 open them in that order.
+
+use config::{Config, ProfileConfig, ThemeConfig};
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    // Replaces the configured theme in tabs running this profile.
+    pub theme: Option<ThemeConfig>,
+}
+
+impl Profile {
+    fn new(name: &str, command: &str, args: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            cwd: None,
+            env: Vec::new(),
+            theme: None,
+        }
+    }
+
+    // wsl.exe takes Linux paths, so it is started in a directory with
+    // `--cd` rather than through the process's working directory.
+    pub fn is_wsl(&self) -> bool {
+        Path::new(&self.command)
+            .file_stem()
+            .is_some_and(|stem| stem.eq_ignore_ascii_case("wsl"))
+    }
+
+    // A copy that starts in `dir`, a path as the shell reported it. None
+    // when this profile can't start there, so it starts where it would
+    // have anyway.
+    pub fn starting_in(&self, dir: &str) -> Option<Self> {
+        let mut profile = self.clone();
+        if self.is_wsl() {
+            if let Some(index) = profile.args.iter().position(|arg| arg == "--cd") {
+                profile
+                    .args
+                    .drain(index..(index + 2).min(profile.args.len()));
+            }
+            profile
+                .args
+                .splice(0..0, ["--cd".to_string(), linux_path(dir)]);
+            return Some(profile);
+        }
+        let dir = native_path(dir);
+        if !dir.is_dir() {
+            return None;
+        }
+        profile.cwd = Some(dir);
+        Some(profile)
+    }
+
+    // Settings the config leaves out keep their current value.
+    fn apply(&mut self, config: &ProfileConfig) {
+        if let Some(command) = &config.command {
+            self.command.clone_from(command);
+        }
+        if let Some(args) = &config.args {
+            self.args.clone_from(args);
+        }
+        if config.cwd.is_some() {
+            self.cwd.clone_from(&config.cwd);
+        }
+        for (key, value) in &config.env {
+            self.env.retain(|(existing, _)| existing != key);
+            self.env.push((key.clone(), value.clone()));
+        }
+        if config.theme.is_some() {
+            self.theme.clone_from(&config.theme);
+        }
+    }
+}
+
+// Names are matched without regard to case.
+pub fn find<'a>(profiles: &'a [Profile], name: &str) -> Option<&'a Profile> {
+    profiles
+        .iter()
+        .find(|profile| profile.name.eq_ignore_ascii_case(name))
+}
+
+// Never empty, since there is always a built-in profile.
+pub fn profiles(config: &Config, notices: &mut Vec<String>) -> Vec<Profile> {
+    let mut profiles = builtin_profiles();
+    for entry in &config.profiles {
+        let name = entry.name.trim();
+        if name.is_empty() {
+            notices.push("A profile has no name and was skipped.".to_string());
+            continue;
+        }
+        if let Some(profile) = profiles
+            .iter_mut()
+            .find(|profile| profile.name.eq_ignore_ascii_case(name))
+        {
+            profile.apply(entry);
+            continue;
+        }
+        let Some(command) = &entry.command else {
+            notices.push(format!("Profile {name:?} has no command and was skipped."));
+            continue;
+        };
+        let mut profile = Profile::new(name, command, &[]);
+        profile.apply(entry);
+        profiles.push(profile);
+    }
+    profiles
+}
+
+// The profile for the first tab and Ctrl+Shift+T: `default_profile` when it
+// names one, then `shell` and `args`, then the first profile.
+pub fn default_profile(
+    config: &Config,
+    profiles: &[Profile],
+    notices: &mut Vec<String>,
+) -> Profile {
+    if let Some(name) = &config.default_profile {
+        match find(profiles, name) {
+            Some(profile) => return profile.clone(),
+            None => notices.push(format!(
+                "Unknown profile {name:?}, using {:?}.",
+                profiles[0].name
+            )),
+        }
+    }
+    if config.shell.is_none() && config.args.is_empty() {
+        return profiles[0].clone();
+    }
+    // `args` alone replace the arguments of the default shell.
+    let mut profile = profiles[0].clone();
+    if let Some(shell) = &config.shell {
+        profile.name.clone_from(shell);
+        profile.command.clone_from(shell);
+        profile.args.clear();
+    }
+    if !config.args.is_empty() {
+        profile.args.clone_from(&config.args);
+    }
+    profile
+}
+
+// `\\wsl$\Distro\home` and `\\wsl.localhost\Distro\home` become `/home`
+// inside the distribution. Other paths are left for `wsl.exe --cd`, which
+// translates Windows paths itself.
+fn linux_path(dir: &str) -> String {
+    let lower = dir.replace('/', "\\").to_ascii_lowercase();
+    let prefix = ["\\\\wsl$\\", "\\\\wsl.localhost\\"]
+        .into_iter()
+        .find(|prefix| lower.starts_with(prefix));
+    let Some(prefix) = prefix else {
+        return dir.to_string();
+    };
+    let inside = dir[prefix.len()..]
+        .split(['\\', '/'])
+        .skip(1)
+        .filter(|part| !part.is_empty());
+    let mut path = String::new();
+    for part in inside {
+        path.push('/');
+        path.push_str(part);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+// Shells report `/C:/Users/me` for `C:\Users\me`.
+#[cfg(windows)]
+fn native_path(dir: &str) -> PathBuf {
+    let dir = match dir.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &dir[1..],
+        _ => dir,
+    };
+    PathBuf::from(dir.replace('/', "\\"))
+}
+
+#[cfg(not(windows))]
+fn native_path(dir: &str) -> PathBuf {
+    PathBuf::from(dir)
+}
+
+// PowerShell starts without PSReadLine, whose rendering the VT parser does
+// not handle yet.
+#[cfg(windows)]
+fn builtin_profiles() -> Vec<Profile> {
+    use std::path::Path;
+
+    let mut profiles = vec![
+        Profile::new(
+            "PowerShell",
+            "powershell.exe",
+            &[
+                "-NoLogo",
+                "-NoProfile",
+                "-NoExit",
+                "-Command",
+                "Remove-Module PSReadLine -ErrorAction SilentlyContinue",
+            ],
+        ),
+        Profile::new("Command Prompt", "cmd.exe", &[]),
+    ];
+    // wsl.exe is only present once the WSL feature is installed.
+    let system_root = env::var_os("SystemRoot").unwrap_or_else(|| "C:\\Windows".into());
+    if Path::new(&system_root)
+        .join("System32")
+        .join("wsl.exe")
+        .is_file()
+    {
+        profiles.push(Profile::new("WSL", "wsl.exe", &[]));
+    }
+    profiles
+}
+
+#[cfg(not(windows))]
+fn builtin_profiles() -> Vec<Profile> {
+    let shell = env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.trim().is_empty())
+        .unwrap_or_else(|| "/bin/sh".to_string());
+    vec![Profile::new("Shell", &shell, &[])]
+}
@@ -0,0 +1,350 @@
+// One shell and the terminal state fed by it. Every tab owns a session;
+// background sessions keep reading their pty so no output is lost.
+
+use crate::profile::Profile;
+use anyhow::{Context, Result};
+use config::CloseOnExit;
+use pty::{CtrlEvent, Pty, PtyBuilder, PtyChunk, PtySize, PtyWriter, ReaderHandle, ReaderOptions};
+use render::Theme;
+use screen::{Screen, ScreenSize};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+use tracing::{info, warn};
+use vt::VtParser;
+
+const PTY_SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+const INPUT_CHUNK_SIZE: usize = 4096;
+const INPUT_WRITE_BUDGET: Duration = Duration::from_millis(4);
+const RESTART_SEPARATOR: &str = "\x1b[2m\u{2500}\u{2500} restarted \u{2500}\u{2500}\x1b[0m";
+
+pub struct Session {
+    pub screen: Screen,
+    pty: Option<Pty>,
+    pty_writer: Option<PtyWriter>,
+    pty_rx: Option<Receiver<PtyChunk>>,
+    pty_reader: Option<ReaderHandle>,
+    pty_exit_rx: Option<Receiver<i32>>,
+    vt_parser: VtParser,
+    // What the shell is started from, again on restart.
+    pub profile: Profile,
+    // Replaces the configured theme while this tab is active.
+    pub theme: Option<Theme>,
+    // Name shown until the shell sets a title of its own.
+    program: String,
+    pub close_on_exit: CloseOnExit,
+    // Jump back to the bottom when output arrives while scrolled back.
+    pub scroll_on_output: bool,
+    // Output arrived while scrolled back and the view stayed put.
+    pub new_output_below: bool,
+    pub pty_closed: bool,
+    pub exit_notice_shown: bool,
+    // Set when the user typed `exit`, so the tab closes without waiting
+    // for the exit notice.
+    pub exit_requested: bool,
+    pub input_buffer: String,
+    // Input not yet written to the pty, fed a little per event-loop turn.
+    pub pending_input: Vec<u8>,
+}
+
+impl Session {
+    pub fn new(size: ScreenSize, profile: Profile) -> Result<Self> {
+        Ok(Self {
+            screen: Screen::new(size).context("initialize screen")?,
+            pty: None,
+            pty_writer: None,
+            pty_rx: None,
+            pty_reader: None,
+            pty_exit_rx: None,
+            vt_parser: VtParser::new(),
+            profile,
+            theme: None,
+            program: String::new(),
+            close_on_exit: CloseOnExit::Auto,
+            scroll_on_output: false,
+            new_output_below: false,
+            pty_closed: false,
+            exit_notice_shown: false,
+            exit_requested: false,
+            input_buffer: String::new(),
+            pending_input: Vec::new(),
+        })
+    }
+
+    pub fn start(&mut self, shell: PtyBuilder, size: PtySize) -> Result<()> {
+        self.spawn(shell, size)?;
+        self.screen.clear();
+        self.screen.scroll_to_bottom();
+        Ok(())
+    }
+
+    // Replaces the shell with a fresh one. The old shell's channels go with
+    // it, so nothing it still has in flight, such as a late Closed, can
+    // reach the new one. A separator marks where the new shell starts,
+    // below the old output when the scrollback is kept.
+    pub fn restart(
+        &mut self,
+        shell: PtyBuilder,
+        size: PtySize,
+        keep_scrollback: bool,
+    ) -> Result<()> {
+        self.drain_pty();
+        self.teardown();
+        self.vt_parser = VtParser::new();
+        if keep_scrollback {
+            // Resets what the old shell may have turned on, then scrolls the
+            // separator up to the top row.
+            self.write_text(&format!(
+                "\x1b[0m\x1b[!p\x1b[?1000;1002;1003;1006;2004l\r\n{RESTART_SEPARATOR}"
+            ));
+            let row = self.screen.cursor().row;
+            let rows = self.screen.size().rows;
+            self.write_text(&format!(
+                "\x1b[{rows};1H{}\x1b[2;1H\x1b[J",
+                "\n".repeat(row as usize)
+            ));
+        } else {
+            self.write_text(&format!("\x1bc{RESTART_SEPARATOR}\r\n"));
+        }
+        self.screen.scroll_to_bottom();
+        self.spawn(shell, size)
+    }
+
+    fn spawn(&mut self, shell: PtyBuilder, size: PtySize) -> Result<()> {
+        let program = shell.program().to_string();
+        let pty = shell.spawn(size).context("spawn pty")?;
+        let writer = pty.writer().context("clone pty writer")?;
+        let (rx, reader) = pty
+            .spawn_reader(ReaderOptions::default())
+            .context("start pty reader")?;
+        let exit_rx = pty.exit_notifier().context("watch pty exit")?;
+
+        self.program = program;
+        self.pty = Some(pty);
+        self.pty_writer = Some(writer);
+        self.pty_rx = Some(rx);
+        self.pty_reader = Some(reader);
+        self.pty_closed = false;
+        self.pty_exit_rx = Some(exit_rx);
+        self.exit_notice_shown = false;
+        self.input_buffer.clear();
+        self.pending_input.clear();
+        self.exit_requested = false;
+        self.new_output_below = false;
+        Ok(())
+    }
+
+    // Kills the shell without waiting for it to exit on its own.
+    fn teardown(&mut self) {
+        if let Some(mut pty) = self.pty.take() {
+            if let Err(err) = pty.terminate() {
+                warn!("pty terminate failed: {err}");
+            }
+        }
+        if let Some(reader) = self.pty_reader.take() {
+            reader.shutdown();
+        }
+        self.pty_writer = None;
+        self.pty_rx = None;
+        self.pty_exit_rx = None;
+        self.pty_closed = true;
+    }
+
+    // Where the shell last said it was, or else where its process is.
+    // PowerShell changes location without changing its process directory,
+    // and wsl.exe's own says nothing about the shell running inside it.
+    pub fn current_dir(&self) -> Option<String> {
+        if let Some(dir) = self.screen.working_directory() {
+            return Some(dir.to_string());
+        }
+        if self.profile.is_wsl() {
+            return None;
+        }
+        match self.pty.as_ref()?.current_dir() {
+            Ok(dir) => Some(dir.to_string_lossy().into_owned()),
+            Err(err) => {
+                warn!("shell directory unavailable: {err}");
+                None
+            }
+        }
+    }
+
+    // The shell's own title, or the program it runs when it hasn't set one.
+    pub fn title(&self) -> &str {
+        match self.screen.title() {
+            "" => self.program.rsplit(['\\', '/']).next().unwrap_or_default(),
+            title => title,
+        }
+    }
+
+    pub fn resize(&mut self, size: ScreenSize, pty_size: PtySize) {
+        if size != self.screen.size() {
+            if let Err(err) = self.screen.resize(size) {
+                warn!("screen resize failed: {err}");
+            }
+        }
+        // Checked separately since a font change can alter the pixel size
+        // without changing the grid.
+        if let Some(pty) = self.pty.as_mut() {
+            if pty.size() != pty_size {
+                if let Err(err) = pty.resize(pty_size) {
+                    warn!("pty resize failed: {err}");
+                }
+            }
+        }
+    }
+
+    // Feeds text through the parser as if the shell had written it.
+    pub fn write_text(&mut self, text: &str) {
+        let mut events = Vec::new();
+        self.vt_parser.advance(text.as_bytes(), &mut events);
+        self.screen.apply_events(&events);
+    }
+
+    pub fn drain_pty(&mut self) {
+        if !self.screen.is_scrolled() {
+            self.new_output_below = false;
+        }
+        let mut events = Vec::new();
+        let mut received = false;
+        if let Some(rx) = self.pty_rx.as_ref() {
+            while let Ok(message) = rx.try_recv() {
+                match message {
+                    PtyChunk::Data(bytes) => {
+                        received = true;
+                        self.vt_parser.advance(&bytes, &mut events);
+                        if !events.is_empty() {
+                            self.screen.apply_events(&events);
+                            events.clear();
+                        }
+                    }
+                    PtyChunk::Closed => {
+                        self.pty_closed = true;
+                        info!("pty closed; stopping input");
+                    }
+                    PtyChunk::Restarted => {
+                        self.vt_parser = VtParser::new();
+                        self.screen.clear();
+                        self.pty_closed = false;
+                        info!("pty restarted");
+                    }
+                    PtyChunk::Failed(err) => {
+                        self.pty_closed = true;
+                        warn!("pty read failed: {err}");
+                    }
+                }
+            }
+        }
+        self.pending_input.extend(self.screen.take_replies());
+        // Checked once for the whole batch rather than per event.
+        if received && self.screen.is_scrolled() {
+            if self.scroll_on_output {
+                self.screen.scroll_to_bottom();
+            } else {
+                self.new_output_below = true;
+            }
+        }
+    }
+
+    // The output pipe can close before the child is reaped (and on Windows
+    // may never close at all), so exit is reported separately by the pty.
+    // Unless close_on_exit says to close, the session then stays open with
+    // a notice until Enter or Escape is pressed. Returns true when the
+    // notice was just shown.
+    pub fn drain_pty_exit(&mut self) -> bool {
+        if self.exit_notice_shown {
+            return false;
+        }
+        let Some(rx) = self.pty_exit_rx.as_ref() else {
+            return false;
+        };
+        let code = match rx.try_recv() {
+            Ok(code) => Some(code),
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => self
+                .pty
+                .as_ref()
+                .and_then(|pty| pty.exit_code().ok().flatten()),
+        };
+        match code {
+            Some(code) => info!("pty process exited with code {code}"),
+            None => warn!("pty exit code unavailable"),
+        }
+        // Output already read is kept on screen either way; the reader
+        // is left to finish, since ConPTY can deliver the last of it after
+        // the exit is reported.
+        self.drain_pty();
+        self.pty_closed = true;
+        self.exit_notice_shown = true;
+        let close = match self.close_on_exit {
+            CloseOnExit::Always => true,
+            CloseOnExit::Auto => code == Some(0),
+            CloseOnExit::Never => false,
+        };
+        if close {
+            self.exit_requested = true;
+            return false;
+        }
+        self.show_exit_notice(code);
+        true
+    }
+
+    fn show_exit_notice(&mut self, code: Option<i32>) {
+        let notice = match code {
+            Some(code) => {
+                format!("[process exited with code {code} \u{2014} press Enter to close]")
+            }
+            None => "[process exited \u{2014} press Enter to close]".to_string(),
+        };
+        self.write_text(&format!("\x1b[0m\r\n{notice}"));
+        self.screen.scroll_to_bottom();
+    }
+
+    // Gives the shell a moment to exit on its own, as it would when a
+    // terminal window is closed, so its jobs are hung up rather than left
+    // running without a console.
+    pub fn shutdown(&mut self) {
+        let Some(pty) = self.pty.as_mut() else {
+            return;
+        };
+        match pty.shutdown(PTY_SHUTDOWN_TIMEOUT) {
+            Ok(code) => info!("pty process shut down with code {code}"),
+            Err(err) => warn!("pty shutdown failed: {err}"),
+        }
+        if let Some(reader) = self.pty_reader.take() {
+            reader.shutdown();
+        }
+    }
+
+    pub fn send_ctrl_event(&mut self, event: CtrlEvent) {
+        if self.pty_closed {
+            return;
+        }
+        if let Some(pty) = self.pty.as_ref() {
+            if let Err(err) = pty.send_ctrl_event(event) {
+                warn!("pty control event failed: {err}");
+            }
+        }
+    }
+
+    // Writes for a few milliseconds at most, so a large paste into a shell
+    // that reads slowly doesn't freeze the window. The rest waits for the
+    // next turn of the event loop, and later keystrokes queue behind it.
+    pub fn flush_input(&mut self) {
+        if self.pending_input.is_empty() {
+            return;
+        }
+        let Some(writer) = self.pty_writer.as_mut() else {
+            self.pending_input.clear();
+            return;
+        };
+        match writer.write_some(&self.pending_input, INPUT_CHUNK_SIZE, INPUT_WRITE_BUDGET) {
+            Ok(written) => {
+                self.pending_input.drain(..written);
+            }
+            Err(err) => {
+                warn!("pty write failed: {err}");
+                self.pending_input.clear();
+            }
+        }
+    }
+}
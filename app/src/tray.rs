@@ -0,0 +1,311 @@
+// The notification-area icon shown while the window is minimized to the
+// tray. Only Windows has one; elsewhere the window just stays minimized.
+
+pub use platform::Tray;
+
+// Only the Windows tray produces these.
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrayEvent {
+    Show,
+    NewWindow,
+    Quit,
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::TrayEvent;
+    use anyhow::{anyhow, Result};
+    use std::cell::RefCell;
+    use std::ffi::OsStr;
+    use std::io;
+    use std::mem::size_of;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr::null;
+    use windows_sys::Win32::Foundation::{HWND, LPARAM, LRESULT, POINT, WPARAM};
+    use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+    use windows_sys::Win32::UI::Shell::{
+        Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NIM_MODIFY,
+        NOTIFYICONDATAW,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        AppendMenuW, CreateIcon, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyIcon,
+        DestroyMenu, DestroyWindow, GetCursorPos, GetSystemMetrics, GetWindowLongPtrW,
+        PostMessageW, RegisterClassW, RegisterWindowMessageW, SetForegroundWindow,
+        SetMenuDefaultItem, SetWindowLongPtrW, TrackPopupMenu, UnregisterClassW, GWLP_USERDATA,
+        HICON, MF_STRING, SM_CXSMICON, TPM_BOTTOMALIGN, TPM_RETURNCMD, TPM_RIGHTBUTTON, WM_APP,
+        WM_LBUTTONUP, WM_NULL, WM_RBUTTONUP, WNDCLASSW,
+    };
+    use winit::event_loop::EventLoopProxy;
+
+    const WM_TRAY: u32 = WM_APP + 1;
+    const ICON_ID: u32 = 1;
+    const MENU_ITEMS: [(TrayEvent, &str); 3] = [
+        (TrayEvent::Show, "Show"),
+        (TrayEvent::NewWindow, "New Window"),
+        (TrayEvent::Quit, "Quit"),
+    ];
+    const BADGE_COLOR: [u8; 4] = [255, 140, 40, 255];
+
+    // Reached from the window procedure through the window's user data.
+    struct TrayState {
+        proxy: EventLoopProxy<()>,
+        events: RefCell<Vec<TrayEvent>>,
+        data: RefCell<NOTIFYICONDATAW>,
+        // Sent when Explorer restarts, which drops every tray icon.
+        taskbar_created: u32,
+    }
+
+    pub struct Tray {
+        // Hidden; it only receives the icon's mouse messages.
+        hwnd: HWND,
+        class_name: Vec<u16>,
+        state: Box<TrayState>,
+        icon: HICON,
+        badge_icon: HICON,
+        attention: bool,
+    }
+
+    impl Tray {
+        pub fn new(proxy: EventLoopProxy<()>, tooltip: &str) -> Result<Self> {
+            let class_name = wide("RING0.Tray");
+            let size = unsafe { GetSystemMetrics(SM_CXSMICON) }.max(16) as u32;
+            unsafe {
+                let instance = GetModuleHandleW(null());
+                let class = WNDCLASSW {
+                    style: 0,
+                    lpfnWndProc: Some(window_proc),
+                    cbClsExtra: 0,
+                    cbWndExtra: 0,
+                    hInstance: instance,
+                    hIcon: 0,
+                    hCursor: 0,
+                    hbrBackground: 0,
+                    lpszMenuName: null(),
+                    lpszClassName: class_name.as_ptr(),
+                };
+                // Fails harmlessly when an earlier tray left it registered.
+                RegisterClassW(&class);
+                let hwnd = CreateWindowExW(
+                    0,
+                    class_name.as_ptr(),
+                    class_name.as_ptr(),
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    instance,
+                    null(),
+                );
+                if hwnd == 0 {
+                    return Err(anyhow!(
+                        "create tray window: {}",
+                        io::Error::last_os_error()
+                    ));
+                }
+                let icon = create_icon(size, false);
+                let badge_icon = create_icon(size, true);
+                let mut data: NOTIFYICONDATAW = std::mem::zeroed();
+                data.cbSize = size_of::<NOTIFYICONDATAW>() as u32;
+                data.hWnd = hwnd;
+                data.uID = ICON_ID;
+                data.uFlags = NIF_MESSAGE | NIF_ICON | NIF_TIP;
+                data.uCallbackMessage = WM_TRAY;
+                data.hIcon = icon;
+                // The last slot stays 0 to end the string.
+                for (slot, unit) in data.szTip.iter_mut().zip(tooltip.encode_utf16().take(127)) {
+                    *slot = unit;
+                }
+                let state = Box::new(TrayState {
+                    proxy,
+                    events: RefCell::new(Vec::new()),
+                    data: RefCell::new(data),
+                    taskbar_created: RegisterWindowMessageW(wide("TaskbarCreated").as_ptr()),
+                });
+                SetWindowLongPtrW(hwnd, GWLP_USERDATA, &*state as *const TrayState as isize);
+                // Built first so that dropping it cleans up on failure.
+                let tray = Self {
+                    hwnd,
+                    class_name,
+                    state,
+                    icon,
+                    badge_icon,
+                    attention: false,
+                };
+                if Shell_NotifyIconW(NIM_ADD, &data) == 0 {
+                    return Err(anyhow!("add tray icon: {}", io::Error::last_os_error()));
+                }
+                Ok(tray)
+            }
+        }
+
+        // Shows a badge on the icon while something wants attention.
+        pub fn set_attention(&mut self, attention: bool) {
+            if attention == self.attention {
+                return;
+            }
+            self.attention = attention;
+            let data = {
+                let mut data = self.state.data.borrow_mut();
+                data.hIcon = match attention {
+                    true => self.badge_icon,
+                    false => self.icon,
+                };
+                *data
+            };
+            unsafe {
+                Shell_NotifyIconW(NIM_MODIFY, &data);
+            }
+        }
+
+        pub fn take_events(&mut self) -> Vec<TrayEvent> {
+            self.state.events.take()
+        }
+    }
+
+    impl Drop for Tray {
+        fn drop(&mut self) {
+            let data = *self.state.data.borrow();
+            unsafe {
+                Shell_NotifyIconW(NIM_DELETE, &data);
+                SetWindowLongPtrW(self.hwnd, GWLP_USERDATA, 0);
+                DestroyWindow(self.hwnd);
+                UnregisterClassW(self.class_name.as_ptr(), GetModuleHandleW(null()));
+                for icon in [self.icon, self.badge_icon] {
+                    if icon != 0 {
+                        DestroyIcon(icon);
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe extern "system" fn window_proc(
+        hwnd: HWND,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const TrayState;
+        let Some(state) = state.as_ref() else {
+            return DefWindowProcW(hwnd, message, wparam, lparam);
+        };
+        if state.taskbar_created != 0 && message == state.taskbar_created {
+            let data = *state.data.borrow();
+            Shell_NotifyIconW(NIM_ADD, &data);
+            return 0;
+        }
+        if message != WM_TRAY {
+            return DefWindowProcW(hwnd, message, wparam, lparam);
+        }
+        let event = match (lparam & 0xFFFF) as u32 {
+            WM_LBUTTONUP => Some(TrayEvent::Show),
+            WM_RBUTTONUP => show_menu(hwnd),
+            _ => None,
+        };
+        if let Some(event) = event {
+            state.events.borrow_mut().push(event);
+            // The event loop may be waiting, with no window to redraw.
+            let _ = state.proxy.send_event(());
+        }
+        0
+    }
+
+    // The window is brought to the front and sent WM_NULL afterwards so the
+    // menu closes when clicking elsewhere, as TrackPopupMenu requires for
+    // notification icons.
+    unsafe fn show_menu(hwnd: HWND) -> Option<TrayEvent> {
+        let menu = CreatePopupMenu();
+        if menu == 0 {
+            return None;
+        }
+        // Item ids start at 1, since 0 means the menu was dismissed.
+        for (index, (_, label)) in MENU_ITEMS.iter().enumerate() {
+            let label = wide(label);
+            AppendMenuW(menu, MF_STRING, index + 1, label.as_ptr());
+        }
+        SetMenuDefaultItem(menu, 1, 0);
+        let mut point = POINT { x: 0, y: 0 };
+        GetCursorPos(&mut point);
+        SetForegroundWindow(hwnd);
+        let picked = TrackPopupMenu(
+            menu,
+            TPM_RETURNCMD | TPM_RIGHTBUTTON | TPM_BOTTOMALIGN,
+            point.x,
+            point.y,
+            0,
+            hwnd,
+            null(),
+        );
+        PostMessageW(hwnd, WM_NULL, 0, 0);
+        DestroyMenu(menu);
+        let index = usize::try_from(picked).ok()?.checked_sub(1)?;
+        MENU_ITEMS.get(index).map(|(event, _)| *event)
+    }
+
+    // The window icon, optionally with a round badge in the lower right.
+    unsafe fn create_icon(size: u32, badge: bool) -> HICON {
+        let mut rgba = crate::make_terminal_icon_rgba(size, size);
+        if badge {
+            let radius = (size / 4).max(3) as i32;
+            let center = size as i32 - radius - 1;
+            for y in 0..size {
+                for x in 0..size {
+                    let (dx, dy) = (x as i32 - center, y as i32 - center);
+                    if dx * dx + dy * dy <= radius * radius {
+                        let offset = ((y * size + x) * 4) as usize;
+                        rgba[offset..offset + 4].copy_from_slice(&BADGE_COLOR);
+                    }
+                }
+            }
+        }
+        // With 32-bit colour the alpha channel is used and the mask is not.
+        let bgra: Vec<u8> = rgba
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+            .collect();
+        let mask = vec![0u8; (size as usize).div_ceil(16) * 2 * size as usize];
+        CreateIcon(
+            GetModuleHandleW(null()),
+            size as i32,
+            size as i32,
+            1,
+            32,
+            mask.as_ptr(),
+            bgra.as_ptr(),
+        )
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        OsStr::new(text)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    }
+}
+
+#[cfg(not(windows))]
+mod platform {
+    use super::TrayEvent;
+    use anyhow::{anyhow, Result};
+    use winit::event_loop::EventLoopProxy;
+
+    // Never built, since there is no tray to put it in.
+    #[allow(dead_code)]
+    pub struct Tray;
+
+    impl Tray {
+        pub fn new(_proxy: EventLoopProxy<()>, _tooltip: &str) -> Result<Self> {
+            Err(anyhow!("the tray is only supported on Windows"))
+        }
+
+        pub fn set_attention(&mut self, _attention: bool) {}
+
+        pub fn take_events(&mut self) -> Vec<TrayEvent> {
+            Vec::new()
+        }
+    }
+}
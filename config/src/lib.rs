@@ -1 +1,512 @@
-pub struct Placeholder;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs, io};
+
+mod window_state;
+
+pub use window_state::{WindowState, WINDOW_STATE_FILE_NAME};
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+// Written out when no config file exists yet. Every setting is commented
+// out, so the file changes nothing until it is edited.
+pub const DEFAULT_CONFIG: &str = r##"# RING0 configuration. Remove the leading # from a setting to change it.
+# Changes apply when the file is saved, except that the shell settings only
+# apply to new tabs and the window size to the next start.
+
+# Program started in new sessions. `args` replaces the default shell's
+# arguments when given.
+# shell = "powershell.exe"
+# args = ["-NoLogo"]
+
+# Profile started in the first tab and by Ctrl+Shift+T, by name. Takes
+# precedence over `shell` and `args`.
+# default_profile = "PowerShell"
+
+# Lines kept above the screen for scrolling back.
+# scrollback_lines = 1000
+
+# Animate scrolling with the mouse wheel instead of jumping a line at a time.
+# smooth_scroll = true
+
+# Jump to the bottom when the shell prints while scrolled back.
+# scroll_on_output = false
+# Jump to the bottom when typing while scrolled back.
+# scroll_on_input = true
+
+# Close a tab when its shell exits: auto (only if it exited with code 0),
+# always or never. Otherwise the tab stays open showing the exit code.
+# close_on_exit = "auto"
+
+# Keep the old output above a separator when the shell is restarted with
+# Ctrl+Shift+R, instead of clearing it.
+# keep_scrollback_on_restart = false
+
+# What Backspace sends: del (^?), which most shells expect, or bs (^H).
+# Ctrl+Backspace sends the other one.
+# backspace = "del"
+
+# Ctrl+C copies the selection when there is one and interrupts the shell
+# otherwise; Ctrl+V pastes. When false, both go to the shell as they are and
+# Ctrl+Shift+C and Ctrl+Shift+V copy and paste.
+# ctrl_c_copies = true
+
+# What the right mouse button does: paste, smart (copy the selection if
+# there is one, otherwise paste) or menu.
+# right_click = "paste"
+
+# Quoting for paths of files dropped on the window: plain or powershell.
+# drop_quoting = "plain"
+
+# Make text that programs mark as blinking (SGR 5) blink. When false it is
+# drawn bold instead.
+# text_blink = true
+
+# Most frames drawn per second; 0 draws as often as the output changes.
+# max_fps = 120
+
+# Space in pixels between the window edges and the text.
+# padding = 12
+
+# A built-in theme: dark, light or gruvbox.
+# theme = "dark"
+
+# Lightens or darkens text whose contrast with its background is below this
+# WCAG ratio, from 1 to 21. Left unset, colours are drawn as they are.
+# minimum_contrast = 4.5
+
+# Or start from a built-in theme and override some of its colours.
+# [theme]
+# name = "dark"
+# foreground = "#e6edf3"
+# background = "#0a0e14"
+# "auto" draws the cursor in the colours of the cell under it, swapped.
+# cursor = "#58a8ff"
+# Width of the bar cursor and height of the underline cursor: below 1 a
+# fraction of the cell, otherwise pixels.
+# cursor_thickness = 2.0
+# selection = "#264f78"
+# ansi = [
+#   "#1c2128", "#e06c75", "#98c379", "#e5c07b", "#61afef", "#c678dd", "#56b6c2", "#abb2bf",
+#   "#5c6370", "#f0878f", "#b0d693", "#f0d296", "#82c3fa", "#d796eb", "#78cdd7", "#e6edf3",
+# ]
+
+# [font]
+# Family name of an installed font, or a path to a font file.
+# family = "Cascadia Code"
+# size = 16.0
+# Weight from 1 to 1000 for variable fonts, such as 350 for semi-light.
+# weight = 400
+# Text smoothing: grayscale, subpixel (for LCD panels, opaque windows only)
+# or none.
+# antialias = "grayscale"
+# Cell height and width relative to the font's own, from 0.5 to 2.0.
+# line_height = 1.0
+# cell_width = 1.0
+
+# [cursor]
+# Block, bar or underline.
+# style = "bar"
+# blink = true
+# Time in milliseconds the blinking cursor spends shown, and then hidden.
+# blink_interval = 600
+
+# [window]
+# Initial size in character cells.
+# cols = 120
+# rows = 30
+# Window title; {title} is replaced by the title the shell sets.
+# title = "{title} — RING0"
+# Reopen where the window was last closed, instead of at the size above.
+# remember_layout = true
+# Hide the window in the notification area when it is minimized (Windows).
+# minimize_to_tray = false
+# Keep the window above other windows; Ctrl+Shift+A toggles it. Left unset,
+# the window starts the way it was last closed.
+# always_on_top = false
+# Background opacity from 0.0 to 1.0. Ctrl+Shift+wheel changes it, and the
+# opacity picked that way is remembered and used instead of this.
+# opacity = 1.0
+
+# [keys]
+# Shortcuts, written like "ctrl+shift+a": any of ctrl, shift and alt followed
+# by a single key.
+# always_on_top = "ctrl+shift+a"
+
+# [bell]
+# What a program ringing the bell does.
+# Play the system sound.
+# audible = false
+# Flash the window.
+# visual = false
+# Flash the taskbar button while the window is in the background.
+# taskbar = true
+
+# Shell profiles, opened in a new tab by Ctrl+Shift+1 to 9 in the order
+# listed. PowerShell, Command Prompt and WSL (when installed) are built in
+# and come first. A profile named like a built-in one changes it, and any
+# setting left out is kept.
+# [[profiles]]
+# name = "Ubuntu"
+# command = "wsl.exe"
+# args = ["-d", "Ubuntu"]
+# cwd = 'C:\Users\me'
+# env = { LANG = "en_US.UTF-8" }
+# theme = "gruvbox"
+"##;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to create {}: {source}", path.display())]
+    CreateDefault { path: PathBuf, source: io::Error },
+    #[error("invalid config in {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("failed to write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+    #[error("failed to serialize settings: {0}")]
+    Serialize(toml::ser::Error),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub shell: Option<String>,
+    pub args: Vec<String>,
+    pub default_profile: Option<String>,
+    pub profiles: Vec<ProfileConfig>,
+    pub font: FontConfig,
+    #[serde(deserialize_with = "theme_name_or_table")]
+    pub theme: ThemeConfig,
+    pub minimum_contrast: Option<f32>,
+    pub scrollback_lines: Option<usize>,
+    pub smooth_scroll: Option<bool>,
+    pub scroll_on_output: Option<bool>,
+    pub scroll_on_input: Option<bool>,
+    pub drop_quoting: Option<DropQuoting>,
+    pub right_click: Option<RightClick>,
+    pub close_on_exit: Option<CloseOnExit>,
+    pub keep_scrollback_on_restart: Option<bool>,
+    pub ctrl_c_copies: Option<bool>,
+    pub backspace: Option<Backspace>,
+    pub padding: Option<u32>,
+    pub max_fps: Option<u32>,
+    pub text_blink: Option<bool>,
+    pub cursor: CursorConfig,
+    pub window: WindowConfig,
+    pub keys: KeysConfig,
+    pub bell: BellConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FontConfig {
+    pub family: Option<String>,
+    pub size: Option<f32>,
+    pub weight: Option<u16>,
+    pub antialias: Option<Antialias>,
+    pub line_height: Option<f32>,
+    pub cell_width: Option<f32>,
+}
+
+// `theme = "name"` fills in just the name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub name: Option<String>,
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub cursor: Option<CursorColor>,
+    pub cursor_thickness: Option<f32>,
+    pub selection: Option<Color>,
+    pub ansi: Option<[Color; 16]>,
+}
+
+// Settings left out are taken from the built-in profile of the same name,
+// if there is one.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    #[serde(deserialize_with = "optional_theme")]
+    pub theme: Option<ThemeConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CursorConfig {
+    pub style: Option<CursorStyle>,
+    pub blink: Option<bool>,
+    pub blink_interval: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BellConfig {
+    pub audible: Option<bool>,
+    pub visual: Option<bool>,
+    pub taskbar: Option<bool>,
+}
+
+// When a tab closes after its shell exits. Otherwise it stays open with the
+// exit code shown until Enter or Escape is pressed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloseOnExit {
+    // Only when the shell exited with code 0.
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backspace {
+    // 0x7f.
+    Del,
+    // 0x08.
+    Bs,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RightClick {
+    Paste,
+    // Copies the selection if there is one, and pastes otherwise.
+    Smart,
+    // Copy, Paste, Select All and Clear Scrollback.
+    Menu,
+}
+
+// How paths of files dropped on the window are quoted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DropQuoting {
+    // Double quotes around paths that need them, as cmd expects.
+    Plain,
+    // Double quotes with `$`, `"` and backticks escaped by a backtick.
+    PowerShell,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Antialias {
+    Grayscale,
+    // Separate coverage for the red, green and blue parts of each pixel.
+    Subpixel,
+    None,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CursorStyle {
+    Block,
+    Bar,
+    Underline,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub cols: Option<u32>,
+    pub rows: Option<u32>,
+    pub title: Option<String>,
+    pub remember_layout: Option<bool>,
+    pub minimize_to_tray: Option<bool>,
+    pub always_on_top: Option<bool>,
+    pub opacity: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct KeysConfig {
+    pub always_on_top: Option<KeyBinding>,
+}
+
+// Written as "ctrl+shift+a". The key is stored in lowercase, since Shift is
+// matched as a modifier rather than by the character it types.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub key: char,
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let invalid = || {
+            de::Error::custom(format!(
+                "expected a shortcut like \"ctrl+shift+a\", got {text:?}"
+            ))
+        };
+        // A trailing "+" is the key itself, as in "ctrl++".
+        let (modifiers, key) = match text.strip_suffix("++") {
+            Some(modifiers) => (modifiers, "+"),
+            None => text.rsplit_once('+').unwrap_or(("", &text)),
+        };
+        let mut chars = key.chars();
+        let (Some(key), None) = (chars.next(), chars.next()) else {
+            return Err(invalid());
+        };
+        let mut binding = Self {
+            ctrl: false,
+            shift: false,
+            alt: false,
+            key: key.to_ascii_lowercase(),
+        };
+        for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+            match modifier.trim().to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => binding.ctrl = true,
+                "shift" => binding.shift = true,
+                "alt" => binding.alt = true,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(binding)
+    }
+}
+
+// Written as "#rrggbb".
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let hex = text
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| de::Error::custom(format!("expected a #rrggbb colour, got {text:?}")))?;
+        Ok(Self {
+            r: (hex >> 16) as u8,
+            g: (hex >> 8) as u8,
+            b: hex as u8,
+        })
+    }
+}
+
+// Written as "auto" or "#rrggbb".
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CursorColor {
+    // Inverse video: the cell's colours swapped.
+    Auto,
+    Color(Color),
+}
+
+impl<'de> Deserialize<'de> for CursorColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        Color::deserialize(de::value::StrDeserializer::<D::Error>::new(&text))
+            .map(Self::Color)
+            .map_err(|_| {
+                de::Error::custom(format!(
+                    "expected \"auto\" or a #rrggbb colour, got {text:?}"
+                ))
+            })
+    }
+}
+
+fn theme_name_or_table<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ThemeConfig, D::Error> {
+    struct ThemeVisitor;
+
+    impl<'de> Visitor<'de> for ThemeVisitor {
+        type Value = ThemeConfig;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a theme name or a table of colours")
+        }
+
+        fn visit_str<E: de::Error>(self, name: &str) -> Result<ThemeConfig, E> {
+            Ok(ThemeConfig {
+                name: Some(name.to_string()),
+                ..ThemeConfig::default()
+            })
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ThemeConfig, A::Error> {
+            ThemeConfig::deserialize(de::value::MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(ThemeVisitor)
+}
+
+fn optional_theme<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ThemeConfig>, D::Error> {
+    theme_name_or_table(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadedConfig {
+    pub config: Config,
+    // Dotted paths of keys that were not recognised and were skipped.
+    pub unknown_keys: Vec<String>,
+}
+
+impl Config {
+    // `%LOCALAPPDATA%\RING0\config.toml`, or None when LOCALAPPDATA is unset.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("LOCALAPPDATA")?;
+        Some(PathBuf::from(base).join("RING0").join(CONFIG_FILE_NAME))
+    }
+
+    pub fn parse(text: &str) -> Result<LoadedConfig, toml::de::Error> {
+        let mut unknown_keys = Vec::new();
+        let config = serde_ignored::deserialize(toml::Deserializer::new(text), |path| {
+            unknown_keys.push(path.to_string());
+        })?;
+        Ok(LoadedConfig {
+            config,
+            unknown_keys,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<LoadedConfig, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(&text).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    // Writes DEFAULT_CONFIG to `path` first if nothing is there yet.
+    pub fn load_or_create(path: &Path) -> Result<LoadedConfig, ConfigError> {
+        if !path.exists() {
+            let create = |source| ConfigError::CreateDefault {
+                path: path.to_path_buf(),
+                source,
+            };
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(create)?;
+            }
+            fs::write(path, DEFAULT_CONFIG).map_err(create)?;
+        }
+        Self::load(path)
+    }
+}
@@ -0,0 +1,70 @@
+// Where the window was when RING0 last closed. Written by the app, not by
+// hand, so unlike the config it is replaced wholesale on every save.
+
+use crate::ConfigError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+pub const WINDOW_STATE_FILE_NAME: &str = "window.toml";
+
+// Position and size are in physical pixels and describe the restored
+// window, even when it was closed maximized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+    pub monitor: Option<String>,
+    // Missing from files saved before it was remembered.
+    #[serde(default)]
+    pub always_on_top: bool,
+    // Set once the opacity has been changed with the hotkeys.
+    pub opacity: Option<f32>,
+}
+
+impl WindowState {
+    // `%LOCALAPPDATA%\RING0\window.toml`, or None when LOCALAPPDATA is unset.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = env::var_os("LOCALAPPDATA")?;
+        Some(
+            PathBuf::from(base)
+                .join("RING0")
+                .join(WINDOW_STATE_FILE_NAME),
+        )
+    }
+
+    // None when nothing has been saved yet.
+    pub fn load(path: &Path) -> Result<Option<Self>, ConfigError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        toml::from_str(&text)
+            .map(Some)
+            .map_err(|source| ConfigError::Parse {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let text = toml::to_string(self).map_err(ConfigError::Serialize)?;
+        let write = |source| ConfigError::Write {
+            path: path.to_path_buf(),
+            source,
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(write)?;
+        }
+        fs::write(path, text).map_err(write)
+    }
+}
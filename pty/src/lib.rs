@@ -1,4 +1,22 @@
-use std::io::{Read, Write};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const READ_BUFFER_SIZE: usize = 4096;
+// Largest single write. Bigger writes are split so the input lock is
+// released in between and a keystroke can get through during a paste.
+const WRITE_CHUNK_SIZE: usize = 4096;
+// How often a reader thread that has nothing to read checks whether it
+// has been asked to stop, and how often a blocked send is retried.
+const READER_STOP_POLL: Duration = Duration::from_millis(100);
+const READER_SEND_RETRY: Duration = Duration::from_millis(5);
 
 #[derive(Debug, thiserror::Error)]
 pub enum PtyError {
@@ -6,20 +24,151 @@ pub enum PtyError {
     UnsupportedPlatform,
     #[error("invalid size: cols={cols}, rows={rows}")]
     InvalidSize { cols: u16, rows: u16 },
+    #[error("working directory {} does not exist or is not a directory", path.display())]
+    InvalidWorkingDirectory { path: PathBuf },
+    #[error("{flag} needs Windows build {required} or later, this is build {build}")]
+    UnsupportedConPtyFlag {
+        flag: &'static str,
+        required: u32,
+        build: u32,
+    },
+    #[error("ConPTY is not available on Windows build {build}; Windows 10 1809 (build 17763) or later is required")]
+    ConPtyUnavailable { build: u32 },
+    #[error("creation flags {flags:#x} can't be used: {reason}")]
+    InvalidCreationFlags { flags: u32, reason: &'static str },
+    #[error("{stage} failed{}: {source}", for_command(.command))]
+    Spawn {
+        stage: SpawnStage,
+        // The command being started, once the failing stage involves it.
+        command: Option<String>,
+        source: io::Error,
+    },
+    #[error("command not found: {command}")]
+    CommandNotFound { command: String },
+    #[error("permission denied running {command}")]
+    AccessDenied { command: String },
+    #[error("{command} needs to run as administrator")]
+    ElevationRequired { command: String },
+    #[error("pty input has been closed")]
+    InputClosed,
     #[error("io error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(#[from] io::Error),
     #[cfg(windows)]
     #[error("windows api error: {0}")]
     Windows(#[from] windows::core::Error),
 }
 
-#[derive(Debug, Copy, Clone)]
+impl PtyError {
+    // The usual reasons a command can't be started get variants of their
+    // own, so callers can explain them without decoding OS error codes.
+    fn spawn(stage: SpawnStage, command: Option<&str>, source: io::Error) -> Self {
+        if let (SpawnStage::CreateProcess | SpawnStage::Exec, Some(command)) = (stage, command) {
+            let command = command.to_string();
+            match source.kind() {
+                io::ErrorKind::NotFound => return PtyError::CommandNotFound { command },
+                io::ErrorKind::PermissionDenied => return PtyError::AccessDenied { command },
+                // ERROR_ELEVATION_REQUIRED
+                _ if cfg!(windows) && source.raw_os_error() == Some(740) => {
+                    return PtyError::ElevationRequired { command };
+                }
+                _ => {}
+            }
+        }
+        PtyError::Spawn {
+            stage,
+            command: command.map(str::to_string),
+            source,
+        }
+    }
+}
+
+fn for_command(command: &Option<String>) -> String {
+    match command {
+        Some(command) => format!(" for `{command}`"),
+        None => String::new(),
+    }
+}
+
+// The step of starting a child that failed, named after the system call.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpawnStage {
+    CreatePipe,
+    CreatePseudoConsole,
+    AttributeList,
+    CreateJob,
+    CreateProcess,
+    OpenPty,
+    Fork,
+    ChangeDirectory,
+    Exec,
+}
+
+impl fmt::Display for SpawnStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SpawnStage::CreatePipe => "CreatePipe",
+            SpawnStage::CreatePseudoConsole => "CreatePseudoConsole",
+            SpawnStage::AttributeList => "setting up the process attribute list",
+            SpawnStage::CreateJob => "CreateJobObject",
+            SpawnStage::CreateProcess => "CreateProcessW",
+            SpawnStage::OpenPty => "openpty",
+            SpawnStage::Fork => "fork",
+            SpawnStage::ChangeDirectory => "chdir",
+            SpawnStage::Exec => "exec",
+        })
+    }
+}
+
+// Lets the std::io impls below use `?` on the inherent methods.
+impl From<PtyError> for io::Error {
+    fn from(err: PtyError) -> Self {
+        match err {
+            PtyError::Io(err) => err,
+            err @ PtyError::InputClosed => io::Error::new(io::ErrorKind::BrokenPipe, err),
+            err => io::Error::other(err),
+        }
+    }
+}
+
+// Flags for CreatePseudoConsole. Windows only; ignored elsewhere.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ConPtyOptions {
+    // The pseudoconsole starts at the host's cursor position. ConPTY asks
+    // for it with a cursor position query (ESC[6n) and waits for the reply
+    // before the child can draw, so the host must answer it.
+    pub inherit_cursor: bool,
+    // VT output from the child is forwarded as-is instead of being
+    // re-rendered by conhost.
+    pub passthrough: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CtrlEvent {
+    C,
+    Break,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub struct PtySize {
     pub cols: u16,
     pub rows: u16,
+    // Size of the whole grid in pixels, or 0 when unknown. Programs divide
+    // it by cols/rows to get the cell size for image protocols such as
+    // sixel.
+    pub pixel_width: u16,
+    pub pixel_height: u16,
 }
 
 impl PtySize {
+    pub const fn cells(cols: u16, rows: u16) -> Self {
+        Self {
+            cols,
+            rows,
+            pixel_width: 0,
+            pixel_height: 0,
+        }
+    }
+
     fn validate(self) -> Result<(), PtyError> {
         if self.cols == 0 || self.rows == 0 {
             return Err(PtyError::InvalidSize {
@@ -33,137 +182,1198 @@ impl PtySize {
 
 pub struct Pty {
     inner: PtyInner,
+    // Shared with every PtyWriter; None once close_input has been called.
+    input: Arc<Mutex<Option<File>>>,
+    // Shared with every PtyReader, which follow it across respawns.
+    output: Arc<SharedOutput>,
+    size: PtySize,
 }
 
 impl Pty {
+    // Runs `command` as given: the Windows command line verbatim, or split
+    // on whitespace on Unix. Use `builder` when arguments need quoting.
     pub fn spawn(command: &str, size: PtySize) -> Result<Self, PtyError> {
+        Self::spawn_command(CommandLine::Raw(command), SpawnOptions::default(), size)
+    }
+
+    // False where spawning can't work at all: off Windows and Unix, or on
+    // Windows builds without ConPTY.
+    pub fn is_supported() -> bool {
+        platform::is_supported()
+    }
+
+    pub fn builder(program: impl Into<String>) -> PtyBuilder {
+        PtyBuilder {
+            program: program.into(),
+            args: Vec::new(),
+            env: Vec::new(),
+            env_clear: false,
+            current_dir: None,
+            options: SpawnOptions::default(),
+        }
+    }
+
+    fn spawn_command(
+        command: CommandLine<'_>,
+        options: SpawnOptions,
+        size: PtySize,
+    ) -> Result<Self, PtyError> {
         size.validate()?;
-        let inner = PtyInner::spawn(command, size)?;
-        Ok(Self { inner })
+        let (inner, input) = PtyInner::spawn(command, options, size)?;
+        let output = inner.clone_reader()?;
+        Ok(Self {
+            inner,
+            input: Arc::new(Mutex::new(Some(input))),
+            output: Arc::new(SharedOutput::new(output)),
+            size,
+        })
+    }
+
+    // Replaces the child with a new one running `command` at the current
+    // size. Readers and writers taken from this Pty keep working and move
+    // on to the new child, and a reader pump sends PtyChunk::Restarted at
+    // the switch. The old child and everything it started is killed once
+    // the new one is running. Exit notifiers from before still report the
+    // old child.
+    pub fn respawn(&mut self, command: &str) -> Result<(), PtyError> {
+        self.respawn_command(CommandLine::Raw(command), SpawnOptions::default())
+    }
+
+    fn respawn_command(
+        &mut self,
+        command: CommandLine<'_>,
+        options: SpawnOptions,
+    ) -> Result<(), PtyError> {
+        let (inner, input) = PtyInner::spawn(command, options, self.size)?;
+        let output = inner.clone_reader()?;
+        let mut old = std::mem::replace(&mut self.inner, inner);
+        *self.input.lock().unwrap_or_else(PoisonError::into_inner) = Some(input);
+        self.output.replace(output);
+        let _ = old.terminate();
+        Ok(())
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, PtyError> {
         self.inner.read(buf)
     }
 
+    pub fn read_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<Option<usize>, PtyError> {
+        self.inner.read_timeout(buf, timeout)
+    }
+
     pub fn write(&mut self, buf: &[u8]) -> Result<usize, PtyError> {
-        self.inner.write(buf)
+        with_input(&self.input, |input| input.write(buf))
+    }
+
+    // Signals end of input to the child, like closing its stdin. Writes
+    // through this Pty or any of its writers fail with InputClosed
+    // afterwards. On Unix the pty cannot be half-closed, so the terminal's
+    // EOF character (normally ^D) is sent instead; it only reads as end of
+    // input to a program using the terminal in canonical mode.
+    pub fn close_input(&mut self) -> Result<(), PtyError> {
+        let mut input = self.input.lock().unwrap_or_else(PoisonError::into_inner);
+        match input.take() {
+            Some(mut file) => platform::end_input(&mut file),
+            None => Ok(()),
+        }
     }
 
     pub fn resize(&mut self, size: PtySize) -> Result<(), PtyError> {
         size.validate()?;
-        self.inner.resize(size)
+        self.inner.resize(size)?;
+        self.size = size;
+        Ok(())
+    }
+
+    // The size given to spawn or the last successful resize. ConPTY has no
+    // use for the pixel dimensions, so on Windows this is the only place
+    // they are kept.
+    pub fn size(&self) -> PtySize {
+        self.size
     }
 
     pub fn is_running(&self) -> Result<bool, PtyError> {
         self.inner.is_running()
     }
 
+    // None while the child is still running.
+    pub fn exit_code(&self) -> Result<Option<i32>, PtyError> {
+        self.inner.exit_code()
+    }
+
+    pub fn wait(&self) -> Result<i32, PtyError> {
+        self.inner.wait()
+    }
+
+    // None if the child is still running after `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<Option<i32>, PtyError> {
+        self.inner.wait_timeout(timeout)
+    }
+
+    // Sends the exit code once, from a background thread, as soon as the
+    // child exits. Works after the Pty itself has been dropped.
+    pub fn exit_notifier(&self) -> Result<Receiver<i32>, PtyError> {
+        self.inner.exit_notifier()
+    }
+
+    // Asks the child to exit the way closing a terminal window would:
+    // the pseudoconsole is closed on Windows, SIGHUP and SIGTERM are sent
+    // on Unix. Does not wait for it to do so.
+    pub fn signal_close(&mut self) -> Result<(), PtyError> {
+        self.inner.signal_close()
+    }
+
+    // Delivers a console control event the way the console itself would.
+    // Writing 0x03 as input usually has the same effect as CtrlEvent::C,
+    // but only when the console is in processed-input mode (the terminal
+    // is in ISIG mode on Unix); this works regardless and is the only way
+    // to deliver Ctrl+Break. On Unix, C sends SIGINT and Break SIGQUIT to
+    // the terminal's foreground process group.
+    pub fn send_ctrl_event(&self, event: CtrlEvent) -> Result<(), PtyError> {
+        self.inner.send_ctrl_event(event)
+    }
+
+    // The console code page in effect once force_utf8 switched it, for
+    // diagnostics. The switch happens in the background shortly after the
+    // child starts, so this is None until then, as well as when it wasn't
+    // switched or the switch failed. Always None on Unix, where the pty
+    // passes bytes through unchanged.
+    pub fn code_page(&self) -> Option<u32> {
+        self.inner.code_page()
+    }
+
+    // Lets a child spawned with PtyBuilder::suspended start running. Does
+    // nothing otherwise.
+    pub fn resume(&mut self) -> Result<(), PtyError> {
+        self.inner.resume()
+    }
+
+    // Kills the child outright. Does nothing if it has already exited.
+    pub fn terminate(&mut self) -> Result<(), PtyError> {
+        self.inner.terminate()
+    }
+
+    // Signals the child to close, gives it `timeout` to exit on its own and
+    // terminates it otherwise, returning its exit code.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<i32, PtyError> {
+        if let Some(code) = self.exit_code()? {
+            return Ok(code);
+        }
+        self.signal_close()?;
+        if let Some(code) = self.wait_timeout(timeout)? {
+            return Ok(code);
+        }
+        self.terminate()?;
+        self.wait()
+    }
+
+    pub fn spawn_reader(
+        &self,
+        options: ReaderOptions,
+    ) -> Result<(Receiver<PtyChunk>, ReaderHandle), PtyError> {
+        self.reader()?.spawn(options)
+    }
+
     pub fn reader(&self) -> Result<PtyReader, PtyError> {
+        let state = self.output.lock();
         Ok(PtyReader {
-            inner: self.inner.clone_reader()?,
+            output: Arc::clone(&self.output),
+            file: Arc::clone(&state.file),
+            generation: state.generation,
+            nonblocking: false,
         })
     }
 
     pub fn writer(&self) -> Result<PtyWriter, PtyError> {
         Ok(PtyWriter {
-            inner: self.inner.clone_writer()?,
+            input: Arc::clone(&self.input),
         })
     }
 
+    pub fn shared_writer(&self) -> SharedPtyWriter {
+        SharedPtyWriter {
+            input: Arc::clone(&self.input),
+        }
+    }
+
     pub fn bytes_available(&self) -> Result<u32, PtyError> {
         self.inner.bytes_available()
     }
+
+    pub fn child_pid(&self) -> u32 {
+        self.inner.child_pid()
+    }
+
+    // The child's current directory as the OS sees it. Shells that track
+    // their location themselves, like PowerShell, may not keep it updated.
+    pub fn current_dir(&self) -> Result<PathBuf, PtyError> {
+        self.inner.current_dir()
+    }
+
+    // The handles below stay owned by the Pty and are only valid while it
+    // lives; callers must not close them.
+    #[cfg(windows)]
+    pub fn raw_process_handle(&self) -> std::os::windows::io::RawHandle {
+        self.inner.process_handle()
+    }
+
+    // None once signal_close has closed the pseudoconsole.
+    #[cfg(windows)]
+    pub fn raw_pseudoconsole(&self) -> Option<windows::Win32::System::Console::HPCON> {
+        self.inner.pseudoconsole()
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        self.output.close();
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for Pty {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.inner.master_fd()
+    }
+}
+
+impl Read for Pty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(Pty::read(self, buf)?)
+    }
+}
+
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(Pty::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(with_input(&self.input, |input| input.flush())?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PtyBuilder {
+    program: String,
+    args: Vec<String>,
+    // Applied in order over the inherited (or cleared) environment; None
+    // removes the variable.
+    env: Vec<(OsString, Option<OsString>)>,
+    env_clear: bool,
+    current_dir: Option<PathBuf>,
+    options: SpawnOptions,
 }
 
+impl PtyBuilder {
+    pub fn program(&self) -> &str {
+        &self.program
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.env
+            .push((key.as_ref().to_owned(), Some(value.as_ref().to_owned())));
+        self
+    }
+
+    pub fn env_remove(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.env.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    // Starts the child with no variables besides those set afterwards.
+    pub fn env_clear(mut self) -> Self {
+        self.env.clear();
+        self.env_clear = true;
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    pub fn conpty_options(mut self, options: ConPtyOptions) -> Self {
+        self.options.conpty = options;
+        self
+    }
+
+    // On by default. Everything the child starts is killed along with it
+    // when the Pty is terminated or dropped: through a job object on
+    // Windows, and by signalling the child's process group on Unix (which
+    // misses descendants that moved to a group of their own, such as an
+    // interactive shell's jobs).
+    pub fn kill_process_tree(mut self, enabled: bool) -> Self {
+        self.options.kill_process_tree = enabled;
+        self
+    }
+
+    // The child is created but doesn't run until Pty::resume. On Windows it
+    // is already in the kill-on-close job by then. On Unix it stops itself
+    // just before exec.
+    pub fn suspended(mut self, enabled: bool) -> Self {
+        self.options.suspended = enabled;
+        self
+    }
+
+    // Windows only (CREATE_NEW_PROCESS_GROUP); on Unix the child always
+    // leads a session of its own. Windows disables Ctrl+C for the new
+    // group, so send_ctrl_event(CtrlEvent::C) is ignored unless the child
+    // re-enables it; Ctrl+Break still works.
+    pub fn new_process_group(mut self, enabled: bool) -> Self {
+        self.options.new_process_group = enabled;
+        self
+    }
+
+    // Windows only, and on by default (CREATE_NO_WINDOW). The child is meant
+    // to attach to the pseudoconsole, and RING0 has no console of its own,
+    // so this makes sure any console the child ends up with otherwise stays
+    // hidden instead of appearing as a stray window.
+    pub fn no_window(mut self, enabled: bool) -> Self {
+        self.options.no_window = enabled;
+        self
+    }
+
+    // Windows only, and on by default. Switches the pseudoconsole's input
+    // and output code pages to UTF-8 (65001) right after the child starts,
+    // so programs such as cmd.exe don't write in the OEM code page. Output
+    // the child writes before the switch lands is still in the old code
+    // page, and the child, or anything it runs, may switch back.
+    pub fn force_utf8(mut self, enabled: bool) -> Self {
+        self.options.force_utf8 = enabled;
+        self
+    }
+
+    // Extra CreateProcessW flags, added to those the options above set.
+    // Windows only; ignored elsewhere. CREATE_SUSPENDED here is the same as
+    // `suspended(true)`. Flags that would keep the child off the
+    // pseudoconsole (DETACHED_PROCESS, CREATE_NEW_CONSOLE) fail the spawn
+    // with InvalidCreationFlags.
+    pub fn creation_flags(mut self, flags: u32) -> Self {
+        self.options.creation_flags = flags;
+        self
+    }
+
+    pub fn spawn(&self, size: PtySize) -> Result<Pty, PtyError> {
+        self.with_command(|command| Pty::spawn_command(command, self.options, size))
+    }
+
+    // Replaces the child of an existing Pty, as Pty::respawn does.
+    pub fn spawn_into(&self, pty: &mut Pty) -> Result<(), PtyError> {
+        self.with_command(|command| pty.respawn_command(command, self.options))
+    }
+
+    fn with_command<T>(
+        &self,
+        spawn: impl FnOnce(CommandLine<'_>) -> Result<T, PtyError>,
+    ) -> Result<T, PtyError> {
+        if let Some(dir) = self.current_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            return Err(PtyError::InvalidWorkingDirectory { path: dir.clone() });
+        }
+        let env = self.environment();
+        spawn(CommandLine::Argv {
+            program: &self.program,
+            args: &self.args,
+            env: env.as_deref(),
+            current_dir: self.current_dir.as_deref(),
+        })
+    }
+
+    // None when the child can simply inherit this process's environment.
+    fn environment(&self) -> Option<Vec<(OsString, OsString)>> {
+        if !self.env_clear && self.env.is_empty() {
+            return None;
+        }
+        let mut vars: Vec<(OsString, OsString)> = match self.env_clear {
+            true => Vec::new(),
+            false => std::env::vars_os().collect(),
+        };
+        for (key, value) in &self.env {
+            vars.retain(|(existing, _)| !env_key_eq(existing, key));
+            if let Some(value) = value {
+                vars.push((key.clone(), value.clone()));
+            }
+        }
+        Some(vars)
+    }
+}
+
+// Builds a Windows command line that splits back into `program` and
+// `args`. It is plain string work, so it is available on every platform.
+// CreateProcessW reads the program name up to the next space unless it is
+// quoted, without any escapes, so it only needs wrapping.
+pub fn windows_quote_argv(program: &str, args: &[&str]) -> String {
+    let mut line = String::new();
+    if program.is_empty() || program.contains([' ', '\t']) {
+        line.push('"');
+        line.push_str(program);
+        line.push('"');
+    } else {
+        line.push_str(program);
+    }
+    for arg in args {
+        line.push(' ');
+        quote_arg(arg, &mut line);
+    }
+    line
+}
+
+// Quotes an argument so CommandLineToArgvW and the MSVC runtime parse it
+// back unchanged: backslashes are literal unless they precede a quote,
+// in which case each one is doubled and the quote escaped.
+fn quote_arg(arg: &str, line: &mut String) {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0b', '"']) {
+        line.push_str(arg);
+        return;
+    }
+    line.push('"');
+    let mut backslashes = 0;
+    for ch in arg.chars() {
+        match ch {
+            '\\' => backslashes += 1,
+            '"' => {
+                line.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                line.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                line.extend(std::iter::repeat_n('\\', backslashes));
+                line.push(ch);
+                backslashes = 0;
+            }
+        }
+    }
+    // The closing quote must not be escaped by trailing backslashes.
+    line.extend(std::iter::repeat_n('\\', backslashes * 2));
+    line.push('"');
+}
+
+// Windows variable names are case-insensitive.
+fn env_key_eq(a: &OsStr, b: &OsStr) -> bool {
+    match cfg!(windows) {
+        true => a.eq_ignore_ascii_case(b),
+        false => a == b,
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct SpawnOptions {
+    conpty: ConPtyOptions,
+    kill_process_tree: bool,
+    suspended: bool,
+    new_process_group: bool,
+    no_window: bool,
+    creation_flags: u32,
+    force_utf8: bool,
+}
+
+impl Default for SpawnOptions {
+    fn default() -> Self {
+        Self {
+            conpty: ConPtyOptions::default(),
+            kill_process_tree: true,
+            suspended: false,
+            new_process_group: false,
+            no_window: true,
+            creation_flags: 0,
+            force_utf8: true,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum CommandLine<'a> {
+    Raw(&'a str),
+    Argv {
+        program: &'a str,
+        args: &'a [String],
+        env: Option<&'a [(OsString, OsString)]>,
+        current_dir: Option<&'a Path>,
+    },
+}
+
+// The output of whichever child a Pty is running. Each respawn installs
+// the new child's output under a new generation.
+struct SharedOutput {
+    state: Mutex<OutputState>,
+    changed: Condvar,
+}
+
+struct OutputState {
+    file: Arc<File>,
+    generation: u64,
+    // Set once the Pty is dropped, after which no respawn can follow.
+    closed: bool,
+}
+
+impl SharedOutput {
+    fn new(file: File) -> Self {
+        Self {
+            state: Mutex::new(OutputState {
+                file: Arc::new(file),
+                generation: 0,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, OutputState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn replace(&self, file: File) {
+        let mut state = self.lock();
+        state.file = Arc::new(file);
+        state.generation += 1;
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Reads the child's output. It implements [`std::io::Read`], so it works
+/// with anything that takes a reader; reads end once the child has exited
+/// and the pty has no more output.
+///
+/// ```no_run
+/// use std::io;
+///
+/// use pty::{Pty, PtySize};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let size = PtySize {
+///     cols: 80,
+///     rows: 24,
+///     ..PtySize::default()
+/// };
+/// let pty = Pty::builder("git").arg("status").spawn(size)?;
+/// let mut output = Vec::new();
+/// io::copy(&mut pty.reader()?, &mut output)?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct PtyReader {
-    inner: std::fs::File,
+    output: Arc<SharedOutput>,
+    file: Arc<File>,
+    generation: u64,
+    nonblocking: bool,
 }
 
 impl PtyReader {
+    // In non-blocking mode a read with nothing to return fails with
+    // io::ErrorKind::WouldBlock instead of waiting. After a respawn, reads
+    // continue with the new child's output once the old one has ended.
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, PtyError> {
-        Ok(self.inner.read(buf)?)
+        if self.nonblocking {
+            return self
+                .read_timeout(buf, Duration::ZERO)?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock).into());
+        }
+        loop {
+            let len = read_stream(&self.file, buf)?;
+            if len > 0 || !self.follow_respawn() {
+                return Ok(len);
+            }
+        }
+    }
+
+    pub fn read_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<Option<usize>, PtyError> {
+        loop {
+            match read_timeout(&self.file, buf, timeout)? {
+                Some(0) if self.follow_respawn() => continue,
+                result => return Ok(result),
+            }
+        }
+    }
+
+    // Switches to the output of a newer child, if the Pty has been
+    // respawned since this reader last looked. Only called once the
+    // current output has ended, so none of the old child's output is lost.
+    fn follow_respawn(&mut self) -> bool {
+        let output = Arc::clone(&self.output);
+        let state = output.lock();
+        self.adopt(&state)
+    }
+
+    fn adopt(&mut self, state: &OutputState) -> bool {
+        if state.generation == self.generation {
+            return false;
+        }
+        self.file = Arc::clone(&state.file);
+        self.generation = state.generation;
+        true
+    }
+
+    // Blocks until the Pty is respawned. False if it is dropped instead or
+    // `stop` is set first.
+    fn wait_for_respawn(&mut self, stop: &AtomicBool) -> bool {
+        let output = Arc::clone(&self.output);
+        let mut state = output.lock();
+        loop {
+            if self.adopt(&state) {
+                return true;
+            }
+            if state.closed || stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            state = output
+                .changed
+                .wait_timeout(state, READER_STOP_POLL)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    // Only affects this reader. The handle itself stays blocking, since its
+    // mode would otherwise be shared with every other clone of it.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    // Reads on a background thread and sends the output as chunks. The
+    // channel is bounded: once `options.capacity` chunks are waiting the
+    // thread stops reading, which in turn stalls the child when the pty's
+    // own buffer fills, instead of buffering without limit.
+    pub fn spawn(
+        mut self,
+        options: ReaderOptions,
+    ) -> Result<(Receiver<PtyChunk>, ReaderHandle), PtyError> {
+        let (tx, rx) = mpsc::sync_channel(options.capacity);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("pty-reader".into())
+            .spawn({
+                let stop = Arc::clone(&stop);
+                move || self.pump(&tx, options, &stop)
+            })?;
+        Ok((rx, ReaderHandle { stop, thread }))
+    }
+
+    // Reads the current output directly rather than through read_timeout,
+    // so the switch to a respawned child can be reported.
+    fn pump(&mut self, tx: &SyncSender<PtyChunk>, options: ReaderOptions, stop: &AtomicBool) {
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            let chunk = match read_timeout(&self.file, &mut buf, READER_STOP_POLL) {
+                Ok(None) => continue,
+                Ok(Some(0)) if self.follow_respawn() => PtyChunk::Restarted,
+                Ok(Some(0)) => PtyChunk::Closed,
+                Ok(Some(len)) => PtyChunk::Data(self.coalesce(&mut buf, len, options.max_chunk)),
+                Err(err) => PtyChunk::Failed(err),
+            };
+            let closed = matches!(chunk, PtyChunk::Closed);
+            let failed = matches!(chunk, PtyChunk::Failed(_));
+            if !send_chunk(tx, chunk, stop) || failed {
+                return;
+            }
+            // The Pty may still be respawned after its child has exited.
+            if closed && !(self.wait_for_respawn(stop) && send_chunk(tx, PtyChunk::Restarted, stop))
+            {
+                return;
+            }
+        }
+    }
+
+    // Merges output that is already waiting into the chunk. End of stream
+    // and errors are left for the next read to report.
+    fn coalesce(&mut self, buf: &mut [u8], len: usize, max_chunk: usize) -> Vec<u8> {
+        let mut data = buf[..len].to_vec();
+        while data.len() < max_chunk {
+            let room = (max_chunk - data.len()).min(buf.len());
+            match read_timeout(&self.file, &mut buf[..room], Duration::ZERO) {
+                Ok(Some(len)) if len > 0 => data.extend_from_slice(&buf[..len]),
+                _ => break,
+            }
+        }
+        data
+    }
+}
+
+// Returns false once the receiver is gone or a stop was requested while
+// the channel was full.
+fn send_chunk(tx: &SyncSender<PtyChunk>, mut chunk: PtyChunk, stop: &AtomicBool) -> bool {
+    loop {
+        match tx.try_send(chunk) {
+            Ok(()) => return true,
+            Err(TrySendError::Disconnected(_)) => return false,
+            Err(TrySendError::Full(returned)) => {
+                if stop.load(Ordering::Relaxed) {
+                    return false;
+                }
+                chunk = returned;
+                std::thread::sleep(READER_SEND_RETRY);
+            }
+        }
     }
 }
 
+// Restarted marks where a respawned child's output begins, whether or not
+// the old child's output had been reported Closed. The thread ends after
+// Failed, or after Closed once the Pty is dropped.
+#[derive(Debug)]
+pub enum PtyChunk {
+    Data(Vec<u8>),
+    Closed,
+    Restarted,
+    Failed(PtyError),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ReaderOptions {
+    // Chunks that may wait in the channel before reading pauses.
+    pub capacity: usize,
+    // Reads that find more output already waiting are merged into one
+    // chunk up to this many bytes.
+    pub max_chunk: usize,
+}
+
+impl Default for ReaderOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 128,
+            max_chunk: 64 * 1024,
+        }
+    }
+}
+
+// Dropping the handle leaves the thread running until the output ends or
+// the receiver is dropped.
+pub struct ReaderHandle {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl ReaderHandle {
+    // Stops the thread and waits for it. It notices within
+    // READER_STOP_POLL, even while the child is silent or the channel is
+    // full.
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for PtyReader {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.file.as_raw_handle()
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for PtyReader {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(PtyReader::read(self, buf)?)
+    }
+}
+
+/// Writes to the child's input. It implements [`std::io::Write`], so input
+/// can be streamed in from any reader.
+///
+/// ```no_run
+/// use std::{fs::File, io};
+///
+/// use pty::{Pty, PtySize};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let size = PtySize {
+///     cols: 80,
+///     rows: 24,
+///     ..PtySize::default()
+/// };
+/// let mut pty = Pty::builder("sh").spawn(size)?;
+/// io::copy(&mut File::open("script.sh")?, &mut pty.writer()?)?;
+/// pty.close_input()?;
+/// pty.wait()?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct PtyWriter {
-    inner: std::fs::File,
+    input: Arc<Mutex<Option<File>>>,
 }
 
 impl PtyWriter {
     pub fn write_all(&mut self, buf: &[u8]) -> Result<(), PtyError> {
-        self.inner.write_all(buf)?;
+        for chunk in buf.chunks(WRITE_CHUNK_SIZE) {
+            with_input(&self.input, |input| input.write_all(chunk))?;
+        }
         Ok(())
     }
+
+    // Writes `buf` in chunks of at most `chunk_size` bytes, flushing after
+    // each and sleeping for `pacing` in between so the child's echo keeps
+    // up with a large paste.
+    pub fn write_chunked(
+        &mut self,
+        buf: &[u8],
+        chunk_size: usize,
+        pacing: Option<Duration>,
+    ) -> Result<(), PtyError> {
+        for (index, chunk) in buf.chunks(chunk_size.max(1)).enumerate() {
+            if index > 0 {
+                if let Some(pacing) = pacing {
+                    std::thread::sleep(pacing);
+                }
+            }
+            with_input(&self.input, |input| {
+                input.write_all(chunk)?;
+                input.flush()
+            })?;
+        }
+        Ok(())
+    }
+
+    // Writes from the front of `buf` until `budget` has been used up and
+    // returns how many bytes were consumed, so a caller can feed the rest
+    // on a later turn of its event loop. At least one chunk is attempted,
+    // and a chunk can still block if the child has stopped reading.
+    pub fn write_some(
+        &mut self,
+        buf: &[u8],
+        chunk_size: usize,
+        budget: Duration,
+    ) -> Result<usize, PtyError> {
+        let start = std::time::Instant::now();
+        let mut consumed = 0;
+        while consumed < buf.len() {
+            let end = buf.len().min(consumed + chunk_size.max(1));
+            consumed += with_input(&self.input, |input| {
+                write_partial(input, &buf[consumed..end])
+            })?;
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        with_input(&self.input, |input| input.flush())?;
+        Ok(consumed)
+    }
+
+    // None once the input has been closed. Unlike the reader this can't
+    // implement AsRawHandle, since the handle may go away while the writer
+    // still exists; the handle is only valid until then.
+    #[cfg(windows)]
+    pub fn as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+        use std::os::windows::io::AsRawHandle;
+        let input = self.input.lock().unwrap_or_else(PoisonError::into_inner);
+        input.as_ref().map(File::as_raw_handle)
+    }
+
+    #[cfg(unix)]
+    pub fn as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
+        use std::os::fd::AsRawFd;
+        let input = self.input.lock().unwrap_or_else(PoisonError::into_inner);
+        input.as_ref().map(File::as_raw_fd)
+    }
+}
+
+impl Write for PtyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(WRITE_CHUNK_SIZE);
+        Ok(with_input(&self.input, |input| {
+            write_partial(input, &buf[..len])
+        })?)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        Ok(PtyWriter::write_all(self, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(with_input(&self.input, |input| input.flush())?)
+    }
+}
+
+// For writing from several threads at once. Each write_all holds the input
+// for its whole length, so an escape sequence is never split by another
+// thread's write, whether through this type or a PtyWriter. That also means
+// a long write holds up every other writer until it is done.
+#[derive(Clone)]
+pub struct SharedPtyWriter {
+    input: Arc<Mutex<Option<File>>>,
+}
+
+impl SharedPtyWriter {
+    pub fn write_all(&self, buf: &[u8]) -> Result<(), PtyError> {
+        with_input(&self.input, |input| input.write_all(buf))
+    }
+
+    pub fn flush(&self) -> Result<(), PtyError> {
+        with_input(&self.input, |input| input.flush())
+    }
+}
+
+// Only write_all is contiguous; a plain write may be partial like any
+// other.
+impl Write for &SharedPtyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(with_input(&self.input, |input| write_partial(input, buf))?)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        Ok(SharedPtyWriter::write_all(self, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(SharedPtyWriter::flush(self)?)
+    }
+}
+
+impl Write for SharedPtyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(&mut &*self, buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        Ok(SharedPtyWriter::write_all(self, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(SharedPtyWriter::flush(self)?)
+    }
+}
+
+// A single write that may accept only part of `buf`. Interrupted writes
+// are retried and a write that accepts nothing is an error, so a count of
+// zero is only returned for an empty buffer.
+fn write_partial(input: &mut File, buf: &[u8]) -> io::Result<usize> {
+    loop {
+        match input.write(buf) {
+            Ok(0) if !buf.is_empty() => return Err(io::ErrorKind::WriteZero.into()),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+fn with_input<T>(
+    input: &Mutex<Option<File>>,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> Result<T, PtyError> {
+    let mut input = input.lock().unwrap_or_else(PoisonError::into_inner);
+    let file = input.as_mut().ok_or(PtyError::InputClosed)?;
+    Ok(write(file)?)
+}
+
+fn read_stream(mut file: &File, buf: &mut [u8]) -> Result<usize, PtyError> {
+    match file.read(buf) {
+        // Linux reports EIO on a pty master once the child side has closed,
+        // which is the end of the stream rather than a failure.
+        #[cfg(unix)]
+        Err(err) if err.raw_os_error() == Some(libc::EIO) => Ok(0),
+        result => Ok(result?),
+    }
+}
+
+// None if nothing arrived within `timeout`. End of stream counts as
+// readable, so it is still reported as Some(0).
+fn read_timeout(file: &File, buf: &mut [u8], timeout: Duration) -> Result<Option<usize>, PtyError> {
+    if !platform::wait_readable(file, timeout)? {
+        return Ok(None);
+    }
+    read_stream(file, buf).map(Some)
+}
+
+// A process may exit with 259, the value GetExitCodeProcess reports as
+// STILL_ACTIVE while it runs, so only the process handle's signaled state
+// says whether it has exited. Kept out of the Windows module so the rule is
+// tested on every platform.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn exit_from_wait(signaled: bool, raw_exit_code: u32) -> Option<i32> {
+    signaled.then_some(raw_exit_code as i32)
 }
 
 #[cfg(windows)]
 mod platform {
-    use super::{PtyError, PtySize};
-    use std::ffi::{c_void, OsStr};
+    use super::{
+        exit_from_wait, read_timeout, windows_quote_argv, CommandLine, ConPtyOptions, CtrlEvent,
+        PtyError, PtySize, SpawnOptions, SpawnStage,
+    };
+    use std::ffi::{c_void, OsStr, OsString};
     use std::fs::File;
-    use std::io::{Read, Write};
+    use std::io::{self, Read};
     use std::mem::{size_of, zeroed};
-    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
     use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
-    use windows::core::{Error, PCWSTR, PWSTR};
+    use std::path::PathBuf;
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+    use std::time::{Duration, Instant};
+    use windows::core::{s, w, Error, HRESULT, PCWSTR, PWSTR};
+    use windows::Wdk::System::SystemServices::RtlGetVersion;
+    use windows::Wdk::System::Threading::{NtQueryInformationProcess, ProcessBasicInformation};
     use windows::Win32::Foundation::{
-        CloseHandle, SetHandleInformation, BOOL, HANDLE, HANDLE_FLAG_INHERIT,
+        CloseHandle, DuplicateHandle, BOOL, DUPLICATE_SAME_ACCESS, HANDLE, INVALID_HANDLE_VALUE,
+        WAIT_FAILED, WAIT_OBJECT_0,
     };
-    use windows::Win32::Security::SECURITY_ATTRIBUTES;
     use windows::Win32::System::Console::{
-        ClosePseudoConsole, CreatePseudoConsole, ResizePseudoConsole, COORD, HPCON,
+        AttachConsole, FreeConsole, GenerateConsoleCtrlEvent, GetConsoleOutputCP, SetConsoleCP,
+        SetConsoleCtrlHandler, SetConsoleOutputCP, COORD, CTRL_BREAK_EVENT, CTRL_C_EVENT, HPCON,
+    };
+    use windows::Win32::System::Diagnostics::Debug::ReadProcessMemory;
+    use windows::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
     };
+    use windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress};
     use windows::Win32::System::Pipes::{CreatePipe, PeekNamedPipe};
+    use windows::Win32::System::SystemInformation::OSVERSIONINFOW;
     use windows::Win32::System::Threading::{
-        CreateProcessW, DeleteProcThreadAttributeList, InitializeProcThreadAttributeList,
-        UpdateProcThreadAttribute, CREATE_NO_WINDOW, EXTENDED_STARTUPINFO_PRESENT,
-        LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_INFORMATION, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
+        CreateProcessW, DeleteProcThreadAttributeList, GetCurrentProcess, GetExitCodeProcess,
+        InitializeProcThreadAttributeList, ResumeThread, TerminateProcess,
+        UpdateProcThreadAttribute, WaitForSingleObject, CREATE_NEW_CONSOLE,
+        CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW, CREATE_SUSPENDED, CREATE_UNICODE_ENVIRONMENT,
+        DETACHED_PROCESS, EXTENDED_STARTUPINFO_PRESENT, INFINITE, LPPROC_THREAD_ATTRIBUTE_LIST,
+        PROCESS_CREATION_FLAGS, PROCESS_INFORMATION, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
         STARTF_USESTDHANDLES, STARTUPINFOEXW,
     };
 
+    const READ_POLL_MAX: Duration = Duration::from_millis(10);
+    const UTF8_CODE_PAGE: u32 = 65001;
+    // How long a new child gets to connect to its pseudoconsole before the
+    // code page switch is given up on.
+    const CODE_PAGE_ATTACH_TIMEOUT: Duration = Duration::from_millis(500);
+    // Not all of these are in the SDK headers the bindings are built from.
+    const PSEUDOCONSOLE_INHERIT_CURSOR: u32 = 0x1;
+    const PSEUDOCONSOLE_PASSTHROUGH_MODE: u32 = 0x8;
+    // Offsets of PEB.ProcessParameters and of
+    // RTL_USER_PROCESS_PARAMETERS.CurrentDirectory, which the SDK headers
+    // leave as reserved fields. They have not changed since NT.
+    const PEB_PROCESS_PARAMETERS: usize = 4 * size_of::<usize>();
+    const PARAMETERS_CURRENT_DIRECTORY: usize = 16 + 5 * size_of::<usize>();
+
+    // PROCESS_BASIC_INFORMATION and UNICODE_STRING, with the pointers into
+    // the other process kept as plain addresses.
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    struct BasicInformation {
+        exit_status: i32,
+        peb_base_address: usize,
+        affinity_mask: usize,
+        base_priority: i32,
+        unique_process_id: usize,
+        inherited_from_unique_process_id: usize,
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Default)]
+    struct RemoteUnicodeString {
+        length: u16,
+        maximum_length: u16,
+        buffer: usize,
+    }
+
     pub(super) struct PtyInner {
-        hpc: HPCON,
-        input_write: File,
+        // None once the pseudoconsole has been closed by signal_close.
+        hpc: Option<HPCON>,
+        conpty: &'static ConPtyApi,
         output_read: File,
-        conpty_input: HANDLE,
-        conpty_output: HANDLE,
         process_handle: HANDLE,
         thread_handle: HANDLE,
+        process_id: u32,
+        // Closing it kills every process still in it.
+        job: Option<HANDLE>,
+        // Spawned suspended and not resumed yet.
+        suspended: bool,
+        force_utf8: bool,
+        // Filled in by the thread switching it, once it has.
+        code_page: Arc<OnceLock<u32>>,
     }
 
     impl PtyInner {
-        pub(super) fn spawn(command: &str, size: PtySize) -> Result<Self, PtyError> {
-            let (input_read, input_write) = create_pipe()?;
-            let (output_read, output_write) = create_pipe()?;
-
-            set_handle_inherit(input_read, true)?;
-            set_handle_inherit(output_write, true)?;
-            set_handle_inherit(input_write, false)?;
-            set_handle_inherit(output_read, false)?;
+        pub(super) fn spawn(
+            command: CommandLine<'_>,
+            options: SpawnOptions,
+            size: PtySize,
+        ) -> Result<(Self, File), PtyError> {
+            let conpty = conpty_api().ok_or_else(|| PtyError::ConPtyUnavailable {
+                build: windows_build().unwrap_or(0),
+            })?;
+            let flags = pseudoconsole_flags(options.conpty)?;
+            let creation_flags = creation_flags(options)?;
+            let (command, env, current_dir) = match command {
+                CommandLine::Raw(command) => (command.to_string(), None, None),
+                CommandLine::Argv {
+                    program,
+                    args,
+                    env,
+                    current_dir,
+                } => (
+                    windows_quote_argv(
+                        program,
+                        &args.iter().map(String::as_str).collect::<Vec<_>>(),
+                    ),
+                    env.map(environment_block),
+                    current_dir.map(|dir| to_wide(dir.as_os_str())),
+                ),
+            };
+            let (input_read, input_write) =
+                create_pipe().map_err(spawn_error(SpawnStage::CreatePipe, None))?;
+            let (output_read, output_write) =
+                create_pipe().map_err(spawn_error(SpawnStage::CreatePipe, None))?;
 
             let input_read_guard = HandleGuard::new(input_read);
             let input_write_guard = HandleGuard::new(input_write);
             let output_read_guard = HandleGuard::new(output_read);
             let output_write_guard = HandleGuard::new(output_write);
 
-            let hpc = unsafe {
-                CreatePseudoConsole(
+            let hpc = conpty
+                .create(
                     COORD {
                         X: size.cols as i16,
                         Y: size.rows as i16,
                     },
                     input_read_guard.handle,
                     output_write_guard.handle,
-                    0,
-                )?
-            };
-            let hpc_guard = PseudoConsoleGuard::new(hpc);
-
+                    flags,
+                )
+                .map_err(spawn_error(SpawnStage::CreatePseudoConsole, None))?;
+            let hpc_guard = PseudoConsoleGuard::new(hpc, conpty);
+
+            // The pseudoconsole keeps its own duplicates of its ends of the
+            // pipes, so ours are closed right away. That way the output pipe
+            // breaks, and blocked reads see end of stream, as soon as the
+            // pseudoconsole is closed.
+            drop(input_read_guard);
+            drop(output_write_guard);
             let input_write_handle = input_write_guard.into_inner();
             let output_read_handle = output_read_guard.into_inner();
-            let conpty_input = input_read_guard.into_inner();
-            let conpty_output = output_write_guard.into_inner();
 
             let input_write = unsafe { File::from_raw_handle(raw_handle(input_write_handle)) };
             let output_read = unsafe { File::from_raw_handle(raw_handle(output_read_handle)) };
@@ -178,14 +1388,17 @@ mod platform {
                 );
             }
             if attr_list_size == 0 {
-                return Err(PtyError::Windows(Error::from_win32()));
+                return Err(spawn_error(SpawnStage::AttributeList, None)(
+                    Error::from_win32(),
+                ));
             }
 
             let mut attr_list_buffer = vec![0u8; attr_list_size];
             let attr_list_ptr =
                 LPPROC_THREAD_ATTRIBUTE_LIST(attr_list_buffer.as_mut_ptr() as *mut c_void);
             unsafe {
-                InitializeProcThreadAttributeList(attr_list_ptr, 1, 0, &mut attr_list_size)?;
+                InitializeProcThreadAttributeList(attr_list_ptr, 1, 0, &mut attr_list_size)
+                    .map_err(spawn_error(SpawnStage::AttributeList, None))?;
             }
             let attr_list_guard = AttrListGuard::new(attr_list_ptr, attr_list_buffer);
 
@@ -199,21 +1412,33 @@ mod platform {
                     size_of::<HPCON>(),
                     None,
                     None,
-                )?;
+                )
+                .map_err(spawn_error(SpawnStage::AttributeList, None))?;
             }
 
             let mut startup_info: STARTUPINFOEXW = unsafe { zeroed() };
             startup_info.StartupInfo.cb = size_of::<STARTUPINFOEXW>() as u32;
             startup_info.lpAttributeList = attr_list_guard.ptr;
+            // The child gets its console handles from the pseudoconsole.
+            // Invalid std handles stop it from picking up whatever this
+            // process's own std handles are redirected to.
             startup_info.StartupInfo.dwFlags |= STARTF_USESTDHANDLES;
-            startup_info.StartupInfo.hStdInput = conpty_input;
-            startup_info.StartupInfo.hStdOutput = conpty_output;
-            startup_info.StartupInfo.hStdError = conpty_output;
+            startup_info.StartupInfo.hStdInput = INVALID_HANDLE_VALUE;
+            startup_info.StartupInfo.hStdOutput = INVALID_HANDLE_VALUE;
+            startup_info.StartupInfo.hStdError = INVALID_HANDLE_VALUE;
 
             let mut proc_info: PROCESS_INFORMATION = unsafe { zeroed() };
-            let mut command_line = wide_command_line(command);
+            let mut command_line = to_wide(OsStr::new(&command));
 
-            let inherit_handles = true;
+            let job = match options.kill_process_tree {
+                true => Some(HandleGuard::new(
+                    create_kill_on_close_job().map_err(spawn_error(SpawnStage::CreateJob, None))?,
+                )),
+                false => None,
+            };
+            // Always created suspended so it is in the job before it can
+            // start anything else.
+            let inherit_handles = false;
             unsafe {
                 CreateProcessW(
                     PCWSTR::null(),
@@ -221,93 +1446,570 @@ mod platform {
                     None,
                     None,
                     inherit_handles,
-                    EXTENDED_STARTUPINFO_PRESENT | CREATE_NO_WINDOW,
-                    None,
-                    PCWSTR::null(),
+                    creation_flags | CREATE_SUSPENDED,
+                    env.as_ref().map(|block| block.as_ptr() as *const c_void),
+                    current_dir
+                        .as_ref()
+                        .map_or(PCWSTR::null(), |dir| PCWSTR(dir.as_ptr())),
                     &startup_info.StartupInfo,
                     &mut proc_info,
-                )?;
+                )
+                .map_err(spawn_error(SpawnStage::CreateProcess, Some(&command)))?;
             }
 
             let process_handle = proc_info.hProcess;
             let thread_handle = proc_info.hThread;
+            // Before Windows 8 a process can only be in one job, so this
+            // fails if RING0 itself was started inside one (by some IDEs and
+            // launchers). The tree is then simply not tracked.
+            let job = job.filter(|job| unsafe {
+                AssignProcessToJobObject(job.handle, process_handle).is_ok()
+            });
+            let suspended = creation_flags.contains(CREATE_SUSPENDED);
+            if !suspended {
+                unsafe {
+                    ResumeThread(thread_handle);
+                }
+            }
+            let code_page = Arc::new(OnceLock::new());
+            if options.force_utf8 && !suspended {
+                switch_to_utf8(proc_info.dwProcessId, &code_page);
+            }
 
-            Ok(Self {
-                hpc: hpc_guard.into_inner(),
-                input_write,
+            let inner = Self {
+                hpc: Some(hpc_guard.into_inner()),
+                conpty,
                 output_read,
-                conpty_input,
-                conpty_output,
                 process_handle,
                 thread_handle,
-            })
+                process_id: proc_info.dwProcessId,
+                job: job.map(HandleGuard::into_inner),
+                suspended,
+                force_utf8: options.force_utf8,
+                code_page,
+            };
+            Ok((inner, input_write))
         }
 
         pub(super) fn read(&mut self, buf: &mut [u8]) -> Result<usize, PtyError> {
             Ok(self.output_read.read(buf)?)
         }
 
-        pub(super) fn write(&mut self, buf: &[u8]) -> Result<usize, PtyError> {
-            Ok(self.input_write.write(buf)?)
+        pub(super) fn read_timeout(
+            &mut self,
+            buf: &mut [u8],
+            timeout: Duration,
+        ) -> Result<Option<usize>, PtyError> {
+            read_timeout(&self.output_read, buf, timeout)
         }
 
         pub(super) fn resize(&mut self, size: PtySize) -> Result<(), PtyError> {
+            let Some(hpc) = self.hpc else {
+                return Ok(());
+            };
+            self.conpty.resize(
+                hpc,
+                COORD {
+                    X: size.cols as i16,
+                    Y: size.rows as i16,
+                },
+            )
+        }
+
+        // Closing the pseudoconsole sends the attached processes
+        // CTRL_CLOSE_EVENT, the same as closing a console window.
+        pub(super) fn signal_close(&mut self) -> Result<(), PtyError> {
+            if let Some(hpc) = self.hpc.take() {
+                unsafe {
+                    self.conpty.close(hpc);
+                }
+            }
+            Ok(())
+        }
+
+        pub(super) fn child_pid(&self) -> u32 {
+            self.process_id
+        }
+
+        // Read out of the child's process parameters, where the process
+        // keeps it for itself.
+        pub(super) fn current_dir(&self) -> Result<PathBuf, PtyError> {
+            let mut basic = BasicInformation::default();
             unsafe {
-                ResizePseudoConsole(
-                    self.hpc,
-                    COORD {
-                        X: size.cols as i16,
-                        Y: size.rows as i16,
-                    },
+                NtQueryInformationProcess(
+                    self.process_handle,
+                    ProcessBasicInformation,
+                    (&mut basic as *mut BasicInformation).cast(),
+                    size_of::<BasicInformation>() as u32,
+                    &mut 0,
+                )
+                .ok()?;
+            }
+            let parameters: usize = read_process_memory(
+                self.process_handle,
+                basic.peb_base_address + PEB_PROCESS_PARAMETERS,
+            )?;
+            let dir: RemoteUnicodeString = read_process_memory(
+                self.process_handle,
+                parameters + PARAMETERS_CURRENT_DIRECTORY,
+            )?;
+            let mut path = vec![0u16; usize::from(dir.length) / 2];
+            unsafe {
+                ReadProcessMemory(
+                    self.process_handle,
+                    dir.buffer as *const c_void,
+                    path.as_mut_ptr().cast(),
+                    path.len() * 2,
+                    None,
+                )?;
+            }
+            Ok(PathBuf::from(OsString::from_wide(&path)))
+        }
+
+        pub(super) fn process_handle(&self) -> RawHandle {
+            raw_handle(self.process_handle)
+        }
+
+        pub(super) fn pseudoconsole(&self) -> Option<HPCON> {
+            self.hpc
+        }
+
+        // Control events only reach processes attached to the same console,
+        // so this process briefly attaches to the pseudoconsole and signals
+        // every process on it, itself included; its own handler ignores the
+        // event. That detaches the caller from any console of its own, and
+        // attaching is process-wide, hence the lock. The child is not put in
+        // a new process group for this: that would disable its Ctrl+C
+        // handling, 0x03 input included.
+        pub(super) fn send_ctrl_event(&self, event: CtrlEvent) -> Result<(), PtyError> {
+            let kind = match event {
+                CtrlEvent::C => CTRL_C_EVENT,
+                CtrlEvent::Break => CTRL_BREAK_EVENT,
+            };
+            with_console(self.process_id, || unsafe {
+                GenerateConsoleCtrlEvent(kind, 0)
+            })
+        }
+
+        pub(super) fn code_page(&self) -> Option<u32> {
+            self.code_page.get().copied()
+        }
+
+        pub(super) fn resume(&mut self) -> Result<(), PtyError> {
+            if !self.suspended {
+                return Ok(());
+            }
+            if unsafe { ResumeThread(self.thread_handle) } == u32::MAX {
+                return Err(Error::from_win32().into());
+            }
+            self.suspended = false;
+            if self.force_utf8 {
+                switch_to_utf8(self.process_id, &self.code_page);
+            }
+            Ok(())
+        }
+
+        // With a job, descendants are killed even if the child itself has
+        // already exited.
+        pub(super) fn terminate(&mut self) -> Result<(), PtyError> {
+            self.signal_close()?;
+            if let Some(job) = self.job {
+                unsafe {
+                    TerminateJobObject(job, 1)?;
+                }
+                return Ok(());
+            }
+            if self.exit_code()?.is_some() {
+                return Ok(());
+            }
+            unsafe {
+                TerminateProcess(self.process_handle, 1)?;
+            }
+            Ok(())
+        }
+
+        pub(super) fn clone_reader(&self) -> Result<File, PtyError> {
+            Ok(self.output_read.try_clone()?)
+        }
+
+        pub(super) fn is_running(&self) -> Result<bool, PtyError> {
+            Ok(self.exit_code()?.is_none())
+        }
+
+        // A process may legitimately exit with STILL_ACTIVE (259), so the
+        // handle's signaled state decides whether it has exited and
+        // GetExitCodeProcess is only trusted after that.
+        pub(super) fn exit_code(&self) -> Result<Option<i32>, PtyError> {
+            wait_for_exit(self.process_handle, 0)
+        }
+
+        pub(super) fn wait(&self) -> Result<i32, PtyError> {
+            wait_process(self.process_handle)
+        }
+
+        pub(super) fn wait_timeout(&self, timeout: Duration) -> Result<Option<i32>, PtyError> {
+            // INFINITE itself is u32::MAX, so longer timeouts stop just short of it.
+            let timeout_ms = timeout.as_millis().min(u128::from(INFINITE - 1)) as u32;
+            wait_for_exit(self.process_handle, timeout_ms)
+        }
+
+        // The waiting thread owns its own duplicate of the process handle,
+        // so it is unaffected by the Pty being dropped.
+        pub(super) fn exit_notifier(&self) -> Result<Receiver<i32>, PtyError> {
+            let mut process = HANDLE::default();
+            unsafe {
+                let current = GetCurrentProcess();
+                DuplicateHandle(
+                    current,
+                    self.process_handle,
+                    current,
+                    &mut process,
+                    0,
+                    false,
+                    DUPLICATE_SAME_ACCESS,
                 )?;
             }
-            Ok(())
+            let process = HandleGuard::new(process);
+            let (tx, rx) = mpsc::channel();
+            std::thread::Builder::new()
+                .name("pty-exit".into())
+                .spawn(move || {
+                    // Moves the whole guard in; capturing just the handle
+                    // field would close it when this function returns.
+                    let process = process;
+                    if let Ok(code) = wait_process(process.handle) {
+                        let _ = tx.send(code);
+                    }
+                })?;
+            Ok(rx)
+        }
+
+        pub(super) fn bytes_available(&self) -> Result<u32, PtyError> {
+            Ok(pipe_bytes_available(&self.output_read)?)
+        }
+    }
+
+    // Dropping the only handle to the write end closes the pipe, which
+    // the pseudoconsole passes on to the child as end of input.
+    pub(super) fn end_input(_input: &mut File) -> Result<(), PtyError> {
+        Ok(())
+    }
+
+    // Anonymous pipes support neither overlapped I/O nor a waitable
+    // "readable" state, so this polls PeekNamedPipe with a backoff capped at
+    // READ_POLL_MAX. Data arriving mid-sleep can be seen up to that late. A
+    // broken pipe counts as readable; the read then reports end of stream.
+    pub(super) fn wait_readable(pipe: &File, timeout: Duration) -> Result<bool, PtyError> {
+        let deadline = Instant::now() + timeout;
+        let mut interval = Duration::from_millis(1);
+        loop {
+            match pipe_bytes_available(pipe) {
+                Ok(0) => {}
+                Ok(_) | Err(_) => return Ok(true),
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+            std::thread::sleep(interval.min(remaining));
+            interval = (interval * 2).min(READ_POLL_MAX);
+        }
+    }
+
+    fn pipe_bytes_available(pipe: &File) -> Result<u32, Error> {
+        let mut available = 0u32;
+        unsafe {
+            PeekNamedPipe(
+                HANDLE(pipe.as_raw_handle() as isize),
+                None,
+                0,
+                None,
+                Some(&mut available),
+                None,
+            )?;
+        }
+        Ok(available)
+    }
+
+    impl Drop for PtyInner {
+        // Closing the pseudoconsole breaks the output pipe, which ends any
+        // read still blocked on a PtyReader with Ok(0).
+        fn drop(&mut self) {
+            unsafe {
+                // Without a job nothing else would ever end it.
+                if self.suspended {
+                    let _ = TerminateProcess(self.process_handle, 1);
+                }
+                if let Some(hpc) = self.hpc {
+                    self.conpty.close(hpc);
+                }
+                close_handle(self.process_handle);
+                close_handle(self.thread_handle);
+                if let Some(job) = self.job {
+                    close_handle(job);
+                }
+            }
+        }
+    }
+
+    pub(super) fn is_supported() -> bool {
+        conpty_api().is_some()
+    }
+
+    type FarProc = unsafe extern "system" fn() -> isize;
+    type CreatePseudoConsoleFn =
+        unsafe extern "system" fn(COORD, HANDLE, HANDLE, u32, *mut HPCON) -> HRESULT;
+    type ResizePseudoConsoleFn = unsafe extern "system" fn(HPCON, COORD) -> HRESULT;
+    type ClosePseudoConsoleFn = unsafe extern "system" fn(HPCON);
+
+    // The ConPTY functions are looked up at run time rather than imported,
+    // so the binary still loads on Windows builds that predate them.
+    struct ConPtyApi {
+        create: CreatePseudoConsoleFn,
+        resize: ResizePseudoConsoleFn,
+        close: ClosePseudoConsoleFn,
+    }
+
+    impl ConPtyApi {
+        fn create(
+            &self,
+            size: COORD,
+            input: HANDLE,
+            output: HANDLE,
+            flags: u32,
+        ) -> windows::core::Result<HPCON> {
+            let mut hpc = HPCON::default();
+            unsafe {
+                (self.create)(size, input, output, flags, &mut hpc).ok()?;
+            }
+            Ok(hpc)
+        }
+
+        fn resize(&self, hpc: HPCON, size: COORD) -> Result<(), PtyError> {
+            unsafe {
+                (self.resize)(hpc, size).ok()?;
+            }
+            Ok(())
+        }
+
+        unsafe fn close(&self, hpc: HPCON) {
+            (self.close)(hpc);
+        }
+    }
+
+    fn conpty_api() -> Option<&'static ConPtyApi> {
+        static API: OnceLock<Option<ConPtyApi>> = OnceLock::new();
+        API.get_or_init(|| unsafe {
+            let kernel32 = GetModuleHandleW(w!("kernel32.dll")).ok()?;
+            let create = GetProcAddress(kernel32, s!("CreatePseudoConsole"))?;
+            let resize = GetProcAddress(kernel32, s!("ResizePseudoConsole"))?;
+            let close = GetProcAddress(kernel32, s!("ClosePseudoConsole"))?;
+            Some(ConPtyApi {
+                create: std::mem::transmute::<FarProc, CreatePseudoConsoleFn>(create),
+                resize: std::mem::transmute::<FarProc, ResizePseudoConsoleFn>(resize),
+                close: std::mem::transmute::<FarProc, ClosePseudoConsoleFn>(close),
+            })
+        })
+        .as_ref()
+    }
+
+    fn creation_flags(options: SpawnOptions) -> Result<PROCESS_CREATION_FLAGS, PtyError> {
+        let extra = PROCESS_CREATION_FLAGS(options.creation_flags);
+        for (flag, reason) in [
+            (
+                DETACHED_PROCESS,
+                "DETACHED_PROCESS leaves the child without a console",
+            ),
+            (
+                CREATE_NEW_CONSOLE,
+                "CREATE_NEW_CONSOLE gives the child a console of its own",
+            ),
+        ] {
+            if extra.contains(flag) {
+                return Err(PtyError::InvalidCreationFlags {
+                    flags: options.creation_flags,
+                    reason,
+                });
+            }
+        }
+        let mut flags = EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT | extra;
+        if options.no_window {
+            flags |= CREATE_NO_WINDOW;
+        }
+        if options.new_process_group {
+            flags |= CREATE_NEW_PROCESS_GROUP;
+        }
+        if options.suspended {
+            flags |= CREATE_SUSPENDED;
+        }
+        Ok(flags)
+    }
+
+    // Older conhost builds silently ignore flags they don't know, so
+    // support is decided by build number: ConPTY itself and
+    // INHERIT_CURSOR arrived in Windows 10 1809, passthrough in Windows 11
+    // 24H2.
+    fn pseudoconsole_flags(options: ConPtyOptions) -> Result<u32, PtyError> {
+        let requested = [
+            (
+                options.inherit_cursor,
+                "inherit_cursor",
+                PSEUDOCONSOLE_INHERIT_CURSOR,
+                17763,
+            ),
+            (
+                options.passthrough,
+                "passthrough",
+                PSEUDOCONSOLE_PASSTHROUGH_MODE,
+                26100,
+            ),
+        ];
+        let mut flags = 0;
+        for (enabled, flag, value, required) in requested {
+            if !enabled {
+                continue;
+            }
+            let build = windows_build()?;
+            if build < required {
+                return Err(PtyError::UnsupportedConPtyFlag {
+                    flag,
+                    required,
+                    build,
+                });
+            }
+            flags |= value;
+        }
+        Ok(flags)
+    }
+
+    // GetVersionEx reports whatever the application manifest claims to
+    // support, so the build number comes from ntdll instead.
+    fn windows_build() -> Result<u32, PtyError> {
+        let mut info = OSVERSIONINFOW {
+            dwOSVersionInfoSize: size_of::<OSVERSIONINFOW>() as u32,
+            ..Default::default()
+        };
+        unsafe {
+            RtlGetVersion(&mut info).ok()?;
+        }
+        Ok(info.dwBuildNumber)
+    }
+
+    // This process has no console of its own, so it acts on the child's
+    // pseudoconsole by attaching to it for the duration of `action`. While
+    // attached it also receives that console's control events, which the
+    // handler installed here ignores.
+    fn with_console<T>(
+        process_id: u32,
+        action: impl FnOnce() -> windows::core::Result<T>,
+    ) -> Result<T, PtyError> {
+        // Holds whether the handler has been installed yet.
+        static CONSOLE_LOCK: Mutex<bool> = Mutex::new(false);
+        let mut handler_installed = CONSOLE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        unsafe {
+            if !*handler_installed {
+                SetConsoleCtrlHandler(Some(ignore_ctrl_event), true)?;
+                *handler_installed = true;
+            }
+            let _ = FreeConsole();
+            AttachConsole(process_id)?;
+            let result = action();
+            let _ = FreeConsole();
+            Ok(result?)
         }
+    }
 
-        pub(super) fn clone_reader(&self) -> Result<File, PtyError> {
-            Ok(self.output_read.try_clone()?)
+    // The code pages belong to the pseudoconsole rather than the child, so
+    // they can be set from outside. Attaching fails until the child has
+    // connected to the pseudoconsole, which it does as it starts up.
+    fn set_utf8_code_page(process_id: u32) -> Result<u32, PtyError> {
+        let deadline = Instant::now() + CODE_PAGE_ATTACH_TIMEOUT;
+        loop {
+            let result = with_console(process_id, || unsafe {
+                SetConsoleCP(UTF8_CODE_PAGE)?;
+                SetConsoleOutputCP(UTF8_CODE_PAGE)?;
+                Ok(GetConsoleOutputCP())
+            });
+            match result {
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                result => return result,
+            }
         }
+    }
+
+    // The child can take a while to connect, and spawn is usually called
+    // from a UI thread, so the switch is made from a thread of its own. The
+    // code page is simply left alone if that thread can't be started.
+    fn switch_to_utf8(process_id: u32, code_page: &Arc<OnceLock<u32>>) {
+        let code_page = Arc::clone(code_page);
+        let _ = std::thread::Builder::new()
+            .name("pty-code-page".into())
+            .spawn(move || {
+                if let Ok(page) = set_utf8_code_page(process_id) {
+                    let _ = code_page.set(page);
+                }
+            });
+    }
+
+    unsafe extern "system" fn ignore_ctrl_event(_event: u32) -> BOOL {
+        BOOL(1)
+    }
 
-        pub(super) fn clone_writer(&self) -> Result<File, PtyError> {
-            Ok(self.input_write.try_clone()?)
+    fn create_kill_on_close_job() -> windows::core::Result<HANDLE> {
+        let job = HandleGuard::new(unsafe { CreateJobObjectW(None, PCWSTR::null())? });
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        unsafe {
+            SetInformationJobObject(
+                job.handle,
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const c_void,
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )?;
         }
+        Ok(job.into_inner())
+    }
 
-        pub(super) fn is_running(&self) -> Result<bool, PtyError> {
-            use windows::Win32::System::Threading::GetExitCodeProcess;
-            const STILL_ACTIVE: u32 = 259;
-            let mut exit_code = 0u32;
-            unsafe {
-                GetExitCodeProcess(self.process_handle, &mut exit_code)?;
+    fn wait_process(process: HANDLE) -> Result<i32, PtyError> {
+        loop {
+            if let Some(code) = wait_for_exit(process, INFINITE)? {
+                return Ok(code);
             }
-            Ok(exit_code == STILL_ACTIVE)
         }
+    }
 
-        pub(super) fn bytes_available(&self) -> Result<u32, PtyError> {
-            let mut available = 0u32;
-            unsafe {
-                PeekNamedPipe(
-                    HANDLE(self.output_read.as_raw_handle() as isize),
-                    None,
-                    0,
-                    None,
-                    Some(&mut available),
-                    None,
-                )?;
-            }
-            Ok(available)
+    fn read_process_memory<T: Copy + Default>(
+        process: HANDLE,
+        address: usize,
+    ) -> Result<T, PtyError> {
+        let mut value = T::default();
+        unsafe {
+            ReadProcessMemory(
+                process,
+                address as *const c_void,
+                (&mut value as *mut T).cast(),
+                size_of::<T>(),
+                None,
+            )?;
         }
+        Ok(value)
     }
 
-    impl Drop for PtyInner {
-        fn drop(&mut self) {
+    fn wait_for_exit(process: HANDLE, timeout_ms: u32) -> Result<Option<i32>, PtyError> {
+        let result = unsafe { WaitForSingleObject(process, timeout_ms) };
+        if result == WAIT_FAILED {
+            return Err(Error::from_win32().into());
+        }
+        let signaled = result == WAIT_OBJECT_0;
+        let mut exit_code = 0u32;
+        if signaled {
             unsafe {
-                ClosePseudoConsole(self.hpc);
-                close_handle(self.conpty_input);
-                close_handle(self.conpty_output);
-                close_handle(self.process_handle);
-                close_handle(self.thread_handle);
+                GetExitCodeProcess(process, &mut exit_code)?;
             }
         }
+        Ok(exit_from_wait(signaled, exit_code))
     }
 
     struct HandleGuard {
@@ -334,11 +2036,12 @@ mod platform {
 
     struct PseudoConsoleGuard {
         handle: HPCON,
+        conpty: &'static ConPtyApi,
     }
 
     impl PseudoConsoleGuard {
-        fn new(handle: HPCON) -> Self {
-            Self { handle }
+        fn new(handle: HPCON, conpty: &'static ConPtyApi) -> Self {
+            Self { handle, conpty }
         }
 
         fn into_inner(self) -> HPCON {
@@ -351,7 +2054,7 @@ mod platform {
     impl Drop for PseudoConsoleGuard {
         fn drop(&mut self) {
             unsafe {
-                ClosePseudoConsole(self.handle);
+                self.conpty.close(self.handle);
             }
         }
     }
@@ -378,31 +2081,29 @@ mod platform {
         }
     }
 
-    fn create_pipe() -> Result<(HANDLE, HANDLE), PtyError> {
+    fn create_pipe() -> windows::core::Result<(HANDLE, HANDLE)> {
         let mut read_pipe = HANDLE::default();
         let mut write_pipe = HANDLE::default();
-        let sa = SECURITY_ATTRIBUTES {
-            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
-            lpSecurityDescriptor: std::ptr::null_mut(),
-            bInheritHandle: BOOL(1),
-        };
         unsafe {
-            CreatePipe(&mut read_pipe, &mut write_pipe, Some(&sa), 0)?;
+            CreatePipe(&mut read_pipe, &mut write_pipe, None, 0)?;
         }
         Ok((read_pipe, write_pipe))
     }
 
-    fn set_handle_inherit(handle: HANDLE, inherit: bool) -> Result<(), PtyError> {
-        let mask = HANDLE_FLAG_INHERIT.0;
-        let value = if inherit {
-            HANDLE_FLAG_INHERIT
-        } else {
-            windows::Win32::Foundation::HANDLE_FLAGS(0)
-        };
-        unsafe {
-            SetHandleInformation(handle, mask, value)?;
+    // Win32 failures arrive as HRESULTs. Unwrapped back to the plain error
+    // code, io::Error prints the usual message and reports the right kind.
+    fn spawn_error(
+        stage: SpawnStage,
+        command: Option<&str>,
+    ) -> impl FnOnce(Error) -> PtyError + '_ {
+        move |err| {
+            let code = err.code().0 as u32;
+            let source = match code & 0xFFFF_0000 {
+                0x8007_0000 => io::Error::from_raw_os_error((code & 0xFFFF) as i32),
+                _ => io::Error::other(err),
+            };
+            PtyError::spawn(stage, command, source)
         }
-        Ok(())
     }
 
     fn close_handle(handle: HANDLE) {
@@ -418,23 +2119,622 @@ mod platform {
         handle.0 as RawHandle
     }
 
-    fn wide_command_line(command: &str) -> Vec<u16> {
-        OsStr::new(command)
-            .encode_wide()
-            .chain(std::iter::once(0))
-            .collect()
+    // NUL-separated NAME=value strings sorted by name, ignoring case, with
+    // an extra NUL at the end as CreateProcessW expects.
+    fn environment_block(vars: &[(OsString, OsString)]) -> Vec<u16> {
+        let mut vars: Vec<_> = vars.iter().collect();
+        vars.sort_by_cached_key(|(key, _)| key.to_string_lossy().to_uppercase());
+        let mut block = Vec::new();
+        for (key, value) in vars {
+            block.extend(key.encode_wide());
+            block.push('=' as u16);
+            block.extend(value.encode_wide());
+            block.push(0);
+        }
+        if block.is_empty() {
+            block.push(0);
+        }
+        block.push(0);
+        block
+    }
+
+    fn to_wide(value: &OsStr) -> Vec<u16> {
+        value.encode_wide().chain(std::iter::once(0)).collect()
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::{
+        read_stream, read_timeout, CommandLine, CtrlEvent, PtyError, PtySize, SpawnOptions,
+        SpawnStage,
+    };
+    use std::ffi::{CStr, CString};
+    use std::fs::{self, File};
+    use std::io::{self, Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::ptr;
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    const MAX_WAIT_INTERVAL: Duration = Duration::from_millis(50);
+    // How long a dropped child gets to act on SIGHUP before it is killed.
+    const HANGUP_GRACE: Duration = Duration::from_secs(2);
+    // What a forked child reports through its error pipe before giving up.
+    const REPORT_CHDIR: i32 = 0;
+    const REPORT_EXEC: i32 = 1;
+
+    pub(super) struct PtyInner {
+        master: File,
+        // Stopped before exec and not resumed yet.
+        suspended: bool,
+        child: Arc<Child>,
+        kill_process_tree: bool,
+    }
+
+    impl PtyInner {
+        pub(super) fn spawn(
+            command: CommandLine<'_>,
+            options: SpawnOptions,
+            size: PtySize,
+        ) -> Result<(Self, File), PtyError> {
+            // Everything the child needs is allocated before forking; only
+            // async-signal-safe calls are made between fork and exec.
+            let (args, env, current_dir) = match command {
+                CommandLine::Raw(command) => (command.split_whitespace().collect(), None, None),
+                CommandLine::Argv {
+                    program,
+                    args,
+                    env,
+                    current_dir,
+                } => (
+                    std::iter::once(program)
+                        .chain(args.iter().map(String::as_str))
+                        .collect::<Vec<_>>(),
+                    env,
+                    current_dir,
+                ),
+            };
+            let args = args
+                .into_iter()
+                .map(CString::new)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let env = env
+                .map(|vars| {
+                    vars.iter()
+                        .map(|(key, value)| {
+                            let mut pair = key.as_bytes().to_vec();
+                            pair.push(b'=');
+                            pair.extend_from_slice(value.as_bytes());
+                            CString::new(pair)
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let current_dir = current_dir
+                .map(|dir| CString::new(dir.as_os_str().as_bytes()))
+                .transpose()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let mut envp: Option<Vec<*mut libc::c_char>> = env.as_ref().map(|env| {
+                env.iter()
+                    .map(|pair| pair.as_ptr() as *mut libc::c_char)
+                    .chain(std::iter::once(ptr::null_mut()))
+                    .collect()
+            });
+            if args.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty command").into());
+            }
+            let mut argv: Vec<*const libc::c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+            argv.push(ptr::null());
+
+            let mut master: RawFd = -1;
+            let mut slave: RawFd = -1;
+            let winsize = winsize(size);
+            check(unsafe {
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    ptr::null_mut(),
+                    ptr::null(),
+                    &winsize,
+                )
+            })
+            .map_err(at_stage(SpawnStage::OpenPty))?;
+            let master = unsafe { File::from_raw_fd(master) };
+            let slave = unsafe { File::from_raw_fd(slave) };
+            set_cloexec(master.as_raw_fd()).map_err(at_stage(SpawnStage::OpenPty))?;
+            set_cloexec(slave.as_raw_fd()).map_err(at_stage(SpawnStage::OpenPty))?;
+
+            // A suspended child stops before it gets as far as exec, so it
+            // can't report there; a failed exec then only shows as exit
+            // code 127 after resuming.
+            let error_pipe = match options.suspended {
+                true => None,
+                false => Some(error_pipe().map_err(at_stage(SpawnStage::CreatePipe))?),
+            };
+            let pid = check(unsafe { libc::fork() }).map_err(at_stage(SpawnStage::Fork))?;
+            if pid == 0 {
+                unsafe {
+                    exec_child(
+                        slave.as_raw_fd(),
+                        &argv,
+                        envp.as_mut(),
+                        current_dir.as_deref(),
+                        options.suspended,
+                        error_pipe
+                            .as_ref()
+                            .map_or(-1, |(_, write)| write.as_raw_fd()),
+                    )
+                }
+            }
+
+            let child = Arc::new(Child {
+                pid,
+                exit_code: Mutex::new(None),
+            });
+            if let Some((mut read, write)) = error_pipe {
+                // Ours has to close for the read to see end of file once
+                // exec has closed the child's copy.
+                drop(write);
+                let mut report = [0u8; 8];
+                if read.read_exact(&mut report).is_ok() {
+                    let _ = child.wait();
+                    let [s0, s1, s2, s3, e0, e1, e2, e3] = report;
+                    let stage = match i32::from_ne_bytes([s0, s1, s2, s3]) {
+                        REPORT_CHDIR => SpawnStage::ChangeDirectory,
+                        _ => SpawnStage::Exec,
+                    };
+                    let errno = i32::from_ne_bytes([e0, e1, e2, e3]);
+                    let command = args
+                        .iter()
+                        .map(|arg| arg.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    return Err(PtyError::spawn(
+                        stage,
+                        Some(&command),
+                        io::Error::from_raw_os_error(errno),
+                    ));
+                }
+            }
+            // Otherwise a resume could arrive before the child has stopped.
+            if options.suspended {
+                child.wait_stopped()?;
+            }
+            let input = master.try_clone()?;
+            let inner = Self {
+                master,
+                child,
+                kill_process_tree: options.kill_process_tree,
+                suspended: options.suspended,
+            };
+            Ok((inner, input))
+        }
+
+        pub(super) fn read(&mut self, buf: &mut [u8]) -> Result<usize, PtyError> {
+            read_stream(&self.master, buf)
+        }
+
+        pub(super) fn read_timeout(
+            &mut self,
+            buf: &mut [u8],
+            timeout: Duration,
+        ) -> Result<Option<usize>, PtyError> {
+            read_timeout(&self.master, buf, timeout)
+        }
+
+        pub(super) fn resize(&mut self, size: PtySize) -> Result<(), PtyError> {
+            let winsize = winsize(size);
+            check(unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) })?;
+            Ok(())
+        }
+
+        pub(super) fn clone_reader(&self) -> Result<File, PtyError> {
+            Ok(self.master.try_clone()?)
+        }
+
+        // ECHILD means something else already reaped the child, e.g. a
+        // SIGCHLD handler set to SIG_IGN, so it is gone but its status is lost.
+        pub(super) fn is_running(&self) -> Result<bool, PtyError> {
+            match self.exit_code() {
+                Ok(code) => Ok(code.is_none()),
+                Err(PtyError::Io(err)) if err.raw_os_error() == Some(libc::ECHILD) => Ok(false),
+                Err(err) => Err(err),
+            }
+        }
+
+        pub(super) fn exit_code(&self) -> Result<Option<i32>, PtyError> {
+            self.child.try_wait()
+        }
+
+        // SIGHUP is what the shell gets when its terminal goes away; SIGTERM
+        // covers programs run directly that ignore or don't expect it.
+        pub(super) fn signal_close(&mut self) -> Result<(), PtyError> {
+            self.kill(libc::SIGHUP)?;
+            self.kill(libc::SIGTERM)
+        }
+
+        pub(super) fn terminate(&mut self) -> Result<(), PtyError> {
+            self.kill(libc::SIGKILL)
+        }
+
+        pub(super) fn code_page(&self) -> Option<u32> {
+            None
+        }
+
+        pub(super) fn resume(&mut self) -> Result<(), PtyError> {
+            if !self.suspended {
+                return Ok(());
+            }
+            check(unsafe { libc::kill(self.child.pid, libc::SIGCONT) })?;
+            self.suspended = false;
+            Ok(())
+        }
+
+        pub(super) fn child_pid(&self) -> u32 {
+            self.child.pid as u32
+        }
+
+        // Only Linux has /proc; elsewhere this fails and callers do
+        // without.
+        pub(super) fn current_dir(&self) -> Result<PathBuf, PtyError> {
+            Ok(fs::read_link(format!("/proc/{}/cwd", self.child.pid))?)
+        }
+
+        pub(super) fn master_fd(&self) -> RawFd {
+            self.master.as_raw_fd()
+        }
+
+        // Whatever is in the foreground rather than the child itself, which
+        // is what typing the key would reach.
+        pub(super) fn send_ctrl_event(&self, event: CtrlEvent) -> Result<(), PtyError> {
+            let signal = match event {
+                CtrlEvent::C => libc::SIGINT,
+                CtrlEvent::Break => libc::SIGQUIT,
+            };
+            let group = check(unsafe { libc::tcgetpgrp(self.master.as_raw_fd()) })?;
+            check(unsafe { libc::kill(-group, signal) })?;
+            Ok(())
+        }
+
+        // Once the child has been reaped its pid may belong to another
+        // process, so nothing is sent after that.
+        fn kill(&self, signal: libc::c_int) -> Result<(), PtyError> {
+            if !self.is_running()? {
+                return Ok(());
+            }
+            check(unsafe { libc::kill(self.kill_target(), signal) })?;
+            Ok(())
+        }
+
+        // The child called setsid, so its pid is also its process group id.
+        fn kill_target(&self) -> libc::pid_t {
+            match self.kill_process_tree {
+                true => -self.child.pid,
+                false => self.child.pid,
+            }
+        }
+
+        pub(super) fn wait(&self) -> Result<i32, PtyError> {
+            self.child.wait()
+        }
+
+        pub(super) fn wait_timeout(&self, timeout: Duration) -> Result<Option<i32>, PtyError> {
+            self.child.wait_timeout(timeout)
+        }
+
+        pub(super) fn exit_notifier(&self) -> Result<Receiver<i32>, PtyError> {
+            let (tx, rx) = mpsc::channel();
+            let child = Arc::clone(&self.child);
+            std::thread::Builder::new()
+                .name("pty-exit".into())
+                .spawn(move || {
+                    if let Ok(code) = child.wait() {
+                        let _ = tx.send(code);
+                    }
+                })?;
+            Ok(rx)
+        }
+
+        pub(super) fn bytes_available(&self) -> Result<u32, PtyError> {
+            let mut available: libc::c_int = 0;
+            check(unsafe { libc::ioctl(self.master.as_raw_fd(), libc::FIONREAD, &mut available) })?;
+            Ok(available.max(0) as u32)
+        }
+    }
+
+    impl Drop for PtyInner {
+        // Readers and writers may still hold the master open, so the child
+        // is hung up explicitly and then waited for off the caller's thread.
+        fn drop(&mut self) {
+            if !matches!(self.is_running(), Ok(true)) {
+                return;
+            }
+            unsafe {
+                libc::kill(self.kill_target(), libc::SIGHUP);
+                // A stopped child only acts on the hangup once continued.
+                if self.suspended {
+                    libc::kill(self.child.pid, libc::SIGCONT);
+                }
+            }
+            // A child that ignores the hangup is killed, so the waiting
+            // thread doesn't outlive it forever.
+            let child = Arc::clone(&self.child);
+            let target = self.kill_target();
+            std::thread::spawn(move || {
+                if let Ok(None) = child.wait_timeout(HANGUP_GRACE) {
+                    unsafe { libc::kill(target, libc::SIGKILL) };
+                }
+                child.wait()
+            });
+        }
+    }
+
+    // Shared with exit notifier threads. The child can only be reaped once,
+    // so reaping happens under the lock and the decoded status is kept for
+    // every later caller; blocking waits use WNOWAIT and leave the reaping
+    // to try_wait.
+    struct Child {
+        pid: libc::pid_t,
+        exit_code: Mutex<Option<i32>>,
+    }
+
+    impl Child {
+        fn try_wait(&self) -> Result<Option<i32>, PtyError> {
+            let mut exit_code = self.exit_code.lock().unwrap_or_else(|err| err.into_inner());
+            if exit_code.is_some() {
+                return Ok(*exit_code);
+            }
+            let mut status = 0;
+            loop {
+                match unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) } {
+                    0 => return Ok(None),
+                    -1 => match io::Error::last_os_error() {
+                        err if err.kind() == io::ErrorKind::Interrupted => continue,
+                        err => return Err(err.into()),
+                    },
+                    _ => break,
+                }
+            }
+            *exit_code = decode_status(status);
+            Ok(*exit_code)
+        }
+
+        // waitpid has no timeout, so this polls with a growing interval.
+        fn wait_timeout(&self, timeout: Duration) -> Result<Option<i32>, PtyError> {
+            let deadline = Instant::now() + timeout;
+            let mut interval = Duration::from_millis(1);
+            loop {
+                if let Some(code) = self.try_wait()? {
+                    return Ok(Some(code));
+                }
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(None);
+                }
+                std::thread::sleep(interval.min(remaining));
+                interval = (interval * 2).min(MAX_WAIT_INTERVAL);
+            }
+        }
+
+        // Waits for a child spawned suspended to stop itself. If it exits
+        // first, e.g. because its working directory was missing, the status
+        // is kept as try_wait would keep it.
+        fn wait_stopped(&self) -> Result<(), PtyError> {
+            let mut exit_code = self.exit_code.lock().unwrap_or_else(|err| err.into_inner());
+            let mut status = 0;
+            while unsafe { libc::waitpid(self.pid, &mut status, libc::WUNTRACED) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+            *exit_code = decode_status(status);
+            Ok(())
+        }
+
+        fn wait(&self) -> Result<i32, PtyError> {
+            loop {
+                if let Some(code) = self.try_wait()? {
+                    return Ok(code);
+                }
+                let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+                let result = unsafe {
+                    libc::waitid(
+                        libc::P_PID,
+                        self.pid as libc::id_t,
+                        &mut info,
+                        libc::WEXITED | libc::WNOWAIT,
+                    )
+                };
+                if result == -1 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        // Another thread may have reaped it in the meantime.
+                        return self.try_wait()?.ok_or_else(|| err.into());
+                    }
+                }
+            }
+        }
+    }
+
+    // Death by signal is reported the way shells do, as 128 plus the signal
+    // number. None for a stopped child.
+    fn decode_status(status: libc::c_int) -> Option<i32> {
+        if libc::WIFEXITED(status) {
+            Some(libc::WEXITSTATUS(status))
+        } else if libc::WIFSIGNALED(status) {
+            Some(128 + libc::WTERMSIG(status))
+        } else {
+            None
+        }
+    }
+
+    unsafe fn exec_child(
+        slave: RawFd,
+        argv: &[*const libc::c_char],
+        envp: Option<&mut Vec<*mut libc::c_char>>,
+        current_dir: Option<&CStr>,
+        suspended: bool,
+        error_pipe: RawFd,
+    ) -> ! {
+        libc::setsid();
+        libc::ioctl(slave, libc::TIOCSCTTY as _, 0);
+        for fd in 0..3 {
+            libc::dup2(slave, fd);
+        }
+        if slave > 2 {
+            libc::close(slave);
+        }
+        // Rust ignores SIGPIPE in its own processes; the shell should not
+        // inherit that.
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+        if let Some(dir) = current_dir {
+            if libc::chdir(dir.as_ptr()) == -1 {
+                report_exec_error(error_pipe, REPORT_CHDIR);
+                libc::_exit(127);
+            }
+        }
+        // execvp searches PATH and passes on `environ`, so pointing that at
+        // the prepared variables replaces them without losing the PATH
+        // lookup. Only this forked child is affected.
+        if let Some(envp) = envp {
+            set_environ(envp.as_mut_ptr());
+        }
+        if suspended {
+            libc::raise(libc::SIGSTOP);
+        }
+        libc::execvp(argv[0], argv.as_ptr());
+        report_exec_error(error_pipe, REPORT_EXEC);
+        libc::_exit(127)
+    }
+
+    // Runs in the forked child, so it only reads errno and makes a single
+    // write. Nothing is sent without a pipe.
+    unsafe fn report_exec_error(pipe: RawFd, stage: i32) {
+        if pipe < 0 {
+            return;
+        }
+        let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        let mut report = [0u8; 8];
+        report[..4].copy_from_slice(&stage.to_ne_bytes());
+        report[4..].copy_from_slice(&errno.to_ne_bytes());
+        libc::write(pipe, report.as_ptr().cast(), report.len());
+    }
+
+    // Both ends close on exec, so the parent reads end of file as soon as
+    // the child has exec'd and exactly one report if it couldn't.
+    fn error_pipe() -> Result<(File, File), PtyError> {
+        let mut fds = [0; 2];
+        check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let read = unsafe { File::from_raw_fd(fds[0]) };
+        let write = unsafe { File::from_raw_fd(fds[1]) };
+        set_cloexec(read.as_raw_fd())?;
+        set_cloexec(write.as_raw_fd())?;
+        Ok((read, write))
+    }
+
+    // check() fails with PtyError::Io, which is put down to `stage`.
+    fn at_stage(stage: SpawnStage) -> impl FnOnce(PtyError) -> PtyError {
+        move |err| match err {
+            PtyError::Io(source) => PtyError::spawn(stage, None, source),
+            err => err,
+        }
+    }
+
+    #[cfg(target_vendor = "apple")]
+    unsafe fn set_environ(envp: *mut *mut libc::c_char) {
+        *libc::_NSGetEnviron() = envp;
+    }
+
+    #[cfg(not(target_vendor = "apple"))]
+    unsafe fn set_environ(envp: *mut *mut libc::c_char) {
+        extern "C" {
+            static mut environ: *mut *mut libc::c_char;
+        }
+        environ = envp;
+    }
+
+    fn winsize(size: PtySize) -> libc::winsize {
+        libc::winsize {
+            ws_row: size.rows,
+            ws_col: size.cols,
+            ws_xpixel: size.pixel_width,
+            ws_ypixel: size.pixel_height,
+        }
+    }
+
+    pub(super) fn is_supported() -> bool {
+        true
+    }
+
+    pub(super) fn end_input(input: &mut File) -> Result<(), PtyError> {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::tcgetattr(input.as_raw_fd(), &mut termios) })?;
+        input.write_all(&[termios.c_cc[libc::VEOF]])?;
+        Ok(())
+    }
+
+    // Hang-ups and errors count as readable so the read reports them.
+    pub(super) fn wait_readable(file: &File, timeout: Duration) -> Result<bool, PtyError> {
+        let deadline = Instant::now() + timeout;
+        let mut fd = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            // Rounded up so a sub-millisecond timeout still waits.
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout_ms = remaining
+                .as_micros()
+                .div_ceil(1000)
+                .min(libc::c_int::MAX as u128);
+            match unsafe { libc::poll(&mut fd, 1, timeout_ms as libc::c_int) } {
+                -1 => match io::Error::last_os_error() {
+                    err if err.kind() == io::ErrorKind::Interrupted => continue,
+                    err => return Err(err.into()),
+                },
+                ready => return Ok(ready > 0),
+            }
+        }
+    }
+
+    fn set_cloexec(fd: RawFd) -> Result<(), PtyError> {
+        let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFD) })?;
+        check(unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) })?;
+        Ok(())
+    }
+
+    fn check<T: Copy + PartialEq + From<i8>>(result: T) -> Result<T, PtyError> {
+        if result == T::from(-1) {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(result)
     }
 }
 
-#[cfg(not(windows))]
+#[cfg(not(any(windows, unix)))]
 mod platform {
-    use super::{PtyError, PtySize};
+    use super::{CommandLine, CtrlEvent, PtyError, PtySize, SpawnOptions};
     use std::fs::File;
+    use std::path::PathBuf;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
 
     pub(super) struct PtyInner;
 
     impl PtyInner {
-        pub(super) fn spawn(_command: &str, _size: PtySize) -> Result<Self, PtyError> {
+        pub(super) fn spawn(
+            _command: CommandLine<'_>,
+            _options: SpawnOptions,
+            _size: PtySize,
+        ) -> Result<(Self, File), PtyError> {
             Err(PtyError::UnsupportedPlatform)
         }
 
@@ -442,7 +2742,11 @@ mod platform {
             Err(PtyError::UnsupportedPlatform)
         }
 
-        pub(super) fn write(&mut self, _buf: &[u8]) -> Result<usize, PtyError> {
+        pub(super) fn read_timeout(
+            &mut self,
+            _buf: &mut [u8],
+            _timeout: Duration,
+        ) -> Result<Option<usize>, PtyError> {
             Err(PtyError::UnsupportedPlatform)
         }
 
@@ -454,11 +2758,51 @@ mod platform {
             Err(PtyError::UnsupportedPlatform)
         }
 
-        pub(super) fn clone_writer(&self) -> Result<File, PtyError> {
+        pub(super) fn is_running(&self) -> Result<bool, PtyError> {
             Err(PtyError::UnsupportedPlatform)
         }
 
-        pub(super) fn is_running(&self) -> Result<bool, PtyError> {
+        pub(super) fn exit_code(&self) -> Result<Option<i32>, PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn wait(&self) -> Result<i32, PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn wait_timeout(&self, _timeout: Duration) -> Result<Option<i32>, PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn exit_notifier(&self) -> Result<Receiver<i32>, PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn signal_close(&mut self) -> Result<(), PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn terminate(&mut self) -> Result<(), PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn code_page(&self) -> Option<u32> {
+            None
+        }
+
+        pub(super) fn resume(&mut self) -> Result<(), PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn send_ctrl_event(&self, _event: CtrlEvent) -> Result<(), PtyError> {
+            Err(PtyError::UnsupportedPlatform)
+        }
+
+        pub(super) fn child_pid(&self) -> u32 {
+            0
+        }
+
+        pub(super) fn current_dir(&self) -> Result<PathBuf, PtyError> {
             Err(PtyError::UnsupportedPlatform)
         }
 
@@ -466,6 +2810,200 @@ mod platform {
             Err(PtyError::UnsupportedPlatform)
         }
     }
+
+    pub(super) fn is_supported() -> bool {
+        false
+    }
+
+    pub(super) fn wait_readable(_file: &File, _timeout: Duration) -> Result<bool, PtyError> {
+        Err(PtyError::UnsupportedPlatform)
+    }
+
+    pub(super) fn end_input(_input: &mut File) -> Result<(), PtyError> {
+        Err(PtyError::UnsupportedPlatform)
+    }
 }
 
 use platform::PtyInner;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Splits arguments the way CommandLineToArgvW does, for the part of the
+    // line after the program name.
+    fn split_args(line: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut chars = line.chars().peekable();
+        loop {
+            while chars.next_if(|ch| *ch == ' ' || *ch == '\t').is_some() {}
+            if chars.peek().is_none() {
+                return args;
+            }
+            let mut arg = String::new();
+            let mut quoted = false;
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '\\' => {
+                        let mut backslashes = 1;
+                        while chars.next_if_eq(&'\\').is_some() {
+                            backslashes += 1;
+                        }
+                        if chars.peek() == Some(&'"') {
+                            arg.extend(std::iter::repeat_n('\\', backslashes / 2));
+                            if backslashes % 2 == 1 {
+                                chars.next();
+                                arg.push('"');
+                            }
+                        } else {
+                            arg.extend(std::iter::repeat_n('\\', backslashes));
+                        }
+                    }
+                    '"' => quoted = !quoted,
+                    ' ' | '\t' if !quoted => break,
+                    _ => arg.push(ch),
+                }
+            }
+            args.push(arg);
+        }
+    }
+
+    #[test]
+    fn quote_argv_handles_the_hard_cases() {
+        let cases = [
+            ("plain", "plain"),
+            ("a b", r#""a b""#),
+            ("", r#""""#),
+            (r#"a"b"#, r#""a\"b""#),
+            (r#"a\"b"#, r#""a\\\"b""#),
+            (r"a\", r"a\"),
+            (r"a b\", r#""a b\\""#),
+            (r"\\", r"\\"),
+            (r"a \\", r#""a \\\\""#),
+            (r"C:\dir\file", r"C:\dir\file"),
+            ("tab\there", "\"tab\there\""),
+        ];
+        for (arg, expected) in cases {
+            let line = windows_quote_argv("prog", &[arg]);
+            assert_eq!(line, format!("prog {expected}"), "quoting {arg:?}");
+            assert_eq!(split_args(expected), [arg], "splitting {expected:?}");
+        }
+    }
+
+    #[test]
+    fn quote_argv_wraps_the_program_only_when_needed() {
+        assert_eq!(windows_quote_argv("cmd.exe", &[]), "cmd.exe");
+        assert_eq!(
+            windows_quote_argv(r"C:\Program Files\PowerShell\7\pwsh.exe", &["-NoLogo"]),
+            r#""C:\Program Files\PowerShell\7\pwsh.exe" -NoLogo"#
+        );
+        assert_eq!(windows_quote_argv("", &["x"]), r#""" x"#);
+    }
+
+    #[test]
+    fn quote_argv_keeps_every_argument_separate() {
+        let args = ["", "a b", r#"a"b"#, r"a\", r"\\", r#"\"\"#, "  "];
+        let line = windows_quote_argv("prog", &args);
+        let rest = line.strip_prefix("prog ").expect("program first");
+        assert_eq!(split_args(rest), args);
+    }
+
+    #[test]
+    fn exit_code_259_counts_as_exited_once_signaled() {
+        const STILL_ACTIVE: u32 = 259;
+        assert_eq!(exit_from_wait(true, STILL_ACTIVE), Some(259));
+        assert_eq!(exit_from_wait(false, STILL_ACTIVE), None);
+        assert_eq!(exit_from_wait(true, 0), Some(0));
+        assert_eq!(exit_from_wait(false, 0), None);
+        // NTSTATUS codes such as STATUS_CONTROL_C_EXIT come out negative.
+        assert_eq!(exit_from_wait(true, 0xC000_013A), Some(-1_073_741_510));
+    }
+
+    const TRICKY_ARGS: [&str; 6] = ["a b", "", r#"x"y"#, r"z\", r"\\", "tab\tin"];
+
+    #[test]
+    fn builder_collects_args_in_order() {
+        let builder = Pty::builder("prog")
+            .arg(TRICKY_ARGS[0])
+            .args(TRICKY_ARGS[1..3].iter().copied())
+            .args(TRICKY_ARGS[3..].iter().map(|arg| arg.to_string()));
+        assert_eq!(builder.program(), "prog");
+        assert_eq!(builder.args, TRICKY_ARGS);
+    }
+
+    #[test]
+    fn builder_args_survive_windows_quoting() {
+        let builder = Pty::builder(r"C:\Program Files\tool.exe").args(TRICKY_ARGS);
+        let args: Vec<&str> = builder.args.iter().map(String::as_str).collect();
+        let line = windows_quote_argv(builder.program(), &args);
+        let rest = line
+            .strip_prefix(r#""C:\Program Files\tool.exe" "#)
+            .expect("quoted program first");
+        assert_eq!(split_args(rest), TRICKY_ARGS);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn builder_args_reach_the_child_unchanged() {
+        let size = PtySize {
+            cols: 80,
+            rows: 24,
+            ..PtySize::default()
+        };
+        let pty = Pty::builder("printf")
+            .arg("[%s]\\n")
+            .args(TRICKY_ARGS)
+            .spawn(size)
+            .expect("spawn printf");
+        let mut output = Vec::new();
+        io::copy(&mut pty.reader().expect("reader"), &mut output).expect("read output");
+        assert_eq!(pty.wait().expect("wait"), 0);
+        let output = String::from_utf8(output).expect("utf-8 output");
+        let printed: Vec<&str> = output
+            .lines()
+            .map(|line| {
+                line.trim_end_matches('\r')
+                    .strip_prefix('[')
+                    .and_then(|line| line.strip_suffix(']'))
+                    .expect("bracketed arg")
+            })
+            .collect();
+        assert_eq!(printed, TRICKY_ARGS);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dropping_kills_a_child_that_ignores_hangup() {
+        let size = PtySize {
+            cols: 80,
+            rows: 24,
+            ..PtySize::default()
+        };
+        let script = "trap '' HUP TERM; echo ready; while :; do sleep 1; done";
+        let pty = Pty::builder("sh")
+            .args(["-c", script])
+            .spawn(size)
+            .expect("spawn sh");
+        let mut reader = pty.reader().expect("reader");
+        let mut output = Vec::new();
+        let mut buf = [0u8; 256];
+        while !String::from_utf8_lossy(&output).contains("ready\r\n") {
+            match reader.read_timeout(&mut buf, Duration::from_secs(10)) {
+                Ok(Some(len)) if len > 0 => output.extend_from_slice(&buf[..len]),
+                other => panic!("no ready line: {other:?}"),
+            }
+        }
+        let pid = pty.child_pid() as libc::pid_t;
+        drop(pty);
+        drop(reader);
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(15);
+        while unsafe { libc::kill(pid, 0) } == 0 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "child outlived its hangup"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
@@ -0,0 +1,411 @@
+// Spawns real children on a pty and checks what they see and what comes
+// back: output, input, environment, exit status.
+#![cfg(unix)]
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use pty::{Pty, PtyChunk, PtyError, PtyReader, PtySize, ReaderOptions};
+
+const SIZE: PtySize = PtySize {
+    cols: 80,
+    rows: 24,
+    pixel_width: 0,
+    pixel_height: 0,
+};
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Everything read until `needle` shows up, or until the output ends.
+fn read_until(reader: &mut PtyReader, needle: &str) -> String {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut output = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !String::from_utf8_lossy(&output).contains(needle) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        assert!(!remaining.is_zero(), "timed out waiting for {needle:?}");
+        match reader.read_timeout(&mut buf, remaining).expect("read") {
+            Some(0) => break,
+            Some(len) => output.extend_from_slice(&buf[..len]),
+            None => {}
+        }
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+// Everything the child writes before its output ends.
+fn read_to_end(reader: &mut PtyReader) -> String {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut output = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        assert!(
+            !remaining.is_zero(),
+            "timed out waiting for the output to end"
+        );
+        match reader.read_timeout(&mut buf, remaining).expect("read") {
+            Some(0) => return String::from_utf8_lossy(&output).into_owned(),
+            Some(len) => output.extend_from_slice(&buf[..len]),
+            None => {}
+        }
+    }
+}
+
+// A fresh directory under the system temp dir, unique to this test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ring0-pty-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("create temp dir");
+    dir.canonicalize().expect("canonical temp dir")
+}
+
+fn wait(pty: &Pty) -> i32 {
+    pty.wait_timeout(TIMEOUT)
+        .expect("wait")
+        .expect("child exited in time")
+}
+
+#[test]
+fn spawned_output_is_read() {
+    let pty = Pty::spawn("echo hello pty", SIZE).expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    let output = read_until(&mut reader, "hello pty");
+    assert!(output.contains("hello pty\r\n"), "{output:?}");
+    assert_eq!(wait(&pty), 0);
+    assert!(!pty.is_running().expect("is_running"));
+}
+
+#[test]
+fn input_is_echoed_and_read_by_the_child() {
+    let mut pty = Pty::builder("cat").spawn(SIZE).expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    pty.writer()
+        .expect("writer")
+        .write_all(b"ping\n")
+        .expect("write");
+    // Once from the terminal's echo and once from cat itself.
+    let output = read_until(&mut reader, "ping\r\nping\r\n");
+    assert_eq!(output.matches("ping").count(), 2, "{output:?}");
+    pty.close_input().expect("close input");
+    assert_eq!(wait(&pty), 0);
+}
+
+#[test]
+fn exit_status_is_reported() {
+    let pty = Pty::builder("sh")
+        .args(["-c", "exit 7"])
+        .spawn(SIZE)
+        .expect("spawn");
+    assert_eq!(wait(&pty), 7);
+    assert_eq!(pty.exit_code().expect("exit_code"), Some(7));
+    assert!(!pty.is_running().expect("is_running"));
+}
+
+#[test]
+fn death_by_signal_is_reported_like_a_shell() {
+    let pty = Pty::builder("sh")
+        .args(["-c", "kill -TERM $$"])
+        .spawn(SIZE)
+        .expect("spawn");
+    assert_eq!(wait(&pty), 128 + 15);
+}
+
+#[test]
+fn terminate_kills_a_running_child() {
+    let mut pty = Pty::builder("sleep").arg("30").spawn(SIZE).expect("spawn");
+    assert!(pty.is_running().expect("is_running"));
+    pty.terminate().expect("terminate");
+    assert_eq!(wait(&pty), 128 + 9);
+}
+
+#[test]
+fn terminal_size_reaches_the_child() {
+    let pty = Pty::builder("stty").arg("size").spawn(SIZE).expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    let output = read_until(&mut reader, "24 80");
+    assert!(output.contains("24 80"), "{output:?}");
+    assert_eq!(wait(&pty), 0);
+}
+
+#[test]
+fn child_starts_in_the_working_directory() {
+    let dir = temp_dir("cwd");
+    let pty = Pty::builder("sh")
+        .args(["-c", "pwd"])
+        .current_dir(&dir)
+        .spawn(SIZE)
+        .expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    let expected = format!("{}\r\n", dir.display());
+    let output = read_until(&mut reader, &expected);
+    assert!(output.contains(&expected), "{output:?}");
+    assert_eq!(wait(&pty), 0);
+    fs::remove_dir_all(&dir).expect("remove temp dir");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn current_dir_reports_the_childs_directory() {
+    let dir = temp_dir("current-dir");
+    let mut pty = Pty::builder("sleep")
+        .arg("30")
+        .current_dir(&dir)
+        .spawn(SIZE)
+        .expect("spawn");
+    assert_eq!(pty.current_dir().expect("current_dir"), dir);
+    pty.terminate().expect("terminate");
+    wait(&pty);
+    fs::remove_dir_all(&dir).expect("remove temp dir");
+}
+
+#[test]
+fn missing_working_directory_is_rejected() {
+    let parent = temp_dir("missing");
+    let dir = parent.join("does-not-exist");
+    let result = Pty::builder("pwd").current_dir(&dir).spawn(SIZE);
+    fs::remove_dir_all(&parent).expect("remove temp dir");
+    match result {
+        Err(PtyError::InvalidWorkingDirectory { path }) => assert_eq!(path, dir),
+        Err(err) => panic!("unexpected error: {err}"),
+        Ok(_) => panic!("spawned in a missing directory"),
+    }
+}
+
+#[test]
+fn environment_changes_reach_the_child() {
+    let pty = Pty::builder("/usr/bin/env")
+        .env("RING0_SET", "set value")
+        .env_remove("PATH")
+        .spawn(SIZE)
+        .expect("spawn");
+    let output = read_to_end(&mut pty.reader().expect("reader"));
+    assert_eq!(wait(&pty), 0);
+    assert!(output.contains("RING0_SET=set value\r\n"), "{output:?}");
+    assert!(
+        !output.lines().any(|line| line.starts_with("PATH=")),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn a_cleared_environment_holds_only_what_was_set() {
+    let pty = Pty::builder("/usr/bin/env")
+        .env("DROPPED", "1")
+        .env_clear()
+        .env("RING0_ONLY", "1")
+        .spawn(SIZE)
+        .expect("spawn");
+    let output = read_to_end(&mut pty.reader().expect("reader"));
+    assert_eq!(wait(&pty), 0);
+    assert_eq!(output, "RING0_ONLY=1\r\n");
+}
+
+#[test]
+fn read_timeout_returns_none_from_a_silent_child() {
+    let mut pty = Pty::builder("sleep").arg("30").spawn(SIZE).expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    let mut buf = [0u8; 64];
+    let started = Instant::now();
+    let read = reader
+        .read_timeout(&mut buf, Duration::from_millis(200))
+        .expect("read");
+    let waited = started.elapsed();
+    assert_eq!(read, None);
+    assert!(waited >= Duration::from_millis(200), "{waited:?}");
+    assert!(waited < Duration::from_secs(5), "{waited:?}");
+
+    reader.set_nonblocking(true);
+    match reader.read(&mut buf) {
+        Err(PtyError::Io(err)) => assert_eq!(err.kind(), ErrorKind::WouldBlock),
+        other => panic!("expected WouldBlock, got {other:?}"),
+    }
+    pty.terminate().expect("terminate");
+    wait(&pty);
+}
+
+#[test]
+fn the_reader_thread_ends_when_the_pty_is_dropped() {
+    let pty = Pty::builder("sleep").arg("30").spawn(SIZE).expect("spawn");
+    let (chunks, handle) = pty
+        .spawn_reader(ReaderOptions::default())
+        .expect("spawn reader");
+    drop(pty);
+    loop {
+        match chunks.recv_timeout(TIMEOUT).expect("chunk before the end") {
+            PtyChunk::Closed => break,
+            PtyChunk::Data(_) => {}
+            other => panic!("unexpected chunk {other:?}"),
+        }
+    }
+    let deadline = Instant::now() + TIMEOUT;
+    while !handle.is_finished() {
+        assert!(Instant::now() < deadline, "reader thread still running");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(chunks.recv().is_err());
+    handle.shutdown();
+}
+
+#[test]
+fn shutdown_joins_the_reader_thread_while_the_child_is_silent() {
+    let mut pty = Pty::builder("sleep").arg("30").spawn(SIZE).expect("spawn");
+    let (_chunks, handle) = pty
+        .spawn_reader(ReaderOptions::default())
+        .expect("spawn reader");
+    let started = Instant::now();
+    handle.shutdown();
+    assert!(
+        started.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        started.elapsed()
+    );
+    pty.terminate().expect("terminate");
+    wait(&pty);
+}
+
+// A child that counts `len` bytes of raw input. Output is read until it is
+// ready, so nothing is written while the terminal is still line-buffered.
+fn byte_counter(len: usize) -> (Pty, PtyReader) {
+    let script = format!("stty -icanon -echo && echo ready && head -c {len} | wc -c");
+    let pty = Pty::builder("sh")
+        .args(["-c", &script])
+        .spawn(SIZE)
+        .expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    read_until(&mut reader, "ready\r\n");
+    (pty, reader)
+}
+
+fn paste(len: usize) -> Vec<u8> {
+    b"0123456789abcdef"
+        .iter()
+        .copied()
+        .cycle()
+        .take(len)
+        .collect()
+}
+
+#[test]
+fn chunked_writes_deliver_a_large_paste() {
+    let len = 256 * 1024;
+    let (pty, mut reader) = byte_counter(len);
+    let started = Instant::now();
+    pty.writer()
+        .expect("writer")
+        .write_chunked(&paste(len), 16 * 1024, Some(Duration::from_millis(5)))
+        .expect("write");
+    // Fifteen pauses between sixteen chunks.
+    assert!(started.elapsed() >= Duration::from_millis(75));
+    let output = read_until(&mut reader, &len.to_string());
+    assert!(output.contains(&len.to_string()), "{output:?}");
+    assert_eq!(wait(&pty), 0);
+}
+
+#[test]
+fn write_some_consumes_within_its_budget() {
+    let len = 64 * 1024;
+    let (pty, mut reader) = byte_counter(len);
+    let data = paste(len);
+    let mut writer = pty.writer().expect("writer");
+    // An exhausted budget still writes one chunk.
+    let mut consumed = writer
+        .write_some(&data, 4096, Duration::ZERO)
+        .expect("write");
+    assert!(consumed > 0 && consumed <= 4096, "{consumed}");
+    while consumed < len {
+        consumed += writer
+            .write_some(&data[consumed..], 4096, Duration::from_millis(10))
+            .expect("write");
+    }
+    assert_eq!(consumed, len);
+    let output = read_until(&mut reader, &len.to_string());
+    assert!(output.contains(&len.to_string()), "{output:?}");
+    assert_eq!(wait(&pty), 0);
+}
+
+#[test]
+fn a_suspended_child_runs_only_once_resumed() {
+    let mut pty = Pty::builder("echo")
+        .arg("resumed")
+        .suspended(true)
+        .spawn(SIZE)
+        .expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    let mut buf = [0u8; 64];
+    let read = reader
+        .read_timeout(&mut buf, Duration::from_millis(300))
+        .expect("read");
+    assert_eq!(read, None);
+    assert!(pty.is_running().expect("is_running"));
+    pty.resume().expect("resume");
+    let output = read_until(&mut reader, "resumed\r\n");
+    assert!(output.contains("resumed\r\n"), "{output:?}");
+    assert_eq!(wait(&pty), 0);
+}
+
+#[test]
+fn shared_writers_never_interleave_their_writes() {
+    const THREADS: u8 = 8;
+    const WRITES: usize = 20;
+    const RECORD: usize = 3000;
+    let total = THREADS as usize * WRITES * RECORD;
+    let dir = temp_dir("shared-writer");
+    let file = dir.join("input");
+    let script = format!(
+        "stty -icanon -echo && echo ready && head -c {total} > '{}' && echo done",
+        file.display()
+    );
+    let pty = Pty::builder("sh")
+        .args(["-c", &script])
+        .spawn(SIZE)
+        .expect("spawn");
+    let mut reader = pty.reader().expect("reader");
+    read_until(&mut reader, "ready\r\n");
+
+    let writer = pty.shared_writer();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let writer = writer.clone();
+            std::thread::spawn(move || {
+                let record = vec![b'a' + thread; RECORD];
+                for _ in 0..WRITES {
+                    writer.write_all(&record).expect("write");
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("writer thread");
+    }
+    read_until(&mut reader, "done\r\n");
+    assert_eq!(wait(&pty), 0);
+
+    let input = fs::read(&file).expect("read input");
+    assert_eq!(input.len(), total);
+    for record in input.chunks(RECORD) {
+        assert!(
+            record.iter().all(|byte| *byte == record[0]),
+            "interleaved write"
+        );
+    }
+    fs::remove_dir_all(&dir).expect("remove temp dir");
+}
+
+#[test]
+fn utf8_output_arrives_intact() {
+    let pty = Pty::builder("sh")
+        .args([
+            "-c",
+            r"printf 'caf\303\251 \342\202\254 \360\237\230\200\n'",
+        ])
+        .force_utf8(true)
+        .spawn(SIZE)
+        .expect("spawn");
+    let output = read_to_end(&mut pty.reader().expect("reader"));
+    assert_eq!(wait(&pty), 0);
+    assert_eq!(output, "café € 😀\r\n");
+    // The pty passes bytes through, so there is no code page to report.
+    assert_eq!(pty.code_page(), None);
+}
@@ -0,0 +1,33 @@
+// A process may exit with code 259, the value of STILL_ACTIVE, and must
+// still be reported as exited. This only builds and runs on Windows; the
+// decision itself is also unit-tested in the crate on every platform.
+#![cfg(windows)]
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use pty::{Pty, PtySize};
+
+const SIZE: PtySize = PtySize {
+    cols: 80,
+    rows: 24,
+    pixel_width: 0,
+    pixel_height: 0,
+};
+
+#[test]
+fn exit_code_259_is_not_still_running() {
+    let pty = Pty::spawn("cmd /c exit 259", SIZE).expect("spawn cmd");
+    // ConPTY can stall the child until its output is read.
+    let mut reader = pty.reader().expect("reader");
+    thread::spawn(move || io::copy(&mut reader, &mut io::sink()));
+
+    let code = pty
+        .wait_timeout(Duration::from_secs(10))
+        .expect("wait")
+        .expect("cmd exited");
+    assert_eq!(code, 259);
+    assert!(!pty.is_running().expect("is_running"));
+    assert_eq!(pty.exit_code().expect("exit_code"), Some(259));
+}
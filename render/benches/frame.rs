@@ -0,0 +1,106 @@
+// CPU cost of building a full 4K frame (3840x2160) from a screen of colored
+// text: cell layout, glyph atlas lookups and instance upload, without
+// waiting on the GPU. Each iteration scrolls the text by a row so no frame
+// matches the one before it. Run with `cargo bench -p render`; it is skipped
+// on machines without an adapter or a monospace font.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use render::{FontSpec, OffscreenRenderer, RenderBackend, RenderGrid, RenderSize};
+use screen::{Cell, Color, CursorShape, Palette};
+
+const SIZE: RenderSize = RenderSize {
+    width: 3840,
+    height: 2160,
+};
+const FONTS: [&str; 3] = [
+    r"C:\Windows\Fonts\consola.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+    "/System/Library/Fonts/Menlo.ttc",
+];
+
+fn renderer() -> Option<OffscreenRenderer> {
+    let bytes = FONTS.iter().find_map(|path| std::fs::read(path).ok())?;
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: false,
+        compatible_surface: None,
+    }))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            required_limits: adapter.limits(),
+        },
+        None,
+    ))
+    .ok()?;
+    let font = FontSpec {
+        bytes,
+        size: 16.0,
+        collection_index: 0,
+        weight: None,
+    };
+    OffscreenRenderer::new(device, queue, SIZE, font).ok()
+}
+
+// Colored lines of text, one more than fits so a window of `rows` can be
+// taken from any offset.
+fn lines(cols: usize, rows: usize) -> Vec<Cell> {
+    let text = "the quick brown fox jumps over the lazy dog ";
+    let mut cells = Vec::with_capacity(cols * rows * 2);
+    for line in 0..rows * 2 {
+        let fg = Color::Indexed(1 + (line % 7) as u8);
+        cells.extend(text.chars().cycle().skip(line).take(cols).map(|ch| Cell {
+            ch,
+            fg,
+            ..Cell::default()
+        }));
+    }
+    cells
+}
+
+fn scroll(c: &mut Criterion) {
+    let Some(mut renderer) = renderer() else {
+        eprintln!("frame bench skipped: no adapter or font");
+        return;
+    };
+    let layout = renderer.grid_layout();
+    let (cols, rows) = (layout.cols as usize, layout.rows as usize);
+    let text = lines(cols, rows);
+    let palette = Palette::default();
+    let grid = |top: usize| RenderGrid {
+        cols: cols as u16,
+        rows: rows as u16,
+        cells: &text[top * cols..(top + rows) * cols],
+        palette: &palette,
+        cursor: None,
+        cursor_visible: false,
+        cursor_shape: CursorShape::Block,
+        cursor_blinking: false,
+        scroll_indicator: None,
+        scroll_pixel_offset: 0.0,
+        blink_on: true,
+        focused: true,
+        preedit: None,
+        selection: None,
+        link: None,
+        notice: None,
+    };
+
+    let mut group = c.benchmark_group("frame");
+    for (name, backend) in [("gpu", RenderBackend::Gpu), ("cpu", RenderBackend::Cpu)] {
+        renderer.set_backend(backend);
+        let mut top = 0;
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                top = (top + 1) % rows;
+                renderer.prepare(&grid(top)).expect("prepare")
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scroll);
+criterion_main!(benches);
@@ -0,0 +1,523 @@
+use crate::{DrawOp, Rect};
+
+const NONE: u8 = 0;
+const LIGHT: u8 = 1;
+const HEAVY: u8 = 2;
+const DOUBLE: u8 = 3;
+
+// Arm weights for U+2500..=U+257F in (up, right, down, left) order, plus the
+// number of dashes per cell for the dashed variants (0 for solid lines).
+// Diagonals have no entry and fall back to the font.
+const LINES: [Option<([u8; 4], u8)>; 128] = {
+    const L: u8 = LIGHT;
+    const H: u8 = HEAVY;
+    const D: u8 = DOUBLE;
+    const N: u8 = NONE;
+    [
+        Some(([N, L, N, L], 0)),
+        Some(([N, H, N, H], 0)),
+        Some(([L, N, L, N], 0)),
+        Some(([H, N, H, N], 0)),
+        Some(([N, L, N, L], 3)),
+        Some(([N, H, N, H], 3)),
+        Some(([L, N, L, N], 3)),
+        Some(([H, N, H, N], 3)),
+        Some(([N, L, N, L], 4)),
+        Some(([N, H, N, H], 4)),
+        Some(([L, N, L, N], 4)),
+        Some(([H, N, H, N], 4)),
+        Some(([N, L, L, N], 0)),
+        Some(([N, H, L, N], 0)),
+        Some(([N, L, H, N], 0)),
+        Some(([N, H, H, N], 0)),
+        Some(([N, N, L, L], 0)),
+        Some(([N, N, L, H], 0)),
+        Some(([N, N, H, L], 0)),
+        Some(([N, N, H, H], 0)),
+        Some(([L, L, N, N], 0)),
+        Some(([L, H, N, N], 0)),
+        Some(([H, L, N, N], 0)),
+        Some(([H, H, N, N], 0)),
+        Some(([L, N, N, L], 0)),
+        Some(([L, N, N, H], 0)),
+        Some(([H, N, N, L], 0)),
+        Some(([H, N, N, H], 0)),
+        Some(([L, L, L, N], 0)),
+        Some(([L, H, L, N], 0)),
+        Some(([H, L, L, N], 0)),
+        Some(([L, L, H, N], 0)),
+        Some(([H, L, H, N], 0)),
+        Some(([H, H, L, N], 0)),
+        Some(([L, H, H, N], 0)),
+        Some(([H, H, H, N], 0)),
+        Some(([L, N, L, L], 0)),
+        Some(([L, N, L, H], 0)),
+        Some(([H, N, L, L], 0)),
+        Some(([L, N, H, L], 0)),
+        Some(([H, N, H, L], 0)),
+        Some(([H, N, L, H], 0)),
+        Some(([L, N, H, H], 0)),
+        Some(([H, N, H, H], 0)),
+        Some(([N, L, L, L], 0)),
+        Some(([N, L, L, H], 0)),
+        Some(([N, H, L, L], 0)),
+        Some(([N, H, L, H], 0)),
+        Some(([N, L, H, L], 0)),
+        Some(([N, L, H, H], 0)),
+        Some(([N, H, H, L], 0)),
+        Some(([N, H, H, H], 0)),
+        Some(([L, L, N, L], 0)),
+        Some(([L, L, N, H], 0)),
+        Some(([L, H, N, L], 0)),
+        Some(([L, H, N, H], 0)),
+        Some(([H, L, N, L], 0)),
+        Some(([H, L, N, H], 0)),
+        Some(([H, H, N, L], 0)),
+        Some(([H, H, N, H], 0)),
+        Some(([L, L, L, L], 0)),
+        Some(([L, L, L, H], 0)),
+        Some(([L, H, L, L], 0)),
+        Some(([L, H, L, H], 0)),
+        Some(([H, L, L, L], 0)),
+        Some(([L, L, H, L], 0)),
+        Some(([H, L, H, L], 0)),
+        Some(([H, L, L, H], 0)),
+        Some(([H, H, L, L], 0)),
+        Some(([L, L, H, H], 0)),
+        Some(([L, H, H, L], 0)),
+        Some(([H, H, L, H], 0)),
+        Some(([L, H, H, H], 0)),
+        Some(([H, L, H, H], 0)),
+        Some(([H, H, H, L], 0)),
+        Some(([H, H, H, H], 0)),
+        Some(([N, L, N, L], 2)),
+        Some(([N, H, N, H], 2)),
+        Some(([L, N, L, N], 2)),
+        Some(([H, N, H, N], 2)),
+        Some(([N, D, N, D], 0)),
+        Some(([D, N, D, N], 0)),
+        Some(([N, D, L, N], 0)),
+        Some(([N, L, D, N], 0)),
+        Some(([N, D, D, N], 0)),
+        Some(([N, N, L, D], 0)),
+        Some(([N, N, D, L], 0)),
+        Some(([N, N, D, D], 0)),
+        Some(([L, D, N, N], 0)),
+        Some(([D, L, N, N], 0)),
+        Some(([D, D, N, N], 0)),
+        Some(([L, N, N, D], 0)),
+        Some(([D, N, N, L], 0)),
+        Some(([D, N, N, D], 0)),
+        Some(([L, D, L, N], 0)),
+        Some(([D, L, D, N], 0)),
+        Some(([D, D, D, N], 0)),
+        Some(([L, N, L, D], 0)),
+        Some(([D, N, D, L], 0)),
+        Some(([D, N, D, D], 0)),
+        Some(([N, D, L, D], 0)),
+        Some(([N, L, D, L], 0)),
+        Some(([N, D, D, D], 0)),
+        Some(([L, D, N, D], 0)),
+        Some(([D, L, N, L], 0)),
+        Some(([D, D, N, D], 0)),
+        Some(([L, D, L, D], 0)),
+        Some(([D, L, D, L], 0)),
+        Some(([D, D, D, D], 0)),
+        Some(([N, L, L, N], 0)),
+        Some(([N, N, L, L], 0)),
+        Some(([L, N, N, L], 0)),
+        Some(([L, L, N, N], 0)),
+        None,
+        None,
+        None,
+        Some(([N, N, N, L], 0)),
+        Some(([L, N, N, N], 0)),
+        Some(([N, L, N, N], 0)),
+        Some(([N, N, L, N], 0)),
+        Some(([N, N, N, H], 0)),
+        Some(([H, N, N, N], 0)),
+        Some(([N, H, N, N], 0)),
+        Some(([N, N, H, N], 0)),
+        Some(([N, H, N, L], 0)),
+        Some(([L, N, H, N], 0)),
+        Some(([N, L, N, H], 0)),
+        Some(([H, N, L, N], 0)),
+    ]
+};
+
+const UP: usize = 0;
+const RIGHT: usize = 1;
+const DOWN: usize = 2;
+const LEFT: usize = 3;
+
+// Draws box-drawing, block-element and Powerline separator characters as fills
+// spanning the exact cell box so neighbouring cells join without gaps. Returns false for other
+// characters, which are left to the font.
+pub(crate) fn push_builtin(ch: char, cell: Rect, color: [u8; 4], ops: &mut Vec<DrawOp>) -> bool {
+    match ch as u32 {
+        code @ 0x2500..=0x257F => match LINES[(code - 0x2500) as usize] {
+            Some((arms, dashes)) => {
+                push_lines(cell, arms, dashes, color, ops);
+                true
+            }
+            None => false,
+        },
+        code @ 0x2580..=0x259F => {
+            push_block(cell, code, color, ops);
+            true
+        }
+        code @ 0xE0B0..=0xE0B3 => {
+            push_powerline(cell, code, color, ops);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn push_lines(cell: Rect, arms: [u8; 4], dashes: u8, color: [u8; 4], ops: &mut Vec<DrawOp>) {
+    let light = (cell.width / 8).max(1);
+    let thickness = |weight: u8| match weight {
+        HEAVY => light * 2,
+        DOUBLE => light * 3,
+        _ => light,
+    };
+    let center_x = cell.width / 2;
+    let center_y = cell.height / 2;
+    // Arms reach past the centre by half the widest perpendicular stroke so
+    // junctions are filled.
+    let vertical = arms[UP].max(arms[DOWN]);
+    let horizontal = arms[LEFT].max(arms[RIGHT]);
+    let reach_x = if vertical == NONE {
+        0
+    } else {
+        thickness(vertical).div_ceil(2)
+    };
+    let reach_y = if horizontal == NONE {
+        0
+    } else {
+        thickness(horizontal).div_ceil(2)
+    };
+
+    let mut fill = |x: u32, y: u32, width: u32, height: u32| {
+        let x = x.min(cell.width);
+        let y = y.min(cell.height);
+        let width = width.min(cell.width - x);
+        let height = height.min(cell.height - y);
+        if width > 0 && height > 0 {
+            push_fill(cell, x, y, width, height, color, ops);
+        }
+    };
+    let mut stroke = |weight: u8, horizontal: bool, from: u32, to: u32| {
+        let strokes: &[u32] = match weight {
+            NONE => &[],
+            DOUBLE => &[0, 2],
+            _ => &[0],
+        };
+        let width = if weight == HEAVY { light * 2 } else { light };
+        let span = thickness(weight);
+        for offset in strokes {
+            let offset = offset * light;
+            if horizontal {
+                let y = (center_y + offset).saturating_sub(span / 2);
+                fill(from, y, to.saturating_sub(from), width);
+            } else {
+                let x = (center_x + offset).saturating_sub(span / 2);
+                fill(x, from, width, to.saturating_sub(from));
+            }
+        }
+    };
+
+    if dashes > 0 {
+        let horizontal = arms[LEFT] != NONE;
+        let weight = if horizontal { arms[LEFT] } else { arms[UP] };
+        let length = if horizontal { cell.width } else { cell.height };
+        for dash in 0..dashes as u32 {
+            let start = length * dash / dashes as u32;
+            let end = length * (dash + 1) / dashes as u32;
+            let gap = ((end - start) / 3).max(1);
+            stroke(weight, horizontal, start, end - gap);
+        }
+        return;
+    }
+
+    stroke(arms[LEFT], true, 0, center_x + reach_x);
+    stroke(
+        arms[RIGHT],
+        true,
+        center_x.saturating_sub(reach_x),
+        cell.width,
+    );
+    stroke(arms[UP], false, 0, center_y + reach_y);
+    stroke(
+        arms[DOWN],
+        false,
+        center_y.saturating_sub(reach_y),
+        cell.height,
+    );
+}
+
+fn push_block(cell: Rect, code: u32, color: [u8; 4], ops: &mut Vec<DrawOp>) {
+    let (w, h) = (cell.width, cell.height);
+    let eighth_w = |n: u32| w * n / 8;
+    let eighth_h = |n: u32| h * n / 8;
+    let (half_w, half_h) = (w / 2, h / 2);
+    let mut fill = |x: u32, y: u32, width: u32, height: u32| {
+        push_fill(cell, x, y, width, height, color, ops);
+    };
+    let mut quadrants =
+        |upper_left: bool, upper_right: bool, lower_left: bool, lower_right: bool| {
+            if upper_left {
+                fill(0, 0, half_w, half_h);
+            }
+            if upper_right {
+                fill(half_w, 0, w - half_w, half_h);
+            }
+            if lower_left {
+                fill(0, half_h, half_w, h - half_h);
+            }
+            if lower_right {
+                fill(half_w, half_h, w - half_w, h - half_h);
+            }
+        };
+
+    match code {
+        0x2580 => quadrants(true, true, false, false),
+        0x2581..=0x2588 => {
+            let height = eighth_h(code - 0x2580);
+            fill(0, h - height, w, height);
+        }
+        0x2589..=0x258F => fill(0, 0, eighth_w(0x2590 - code), h),
+        0x2590 => quadrants(false, true, false, true),
+        0x2591..=0x2593 => {
+            let alpha = (color[3] as u32 * (code - 0x2590) / 4) as u8;
+            push_fill(cell, 0, 0, w, h, [color[0], color[1], color[2], alpha], ops);
+        }
+        0x2594 => fill(0, 0, w, eighth_h(1)),
+        0x2595 => fill(w - eighth_w(1), 0, eighth_w(1), h),
+        0x2596 => quadrants(false, false, true, false),
+        0x2597 => quadrants(false, false, false, true),
+        0x2598 => quadrants(true, false, false, false),
+        0x2599 => quadrants(true, false, true, true),
+        0x259A => quadrants(true, false, false, true),
+        0x259B => quadrants(true, true, true, false),
+        0x259C => quadrants(true, true, false, true),
+        0x259D => quadrants(false, true, false, false),
+        0x259E => quadrants(false, true, true, false),
+        _ => quadrants(false, true, true, true),
+    }
+}
+
+// Separators are drawn one pixel row at a time. The solid triangles always
+// cover their flat edge so they meet the adjacent cell without a seam.
+fn push_powerline(cell: Rect, code: u32, color: [u8; 4], ops: &mut Vec<DrawOp>) {
+    let (w, h) = (cell.width, cell.height);
+    let solid = matches!(code, 0xE0B0 | 0xE0B2);
+    let points_right = code < 0xE0B2;
+    let thickness = (w / 8).max(1);
+    for row in 0..h {
+        let distance = ((row * 2 + 1) as f32 / h as f32 - 1.0).abs();
+        let reach = ((w as f32 * (1.0 - distance)).ceil() as u32).clamp(1, w);
+        let (start, width) = if solid {
+            (0, reach)
+        } else {
+            let start = reach.saturating_sub(thickness);
+            (start, reach - start)
+        };
+        let x = match points_right {
+            true => start,
+            false => w - start - width,
+        };
+        push_fill(cell, x, row, width, 1, color, ops);
+    }
+}
+
+fn push_fill(
+    cell: Rect,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    color: [u8; 4],
+    ops: &mut Vec<DrawOp>,
+) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    ops.push(DrawOp::Fill {
+        rect: Rect {
+            x: cell.x + x,
+            y: cell.y + y as i32,
+            width,
+            height,
+        },
+        color,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const SIZES: [(u32, u32); 2] = [(8, 16), (11, 23)];
+
+    fn cell(width: u32, height: u32) -> Rect {
+        Rect {
+            x: 100,
+            y: 40,
+            width,
+            height,
+        }
+    }
+
+    fn fills(ch: char, cell: Rect) -> Vec<Rect> {
+        let mut ops = Vec::new();
+        assert!(push_builtin(ch, cell, WHITE, &mut ops));
+        ops.into_iter()
+            .map(|op| match op {
+                DrawOp::Fill { rect, .. } => rect,
+                other => panic!("unexpected op {other:?}"),
+            })
+            .collect()
+    }
+
+    fn left(rects: &[Rect]) -> u32 {
+        rects.iter().map(|rect| rect.x).min().unwrap_or(u32::MAX)
+    }
+
+    fn right(rects: &[Rect]) -> u32 {
+        rects
+            .iter()
+            .map(|rect| rect.x + rect.width)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn top(rects: &[Rect]) -> i32 {
+        rects.iter().map(|rect| rect.y).min().unwrap_or(i32::MAX)
+    }
+
+    fn bottom(rects: &[Rect]) -> i32 {
+        rects
+            .iter()
+            .map(|rect| rect.y + rect.height as i32)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn inside(rects: &[Rect], cell: Rect) -> bool {
+        rects.iter().all(|rect| {
+            rect.x >= cell.x
+                && rect.x + rect.width <= cell.x + cell.width
+                && rect.y >= cell.y
+                && rect.y + rect.height as i32 <= cell.y + cell.height as i32
+        })
+    }
+
+    #[test]
+    fn horizontal_lines_reach_both_side_edges() {
+        for (width, height) in SIZES {
+            let cell = cell(width, height);
+            for ch in ['─', '━', '═'] {
+                let rects = fills(ch, cell);
+                assert!(inside(&rects, cell), "{ch} at {width}x{height}");
+                assert_eq!(left(&rects), cell.x, "{ch} at {width}x{height}");
+                assert_eq!(right(&rects), cell.x + width, "{ch} at {width}x{height}");
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_lines_reach_top_and_bottom_edges() {
+        for (width, height) in SIZES {
+            let cell = cell(width, height);
+            for ch in ['│', '┃', '║'] {
+                let rects = fills(ch, cell);
+                assert!(inside(&rects, cell), "{ch} at {width}x{height}");
+                assert_eq!(top(&rects), cell.y, "{ch} at {width}x{height}");
+                assert_eq!(
+                    bottom(&rects),
+                    cell.y + height as i32,
+                    "{ch} at {width}x{height}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn crossings_reach_all_four_edges() {
+        for (width, height) in SIZES {
+            let cell = cell(width, height);
+            for ch in ['┼', '╋', '╬'] {
+                let rects = fills(ch, cell);
+                assert!(inside(&rects, cell), "{ch} at {width}x{height}");
+                assert_eq!(left(&rects), cell.x);
+                assert_eq!(right(&rects), cell.x + width);
+                assert_eq!(top(&rects), cell.y);
+                assert_eq!(bottom(&rects), cell.y + height as i32);
+            }
+        }
+    }
+
+    #[test]
+    fn corners_reach_only_their_arm_edges() {
+        for (width, height) in SIZES {
+            let cell = cell(width, height);
+            let rects = fills('┌', cell);
+            assert_eq!(right(&rects), cell.x + width);
+            assert_eq!(bottom(&rects), cell.y + height as i32);
+            assert!(left(&rects) > cell.x);
+            assert!(top(&rects) > cell.y);
+        }
+    }
+
+    #[test]
+    fn full_block_covers_the_cell() {
+        for (width, height) in SIZES {
+            let cell = cell(width, height);
+            assert_eq!(fills('█', cell), vec![cell]);
+        }
+    }
+
+    #[test]
+    fn solid_powerline_separators_cover_their_flat_edge() {
+        for (width, height) in SIZES {
+            let cell = cell(width, height);
+            let right_arrow = fills('\u{e0b0}', cell);
+            assert!(inside(&right_arrow, cell));
+            assert_eq!(right_arrow.len(), height as usize);
+            assert!(right_arrow.iter().all(|rect| rect.x == cell.x));
+            assert_eq!(right(&right_arrow), cell.x + width);
+            assert_eq!(top(&right_arrow), cell.y);
+            assert_eq!(bottom(&right_arrow), cell.y + height as i32);
+
+            let left_arrow = fills('\u{e0b2}', cell);
+            assert!(inside(&left_arrow, cell));
+            assert!(left_arrow
+                .iter()
+                .all(|rect| rect.x + rect.width == cell.x + width));
+            assert_eq!(left(&left_arrow), cell.x);
+        }
+    }
+
+    #[test]
+    fn thin_powerline_separators_stay_inside_the_cell() {
+        for (width, height) in SIZES {
+            let cell = cell(width, height);
+            for ch in ['\u{e0b1}', '\u{e0b3}'] {
+                let rects = fills(ch, cell);
+                assert!(inside(&rects, cell));
+                assert_eq!(top(&rects), cell.y);
+                assert_eq!(bottom(&rects), cell.y + height as i32);
+            }
+        }
+    }
+
+    #[test]
+    fn other_characters_fall_back_to_the_font() {
+        let mut ops = Vec::new();
+        assert!(!push_builtin('a', cell(8, 16), WHITE, &mut ops));
+        assert!(!push_builtin('╱', cell(8, 16), WHITE, &mut ops));
+        assert!(ops.is_empty());
+    }
+}
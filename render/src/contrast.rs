@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use screen::Rgb;
+
+const MAX_CACHED_PAIRS: usize = 4096;
+const SEARCH_STEPS: u32 = 12;
+
+pub(crate) struct ContrastAdjuster {
+    ratio: f32,
+    cache: HashMap<(Rgb, Rgb), Rgb>,
+}
+
+impl ContrastAdjuster {
+    pub(crate) fn new(ratio: f32) -> Self {
+        Self {
+            ratio,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn ratio(&self) -> f32 {
+        self.ratio
+    }
+
+    pub(crate) fn adjust(&mut self, fg: Rgb, bg: Rgb) -> Rgb {
+        if let Some(adjusted) = self.cache.get(&(fg, bg)) {
+            return *adjusted;
+        }
+        let adjusted = enforce_contrast(fg, bg, self.ratio);
+        if self.cache.len() >= MAX_CACHED_PAIRS {
+            self.cache.clear();
+        }
+        self.cache.insert((fg, bg), adjusted);
+        adjusted
+    }
+}
+
+// Picks whichever candidate reads better on top of `bg`, preferring the first
+// on a tie.
+pub(crate) fn more_legible(bg: Rgb, first: Rgb, second: Rgb) -> Rgb {
+    let bg_luminance = luminance(bg);
+    if contrast(luminance(second), bg_luminance) > contrast(luminance(first), bg_luminance) {
+        second
+    } else {
+        first
+    }
+}
+
+fn enforce_contrast(fg: Rgb, bg: Rgb, ratio: f32) -> Rgb {
+    let bg_luminance = luminance(bg);
+    if contrast(luminance(fg), bg_luminance) >= ratio {
+        return fg;
+    }
+
+    let white = Rgb {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+    let black = Rgb { r: 0, g: 0, b: 0 };
+    let lighter_reaches = contrast(1.0, bg_luminance) >= ratio;
+    let darker_reaches = contrast(0.0, bg_luminance) >= ratio;
+    let target = match (lighter_reaches, darker_reaches) {
+        (true, true) if luminance(fg) >= bg_luminance => white,
+        (true, true) => black,
+        (true, false) => white,
+        (false, true) => black,
+        (false, false) if contrast(1.0, bg_luminance) >= contrast(0.0, bg_luminance) => {
+            return white
+        }
+        (false, false) => return black,
+    };
+
+    // Smallest blend toward the target that meets the ratio.
+    let (mut low, mut high) = (0.0f32, 1.0f32);
+    for _ in 0..SEARCH_STEPS {
+        let mid = (low + high) * 0.5;
+        if contrast(luminance(mix(fg, target, mid)), bg_luminance) >= ratio {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    mix(fg, target, high)
+}
+
+pub(crate) fn mix(from: Rgb, to: Rgb, amount: f32) -> Rgb {
+    let channel = |a: u8, b: u8| {
+        (a as f32 + (b as f32 - a as f32) * amount)
+            .round()
+            .clamp(0.0, 255.0) as u8
+    };
+    Rgb {
+        r: channel(from.r, to.r),
+        g: channel(from.g, to.g),
+        b: channel(from.b, to.b),
+    }
+}
+
+fn contrast(a: f32, b: f32) -> f32 {
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+fn luminance(color: Rgb) -> f32 {
+    let channel = |value: u8| {
+        let value = value as f32 / 255.0;
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(color.r) + 0.7152 * channel(color.g) + 0.0722 * channel(color.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgb = Rgb { r: 0, g: 0, b: 0 };
+    const WHITE: Rgb = Rgb {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+    const DARK_BLUE: Rgb = Rgb { r: 0, g: 0, b: 128 };
+
+    fn ratio_between(a: Rgb, b: Rgb) -> f32 {
+        contrast(luminance(a), luminance(b))
+    }
+
+    #[test]
+    fn contrast_spans_one_to_twenty_one() {
+        assert!((ratio_between(WHITE, BLACK) - 21.0).abs() < 0.01);
+        assert!((ratio_between(DARK_BLUE, DARK_BLUE) - 1.0).abs() < 0.01);
+        assert_eq!(ratio_between(WHITE, BLACK), ratio_between(BLACK, WHITE));
+    }
+
+    #[test]
+    fn readable_colours_are_left_alone() {
+        let mut adjuster = ContrastAdjuster::new(4.5);
+        assert_eq!(adjuster.adjust(WHITE, BLACK), WHITE);
+        assert_eq!(adjuster.adjust(BLACK, WHITE), BLACK);
+    }
+
+    #[test]
+    fn dark_text_on_black_is_lightened_to_the_ratio() {
+        let mut adjuster = ContrastAdjuster::new(4.5);
+        let adjusted = adjuster.adjust(DARK_BLUE, BLACK);
+        assert!(ratio_between(adjusted, BLACK) >= 4.5);
+        assert!(luminance(adjusted) > luminance(DARK_BLUE));
+        // Only as far as needed, not all the way to white.
+        assert_ne!(adjusted, WHITE);
+    }
+
+    #[test]
+    fn light_text_on_white_is_darkened_to_the_ratio() {
+        let pale = Rgb {
+            r: 220,
+            g: 220,
+            b: 170,
+        };
+        let mut adjuster = ContrastAdjuster::new(7.0);
+        let adjusted = adjuster.adjust(pale, WHITE);
+        assert!(ratio_between(adjusted, WHITE) >= 7.0);
+        assert!(luminance(adjusted) < luminance(pale));
+    }
+
+    #[test]
+    fn an_unreachable_ratio_picks_the_better_extreme() {
+        let grey = Rgb {
+            r: 119,
+            g: 119,
+            b: 119,
+        };
+        assert_eq!(enforce_contrast(grey, grey, 21.0), BLACK);
+        let light = Rgb {
+            r: 200,
+            g: 200,
+            b: 200,
+        };
+        assert_eq!(enforce_contrast(light, BLACK, 21.0), WHITE);
+    }
+
+    #[test]
+    fn adjusted_colours_are_cached_per_pair() {
+        let mut adjuster = ContrastAdjuster::new(4.5);
+        let first = adjuster.adjust(DARK_BLUE, BLACK);
+        assert_eq!(adjuster.cache.get(&(DARK_BLUE, BLACK)), Some(&first));
+        assert_eq!(adjuster.adjust(DARK_BLUE, BLACK), first);
+        assert_eq!(adjuster.cache.len(), 1);
+    }
+
+    #[test]
+    fn more_legible_prefers_the_first_on_a_tie() {
+        assert_eq!(more_legible(BLACK, DARK_BLUE, WHITE), WHITE);
+        assert_eq!(more_legible(BLACK, WHITE, WHITE), WHITE);
+        assert_eq!(more_legible(WHITE, BLACK, DARK_BLUE), BLACK);
+    }
+}
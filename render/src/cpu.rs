@@ -0,0 +1,567 @@
+use std::ops::{Range, RangeInclusive};
+
+use wgpu::util::DeviceExt;
+
+use crate::font::FontRasterizer;
+use crate::{DrawOp, Rect, RenderSize};
+
+pub(crate) struct CpuBackend {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+    texture_format: wgpu::TextureFormat,
+    vertex_buffer: wgpu::Buffer,
+    pixel_buffer: Vec<u8>,
+    texture_size: RenderSize,
+    row_stride: u32,
+    bands: Vec<Vec<DrawOp>>,
+    next_bands: Vec<Vec<DrawOp>>,
+    band_height: u32,
+    grid_top: u32,
+    background: [u8; 4],
+    clip: Range<u32>,
+}
+
+impl CpuBackend {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        alpha_mode: wgpu::CompositeAlphaMode,
+        size: RenderSize,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cpu_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("cpu_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("cpu_shader"),
+            source: wgpu::ShaderSource::Wgsl(RENDER_SHADER.into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("cpu_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: if alpha_mode == wgpu::CompositeAlphaMode::PreMultiplied {
+                    "fs_premultiplied"
+                } else {
+                    "fs_main"
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("render_vertex_buffer"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        // The pixel buffer holds sRGB-encoded bytes. An sRGB texture decodes
+        // them for an sRGB target to encode again; a UNORM target takes the
+        // bytes as they are, so the texture must not decode them either.
+        let texture_format = if format.is_srgb() {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        let (texture, bind_group, pixel_buffer, row_stride) =
+            create_texture_resources(device, &bind_group_layout, texture_format, size);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            texture,
+            texture_format,
+            vertex_buffer,
+            pixel_buffer,
+            texture_size: size,
+            row_stride,
+            bands: Vec::new(),
+            next_bands: Vec::new(),
+            band_height: 0,
+            grid_top: 0,
+            background: [0; 4],
+            clip: 0..size.height,
+        }
+    }
+
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, size: RenderSize) {
+        let (texture, bind_group, pixel_buffer, row_stride) =
+            create_texture_resources(device, &self.bind_group_layout, self.texture_format, size);
+        self.texture = texture;
+        self.bind_group = bind_group;
+        self.pixel_buffer = pixel_buffer;
+        self.texture_size = size;
+        self.row_stride = row_stride;
+        self.clip = 0..size.height;
+        self.invalidate();
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        self.bands.clear();
+    }
+
+    pub(crate) fn prepare(
+        &mut self,
+        queue: &wgpu::Queue,
+        ops: &[DrawOp],
+        font: &mut FontRasterizer,
+        background: [u8; 4],
+        grid_top: u32,
+    ) -> u64 {
+        let band_height = font.cell.height.max(1);
+        let band_count =
+            (self.texture_size.height.saturating_sub(grid_top) / band_height) as usize + 1;
+        let mut bands = std::mem::take(&mut self.next_bands);
+        bands.iter_mut().for_each(Vec::clear);
+        bands.resize_with(band_count, Vec::new);
+        let band_of = |y: i32| {
+            let y = y.saturating_sub(grid_top as i32).max(0) as u32;
+            ((y / band_height) as usize).min(band_count - 1)
+        };
+        for op in ops {
+            // Ops are stored in every band their rect or cell covers; glyph
+            // overhang past the cell is handled by redrawing neighbouring bands.
+            let covered = match *op {
+                DrawOp::Fill { rect, .. } => {
+                    band_of(rect.y)..=band_of(rect.y + rect.height.saturating_sub(1) as i32)
+                }
+                DrawOp::Glyph { y, .. } => band_of(y)..=band_of(y + band_height as i32 - 1),
+            };
+            for band in covered {
+                bands[band].push(*op);
+            }
+        }
+
+        let full = self.bands.len() != band_count
+            || self.band_height != band_height
+            || self.grid_top != grid_top
+            || self.background != background;
+        let band_top = |band: usize| {
+            if band == 0 {
+                0
+            } else {
+                grid_top + band as u32 * band_height
+            }
+        };
+        let mut uploaded = 0;
+        let mut band = 0;
+        while band < band_count {
+            if !full && bands[band] == self.bands[band] {
+                band += 1;
+                continue;
+            }
+            let first = band;
+            while band < band_count && (full || bands[band] != self.bands[band]) {
+                band += 1;
+            }
+            let last = band - 1;
+
+            let top = band_top(first).min(self.texture_size.height);
+            let bottom = if band == band_count {
+                self.texture_size.height
+            } else {
+                band_top(band).min(self.texture_size.height)
+            };
+            self.redraw_rows(
+                top..bottom,
+                first.saturating_sub(1)..=last + 1,
+                ops,
+                font,
+                background,
+                &band_of,
+            );
+            uploaded += self.upload_rows(queue, top..bottom);
+        }
+
+        self.next_bands = std::mem::replace(&mut self.bands, bands);
+        self.band_height = band_height;
+        self.grid_top = grid_top;
+        self.background = background;
+        uploaded
+    }
+
+    // Redraws every op touching `rows` in frame order, so overlapping ops
+    // from neighbouring bands stack exactly as in a full redraw.
+    fn redraw_rows(
+        &mut self,
+        rows: Range<u32>,
+        glyph_bands: RangeInclusive<usize>,
+        ops: &[DrawOp],
+        font: &mut FontRasterizer,
+        background: [u8; 4],
+        band_of: &impl Fn(i32) -> usize,
+    ) {
+        self.clip = rows.clone();
+        self.fill_rect(
+            Rect {
+                x: 0,
+                y: rows.start as i32,
+                width: self.texture_size.width,
+                height: rows.end - rows.start,
+            },
+            background,
+        );
+        for op in ops {
+            match *op {
+                DrawOp::Fill { rect, color } => {
+                    let bottom = rect.y + rect.height as i32;
+                    if rect.y < rows.end as i32 && bottom > rows.start as i32 {
+                        self.fill_rect(rect, color);
+                    }
+                }
+                DrawOp::Glyph {
+                    glyph,
+                    x,
+                    y,
+                    width,
+                    color,
+                } => {
+                    if glyph_bands.contains(&band_of(y)) {
+                        self.draw_glyph(font, glyph, x, y, width, color);
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn draw<'p>(&'p self, pass: &mut wgpu::RenderPass<'p>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..VERTICES.len() as u32, 0..1);
+    }
+
+    fn fill_rect(&mut self, rect: Rect, color: [u8; 4]) {
+        let stride = self.row_stride as usize;
+        let right = (rect.x.saturating_add(rect.width)).min(self.texture_size.width) as usize;
+        let top = rect.y.max(self.clip.start as i32) as usize;
+        let bottom = rect
+            .y
+            .saturating_add(rect.height as i32)
+            .clamp(0, self.clip.end as i32) as usize;
+        for py in top..bottom {
+            for px in rect.x as usize..right {
+                let idx = py * stride + px * 4;
+                if idx + 4 > self.pixel_buffer.len() {
+                    continue;
+                }
+                if color[3] == 255 {
+                    self.pixel_buffer[idx..idx + 4].copy_from_slice(&color);
+                } else {
+                    blend_pixel(&mut self.pixel_buffer[idx..idx + 4], color, 255);
+                }
+            }
+        }
+    }
+
+    fn draw_glyph(
+        &mut self,
+        font: &mut FontRasterizer,
+        glyph: u16,
+        x: u32,
+        y: i32,
+        cell_width: u32,
+        color: [u8; 4],
+    ) {
+        let baseline = font.baseline;
+        let glyph = match font.rasterize(glyph) {
+            Some(glyph) => glyph,
+            None => return,
+        };
+        let metrics = glyph.metrics;
+        if metrics.width == 0 || metrics.height == 0 {
+            return;
+        }
+
+        let (base_x, base_y) = metrics.position(cell_width, baseline, x, y);
+        let width = self.texture_size.width as usize;
+        let clip = self.clip.start as usize..self.clip.end as usize;
+        let stride = self.row_stride as usize;
+        let channels = if glyph.subpixel { 3 } else { 1 };
+        for gy in 0..metrics.height {
+            for gx in 0..metrics.width {
+                let offset = ((gy * metrics.width + gx) * channels) as usize;
+                let coverage = &glyph.data[offset..offset + channels as usize];
+                if coverage.iter().all(|alpha| *alpha == 0) {
+                    continue;
+                }
+                let px = base_x + gx as i32;
+                let py = base_y + gy as i32;
+                if px < 0 || py < 0 {
+                    continue;
+                }
+                let px = px as usize;
+                let py = py as usize;
+                if px >= width || !clip.contains(&py) {
+                    continue;
+                }
+                let idx = py * stride + px * 4;
+                if idx + 4 > self.pixel_buffer.len() {
+                    continue;
+                }
+                let dst = &mut self.pixel_buffer[idx..idx + 4];
+                match *coverage {
+                    [r, g, b] => blend_subpixel(dst, color, [r, g, b]),
+                    _ => blend_pixel(dst, color, coverage[0]),
+                }
+            }
+        }
+    }
+
+    fn upload_rows(&self, queue: &wgpu::Queue, rows: Range<u32>) -> u64 {
+        if rows.is_empty() {
+            return 0;
+        }
+        let offset = rows.start as u64 * self.row_stride as u64;
+        let height = rows.end - rows.start;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: rows.start,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &self.pixel_buffer,
+            wgpu::ImageDataLayout {
+                offset,
+                bytes_per_row: Some(self.row_stride),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d {
+                width: self.texture_size.width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        height as u64 * self.row_stride as u64
+    }
+}
+
+fn create_texture_resources(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    size: RenderSize,
+) -> (wgpu::Texture, wgpu::BindGroup, Vec<u8>, u32) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("render_text_texture"),
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("render_sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("render_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+        ],
+    });
+
+    let row_stride = aligned_row_bytes(size.width);
+    let pixel_buffer = vec![0u8; (row_stride * size.height) as usize];
+
+    (texture, bind_group, pixel_buffer, row_stride)
+}
+
+fn aligned_row_bytes(width: u32) -> u32 {
+    let bytes_per_pixel = 4;
+    let row_bytes = width * bytes_per_pixel;
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padding = (alignment - (row_bytes % alignment)) % alignment;
+    row_bytes + padding
+}
+
+// The pixel buffer holds straight (non-premultiplied) alpha so it can be
+// stored in an sRGB texture; the shader premultiplies when the surface needs it.
+fn blend_pixel(dst: &mut [u8], fg: [u8; 4], alpha: u8) {
+    let a = alpha as u32 * fg[3] as u32 / 255;
+    let dst_a = dst[3] as u32 * (255 - a) / 255;
+    let out_a = a + dst_a;
+    if out_a == 0 {
+        return;
+    }
+    for (dst, fg) in dst[..3].iter_mut().zip(fg) {
+        *dst = ((fg as u32 * a + *dst as u32 * dst_a) / out_a) as u8;
+    }
+    dst[3] = out_a as u8;
+}
+
+// Each colour channel is blended with its own coverage. Only used over an
+// opaque background, so the destination alpha just keeps the larger value.
+fn blend_subpixel(dst: &mut [u8], fg: [u8; 4], coverage: [u8; 3]) {
+    let mut out_a = dst[3];
+    for ((dst, channel), coverage) in dst[..3].iter_mut().zip(fg).zip(coverage) {
+        let a = coverage as u32 * fg[3] as u32 / 255;
+        *dst = ((channel as u32 * a + *dst as u32 * (255 - a)) / 255) as u8;
+        out_a = out_a.max(a as u8);
+    }
+    dst[3] = out_a;
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+}
+
+impl Vertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ],
+        }
+    }
+}
+
+const VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [-1.0, -1.0],
+        uv: [0.0, 1.0],
+    },
+    Vertex {
+        position: [1.0, -1.0],
+        uv: [1.0, 1.0],
+    },
+    Vertex {
+        position: [1.0, 1.0],
+        uv: [1.0, 0.0],
+    },
+    Vertex {
+        position: [-1.0, -1.0],
+        uv: [0.0, 1.0],
+    },
+    Vertex {
+        position: [1.0, 1.0],
+        uv: [1.0, 0.0],
+    },
+    Vertex {
+        position: [-1.0, 1.0],
+        uv: [0.0, 0.0],
+    },
+];
+
+const RENDER_SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec2<f32>, @location(1) uv: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var screen_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var screen_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(screen_texture, screen_sampler, in.uv);
+}
+
+@fragment
+fn fs_premultiplied(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(screen_texture, screen_sampler, in.uv);
+    return vec4<f32>(color.rgb * color.a, color.a);
+}
+"#;
@@ -0,0 +1,583 @@
+use std::collections::{HashMap, HashSet};
+
+use ab_glyph::{Font as _, FontVec, GlyphId, PxScale, ScaleFont, VariableFont};
+use fontdue::Font;
+use screen::Cell;
+
+use crate::{
+    CellMetrics, FontSpec, GlyphCacheStats, RenderError, TextAntialias, DEFAULT_CELL_METRICS,
+};
+
+const MAX_SHAPED_RUNS: usize = 4096;
+// Spreads each subpixel sample over its neighbours to tame colour fringes.
+const SUBPIXEL_FILTER: [u32; 5] = [1, 2, 3, 2, 1];
+
+pub(crate) struct FontRasterizer {
+    font: Font,
+    variable: Option<FontVec>,
+    bytes: Vec<u8>,
+    collection_index: u32,
+    weight: Option<u16>,
+    pub(crate) size: f32,
+    pub(crate) scale: CellScale,
+    pub(crate) antialias: TextAntialias,
+    cache: GlyphCache,
+    icons: HashSet<u16>,
+    shaped: HashMap<String, Vec<Option<u16>>>,
+    pub(crate) cell: CellMetrics,
+    pub(crate) baseline: f32,
+    pub(crate) decorations: DecorationMetrics,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct CellScale {
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+}
+
+impl Default for CellScale {
+    fn default() -> Self {
+        Self {
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct DecorationMetrics {
+    pub(crate) underline: u32,
+    pub(crate) strikethrough: u32,
+    pub(crate) thickness: u32,
+}
+
+impl FontRasterizer {
+    pub(crate) fn new(
+        spec: FontSpec,
+        scale: CellScale,
+        antialias: TextAntialias,
+    ) -> Result<Self, RenderError> {
+        let faces = rustybuzz::ttf_parser::fonts_in_collection(&spec.bytes).unwrap_or(1);
+        if spec.collection_index >= faces {
+            return Err(RenderError::Font(format!(
+                "font index {} out of range: the file has {faces} face(s)",
+                spec.collection_index
+            )));
+        }
+        let settings = fontdue::FontSettings {
+            collection_index: spec.collection_index,
+            ..fontdue::FontSettings::default()
+        };
+        let font = Font::from_bytes(spec.bytes.as_slice(), settings)
+            .map_err(|err| RenderError::Font(err.to_string()))?;
+        let variable = match spec.weight {
+            Some(weight) => variable_instance(&spec.bytes, spec.collection_index, weight),
+            None => None,
+        };
+        let natural = cell_metrics(&font, spec.size);
+        let cell = CellMetrics {
+            width: (natural.width as f32 * scale.width).round().max(1.0) as u32,
+            height: (natural.height as f32 * scale.height).round().max(1.0) as u32,
+        };
+        let cell_h = cell.height as f32;
+        // The line box is centred in the cell, so a scaled line height adds
+        // or removes space evenly above and below the text.
+        let baseline = match font.horizontal_line_metrics(spec.size) {
+            Some(metrics) => {
+                let line_height = metrics.ascent - metrics.descent;
+                let padding = (cell_h - line_height) * 0.5;
+                padding + metrics.ascent
+            }
+            None => cell_h * 0.8,
+        };
+        let thickness = (spec.size / 14.0).round().clamp(1.0, 2.0);
+        let x_height = font.metrics('x', spec.size).height as f32;
+        let decorations = DecorationMetrics {
+            underline: ((baseline + thickness).round().max(0.0) as u32)
+                .min(cell.height.saturating_sub(thickness as u32)),
+            strikethrough: (baseline - x_height * 0.5).round().max(0.0) as u32,
+            thickness: thickness as u32,
+        };
+        Ok(Self {
+            font,
+            variable,
+            bytes: spec.bytes,
+            collection_index: spec.collection_index,
+            weight: spec.weight,
+            size: spec.size,
+            scale,
+            antialias,
+            cache: GlyphCache::default(),
+            icons: HashSet::new(),
+            shaped: HashMap::new(),
+            cell,
+            baseline,
+            decorations,
+        })
+    }
+
+    pub(crate) fn spec(&self, size: f32) -> FontSpec {
+        FontSpec {
+            bytes: self.bytes.clone(),
+            size,
+            collection_index: self.collection_index,
+            weight: self.weight,
+        }
+    }
+
+    // Bitmaps depend on the mode, so changing it empties the cache.
+    pub(crate) fn set_antialias(&mut self, antialias: TextAntialias) -> bool {
+        if antialias == self.antialias {
+            return false;
+        }
+        self.antialias = antialias;
+        self.cache.clear();
+        true
+    }
+
+    pub(crate) fn glyph_index(&mut self, ch: char) -> u16 {
+        let index = self.font.lookup_glyph_index(ch);
+        self.note_icon(ch, index);
+        index
+    }
+
+    pub(crate) fn rasterize(&mut self, index: u16) -> Option<&GlyphBitmap> {
+        if !self.cache.touch(index) {
+            let glyph = match self.icons.contains(&index) {
+                true => self.rasterize_icon(index),
+                false => None,
+            };
+            let glyph = glyph.or_else(|| {
+                let variable = self.variable.as_ref()?;
+                Some(rasterize_variable(variable, index, self.size))
+            });
+            let mut glyph = glyph.unwrap_or_else(|| self.rasterize_outline(index));
+            if self.antialias == TextAntialias::None && !glyph.subpixel {
+                for coverage in &mut glyph.data {
+                    *coverage = if *coverage >= 128 { 255 } else { 0 };
+                }
+            }
+            self.cache.insert(index, glyph);
+        }
+        self.cache.get(index)
+    }
+
+    pub(crate) fn trim_cache(&mut self, budget: usize) {
+        self.cache.trim(budget);
+    }
+
+    pub(crate) fn cache_stats(&self, budget: usize) -> GlyphCacheStats {
+        self.cache.stats(budget)
+    }
+
+    fn rasterize_outline(&self, index: u16) -> GlyphBitmap {
+        if self.antialias == TextAntialias::Subpixel {
+            let (metrics, bitmap) = self.font.rasterize_indexed_subpixel(index, self.size);
+            if metrics.width > 0 && metrics.height > 0 {
+                return GlyphBitmap {
+                    metrics: GlyphMetrics {
+                        width: metrics.width as u32 + 2,
+                        height: metrics.height as u32,
+                        xmin: metrics.xmin - 1,
+                        ymin: metrics.ymin,
+                        advance_width: metrics.advance_width,
+                    },
+                    data: filter_subpixel(metrics.width, &bitmap),
+                    subpixel: true,
+                };
+            }
+        }
+        let (metrics, bitmap) = self.font.rasterize_indexed(index, self.size);
+        GlyphBitmap {
+            metrics: GlyphMetrics {
+                width: metrics.width as u32,
+                height: metrics.height as u32,
+                xmin: metrics.xmin,
+                ymin: metrics.ymin,
+                advance_width: metrics.advance_width,
+            },
+            data: bitmap,
+            subpixel: false,
+        }
+    }
+
+    // Icon fonts draw private-use glyphs at arbitrary sizes, so they are
+    // scaled to fit the cell box and centred in it instead of sitting on the
+    // baseline.
+    fn rasterize_icon(&self, index: u16) -> Option<GlyphBitmap> {
+        let natural = self.font.metrics_indexed(index, self.size);
+        if natural.width == 0 || natural.height == 0 {
+            return None;
+        }
+        let scale = (self.cell.width as f32 / natural.width as f32)
+            .min(self.cell.height as f32 / natural.height as f32);
+        let (metrics, bitmap) = self.font.rasterize_indexed(index, self.size * scale);
+        let height = metrics.height as u32;
+        Some(GlyphBitmap {
+            metrics: GlyphMetrics {
+                width: metrics.width as u32,
+                height,
+                xmin: 0,
+                ymin: (self.baseline - (self.cell.height + height) as f32 * 0.5).round() as i32,
+                advance_width: metrics.width as f32,
+            },
+            data: bitmap,
+            subpixel: false,
+        })
+    }
+
+    fn note_icon(&mut self, ch: char, index: u16) {
+        if index != 0 && is_private_use(ch) {
+            self.icons.insert(index);
+        }
+    }
+
+    pub(crate) fn shape_row(&mut self, cells: &[Cell], out: &mut Vec<Option<u16>>) {
+        out.clear();
+        let mut start = 0;
+        while start < cells.len() {
+            let style = (cells[start].fg, cells[start].bg, cells[start].flags);
+            let len = cells[start..]
+                .iter()
+                .take_while(|cell| (cell.fg, cell.bg, cell.flags) == style)
+                .count();
+            self.shape_run(&cells[start..start + len], out);
+            start += len;
+        }
+    }
+
+    fn shape_run(&mut self, cells: &[Cell], out: &mut Vec<Option<u16>>) {
+        let text: String = cells.iter().map(|cell| cell.ch).collect();
+        if let Some(glyphs) = self.shaped.get(&text) {
+            out.extend_from_slice(glyphs);
+            return;
+        }
+
+        let glyphs = self.shape_text(&text).unwrap_or_else(|| {
+            cells
+                .iter()
+                .map(|cell| Some(self.glyph_index(cell.ch)))
+                .collect()
+        });
+        for (cell, glyph) in cells.iter().zip(&glyphs) {
+            if let Some(glyph) = glyph {
+                self.note_icon(cell.ch, *glyph);
+            }
+        }
+        out.extend_from_slice(&glyphs);
+        if self.shaped.len() >= MAX_SHAPED_RUNS {
+            self.shaped.clear();
+        }
+        self.shaped.insert(text, glyphs);
+    }
+
+    fn shape_text(&self, text: &str) -> Option<Vec<Option<u16>>> {
+        let mut face = rustybuzz::Face::from_slice(&self.bytes, self.collection_index)?;
+        if let (Some(weight), Some(_)) = (self.weight, &self.variable) {
+            face.set_variation(
+                rustybuzz::ttf_parser::Tag::from_bytes(b"wght"),
+                weight as f32,
+            );
+        }
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.set_direction(rustybuzz::Direction::LeftToRight);
+        let shaped = rustybuzz::shape(&face, &[], buffer);
+
+        let mut cell_of_byte = vec![0; text.len()];
+        for (cell, (offset, ch)) in text.char_indices().enumerate() {
+            cell_of_byte[offset..offset + ch.len_utf8()].fill(cell);
+        }
+        let mut glyphs = vec![None; text.chars().count()];
+        for info in shaped.glyph_infos() {
+            let cell = *cell_of_byte.get(info.cluster as usize)?;
+            if glyphs[cell].is_none() {
+                glyphs[cell] = Some(info.glyph_id as u16);
+            }
+        }
+        Some(glyphs)
+    }
+}
+
+// fontdue cannot apply variation axes, so a requested weight is rendered
+// through ab_glyph when the font has a weight axis. Static fonts ignore it.
+fn variable_instance(bytes: &[u8], index: u32, weight: u16) -> Option<FontVec> {
+    let mut font = FontVec::try_from_vec_and_index(bytes.to_vec(), index).ok()?;
+    font.set_variation(b"wght", weight as f32).then_some(font)
+}
+
+fn rasterize_variable(font: &FontVec, index: u16, size: f32) -> GlyphBitmap {
+    let units_per_em = font.units_per_em().unwrap_or(font.height_unscaled());
+    let scale = PxScale::from(size * font.height_unscaled() / units_per_em);
+    let id = GlyphId(index);
+    let advance_width = font.as_scaled(scale).h_advance(id);
+    let Some(outline) = font.outline_glyph(id.with_scale(scale)) else {
+        return GlyphBitmap {
+            metrics: GlyphMetrics {
+                width: 0,
+                height: 0,
+                xmin: 0,
+                ymin: 0,
+                advance_width,
+            },
+            data: Vec::new(),
+            subpixel: false,
+        };
+    };
+    let bounds = outline.px_bounds();
+    let width = bounds.width() as u32;
+    let height = bounds.height() as u32;
+    let mut data = vec![0; (width * height) as usize];
+    outline.draw(|x, y, coverage| {
+        if let Some(pixel) = data.get_mut((y * width + x) as usize) {
+            *pixel = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    });
+    GlyphBitmap {
+        metrics: GlyphMetrics {
+            width,
+            height,
+            xmin: bounds.min.x as i32,
+            ymin: -(bounds.max.y as i32),
+            advance_width,
+        },
+        data,
+        subpixel: false,
+    }
+}
+
+// fontdue samples the outline at three times the horizontal resolution
+// without filtering. The output gains a pixel on each side to hold the
+// filter's spread, so it is `width + 2` pixels of RGB coverage per row.
+fn filter_subpixel(width: usize, samples: &[u8]) -> Vec<u8> {
+    let row_in = width * 3;
+    let row_out = (width + 2) * 3;
+    let rows = samples.len() / row_in;
+    let mut out = vec![0; row_out * rows];
+    for (src, dst) in samples
+        .chunks_exact(row_in)
+        .zip(out.chunks_exact_mut(row_out))
+    {
+        for (j, dst) in dst.iter_mut().enumerate() {
+            let mut sum = 0;
+            for (k, weight) in SUBPIXEL_FILTER.iter().enumerate() {
+                let i = (j + k).checked_sub(5).filter(|i| *i < row_in);
+                if let Some(i) = i {
+                    sum += weight * src[i] as u32;
+                }
+            }
+            let total: u32 = SUBPIXEL_FILTER.iter().sum();
+            *dst = ((sum + total / 2) / total) as u8;
+        }
+    }
+    out
+}
+
+fn is_private_use(ch: char) -> bool {
+    matches!(ch as u32, 0xE000..=0xF8FF | 0xF0000..=0x10FFFD)
+}
+
+fn cell_metrics(font: &Font, size: f32) -> CellMetrics {
+    let advance = ['0', 'M']
+        .into_iter()
+        .filter(|ch| font.lookup_glyph_index(*ch) != 0)
+        .map(|ch| font.metrics(ch, size).advance_width)
+        .find(|advance| *advance > 0.0);
+    let width = match advance {
+        Some(advance) => advance.round().max(1.0) as u32,
+        None => DEFAULT_CELL_METRICS.width,
+    };
+    let height = match font.horizontal_line_metrics(size) {
+        Some(metrics) => (metrics.ascent - metrics.descent + metrics.line_gap)
+            .round()
+            .max(1.0) as u32,
+        None => DEFAULT_CELL_METRICS.height,
+    };
+    CellMetrics { width, height }
+}
+
+// Rasterized bitmaps by glyph index, with the bytes they hold and the frame
+// each was last used in.
+#[derive(Default)]
+struct GlyphCache {
+    entries: HashMap<u16, CachedGlyph>,
+    bytes: usize,
+    generation: u64,
+    evictions: u64,
+}
+
+struct CachedGlyph {
+    bitmap: GlyphBitmap,
+    last_used: u64,
+}
+
+impl GlyphCache {
+    // Marks a cached glyph as used this frame; false if it isn't cached.
+    fn touch(&mut self, index: u16) -> bool {
+        match self.entries.get_mut(&index) {
+            Some(cached) => {
+                cached.last_used = self.generation;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, index: u16, bitmap: GlyphBitmap) {
+        self.bytes += bitmap.data.len();
+        let cached = CachedGlyph {
+            bitmap,
+            last_used: self.generation,
+        };
+        if let Some(old) = self.entries.insert(index, cached) {
+            self.bytes -= old.bitmap.data.len();
+        }
+    }
+
+    fn get(&self, index: u16) -> Option<&GlyphBitmap> {
+        self.entries.get(&index).map(|cached| &cached.bitmap)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+
+    // Called between frames so no bitmap is evicted while a frame still
+    // refers to it; the least recently used glyphs go first.
+    fn trim(&mut self, budget: usize) {
+        self.generation += 1;
+        if self.bytes <= budget {
+            return;
+        }
+        let mut by_age: Vec<(u64, u16)> = self
+            .entries
+            .iter()
+            .map(|(index, cached)| (cached.last_used, *index))
+            .collect();
+        by_age.sort_unstable();
+        for (_, index) in by_age {
+            if self.bytes <= budget {
+                break;
+            }
+            if let Some(cached) = self.entries.remove(&index) {
+                self.bytes -= cached.bitmap.data.len();
+                self.evictions += 1;
+            }
+        }
+    }
+
+    fn stats(&self, budget: usize) -> GlyphCacheStats {
+        GlyphCacheStats {
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            budget,
+            evictions: self.evictions,
+        }
+    }
+}
+
+pub(crate) struct GlyphBitmap {
+    pub(crate) metrics: GlyphMetrics,
+    // One coverage byte per pixel, or three (R, G, B) when `subpixel` is set.
+    pub(crate) data: Vec<u8>,
+    pub(crate) subpixel: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct GlyphMetrics {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) xmin: i32,
+    pub(crate) ymin: i32,
+    pub(crate) advance_width: f32,
+}
+
+impl GlyphMetrics {
+    pub(crate) fn position(&self, cell_width: u32, baseline: f32, x: u32, y: i32) -> (i32, i32) {
+        let mut base_x = x as f32;
+        if self.advance_width > 0.0 {
+            base_x += (cell_width as f32 - self.advance_width).max(0.0) * 0.5;
+        }
+        let base_y = y as f32 + baseline;
+        (
+            (base_x + self.xmin as f32).round() as i32,
+            (base_y - (self.ymin as f32 + self.height as f32)).round() as i32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitmap(bytes: usize) -> GlyphBitmap {
+        GlyphBitmap {
+            metrics: GlyphMetrics {
+                width: bytes as u32,
+                height: 1,
+                xmin: 0,
+                ymin: 0,
+                advance_width: bytes as f32,
+            },
+            data: vec![255; bytes],
+            subpixel: false,
+        }
+    }
+
+    fn cached(cache: &GlyphCache) -> Vec<u16> {
+        let mut indexes: Vec<u16> = cache.entries.keys().copied().collect();
+        indexes.sort_unstable();
+        indexes
+    }
+
+    #[test]
+    fn under_budget_nothing_is_evicted() {
+        let mut cache = GlyphCache::default();
+        cache.insert(1, bitmap(100));
+        cache.insert(2, bitmap(100));
+        cache.trim(200);
+        assert_eq!(cached(&cache), [1, 2]);
+        let stats = cache.stats(200);
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (2, 200, 0));
+    }
+
+    #[test]
+    fn least_recently_used_glyphs_are_evicted_at_the_limit() {
+        let mut cache = GlyphCache::default();
+        cache.insert(1, bitmap(100));
+        cache.insert(2, bitmap(100));
+        cache.trim(250);
+        cache.insert(3, bitmap(100));
+        assert!(cache.touch(1));
+        cache.trim(250);
+        assert_eq!(cached(&cache), [1, 3]);
+        let stats = cache.stats(250);
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (2, 200, 1));
+        assert!(!cache.touch(2));
+    }
+
+    #[test]
+    fn eviction_stops_once_the_cache_fits() {
+        let mut cache = GlyphCache::default();
+        for index in 0..10 {
+            cache.insert(index, bitmap(10));
+            cache.trim(usize::MAX);
+        }
+        cache.trim(35);
+        assert_eq!(cached(&cache), [7, 8, 9]);
+        assert_eq!(cache.stats(35).bytes, 30);
+        assert_eq!(cache.stats(35).evictions, 7);
+    }
+
+    #[test]
+    fn replacing_a_glyph_counts_its_bytes_once() {
+        let mut cache = GlyphCache::default();
+        cache.insert(1, bitmap(100));
+        cache.insert(1, bitmap(40));
+        assert_eq!(cache.stats(0).bytes, 40);
+        cache.clear();
+        assert_eq!(cache.stats(0).bytes, 0);
+        assert!(cache.get(1).is_none());
+    }
+}
@@ -0,0 +1,1287 @@
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use screen::{Cell, CellFlags, Color, CursorShape, Palette, Rgb, UnderlineStyle};
+use unicode_width::UnicodeWidthChar;
+
+use crate::boxdraw;
+use crate::contrast::{self, ContrastAdjuster};
+use crate::cpu::CpuBackend;
+use crate::font::{CellScale, FontRasterizer};
+use crate::gpu::GpuBackend;
+use crate::label;
+use crate::post::{PostEffect, PostProcess};
+use crate::{
+    color_to_wgpu, cursor_rect, hollow_cursor_rects, rgba, supports_transparency, CellMetrics,
+    CursorBlink, DrawOp, FontSpec, FrameCapture, FrameStats, GlyphCacheStats, GridLayout, Notice,
+    Rect, RenderBackend, RenderError, RenderGrid, RenderSize, RowSource, ScrollIndicator, TabBar,
+    TextAntialias, Theme, BELL_FLASH_ALPHA, BELL_FLASH_DURATION, DEFAULT_GLYPH_CACHE_BUDGET,
+    MAX_CELL_SCALE, MAX_CONTRAST_RATIO, MAX_FONT_SIZE, MIN_CELL_SCALE, MIN_FONT_SIZE, PADDING_X,
+    PADDING_Y, SCROLLBAR_MARGIN, SCROLLBAR_MIN_THUMB, SCROLLBAR_WIDTH, SCROLL_BADGE_ALPHA,
+    TAB_BAR_PADDING_Y, TAB_INACTIVE_ALPHA, TAB_PADDING_X,
+};
+
+pub(crate) struct FrameRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    format: wgpu::TextureFormat,
+    alpha_mode: wgpu::CompositeAlphaMode,
+    backend: Backend,
+    size: RenderSize,
+    padding: (u32, u32),
+    tab_bar: Option<TabBar>,
+    font: FontRasterizer,
+    scale_factor: f32,
+    theme: Theme,
+    text_antialias: TextAntialias,
+    ligatures: bool,
+    text_blink: bool,
+    cursor_blink: CursorBlink,
+    blink_epoch: Instant,
+    cursor_blinking: bool,
+    contrast: Option<ContrastAdjuster>,
+    row_glyphs: Vec<Option<u16>>,
+    ops: Vec<DrawOp>,
+    last_ops: Vec<DrawOp>,
+    stale: bool,
+    stats: FrameStats,
+    glyph_cache_budget: usize,
+    opacity: f32,
+    background: [u8; 4],
+    scissor: Option<Rect>,
+    post: Option<PostProcess>,
+    bell_flash: Option<Instant>,
+}
+
+enum Backend {
+    Gpu(GpuBackend),
+    Cpu(CpuBackend),
+}
+
+impl FrameRenderer {
+    pub(crate) fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        format: wgpu::TextureFormat,
+        alpha_mode: wgpu::CompositeAlphaMode,
+        size: RenderSize,
+        font: FontSpec,
+    ) -> Result<Self, RenderError> {
+        let font = FontRasterizer::new(font, CellScale::default(), TextAntialias::default())?;
+        let backend = Backend::Gpu(GpuBackend::new(&device, format, size));
+
+        Ok(Self {
+            device,
+            queue,
+            format,
+            alpha_mode,
+            backend,
+            size,
+            padding: (PADDING_X, PADDING_Y),
+            tab_bar: None,
+            font,
+            scale_factor: 1.0,
+            theme: Theme::default(),
+            text_antialias: TextAntialias::default(),
+            ligatures: false,
+            text_blink: true,
+            cursor_blink: CursorBlink::default(),
+            blink_epoch: Instant::now(),
+            cursor_blinking: false,
+            contrast: None,
+            row_glyphs: Vec::new(),
+            ops: Vec::new(),
+            last_ops: Vec::new(),
+            stale: true,
+            stats: FrameStats::default(),
+            glyph_cache_budget: DEFAULT_GLYPH_CACHE_BUDGET,
+            opacity: 1.0,
+            background: [0, 0, 0, 255],
+            scissor: None,
+            post: None,
+            bell_flash: None,
+        })
+    }
+
+    pub(crate) fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub(crate) fn resize(&mut self, size: RenderSize) -> Result<(), RenderError> {
+        self.size = size;
+        self.stale = true;
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.resize(size),
+            Backend::Cpu(cpu) => cpu.resize(&self.device, size),
+        }
+        if let Some(post) = &mut self.post {
+            post.resize(&self.device, self.format, size);
+        }
+        let validation = pop_error_scope(&self.device);
+        let memory = pop_error_scope(&self.device);
+        match validation.or(memory) {
+            Some(err) => Err(RenderError::Allocation(err.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn set_font(&mut self, font: FontSpec) -> Result<bool, RenderError> {
+        let previous = self.font.cell;
+        let font = FontSpec {
+            size: font.size * self.scale_factor,
+            ..font
+        };
+        self.font = FontRasterizer::new(font, self.font.scale, self.font.antialias)?;
+        self.clear_glyphs();
+        Ok(self.font.cell != previous)
+    }
+
+    // Font sizes are logical; the rasterizer works in physical pixels,
+    // `scale_factor` times larger.
+    pub(crate) fn set_font_size(&mut self, size: f32) -> Result<CellMetrics, RenderError> {
+        let size = size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE) * self.scale_factor;
+        if size != self.font.size {
+            self.font =
+                FontRasterizer::new(self.font.spec(size), self.font.scale, self.font.antialias)?;
+            self.clear_glyphs();
+        }
+        Ok(self.font.cell)
+    }
+
+    pub(crate) fn scale_factor(&self) -> f64 {
+        self.scale_factor as f64
+    }
+
+    pub(crate) fn set_scale_factor(&mut self, scale: f64) -> Result<CellMetrics, RenderError> {
+        let scale = scale as f32;
+        if !scale.is_finite() || scale <= 0.0 || scale == self.scale_factor {
+            return Ok(self.font.cell);
+        }
+        let size = self.font_size() * scale;
+        self.font =
+            FontRasterizer::new(self.font.spec(size), self.font.scale, self.font.antialias)?;
+        self.scale_factor = scale;
+        self.clear_glyphs();
+        Ok(self.font.cell)
+    }
+
+    pub(crate) fn line_height_scale(&self) -> f32 {
+        self.font.scale.height
+    }
+
+    pub(crate) fn set_line_height_scale(&mut self, scale: f32) -> Result<CellMetrics, RenderError> {
+        self.set_cell_scale(CellScale {
+            height: scale.clamp(MIN_CELL_SCALE, MAX_CELL_SCALE),
+            ..self.font.scale
+        })
+    }
+
+    pub(crate) fn cell_width_scale(&self) -> f32 {
+        self.font.scale.width
+    }
+
+    pub(crate) fn set_cell_width_scale(&mut self, scale: f32) -> Result<CellMetrics, RenderError> {
+        self.set_cell_scale(CellScale {
+            width: scale.clamp(MIN_CELL_SCALE, MAX_CELL_SCALE),
+            ..self.font.scale
+        })
+    }
+
+    fn set_cell_scale(&mut self, scale: CellScale) -> Result<CellMetrics, RenderError> {
+        if scale != self.font.scale {
+            self.font =
+                FontRasterizer::new(self.font.spec(self.font.size), scale, self.font.antialias)?;
+            self.clear_glyphs();
+        }
+        Ok(self.font.cell)
+    }
+
+    pub(crate) fn font_size(&self) -> f32 {
+        self.font.size / self.scale_factor
+    }
+
+    pub(crate) fn cell_metrics(&self) -> CellMetrics {
+        self.font.cell
+    }
+
+    // The tab bar's height is taken off the top before the grid is fitted.
+    pub(crate) fn grid_layout(&self) -> GridLayout {
+        let (padding_x, padding_y) = self.padding;
+        let bar = self.tab_bar_height();
+        let size = RenderSize {
+            width: self.size.width,
+            height: self.size.height.saturating_sub(bar),
+        };
+        let mut layout = GridLayout::with_padding(size, self.font.cell, padding_x, padding_y);
+        layout.origin_y += bar;
+        layout
+    }
+
+    fn tab_bar_height(&self) -> u32 {
+        match self.tab_bar {
+            Some(_) => self.font.cell.height + TAB_BAR_PADDING_Y * 2,
+            None => 0,
+        }
+    }
+
+    pub(crate) fn tab_bar(&self) -> Option<&TabBar> {
+        self.tab_bar.as_ref()
+    }
+
+    pub(crate) fn set_tab_bar(&mut self, tabs: Option<TabBar>) {
+        self.tab_bar = tabs;
+    }
+
+    pub(crate) fn set_padding(&mut self, x: u32, y: u32) {
+        self.padding = (x, y);
+    }
+
+    pub(crate) fn backend(&self) -> RenderBackend {
+        match self.backend {
+            Backend::Gpu(_) => RenderBackend::Gpu,
+            Backend::Cpu(_) => RenderBackend::Cpu,
+        }
+    }
+
+    pub(crate) fn set_backend(&mut self, backend: RenderBackend) {
+        if backend == self.backend() {
+            return;
+        }
+        self.stale = true;
+        self.backend = match backend {
+            RenderBackend::Gpu => {
+                Backend::Gpu(GpuBackend::new(&self.device, self.format, self.size))
+            }
+            RenderBackend::Cpu => Backend::Cpu(CpuBackend::new(
+                &self.device,
+                self.format,
+                self.alpha_mode,
+                self.size,
+            )),
+        };
+        self.sync_antialias();
+    }
+
+    pub(crate) fn text_antialias(&self) -> TextAntialias {
+        self.text_antialias
+    }
+
+    pub(crate) fn set_text_antialias(&mut self, antialias: TextAntialias) {
+        self.text_antialias = antialias;
+        self.sync_antialias();
+    }
+
+    // Subpixel coverage only blends correctly over an opaque background, and
+    // the GPU backend needs dual-source blending for it.
+    fn sync_antialias(&mut self) {
+        let subpixel_ok = self.opacity >= 1.0
+            && match &self.backend {
+                Backend::Gpu(gpu) => gpu.supports_subpixel(),
+                Backend::Cpu(_) => true,
+            };
+        let antialias = match self.text_antialias {
+            TextAntialias::Subpixel if !subpixel_ok => TextAntialias::Grayscale,
+            antialias => antialias,
+        };
+        if self.font.set_antialias(antialias) {
+            self.clear_glyphs();
+        }
+    }
+
+    pub(crate) fn set_ligatures(&mut self, enabled: bool) {
+        self.ligatures = enabled;
+    }
+
+    // With blinking disabled, blinking cells are drawn as bold instead.
+    pub(crate) fn set_text_blink(&mut self, enabled: bool) {
+        self.text_blink = enabled;
+    }
+
+    pub(crate) fn post_effect(&self) -> Option<&PostEffect> {
+        self.post.as_ref().map(PostProcess::effect)
+    }
+
+    pub(crate) fn set_post_effect(
+        &mut self,
+        effect: Option<PostEffect>,
+    ) -> Result<(), RenderError> {
+        if effect.as_ref() == self.post_effect() {
+            return Ok(());
+        }
+        self.post = effect
+            .map(|effect| PostProcess::new(&self.device, self.format, self.size, effect))
+            .transpose()?;
+        self.stale = true;
+        Ok(())
+    }
+
+    pub(crate) fn cursor_blink(&self) -> CursorBlink {
+        self.cursor_blink
+    }
+
+    pub(crate) fn set_cursor_blink(&mut self, blink: CursorBlink) {
+        self.cursor_blink = blink;
+        self.reset_cursor_blink();
+    }
+
+    pub(crate) fn reset_cursor_blink(&mut self) {
+        self.blink_epoch = Instant::now();
+    }
+
+    pub(crate) fn flash_bell(&mut self) {
+        self.bell_flash = Some(Instant::now());
+    }
+
+    pub(crate) fn next_blink_deadline(&self) -> Option<Instant> {
+        if !self.cursor_blinking {
+            return None;
+        }
+        let interval = self.cursor_blink.interval.as_nanos();
+        let phases = self.blink_epoch.elapsed().as_nanos() / interval + 1;
+        let offset = Duration::from_nanos(u64::try_from(phases * interval).ok()?);
+        Some(self.blink_epoch + offset)
+    }
+
+    // The cursor shows during even blink phases counted from the epoch.
+    fn cursor_blink_on(&self) -> bool {
+        let interval = self.cursor_blink.interval.as_nanos();
+        !self.cursor_blinking
+            || (self.blink_epoch.elapsed().as_nanos() / interval).is_multiple_of(2)
+    }
+
+    pub(crate) fn minimum_contrast(&self) -> Option<f32> {
+        self.contrast.as_ref().map(ContrastAdjuster::ratio)
+    }
+
+    pub(crate) fn set_minimum_contrast(&mut self, ratio: Option<f32>) {
+        let ratio = ratio
+            .map(|ratio| ratio.min(MAX_CONTRAST_RATIO))
+            .filter(|ratio| *ratio > 1.0);
+        if ratio != self.minimum_contrast() {
+            self.contrast = ratio.map(ContrastAdjuster::new);
+        }
+    }
+
+    pub(crate) fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    pub(crate) fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        if let Backend::Cpu(cpu) = &mut self.backend {
+            cpu.invalidate();
+        }
+    }
+
+    pub(crate) fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    pub(crate) fn supports_transparency(&self) -> bool {
+        supports_transparency(self.alpha_mode)
+    }
+
+    pub(crate) fn set_opacity(&mut self, opacity: f32) -> Result<(), RenderError> {
+        let opacity = opacity.clamp(0.0, 1.0);
+        if opacity < 1.0 && !supports_transparency(self.alpha_mode) {
+            self.opacity = 1.0;
+            self.sync_antialias();
+            return Err(RenderError::TransparencyUnsupported);
+        }
+        self.opacity = opacity;
+        self.sync_antialias();
+        Ok(())
+    }
+
+    // Returns false when the frame is identical to the one already prepared,
+    // in which case the backend is left untouched and nothing needs
+    // presenting.
+    pub(crate) fn prepare<R: RowSource + ?Sized>(
+        &mut self,
+        grid: &RenderGrid<'_, R>,
+    ) -> Result<bool, RenderError> {
+        self.font.trim_cache(self.glyph_cache_budget);
+        let previous_scissor = self.scissor;
+        self.build_frame(grid)?;
+        let mut background = rgba(grid.palette.background);
+        background[3] = (self.opacity * 255.0).round() as u8;
+        if !self.stale
+            && background == self.background
+            && self.scissor == previous_scissor
+            && self.ops == self.last_ops
+        {
+            self.stats = FrameStats::default();
+            return Ok(false);
+        }
+        self.stale = false;
+        self.last_ops.clone_from(&self.ops);
+        let grid_top = self.grid_layout().origin_y;
+        let bytes_uploaded = match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.prepare(&self.device, &self.queue, &self.ops, &mut self.font),
+            Backend::Cpu(cpu) => {
+                cpu.prepare(&self.queue, &self.ops, &mut self.font, background, grid_top)
+            }
+        };
+        self.stats = FrameStats {
+            bytes_uploaded,
+            ..FrameStats::default()
+        };
+        self.background = background;
+        Ok(true)
+    }
+
+    pub(crate) fn present(&self, texture: &wgpu::Texture) {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("render_encoder"),
+            });
+        self.encode_frame(&mut encoder, &view);
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    pub(crate) fn capture_frame(&self) -> Result<FrameCapture, RenderError> {
+        let RenderSize { width, height } = self.size;
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("capture_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bytes_per_pixel = self
+            .format
+            .block_copy_size(None)
+            .filter(|size| *size == 4)
+            .ok_or_else(|| RenderError::Capture(format!("unsupported format {:?}", self.format)))?;
+        let unpadded_row = width * bytes_per_pixel;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = unpadded_row.div_ceil(alignment) * alignment;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("capture_buffer"),
+            size: padded_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("capture_encoder"),
+            });
+        self.encode_frame(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.recv()
+            .map_err(|err| RenderError::Capture(err.to_string()))?
+            .map_err(|err| RenderError::Capture(err.to_string()))?;
+
+        let bgra = matches!(
+            self.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut rgba = Vec::with_capacity((unpadded_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_row as usize) {
+                rgba.extend_from_slice(&row[..unpadded_row as usize]);
+            }
+        }
+        buffer.unmap();
+        if bgra {
+            rgba.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        Ok(FrameCapture {
+            width,
+            height,
+            rgba,
+        })
+    }
+
+    // With a post effect the frame is drawn into the effect's source texture
+    // and the effect pass writes the final image; otherwise it is drawn
+    // straight into the target.
+    fn encode_frame(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        match &self.post {
+            Some(post) => {
+                self.encode_pass(encoder, post.view());
+                post.encode(&self.queue, encoder, view, self.size);
+            }
+            None => self.encode_pass(encoder, view),
+        }
+    }
+
+    fn encode_pass(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(color_to_wgpu(
+                        self.background,
+                        self.format.is_srgb(),
+                        self.alpha_mode == wgpu::CompositeAlphaMode::PreMultiplied,
+                    )),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        if let Some(rect) = self.scissor {
+            pass.set_scissor_rect(rect.x, rect.y as u32, rect.width, rect.height);
+        }
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.draw(&mut pass),
+            Backend::Cpu(cpu) => cpu.draw(&mut pass),
+        }
+    }
+
+    pub(crate) fn last_frame_stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    pub(crate) fn glyph_cache_stats(&self) -> GlyphCacheStats {
+        self.font.cache_stats(self.glyph_cache_budget)
+    }
+
+    pub(crate) fn set_glyph_cache_budget(&mut self, bytes: usize) {
+        self.glyph_cache_budget = bytes;
+    }
+
+    fn clear_glyphs(&mut self) {
+        self.stale = true;
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.clear_glyphs(),
+            Backend::Cpu(cpu) => cpu.invalidate(),
+        }
+    }
+
+    fn build_frame<R: RowSource + ?Sized>(
+        &mut self,
+        grid: &RenderGrid<'_, R>,
+    ) -> Result<(), RenderError> {
+        let expected = grid.cols as usize * grid.rows as usize;
+        if let Some(actual) = grid.cells.cell_count().filter(|actual| *actual != expected) {
+            return Err(RenderError::GridMismatch { expected, actual });
+        }
+
+        self.ops.clear();
+        let palette = grid.palette;
+        let background = rgba(palette.background);
+        let layout = self.grid_layout();
+        let cell_size = layout.cell;
+        let max_cols = layout.cols as usize;
+        let max_rows = layout.rows as usize;
+        let cols = grid.cols.min(max_cols as u16) as usize;
+        let scroll_offset =
+            (grid.scroll_pixel_offset.clamp(0.0, cell_size.height as f32)).round() as i32;
+        let drawn_rows = if scroll_offset > 0 {
+            max_rows + 1
+        } else {
+            max_rows
+        };
+        let rows = (grid.rows as usize).min(drawn_rows);
+        // With a tab bar the clip starts at the top edge instead; the bar is
+        // drawn over the row scrolled above the grid.
+        let clip_top = match self.tab_bar {
+            Some(_) => 0,
+            None => layout.origin_y,
+        };
+        self.scissor = (scroll_offset > 0).then(|| Rect {
+            x: 0,
+            y: clip_top as i32,
+            width: self.size.width,
+            height: (layout.origin_y - clip_top + max_rows as u32 * cell_size.height)
+                .min(self.size.height.saturating_sub(clip_top)),
+        });
+        let row_y = |row: usize| {
+            layout.origin_y as i32 + (row as u32 * cell_size.height) as i32 - scroll_offset
+        };
+
+        for row in 0..rows {
+            let Some(row_cells) = grid.cells.row(row, grid.cols as usize) else {
+                continue;
+            };
+            let row_cells = &row_cells[..cols.min(row_cells.len())];
+            if self.ligatures {
+                self.font.shape_row(row_cells, &mut self.row_glyphs);
+            } else {
+                self.row_glyphs.clear();
+                self.row_glyphs.extend(
+                    row_cells
+                        .iter()
+                        .map(|cell| Some(self.font.glyph_index(cell.ch))),
+                );
+            }
+            for (col, cell) in row_cells.iter().enumerate() {
+                if cell.flags.contains(CellFlags::WIDE_SPACER) {
+                    continue;
+                }
+                let wide = cell.flags.contains(CellFlags::WIDE);
+                let fits = !wide || col + 1 < cols;
+                let width = if wide && fits {
+                    cell_size.width * 2
+                } else {
+                    cell_size.width
+                };
+                let x = layout.origin_x + col as u32 * cell_size.width;
+                let y = row_y(row);
+                let (fg_color, mut bg_color) = self.cell_colors(cell, palette);
+                if grid
+                    .selection
+                    .is_some_and(|selection| selection.contains(col as u16, row as u16))
+                {
+                    bg_color = self.theme.selection;
+                }
+                let bg = rgba(bg_color);
+                if bg != background {
+                    self.ops.push(DrawOp::Fill {
+                        rect: Rect {
+                            x,
+                            y,
+                            width,
+                            height: cell_size.height,
+                        },
+                        color: bg,
+                    });
+                }
+                if self.text_blink && !grid.blink_on && cell.flags.contains(CellFlags::BLINK) {
+                    continue;
+                }
+                let mut fg = fg_color;
+                if cell.flags.contains(CellFlags::FAINT) {
+                    fg = contrast::mix(fg, bg_color, self.theme.faint_factor.clamp(0.0, 1.0));
+                }
+                if let Some(contrast) = &mut self.contrast {
+                    fg = contrast.adjust(fg, bg_color);
+                }
+                let cell_box = Rect {
+                    x,
+                    y,
+                    width,
+                    height: cell_size.height,
+                };
+                let builtin =
+                    fits && boxdraw::push_builtin(cell.ch, cell_box, rgba(fg), &mut self.ops);
+                if let (Some(glyph), true) = (self.row_glyphs[col], fits && !builtin) {
+                    self.ops.push(DrawOp::Glyph {
+                        glyph,
+                        x,
+                        y,
+                        width,
+                        color: rgba(fg),
+                    });
+                }
+                let linked = grid
+                    .link
+                    .is_some_and(|link| link.contains(col as u16, row as u16));
+                if linked && cell.underline.is_none() {
+                    let cell = Cell {
+                        underline: Some(UnderlineStyle::Single),
+                        ..*cell
+                    };
+                    self.push_decorations(&cell, palette, fg, x, y, width);
+                } else {
+                    self.push_decorations(cell, palette, fg, x, y, width);
+                }
+            }
+        }
+
+        let preedit = grid.preedit.is_some_and(|preedit| !preedit.text.is_empty());
+        if preedit {
+            self.push_preedit(grid, layout, rows.min(max_rows), scroll_offset);
+        }
+
+        // Unfocused windows keep a steady hollow cursor.
+        self.cursor_blinking = self.cursor_blink.enabled
+            && !self.cursor_blink.interval.is_zero()
+            && grid.cursor_blinking
+            && grid.focused
+            && grid.cursor_visible
+            && grid.cursor.is_some();
+        if grid.cursor_visible && self.cursor_blink_on() && !preedit {
+            if let Some(cursor) = grid.cursor {
+                if cursor.col < grid.cols && cursor.row < grid.rows {
+                    let cursor_row = grid.cells.row(cursor.row as usize, grid.cols as usize);
+                    let cell_at = |col: u16| cursor_row.and_then(|row| row.get(col as usize));
+                    let mut col = cursor.col;
+                    if col > 0
+                        && cell_at(col)
+                            .is_some_and(|cell| cell.flags.contains(CellFlags::WIDE_SPACER))
+                    {
+                        col -= 1;
+                    }
+                    let wide = cell_at(col)
+                        .is_some_and(|cell| cell.flags.contains(CellFlags::WIDE))
+                        && (col as usize + 1) < cols;
+                    let cursor_cell = CellMetrics {
+                        width: if wide {
+                            cell_size.width * 2
+                        } else {
+                            cell_size.width
+                        },
+                        height: cell_size.height,
+                    };
+                    let x = layout.origin_x + col as u32 * cell_size.width;
+                    let y = row_y(cursor.row as usize);
+                    let (cell_fg, cell_bg) = match cell_at(col) {
+                        Some(cell) => self.cell_colors(cell, palette),
+                        None => (palette.foreground, palette.background),
+                    };
+                    let (cursor_color, text_color) = match palette.cursor {
+                        Some(color) => (color, contrast::more_legible(color, cell_bg, cell_fg)),
+                        None => (cell_fg, cell_bg),
+                    };
+                    if !grid.focused {
+                        for rect in hollow_cursor_rects(cursor_cell, x, y) {
+                            self.ops.push(DrawOp::Fill {
+                                rect,
+                                color: rgba(cursor_color),
+                            });
+                        }
+                    } else {
+                        self.ops.push(DrawOp::Fill {
+                            rect: cursor_rect(
+                                grid.cursor_shape,
+                                cursor_cell,
+                                x,
+                                y,
+                                self.cursor_thickness(grid.cursor_shape, cursor_cell),
+                            ),
+                            color: rgba(cursor_color),
+                        });
+                    }
+                    let filled_block = grid.focused && grid.cursor_shape == CursorShape::Block;
+                    if let Some(cell) = cell_at(col).filter(|_| filled_block) {
+                        let color = rgba(text_color);
+                        let cell_box = Rect {
+                            x,
+                            y,
+                            width: cursor_cell.width,
+                            height: cursor_cell.height,
+                        };
+                        if !boxdraw::push_builtin(cell.ch, cell_box, color, &mut self.ops) {
+                            self.ops.push(DrawOp::Glyph {
+                                glyph: self.font.glyph_index(cell.ch),
+                                x,
+                                y,
+                                width: cursor_cell.width,
+                                color,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        self.push_tab_bar(layout.origin_y, rgba(palette.background));
+
+        if let Some(indicator) = grid.scroll_indicator {
+            self.push_scrollbar(
+                indicator,
+                layout.origin_y,
+                rows.min(max_rows) as u32 * cell_size.height,
+            );
+            self.push_scroll_badge(indicator, layout.origin_y);
+        }
+        if let Some(notice) = grid.notice {
+            self.push_notice(notice, layout.origin_y);
+        }
+
+        self.push_bell_flash();
+
+        Ok(())
+    }
+
+    // Fades out over BELL_FLASH_DURATION.
+    fn push_bell_flash(&mut self) {
+        let Some(started) = self.bell_flash else {
+            return;
+        };
+        let progress = started.elapsed().as_secs_f32() / BELL_FLASH_DURATION.as_secs_f32();
+        if progress >= 1.0 {
+            self.bell_flash = None;
+            return;
+        }
+        let mut color = rgba(self.theme.foreground);
+        color[3] = ((1.0 - progress) * BELL_FLASH_ALPHA * 255.0).round() as u8;
+        self.ops.push(DrawOp::Fill {
+            rect: Rect {
+                x: 0,
+                y: 0,
+                width: self.size.width,
+                height: self.size.height,
+            },
+            color,
+        });
+    }
+
+    // Clamped so the cursor stays inside the cell; the bar starts a pixel in
+    // from the left edge.
+    fn cursor_thickness(&self, shape: CursorShape, cell: CellMetrics) -> u32 {
+        let extent = match shape {
+            CursorShape::Bar => cell.width.saturating_sub(1),
+            _ => cell.height,
+        };
+        let thickness = self.theme.cursor_thickness.max(0.0);
+        let pixels = match thickness < 1.0 {
+            true => thickness * extent as f32,
+            false => thickness * self.scale_factor,
+        };
+        (pixels.round() as u32).clamp(1, extent.max(1))
+    }
+
+    // Resolved (foreground, background) for a cell, shared by the cell and
+    // cursor drawing. Inverse swaps the pair after defaults are resolved.
+    fn cell_colors(&self, cell: &Cell, palette: &Palette) -> (Rgb, Rgb) {
+        let fg = self.cell_foreground(cell, palette);
+        let bg = palette.resolve(cell.bg, palette.background);
+        if cell.flags.contains(CellFlags::INVERSE) {
+            (bg, fg)
+        } else {
+            (fg, bg)
+        }
+    }
+
+    fn cell_foreground(&self, cell: &Cell, palette: &Palette) -> Rgb {
+        let bold = cell.flags.contains(CellFlags::BOLD)
+            || (!self.text_blink && cell.flags.contains(CellFlags::BLINK));
+        let fg = match cell.fg {
+            Color::Indexed(index) if index < 8 && self.theme.bold_is_bright && bold => {
+                Color::Indexed(index + 8)
+            }
+            color => color,
+        };
+        palette.resolve(fg, palette.foreground)
+    }
+
+    fn push_scrollbar(&mut self, indicator: ScrollIndicator, track_y: u32, track_height: u32) {
+        let alpha = (indicator.opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let position = indicator.position;
+        if alpha == 0 || track_height == 0 || position.scrollback == 0 {
+            return;
+        }
+
+        let x = self
+            .size
+            .width
+            .saturating_sub(SCROLLBAR_WIDTH + SCROLLBAR_MARGIN);
+        let total = position.scrollback as u64 + position.rows as u64;
+        let thumb_height = ((track_height as u64 * position.rows as u64 / total) as u32)
+            .clamp(SCROLLBAR_MIN_THUMB.min(track_height), track_height);
+        let lines_above = position.scrollback.saturating_sub(position.offset) as u64;
+        let travel = (track_height - thumb_height) as u64;
+        let thumb_y = track_y as i32 + (travel * lines_above / position.scrollback as u64) as i32;
+
+        let mut track = rgba(self.theme.scrollbar_track);
+        track[3] = alpha;
+        let mut thumb = rgba(self.theme.scrollbar_thumb);
+        thumb[3] = alpha;
+        self.ops.push(DrawOp::Fill {
+            rect: Rect {
+                x,
+                y: track_y as i32,
+                width: SCROLLBAR_WIDTH,
+                height: track_height,
+            },
+            color: track,
+        });
+        self.ops.push(DrawOp::Fill {
+            rect: Rect {
+                x,
+                y: thumb_y,
+                width: SCROLLBAR_WIDTH,
+                height: thumb_height,
+            },
+            color: thumb,
+        });
+    }
+
+    // Lays the preedit out cell by cell from the cursor, wrapping onto the
+    // following rows and stopping at the bottom of the grid. The cells
+    // underneath are only covered, never modified.
+    fn push_preedit<R: RowSource + ?Sized>(
+        &mut self,
+        grid: &RenderGrid<'_, R>,
+        layout: GridLayout,
+        rows: usize,
+        scroll_offset: i32,
+    ) {
+        let (Some(preedit), Some(cursor)) = (grid.preedit, grid.cursor) else {
+            return;
+        };
+        let palette = grid.palette;
+        let cols = (grid.cols as usize).min(layout.cols as usize);
+        let cell_size = layout.cell;
+        let metrics = self.font.decorations;
+        let fg = palette.foreground;
+        let bg = palette.background;
+        let caret_color = rgba(palette.cursor.unwrap_or(fg));
+        let mut col = cursor.col as usize;
+        let mut row = cursor.row as usize;
+        let position = |col: usize, row: usize| {
+            (
+                layout.origin_x + col as u32 * cell_size.width,
+                layout.origin_y as i32 + (row as u32 * cell_size.height) as i32 - scroll_offset,
+            )
+        };
+        let mut caret = None;
+        for (offset, ch) in preedit.text.char_indices() {
+            let span = match ch.width() {
+                Some(width @ 1..=2) => width,
+                _ => continue,
+            };
+            if col + span > cols {
+                col = 0;
+                row += 1;
+            }
+            if row >= rows {
+                return;
+            }
+            if preedit.cursor == Some(offset) {
+                caret = Some((col, row));
+            }
+            let highlighted = preedit
+                .highlights
+                .iter()
+                .any(|range| range.contains(&offset));
+            let (fg, bg) = if highlighted { (bg, fg) } else { (fg, bg) };
+            let (x, y) = position(col, row);
+            let width = cell_size.width * span as u32;
+            self.ops.push(DrawOp::Fill {
+                rect: Rect {
+                    x,
+                    y,
+                    width,
+                    height: cell_size.height,
+                },
+                color: rgba(bg),
+            });
+            self.ops.push(DrawOp::Glyph {
+                glyph: self.font.glyph_index(ch),
+                x,
+                y,
+                width,
+                color: rgba(fg),
+            });
+            self.ops.push(DrawOp::Fill {
+                rect: Rect {
+                    x,
+                    y: y + metrics.underline as i32,
+                    width,
+                    height: metrics.thickness,
+                },
+                color: rgba(fg),
+            });
+            col += span;
+        }
+        if preedit.cursor == Some(preedit.text.len()) {
+            caret = Some((col.min(cols.saturating_sub(1)), row));
+        }
+        if let Some((col, row)) = caret.filter(|(_, row)| *row < rows) {
+            let (x, y) = position(col, row);
+            self.ops.push(DrawOp::Fill {
+                rect: cursor_rect(
+                    CursorShape::Bar,
+                    cell_size,
+                    x,
+                    y,
+                    self.cursor_thickness(CursorShape::Bar, cell_size),
+                ),
+                color: caret_color,
+            });
+        }
+    }
+
+    // Equal-width tabs across a strip that reaches down to the grid. The
+    // active tab takes the grid's background so it joins the terminal below;
+    // titles that don't fit end in an ellipsis.
+    fn push_tab_bar(&mut self, bottom: u32, background: [u8; 4]) {
+        let Some(tabs) = &self.tab_bar else {
+            return;
+        };
+        let cell = self.font.cell;
+        let tab_width = self.size.width / tabs.titles.len().max(1) as u32;
+        let max_chars = (tab_width.saturating_sub(TAB_PADDING_X * 2) / cell.width.max(1)) as usize;
+        let text_y = (bottom.saturating_sub(cell.height) / 2) as i32;
+        self.ops.push(DrawOp::Fill {
+            rect: Rect {
+                x: 0,
+                y: 0,
+                width: self.size.width,
+                height: bottom,
+            },
+            color: rgba(self.theme.scrollbar_track),
+        });
+        for (index, title) in tabs.titles.iter().enumerate() {
+            let x = index as u32 * tab_width;
+            let active = index == tabs.active;
+            let mut color = rgba(self.theme.foreground);
+            if active {
+                self.ops.push(DrawOp::Fill {
+                    rect: Rect {
+                        x,
+                        y: 0,
+                        width: tab_width,
+                        height: bottom,
+                    },
+                    color: background,
+                });
+            } else {
+                color[3] = TAB_INACTIVE_ALPHA;
+            }
+            let truncated = title.chars().count() > max_chars;
+            let shown = title
+                .chars()
+                .take(max_chars.saturating_sub(usize::from(truncated)))
+                .chain(truncated.then_some('\u{2026}'));
+            let mut glyph_x = x + TAB_PADDING_X;
+            for ch in shown {
+                self.ops.push(DrawOp::Glyph {
+                    glyph: self.font.glyph_index(ch),
+                    x: glyph_x,
+                    y: text_y,
+                    width: cell.width,
+                    color,
+                });
+                glyph_x += cell.width;
+            }
+        }
+    }
+
+    // "top line/total lines" in the top-right corner while scrolled back,
+    // fading with the scrollbar unless there is new output to point at.
+    fn push_scroll_badge(&mut self, indicator: ScrollIndicator, top: u32) {
+        let opacity = match indicator.new_output {
+            true => 1.0,
+            false => indicator.opacity.clamp(0.0, 1.0),
+        };
+        let position = indicator.position;
+        if opacity == 0.0 || position.offset == 0 {
+            return;
+        }
+
+        let total = position.scrollback + position.rows as usize;
+        let line = position.scrollback.saturating_sub(position.offset) + 1;
+        let text = match indicator.new_output {
+            true => format!("{line}/{total} \u{2193} new output"),
+            false => format!("{line}/{total}"),
+        };
+        let width = label::label_size(self.font.cell, &text).width;
+        let x = self
+            .size
+            .width
+            .saturating_sub(SCROLLBAR_WIDTH + SCROLLBAR_MARGIN * 2 + width);
+        let mut text_color = rgba(self.theme.foreground);
+        text_color[3] = (opacity * 255.0).round() as u8;
+        let mut box_color = rgba(self.theme.scrollbar_track);
+        box_color[3] = (opacity * SCROLL_BADGE_ALPHA * 255.0).round() as u8;
+        label::push_label(
+            &mut self.ops,
+            &mut self.font,
+            &text,
+            x,
+            (top + SCROLLBAR_MARGIN) as i32,
+            text_color,
+            box_color,
+        );
+    }
+
+    // Centred at the top, clear of the scroll badge in the corner.
+    fn push_notice(&mut self, notice: Notice<'_>, top: u32) {
+        let opacity = notice.opacity.clamp(0.0, 1.0);
+        if opacity == 0.0 || notice.text.is_empty() {
+            return;
+        }
+        let width = label::label_size(self.font.cell, notice.text).width;
+        let x = self.size.width.saturating_sub(width) / 2;
+        let mut text_color = rgba(self.theme.foreground);
+        text_color[3] = (opacity * 255.0).round() as u8;
+        let mut box_color = rgba(self.theme.scrollbar_track);
+        box_color[3] = (opacity * SCROLL_BADGE_ALPHA * 255.0).round() as u8;
+        label::push_label(
+            &mut self.ops,
+            &mut self.font,
+            notice.text,
+            x,
+            (top + SCROLLBAR_MARGIN) as i32,
+            text_color,
+            box_color,
+        );
+    }
+
+    fn push_decorations(
+        &mut self,
+        cell: &Cell,
+        palette: &Palette,
+        fg: Rgb,
+        x: u32,
+        y: i32,
+        width: u32,
+    ) {
+        let metrics = self.font.decorations;
+        let cell_size = self.font.cell;
+        let origin_x = self.grid_layout().origin_x;
+        let line = |offset: u32, thickness: u32, color: [u8; 4]| DrawOp::Fill {
+            rect: Rect {
+                x,
+                y: y + offset.min(cell_size.height.saturating_sub(thickness)) as i32,
+                width,
+                height: thickness,
+            },
+            color,
+        };
+
+        if let Some(style) = cell.underline {
+            let color = rgba(palette.resolve(cell.underline_color, fg));
+            let thickness = metrics.thickness;
+            match style {
+                UnderlineStyle::Single => self.ops.push(line(metrics.underline, thickness, color)),
+                UnderlineStyle::Double => {
+                    let gap = thickness.max(1);
+                    let top = metrics
+                        .underline
+                        .min(cell_size.height.saturating_sub(thickness * 2 + gap));
+                    self.ops.push(line(top, thickness, color));
+                    self.ops.push(line(top + thickness + gap, thickness, color));
+                }
+                UnderlineStyle::Curly => self.push_curly_underline(x, y, width, color),
+                UnderlineStyle::Dotted | UnderlineStyle::Dashed => {
+                    // Patterns are anchored to the grid origin rather than
+                    // the cell so they continue unbroken across cells.
+                    let period = match style {
+                        UnderlineStyle::Dotted => thickness * 2,
+                        _ => cell_size.width.max(2),
+                    };
+                    let on = match style {
+                        UnderlineStyle::Dotted => thickness,
+                        _ => (period * 3).div_ceil(5),
+                    };
+                    let top = y + metrics.underline as i32;
+                    let mut run_start = None;
+                    for dx in 0..=width {
+                        let lit = dx < width && (x - origin_x + dx) % period < on;
+                        match (lit, run_start) {
+                            (true, None) => run_start = Some(dx),
+                            (false, Some(start)) => {
+                                self.ops.push(DrawOp::Fill {
+                                    rect: Rect {
+                                        x: x + start,
+                                        y: top,
+                                        width: dx - start,
+                                        height: thickness,
+                                    },
+                                    color,
+                                });
+                                run_start = None;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        if cell.flags.contains(CellFlags::STRIKETHROUGH) {
+            self.ops
+                .push(line(metrics.strikethrough, metrics.thickness, rgba(fg)));
+        }
+        if cell.flags.contains(CellFlags::OVERLINE) {
+            self.ops.push(line(0, metrics.thickness, rgba(fg)));
+        }
+    }
+
+    // One sine period per cell width, measured from the grid origin, so the
+    // wave meets itself at every cell boundary. Each pixel column covers the
+    // span between its neighbours' samples to keep steep slopes joined.
+    fn push_curly_underline(&mut self, x: u32, y: i32, width: u32, color: [u8; 4]) {
+        let metrics = self.font.decorations;
+        let cell_size = self.font.cell;
+        let origin_x = self.grid_layout().origin_x;
+        let thickness = metrics.thickness.max(2);
+        let amplitude = (cell_size.height / 10).max(1) as f32;
+        let band = amplitude as u32 * 2 + thickness;
+        let top = y + metrics
+            .underline
+            .saturating_sub(amplitude as u32)
+            .min(cell_size.height.saturating_sub(band)) as i32;
+        let period = cell_size.width.max(1) as f32;
+        let sample = |dx: f32| {
+            let phase = (x - origin_x) as f32 + dx;
+            amplitude * (1.0 - (phase / period * std::f32::consts::TAU).cos())
+        };
+        for dx in 0..width {
+            let center = sample(dx as f32 + 0.5);
+            let low = center
+                .min(sample(dx as f32 - 0.5))
+                .min(sample(dx as f32 + 1.5));
+            let high = center
+                .max(sample(dx as f32 - 0.5))
+                .max(sample(dx as f32 + 1.5));
+            let start = ((low + center) * 0.5).round() as i32;
+            let end = ((high + center) * 0.5).round() as i32 + thickness as i32;
+            self.ops.push(DrawOp::Fill {
+                rect: Rect {
+                    x: x + dx,
+                    y: top + start,
+                    width: 1,
+                    height: (end - start) as u32,
+                },
+                color,
+            });
+        }
+    }
+}
+
+// Native wgpu resolves error scopes synchronously, so a single poll with a
+// no-op waker is enough; a pending scope is treated as success.
+pub(crate) fn pop_error_scope(device: &wgpu::Device) -> Option<wgpu::Error> {
+    let scope = pin!(device.pop_error_scope());
+    match scope.poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(error) => error,
+        Poll::Pending => None,
+    }
+}
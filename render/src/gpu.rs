@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::font::{FontRasterizer, GlyphMetrics};
+use crate::{linear_color, DrawOp, RenderSize};
+
+const ATLAS_SIZE: u32 = 1024;
+const ATLAS_PADDING: u32 = 1;
+const INITIAL_INSTANCES: usize = 4096;
+const KIND_FILL: u32 = 0;
+const KIND_GLYPH: u32 = 1;
+const KIND_SUBPIXEL: u32 = 2;
+
+pub(crate) struct GpuBackend {
+    pipeline: wgpu::RenderPipeline,
+    subpixel_pipeline: Option<wgpu::RenderPipeline>,
+    bind_group: wgpu::BindGroup,
+    globals: wgpu::Buffer,
+    instances: Vec<Instance>,
+    // Consecutive instances sharing a pipeline; true for subpixel glyphs.
+    batches: Vec<(bool, Range<u32>)>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    atlas: GlyphAtlas,
+    srgb: bool,
+    viewport: RenderSize,
+}
+
+impl GpuBackend {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: RenderSize,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("gpu_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("gpu_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("gpu_shader"),
+            source: wgpu::ShaderSource::Wgsl(GPU_SHADER.into()),
+        });
+
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            format,
+            wgpu::BlendState::ALPHA_BLENDING,
+        );
+
+        // The second blend source carries per-channel coverage, which the
+        // fixed-function blender applies to each channel separately.
+        let subpixel_pipeline = device
+            .features()
+            .contains(wgpu::Features::DUAL_SOURCE_BLENDING)
+            .then(|| {
+                let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("gpu_subpixel_shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        format!("{GPU_SHADER}{SUBPIXEL_SHADER}").into(),
+                    ),
+                });
+                let component = |src_factor, dst_factor| wgpu::BlendComponent {
+                    src_factor,
+                    dst_factor,
+                    operation: wgpu::BlendOperation::Add,
+                };
+                let blend = wgpu::BlendState {
+                    color: component(wgpu::BlendFactor::Src1, wgpu::BlendFactor::OneMinusSrc1),
+                    alpha: component(
+                        wgpu::BlendFactor::Src1Alpha,
+                        wgpu::BlendFactor::OneMinusSrc1Alpha,
+                    ),
+                };
+                create_pipeline(
+                    device,
+                    &pipeline_layout,
+                    &shader,
+                    "fs_subpixel",
+                    format,
+                    blend,
+                )
+            });
+
+        let globals = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_globals"),
+            size: std::mem::size_of::<Globals>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let atlas = GlyphAtlas::new(device);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("gpu_atlas_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let atlas_view = atlas
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("gpu_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: globals.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self {
+            pipeline,
+            subpixel_pipeline,
+            bind_group,
+            globals,
+            instances: Vec::with_capacity(INITIAL_INSTANCES),
+            batches: Vec::new(),
+            instance_buffer: create_instance_buffer(device, INITIAL_INSTANCES),
+            instance_capacity: INITIAL_INSTANCES,
+            atlas,
+            srgb: format.is_srgb(),
+            viewport: size,
+        }
+    }
+
+    pub(crate) fn resize(&mut self, size: RenderSize) {
+        self.viewport = size;
+    }
+
+    pub(crate) fn supports_subpixel(&self) -> bool {
+        self.subpixel_pipeline.is_some()
+    }
+
+    pub(crate) fn clear_glyphs(&mut self) {
+        self.atlas.clear();
+    }
+
+    pub(crate) fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        ops: &[DrawOp],
+        font: &mut FontRasterizer,
+    ) -> u64 {
+        self.atlas.uploaded = 0;
+        if !self.build_instances(queue, ops, font) {
+            self.atlas.clear();
+            self.build_instances(queue, ops, font);
+        }
+        self.build_batches();
+
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.instances),
+        );
+        let globals = Globals {
+            viewport: [self.viewport.width as f32, self.viewport.height as f32],
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.globals, 0, bytemuck::bytes_of(&globals));
+        (std::mem::size_of_val(self.instances.as_slice()) + std::mem::size_of::<Globals>()) as u64
+            + self.atlas.uploaded
+    }
+
+    pub(crate) fn draw<'p>(&'p self, pass: &mut wgpu::RenderPass<'p>) {
+        if self.instances.is_empty() {
+            return;
+        }
+        let bytes = (self.instances.len() * std::mem::size_of::<Instance>()) as u64;
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.instance_buffer.slice(..bytes));
+        for (subpixel, range) in &self.batches {
+            let pipeline = match (subpixel, &self.subpixel_pipeline) {
+                (true, Some(pipeline)) => pipeline,
+                _ => &self.pipeline,
+            };
+            pass.set_pipeline(pipeline);
+            pass.draw(0..6, range.clone());
+        }
+    }
+
+    fn build_batches(&mut self) {
+        self.batches.clear();
+        for (index, instance) in self.instances.iter().enumerate() {
+            let subpixel = instance.kind == KIND_SUBPIXEL;
+            let index = index as u32;
+            match self.batches.last_mut() {
+                Some((kind, range)) if *kind == subpixel => range.end = index + 1,
+                _ => self.batches.push((subpixel, index..index + 1)),
+            }
+        }
+    }
+
+    fn build_instances(
+        &mut self,
+        queue: &wgpu::Queue,
+        ops: &[DrawOp],
+        font: &mut FontRasterizer,
+    ) -> bool {
+        self.instances.clear();
+        let mut fits = true;
+        for op in ops {
+            match *op {
+                DrawOp::Fill { rect, color } => self.instances.push(Instance {
+                    rect: [
+                        rect.x as f32,
+                        rect.y as f32,
+                        rect.width as f32,
+                        rect.height as f32,
+                    ],
+                    uv: [0.0; 4],
+                    color: linear_color(color, self.srgb),
+                    kind: KIND_FILL,
+                    _padding: [0; 3],
+                }),
+                DrawOp::Glyph {
+                    glyph,
+                    x,
+                    y,
+                    width,
+                    color,
+                } => {
+                    let entry = match self.atlas.entry(queue, font, glyph) {
+                        Ok(Some(entry)) => entry,
+                        Ok(None) => continue,
+                        Err(AtlasFull) => {
+                            fits = false;
+                            continue;
+                        }
+                    };
+                    let metrics = entry.metrics;
+                    let (gx, gy) = metrics.position(width, font.baseline, x, y);
+                    // Subpixel glyphs are read texel by texel, so their uv is
+                    // in pixels; entries start on a multiple of three texels.
+                    let (origin_x, scale, kind) = match entry.subpixel {
+                        true => (entry.x as f32 / 3.0, 1.0, KIND_SUBPIXEL),
+                        false => (entry.x as f32, ATLAS_SIZE as f32, KIND_GLYPH),
+                    };
+                    self.instances.push(Instance {
+                        rect: [
+                            gx as f32,
+                            gy as f32,
+                            metrics.width as f32,
+                            metrics.height as f32,
+                        ],
+                        uv: [
+                            origin_x / scale,
+                            entry.y as f32 / scale,
+                            metrics.width as f32 / scale,
+                            metrics.height as f32 / scale,
+                        ],
+                        color: linear_color(color, self.srgb),
+                        kind,
+                        _padding: [0; 3],
+                    });
+                }
+            }
+        }
+        fits
+    }
+}
+
+struct AtlasFull;
+
+#[derive(Debug, Copy, Clone)]
+struct AtlasEntry {
+    x: u32,
+    y: u32,
+    metrics: GlyphMetrics,
+    subpixel: bool,
+}
+
+struct GlyphAtlas {
+    texture: wgpu::Texture,
+    entries: HashMap<u16, Option<AtlasEntry>>,
+    cursor_x: u32,
+    cursor_y: u32,
+    row_height: u32,
+    uploaded: u64,
+}
+
+impl GlyphAtlas {
+    fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("gpu_glyph_atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        Self {
+            texture,
+            entries: HashMap::new(),
+            cursor_x: 0,
+            cursor_y: 0,
+            row_height: 0,
+            uploaded: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.row_height = 0;
+    }
+
+    fn entry(
+        &mut self,
+        queue: &wgpu::Queue,
+        font: &mut FontRasterizer,
+        glyph: u16,
+    ) -> Result<Option<AtlasEntry>, AtlasFull> {
+        if let Some(entry) = self.entries.get(&glyph) {
+            return Ok(*entry);
+        }
+
+        let Some(bitmap) = font.rasterize(glyph) else {
+            self.entries.insert(glyph, None);
+            return Ok(None);
+        };
+        let metrics = bitmap.metrics;
+        let subpixel = bitmap.subpixel;
+        let texel_width = if subpixel {
+            metrics.width * 3
+        } else {
+            metrics.width
+        };
+        if metrics.width == 0
+            || metrics.height == 0
+            || texel_width > ATLAS_SIZE
+            || metrics.height > ATLAS_SIZE
+        {
+            self.entries.insert(glyph, None);
+            return Ok(None);
+        }
+
+        if subpixel {
+            self.cursor_x = self.cursor_x.next_multiple_of(3);
+        }
+        if self.cursor_x + texel_width > ATLAS_SIZE {
+            self.cursor_x = 0;
+            self.cursor_y += self.row_height + ATLAS_PADDING;
+            self.row_height = 0;
+        }
+        if self.cursor_y + metrics.height > ATLAS_SIZE {
+            return Err(AtlasFull);
+        }
+
+        let entry = AtlasEntry {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            metrics,
+            subpixel,
+        };
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: entry.x,
+                    y: entry.y,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &bitmap.data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(texel_width),
+                rows_per_image: Some(metrics.height),
+            },
+            wgpu::Extent3d {
+                width: texel_width,
+                height: metrics.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        self.uploaded += bitmap.data.len() as u64;
+        self.cursor_x += texel_width + ATLAS_PADDING;
+        self.row_height = self.row_height.max(metrics.height);
+        self.entries.insert(glyph, Some(entry));
+        Ok(Some(entry))
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fragment_entry: &str,
+    format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("gpu_pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Instance::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("gpu_instance_buffer"),
+        size: (capacity * std::mem::size_of::<Instance>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Globals {
+    viewport: [f32; 2],
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Instance {
+    rect: [f32; 4],
+    uv: [f32; 4],
+    color: [f32; 4],
+    kind: u32,
+    _padding: [u32; 3],
+}
+
+impl Instance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+            0 => Float32x4,
+            1 => Float32x4,
+            2 => Float32x4,
+            3 => Uint32,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+const GPU_SHADER: &str = r#"
+struct Globals {
+    viewport: vec2<f32>,
+    padding: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) kind: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+@group(0) @binding(1)
+var atlas_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var atlas_sampler: sampler;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex: u32,
+    @location(0) rect: vec4<f32>,
+    @location(1) uv: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) kind: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[vertex];
+    let pixel = rect.xy + corner * rect.zw;
+    var out: VertexOutput;
+    out.position = vec4<f32>(
+        pixel.x / globals.viewport.x * 2.0 - 1.0,
+        1.0 - pixel.y / globals.viewport.y * 2.0,
+        0.0,
+        1.0,
+    );
+    out.uv = uv.xy + corner * uv.zw;
+    out.color = color;
+    out.kind = kind;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSampleLevel(atlas_texture, atlas_sampler, in.uv, 0.0).r;
+    if in.kind == 1u {
+        return vec4<f32>(in.color.rgb, in.color.a * coverage);
+    }
+    return in.color;
+}
+"#;
+
+const SUBPIXEL_SHADER: &str = r#"
+struct SubpixelOutput {
+    @location(0) color: vec4<f32>,
+    @location(0) @second_blend_source coverage: vec4<f32>,
+};
+
+@fragment
+fn fs_subpixel(in: VertexOutput) -> SubpixelOutput {
+    let texel = vec2<i32>(floor(in.uv));
+    let x = texel.x * 3;
+    let coverage = vec3<f32>(
+        textureLoad(atlas_texture, vec2<i32>(x, texel.y), 0).r,
+        textureLoad(atlas_texture, vec2<i32>(x + 1, texel.y), 0).r,
+        textureLoad(atlas_texture, vec2<i32>(x + 2, texel.y), 0).r,
+    ) * in.color.a;
+    var out: SubpixelOutput;
+    out.color = vec4<f32>(in.color.rgb, 1.0);
+    out.coverage = vec4<f32>(coverage, max(coverage.r, max(coverage.g, coverage.b)));
+    return out;
+}
+"#;
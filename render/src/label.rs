@@ -0,0 +1,52 @@
+use crate::font::FontRasterizer;
+use crate::{CellMetrics, DrawOp, Rect};
+
+const LABEL_PADDING_X: u32 = 6;
+const LABEL_PADDING_Y: u32 = 2;
+
+// Size of the box `push_label` draws for `text`: one cell per character
+// plus padding on every side.
+pub(crate) fn label_size(cell: CellMetrics, text: &str) -> CellMetrics {
+    CellMetrics {
+        width: text.chars().count() as u32 * cell.width + LABEL_PADDING_X * 2,
+        height: cell.height + LABEL_PADDING_Y * 2,
+    }
+}
+
+// Draws a single line of text over a filled box whose top-left corner is at
+// (x, y), returning the box. Meant for overlays on top of the grid, so no
+// shaping, wide characters or wrapping.
+pub(crate) fn push_label(
+    ops: &mut Vec<DrawOp>,
+    font: &mut FontRasterizer,
+    text: &str,
+    x: u32,
+    y: i32,
+    text_color: [u8; 4],
+    box_color: [u8; 4],
+) -> Rect {
+    let cell = font.cell;
+    let size = label_size(cell, text);
+    let rect = Rect {
+        x,
+        y,
+        width: size.width,
+        height: size.height,
+    };
+    ops.push(DrawOp::Fill {
+        rect,
+        color: box_color,
+    });
+    let mut glyph_x = x + LABEL_PADDING_X;
+    for ch in text.chars() {
+        ops.push(DrawOp::Glyph {
+            glyph: font.glyph_index(ch),
+            x: glyph_x,
+            y: y + LABEL_PADDING_Y as i32,
+            width: cell.width,
+            color: text_color,
+        });
+        glyph_x += cell.width;
+    }
+    rect
+}
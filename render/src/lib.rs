@@ -1,17 +1,54 @@
-use std::collections::HashMap;
-
-use fontdue::Font;
-use wgpu::util::DeviceExt;
-
-pub const CELL_WIDTH: u32 = 10;
-pub const CELL_HEIGHT: u32 = 20;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use screen::{Cell, CursorShape, Palette, Rgb, ScrollPosition};
+
+mod boxdraw;
+mod contrast;
+mod cpu;
+mod font;
+mod frame;
+mod gpu;
+mod label;
+mod offscreen;
+mod post;
+mod theme;
+
+use frame::FrameRenderer;
+
+pub use offscreen::OffscreenRenderer;
+pub use post::PostEffect;
+pub use theme::Theme;
+
+#[deprecated(note = "use Renderer::cell_metrics()")]
+pub const CELL_WIDTH: u32 = DEFAULT_CELL_METRICS.width;
+#[deprecated(note = "use Renderer::cell_metrics()")]
+pub const CELL_HEIGHT: u32 = DEFAULT_CELL_METRICS.height;
 pub const PADDING_X: u32 = 12;
 pub const PADDING_Y: u32 = 12;
 pub const DEFAULT_FONT_SIZE: f32 = 16.0;
-
-const COLOR_BG: [u8; 4] = [10, 14, 20, 255];
-const COLOR_FG: [u8; 4] = [230, 237, 243, 255];
-const COLOR_CURSOR: [u8; 4] = [88, 168, 255, 255];
+pub const MIN_FONT_SIZE: f32 = 6.0;
+pub const MAX_FONT_SIZE: f32 = 72.0;
+pub const MIN_CELL_SCALE: f32 = 0.5;
+pub const MAX_CELL_SCALE: f32 = 2.0;
+pub const MAX_CONTRAST_RATIO: f32 = 21.0;
+pub const DEFAULT_GLYPH_CACHE_BUDGET: usize = 4 * 1024 * 1024;
+pub const DEFAULT_CURSOR_BLINK_INTERVAL: Duration = Duration::from_millis(600);
+
+const DEFAULT_CELL_METRICS: CellMetrics = CellMetrics {
+    width: 10,
+    height: 20,
+};
+const HOLLOW_CURSOR_THICKNESS: u32 = 1;
+const SCROLLBAR_WIDTH: u32 = 6;
+const SCROLLBAR_MARGIN: u32 = 3;
+const SCROLLBAR_MIN_THUMB: u32 = 16;
+const SCROLL_BADGE_ALPHA: f32 = 0.85;
+const TAB_BAR_PADDING_Y: u32 = 4;
+const TAB_PADDING_X: u32 = 8;
+const TAB_INACTIVE_ALPHA: u8 = 150;
+const BELL_FLASH_DURATION: Duration = Duration::from_millis(150);
+const BELL_FLASH_ALPHA: f32 = 0.25;
 
 #[derive(Debug, thiserror::Error)]
 pub enum RenderError {
@@ -21,22 +58,196 @@ pub enum RenderError {
     GridMismatch { expected: usize, actual: usize },
     #[error("font error: {0}")]
     Font(String),
+    #[error("surface does not support transparency")]
+    TransparencyUnsupported,
+    #[error("frame capture failed: {0}")]
+    Capture(String),
+    #[error("surface size {width}x{height} exceeds the device limit of {max}px")]
+    SizeExceedsLimit { width: u32, height: u32, max: u32 },
+    #[error("GPU allocation failed: {0}")]
+    Allocation(String),
+    #[error("post effect failed to compile: {0}")]
+    PostEffect(String),
+    #[error("surface has no compatible format, present mode or alpha mode")]
+    NoCompatibleFormat,
     #[error("surface error: {0}")]
     Surface(#[from] wgpu::SurfaceError),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct RenderSize {
     pub width: u32,
     pub height: u32,
 }
 
-pub struct RenderGrid<'a> {
+// `cells` is anything that can hand out rows, so callers can lend the
+// screen's own storage instead of copying the viewport. A flat `&[Cell]` of
+// `cols * rows` cells still works as before.
+pub struct RenderGrid<'a, R: ?Sized = [Cell]> {
     pub cols: u16,
     pub rows: u16,
-    pub cells: &'a [char],
+    pub cells: &'a R,
+    pub palette: &'a Palette,
     pub cursor: Option<CursorPosition>,
     pub cursor_visible: bool,
+    pub cursor_shape: CursorShape,
+    // Whether the cursor style asks to blink (DECSCUSR); the renderer decides
+    // the phase from its own clock.
+    pub cursor_blinking: bool,
+    pub scroll_indicator: Option<ScrollIndicator>,
+    pub scroll_pixel_offset: f32,
+    // Blink phase; blinking cells show only their background while false.
+    pub blink_on: bool,
+    // Unfocused windows draw the cursor as a hollow outline of its cell.
+    pub focused: bool,
+    // Uncommitted input method text, drawn over the cells from the cursor
+    // onwards in place of the cursor itself.
+    pub preedit: Option<Preedit<'a>>,
+    // Cells drawn on the theme's selection colour.
+    pub selection: Option<GridSelection>,
+    // Cells of the link under the mouse, underlined while it can be opened.
+    pub link: Option<GridSelection>,
+    pub notice: Option<Notice<'a>>,
+}
+
+pub trait RowSource {
+    // Row `index` of the frame; rows shorter than `cols` leave the rest of
+    // the line blank and None leaves the whole line blank.
+    fn row(&self, index: usize, cols: usize) -> Option<&[Cell]>;
+
+    // Total cell count when the source is a flat buffer, so it can be
+    // checked against the grid dimensions.
+    fn cell_count(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl RowSource for [Cell] {
+    fn row(&self, index: usize, cols: usize) -> Option<&[Cell]> {
+        self.get(index * cols..(index + 1) * cols)
+    }
+
+    fn cell_count(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl RowSource for Vec<Cell> {
+    fn row(&self, index: usize, cols: usize) -> Option<&[Cell]> {
+        self.as_slice().row(index, cols)
+    }
+
+    fn cell_count(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Preedit<'a> {
+    pub text: &'a str,
+    // Byte offset of the input method's caret within `text`.
+    pub cursor: Option<usize>,
+    // Byte ranges of `text` shown highlighted, such as the active candidate.
+    pub highlights: &'a [Range<usize>],
+}
+
+// A short message shown at the top of the window, such as a setting that
+// was just changed. The app fades it out through `opacity`.
+#[derive(Debug, Copy, Clone)]
+pub struct Notice<'a> {
+    pub text: &'a str,
+    pub opacity: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CursorBlink {
+    // Time the cursor spends in each of the on and off phases.
+    pub interval: Duration,
+    pub enabled: bool,
+}
+
+impl Default for CursorBlink {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_CURSOR_BLINK_INTERVAL,
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ScrollIndicator {
+    pub position: ScrollPosition,
+    pub opacity: f32,
+    // Output arrived below the view; the badge says so and stays visible.
+    pub new_output: bool,
+}
+
+// A strip of tabs drawn above the grid, one per title.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabBar {
+    pub titles: Vec<String>,
+    pub active: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CellMetrics {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for CellMetrics {
+    fn default() -> Self {
+        DEFAULT_CELL_METRICS
+    }
+}
+
+// Placement of the cell grid inside the surface. Space left over after
+// fitting whole cells is split evenly around the grid on each axis.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GridLayout {
+    pub cols: u32,
+    pub rows: u32,
+    pub origin_x: u32,
+    pub origin_y: u32,
+    pub cell: CellMetrics,
+}
+
+impl GridLayout {
+    pub fn new(size: RenderSize, cell: CellMetrics) -> Self {
+        Self::with_padding(size, cell, PADDING_X, PADDING_Y)
+    }
+
+    pub fn with_padding(
+        size: RenderSize,
+        cell: CellMetrics,
+        padding_x: u32,
+        padding_y: u32,
+    ) -> Self {
+        let usable_width = size.width.saturating_sub(padding_x * 2);
+        let usable_height = size.height.saturating_sub(padding_y * 2);
+        let cols = usable_width / cell.width.max(1);
+        let rows = usable_height / cell.height.max(1);
+        Self {
+            cols,
+            rows,
+            origin_x: padding_x + (usable_width - cols * cell.width) / 2,
+            origin_y: padding_y + (usable_height - rows * cell.height) / 2,
+            cell,
+        }
+    }
+
+    // Maps a surface pixel to the grid cell under it, if any.
+    pub fn cell_at(&self, x: f64, y: f64) -> Option<CursorPosition> {
+        let col = ((x - self.origin_x as f64) / self.cell.width.max(1) as f64).floor();
+        let row = ((y - self.origin_y as f64) / self.cell.height.max(1) as f64).floor();
+        let inside =
+            (0.0..self.cols as f64).contains(&col) && (0.0..self.rows as f64).contains(&row);
+        inside.then_some(CursorPosition {
+            col: col as u16,
+            row: row as u16,
+        })
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -45,27 +256,81 @@ pub struct CursorPosition {
     pub row: u16,
 }
 
+// A run of cells in reading order, from `start` to `end` inclusive. Rows
+// between the two are covered in full.
+#[derive(Debug, Copy, Clone)]
+pub struct GridSelection {
+    pub start: CursorPosition,
+    pub end: CursorPosition,
+}
+
+impl GridSelection {
+    pub fn contains(&self, col: u16, row: u16) -> bool {
+        (row, col) >= (self.start.row, self.start.col) && (row, col) <= (self.end.row, self.end.col)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum RenderBackend {
+    #[default]
+    Gpu,
+    Cpu,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TextAntialias {
+    #[default]
+    Grayscale,
+    // Per-channel coverage for RGB-striped LCD panels. Falls back to
+    // grayscale when the window is transparent or the GPU cannot blend it.
+    Subpixel,
+    None,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub bytes_uploaded: u64,
+    pub frame_time: Duration,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderOutcome {
+    Presented,
+    // The grid produced the same frame as the last one presented.
+    Unchanged,
+    // Called again before the frame-rate cap allows; see next_frame_at.
+    Throttled,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct GlyphCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub budget: usize,
+    pub evictions: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameCapture {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
 pub struct FontSpec {
     pub bytes: Vec<u8>,
     pub size: f32,
+    pub collection_index: u32,
+    pub weight: Option<u16>,
 }
 
 pub struct Renderer<'a> {
     surface: wgpu::Surface<'a>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
-    pipeline: wgpu::RenderPipeline,
-    bind_group_layout: wgpu::BindGroupLayout,
-    bind_group: wgpu::BindGroup,
-    texture: wgpu::Texture,
-    texture_view: wgpu::TextureView,
-    sampler: wgpu::Sampler,
-    vertex_buffer: wgpu::Buffer,
-    pixel_buffer: Vec<u8>,
-    texture_size: RenderSize,
-    row_stride: u32,
-    font: FontRasterizer,
+    frame: FrameRenderer,
+    max_fps: Option<u32>,
+    last_present: Option<Instant>,
+    frame_time: Duration,
 }
 
 impl<'a> Renderer<'a> {
@@ -77,257 +342,263 @@ impl<'a> Renderer<'a> {
         size: RenderSize,
         font: FontSpec,
     ) -> Result<Self, RenderError> {
+        let size = fit_to_limit(size, device.limits().max_texture_dimension_2d);
         let config = configure_surface(&surface, adapter, size)?;
         surface.configure(&device, &config);
-
-        let font = FontRasterizer::new(font)?;
-
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("render_bind_group_layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-        });
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("render_pipeline_layout"),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("render_shader"),
-            source: wgpu::ShaderSource::Wgsl(RENDER_SHADER.into()),
-        });
-
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        });
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("render_vertex_buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let texture_size = RenderSize {
+        let size = RenderSize {
             width: config.width,
             height: config.height,
         };
-
-        let (texture, texture_view, sampler, bind_group, pixel_buffer, row_stride) =
-            create_texture_resources(&device, &bind_group_layout, texture_size);
+        let frame =
+            FrameRenderer::new(device, queue, config.format, config.alpha_mode, size, font)?;
 
         Ok(Self {
             surface,
-            device,
-            queue,
             config,
-            pipeline,
-            bind_group_layout,
-            bind_group,
-            texture,
-            texture_view,
-            sampler,
-            vertex_buffer,
-            pixel_buffer,
-            texture_size,
-            row_stride,
-            font,
+            frame,
+            max_fps: None,
+            last_present: None,
+            frame_time: Duration::ZERO,
         })
     }
 
     pub fn resize(&mut self, size: RenderSize) -> Result<(), RenderError> {
-        self.config.width = size.width;
-        self.config.height = size.height;
         if size.width == 0 || size.height == 0 {
             return Err(RenderError::InvalidSize {
                 width: size.width,
                 height: size.height,
             });
         }
-        self.surface.configure(&self.device, &self.config);
-        let (texture, texture_view, sampler, bind_group, pixel_buffer, row_stride) =
-            create_texture_resources(&self.device, &self.bind_group_layout, size);
-        self.texture = texture;
-        self.texture_view = texture_view;
-        self.sampler = sampler;
-        self.bind_group = bind_group;
-        self.pixel_buffer = pixel_buffer;
-        self.texture_size = size;
-        self.row_stride = row_stride;
-        Ok(())
+        let size = fit_to_limit(size, self.frame.device().limits().max_texture_dimension_2d);
+        self.config.width = size.width;
+        self.config.height = size.height;
+        self.surface.configure(self.frame.device(), &self.config);
+        self.frame.resize(size)
     }
 
-    pub fn set_font(&mut self, font: FontSpec) -> Result<(), RenderError> {
-        self.font = FontRasterizer::new(font)?;
-        Ok(())
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
     }
 
-    pub fn render(&mut self, grid: &RenderGrid<'_>) -> Result<(), RenderError> {
-        self.update_pixels(grid)?;
-        self.upload_texture();
+    // Smaller than the requested size when the window is larger than the
+    // device can allocate; the compositor then stretches the frame to fit.
+    pub fn render_size(&self) -> RenderSize {
+        RenderSize {
+            width: self.config.width,
+            height: self.config.height,
+        }
+    }
 
-        let frame = self.surface.get_current_texture()?;
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("render_encoder"),
-            });
+    pub fn set_font(&mut self, font: FontSpec) -> Result<bool, RenderError> {
+        self.frame.set_font(font)
+    }
 
-        {
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render_pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(color_to_wgpu(COLOR_BG)),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-            pass.set_pipeline(&self.pipeline);
-            pass.set_bind_group(0, &self.bind_group, &[]);
-            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            pass.draw(0..VERTICES.len() as u32, 0..1);
-        }
+    pub fn set_font_size(&mut self, size: f32) -> Result<CellMetrics, RenderError> {
+        self.frame.set_font_size(size)
+    }
 
-        self.queue.submit(Some(encoder.finish()));
-        frame.present();
-        Ok(())
+    pub fn scale_factor(&self) -> f64 {
+        self.frame.scale_factor()
     }
 
-    fn update_pixels(&mut self, grid: &RenderGrid<'_>) -> Result<(), RenderError> {
-        let expected = grid.cols as usize * grid.rows as usize;
-        if grid.cells.len() != expected {
-            return Err(RenderError::GridMismatch {
-                expected,
-                actual: grid.cells.len(),
-            });
-        }
+    // Display scale (e.g. 1.5 at 150%); font sizes are multiplied by it.
+    // Returns the new cell metrics so callers can resize the grid.
+    pub fn set_scale_factor(&mut self, scale: f64) -> Result<CellMetrics, RenderError> {
+        self.frame.set_scale_factor(scale)
+    }
 
-        fill_background(&mut self.pixel_buffer, self.row_stride as usize, COLOR_BG);
-
-        let usable_width = self.texture_size.width.saturating_sub(PADDING_X * 2);
-        let usable_height = self.texture_size.height.saturating_sub(PADDING_Y * 2);
-        let max_cols = (usable_width / CELL_WIDTH) as usize;
-        let max_rows = (usable_height / CELL_HEIGHT) as usize;
-        let cols = grid.cols.min(max_cols as u16) as usize;
-        let rows = grid.rows.min(max_rows as u16) as usize;
-
-        for row in 0..rows {
-            for col in 0..cols {
-                let idx = row * grid.cols as usize + col;
-                let ch = grid.cells[idx];
-                let draw = DrawContext {
-                    font: &mut self.font,
-                    ch,
-                    origin_x: PADDING_X + col as u32 * CELL_WIDTH,
-                    origin_y: PADDING_Y + row as u32 * CELL_HEIGHT,
-                    width: self.texture_size.width as usize,
-                    height: self.texture_size.height as usize,
-                    stride: self.row_stride as usize,
-                    buffer: &mut self.pixel_buffer,
-                };
-                draw_glyph(draw);
-            }
-        }
+    pub fn font_size(&self) -> f32 {
+        self.frame.font_size()
+    }
 
-        if grid.cursor_visible {
-            if let Some(cursor) = grid.cursor {
-                if cursor.col < grid.cols && cursor.row < grid.rows {
-                    let cursor_x = PADDING_X + cursor.col as u32 * CELL_WIDTH;
-                    let cursor_y = PADDING_Y + cursor.row as u32 * CELL_HEIGHT;
-                    draw_cursor_bar(
-                        cursor_x,
-                        cursor_y,
-                        self.texture_size.width as usize,
-                        self.texture_size.height as usize,
-                        self.row_stride as usize,
-                        &mut self.pixel_buffer,
-                    );
-                }
-            }
+    pub fn line_height_scale(&self) -> f32 {
+        self.frame.line_height_scale()
+    }
+
+    pub fn set_line_height_scale(&mut self, scale: f32) -> Result<CellMetrics, RenderError> {
+        self.frame.set_line_height_scale(scale)
+    }
+
+    pub fn cell_width_scale(&self) -> f32 {
+        self.frame.cell_width_scale()
+    }
+
+    pub fn set_cell_width_scale(&mut self, scale: f32) -> Result<CellMetrics, RenderError> {
+        self.frame.set_cell_width_scale(scale)
+    }
+
+    pub fn cell_metrics(&self) -> CellMetrics {
+        self.frame.cell_metrics()
+    }
+
+    pub fn grid_layout(&self) -> GridLayout {
+        self.frame.grid_layout()
+    }
+
+    // Minimum space in pixels between the surface edges and the grid,
+    // PADDING_X and PADDING_Y by default.
+    pub fn set_padding(&mut self, x: u32, y: u32) {
+        self.frame.set_padding(x, y);
+    }
+
+    pub fn tab_bar(&self) -> Option<&TabBar> {
+        self.frame.tab_bar()
+    }
+
+    // Showing or hiding the bar moves the grid, so callers should resize
+    // their grid to the new grid_layout afterwards.
+    pub fn set_tab_bar(&mut self, tabs: Option<TabBar>) {
+        self.frame.set_tab_bar(tabs);
+    }
+
+    pub fn backend(&self) -> RenderBackend {
+        self.frame.backend()
+    }
+
+    pub fn set_backend(&mut self, backend: RenderBackend) {
+        self.frame.set_backend(backend);
+    }
+
+    pub fn text_antialias(&self) -> TextAntialias {
+        self.frame.text_antialias()
+    }
+
+    pub fn set_text_antialias(&mut self, antialias: TextAntialias) {
+        self.frame.set_text_antialias(antialias);
+    }
+
+    pub fn set_ligatures(&mut self, enabled: bool) {
+        self.frame.set_ligatures(enabled);
+    }
+
+    pub fn set_text_blink(&mut self, enabled: bool) {
+        self.frame.set_text_blink(enabled);
+    }
+
+    pub fn cursor_blink(&self) -> CursorBlink {
+        self.frame.cursor_blink()
+    }
+
+    pub fn set_cursor_blink(&mut self, blink: CursorBlink) {
+        self.frame.set_cursor_blink(blink);
+    }
+
+    // Restarts the blink cycle in the visible phase, e.g. after input.
+    pub fn reset_cursor_blink(&mut self) {
+        self.frame.reset_cursor_blink();
+    }
+
+    // Briefly washes the window with the foreground colour.
+    pub fn flash_bell(&mut self) {
+        self.frame.flash_bell();
+    }
+
+    // When the blinking cursor next changes phase; None while the last frame
+    // had no blinking cursor.
+    pub fn next_blink_deadline(&self) -> Option<Instant> {
+        self.frame.next_blink_deadline()
+    }
+
+    pub fn post_effect(&self) -> Option<&PostEffect> {
+        self.frame.post_effect()
+    }
+
+    pub fn set_post_effect(&mut self, effect: Option<PostEffect>) -> Result<(), RenderError> {
+        self.frame.set_post_effect(effect)
+    }
+
+    pub fn minimum_contrast(&self) -> Option<f32> {
+        self.frame.minimum_contrast()
+    }
+
+    pub fn set_minimum_contrast(&mut self, ratio: Option<f32>) {
+        self.frame.set_minimum_contrast(ratio);
+    }
+
+    pub fn theme(&self) -> &Theme {
+        self.frame.theme()
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.frame.set_theme(theme);
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.frame.opacity()
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) -> Result<(), RenderError> {
+        self.frame.set_opacity(opacity)
+    }
+
+    // False when the surface can only be presented opaque.
+    pub fn supports_transparency(&self) -> bool {
+        self.frame.supports_transparency()
+    }
+
+    pub fn last_frame_stats(&self) -> FrameStats {
+        FrameStats {
+            frame_time: self.frame_time,
+            ..self.frame.last_frame_stats()
         }
+    }
 
-        Ok(())
+    pub fn glyph_cache_stats(&self) -> GlyphCacheStats {
+        self.frame.glyph_cache_stats()
     }
 
-    fn upload_texture(&self) {
-        let width = self.texture_size.width;
-        let height = self.texture_size.height;
-        let bytes_per_row = Some(self.row_stride);
-        let rows_per_image = Some(height);
+    pub fn set_glyph_cache_budget(&mut self, bytes: usize) {
+        self.frame.set_glyph_cache_budget(bytes);
+    }
 
-        self.queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &self.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &self.pixel_buffer,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row,
-                rows_per_image,
-            },
-            wgpu::Extent3d {
-                width,
-                height,
-                depth_or_array_layers: 1,
-            },
-        );
+    pub fn max_fps(&self) -> Option<u32> {
+        self.max_fps
+    }
+
+    pub fn set_max_fps(&mut self, fps: Option<u32>) {
+        self.max_fps = fps.filter(|fps| *fps > 0);
+    }
+
+    // Earliest time the frame-rate cap allows the next present, if capped.
+    pub fn next_frame_at(&self) -> Option<Instant> {
+        let budget = Duration::from_secs(1) / self.max_fps?;
+        Some(self.last_present? + budget)
+    }
+
+    pub fn render<R: RowSource + ?Sized>(
+        &mut self,
+        grid: &RenderGrid<'_, R>,
+    ) -> Result<RenderOutcome, RenderError> {
+        let started = Instant::now();
+        if self.next_frame_at().is_some_and(|next| started < next) {
+            return Ok(RenderOutcome::Throttled);
+        }
+        if !self.frame.prepare(grid)? {
+            return Ok(RenderOutcome::Unchanged);
+        }
+        let frame = self.surface.get_current_texture()?;
+        self.frame.present(&frame.texture);
+        frame.present();
+        self.last_present = Some(started);
+        self.frame_time = started.elapsed();
+        Ok(RenderOutcome::Presented)
+    }
+
+    pub fn capture_frame(&self) -> Result<FrameCapture, RenderError> {
+        self.frame.capture_frame()
+    }
+}
+
+fn fit_to_limit(size: RenderSize, max: u32) -> RenderSize {
+    let largest = size.width.max(size.height);
+    if largest <= max {
+        return size;
+    }
+    // Scale uniformly so the stretched frame keeps its aspect ratio.
+    let scale = |value: u32| ((value as u64 * max as u64) / largest as u64).max(1) as u32;
+    RenderSize {
+        width: scale(size.width),
+        height: scale(size.height),
     }
 }
 
@@ -344,31 +615,21 @@ fn configure_surface(
     }
 
     let capabilities = surface.get_capabilities(adapter);
-    let format = capabilities
-        .formats
+    let format =
+        preferred_surface_format(&capabilities.formats).ok_or(RenderError::NoCompatibleFormat)?;
+    let present_mode = capabilities
+        .present_modes
         .first()
         .copied()
-        .ok_or(RenderError::InvalidSize {
-            width: size.width,
-            height: size.height,
-        })?;
-    let present_mode =
-        capabilities
-            .present_modes
-            .first()
-            .copied()
-            .ok_or(RenderError::InvalidSize {
-                width: size.width,
-                height: size.height,
-            })?;
-    let alpha_mode = capabilities
-        .alpha_modes
-        .first()
-        .copied()
-        .ok_or(RenderError::InvalidSize {
-            width: size.width,
-            height: size.height,
-        })?;
+        .ok_or(RenderError::NoCompatibleFormat)?;
+    let alpha_mode = [
+        wgpu::CompositeAlphaMode::PreMultiplied,
+        wgpu::CompositeAlphaMode::PostMultiplied,
+    ]
+    .into_iter()
+    .find(|mode| capabilities.alpha_modes.contains(mode))
+    .or_else(|| capabilities.alpha_modes.first().copied())
+    .ok_or(RenderError::NoCompatibleFormat)?;
 
     Ok(wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -382,325 +643,214 @@ fn configure_surface(
     })
 }
 
-fn create_texture_resources(
-    device: &wgpu::Device,
-    layout: &wgpu::BindGroupLayout,
-    size: RenderSize,
-) -> (
-    wgpu::Texture,
-    wgpu::TextureView,
-    wgpu::Sampler,
-    wgpu::BindGroup,
-    Vec<u8>,
-    u32,
-) {
-    let texture = device.create_texture(&wgpu::TextureDescriptor {
-        label: Some("render_text_texture"),
-        size: wgpu::Extent3d {
-            width: size.width,
-            height: size.height,
-            depth_or_array_layers: 1,
-        },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba8UnormSrgb,
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-        view_formats: &[],
-    });
-
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-        label: Some("render_sampler"),
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Nearest,
-        min_filter: wgpu::FilterMode::Nearest,
-        mipmap_filter: wgpu::FilterMode::Nearest,
-        ..Default::default()
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("render_bind_group"),
-        layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&texture_view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&sampler),
-            },
-        ],
-    });
-
-    let row_stride = aligned_row_bytes(size.width);
-    let pixel_buffer = vec![0u8; (row_stride * size.height) as usize];
-
-    (
-        texture,
-        texture_view,
-        sampler,
-        bind_group,
-        pixel_buffer,
-        row_stride,
-    )
-}
-
-fn aligned_row_bytes(width: u32) -> u32 {
-    let bytes_per_pixel = 4;
-    let row_bytes = width * bytes_per_pixel;
-    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
-    let padding = (alignment - (row_bytes % alignment)) % alignment;
-    row_bytes + padding
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Rect {
+    x: u32,
+    y: i32,
+    width: u32,
+    height: u32,
 }
 
-fn fill_background(buffer: &mut [u8], stride: usize, color: [u8; 4]) {
-    for row in buffer.chunks_exact_mut(stride) {
-        for pixel in row.chunks_exact_mut(4) {
-            pixel.copy_from_slice(&color);
-        }
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DrawOp {
+    Fill {
+        rect: Rect,
+        color: [u8; 4],
+    },
+    Glyph {
+        glyph: u16,
+        x: u32,
+        y: i32,
+        width: u32,
+        color: [u8; 4],
+    },
 }
 
-struct DrawContext<'a> {
-    font: &'a mut FontRasterizer,
-    ch: char,
+fn cursor_rect(
+    shape: CursorShape,
+    cell: CellMetrics,
     origin_x: u32,
-    origin_y: u32,
-    width: usize,
-    height: usize,
-    stride: usize,
-    buffer: &'a mut [u8],
-}
-
-fn draw_glyph(ctx: DrawContext<'_>) {
-    let line_metrics = ctx.font.line_metrics;
-    let glyph = match ctx.font.rasterize(ctx.ch) {
-        Some(glyph) => glyph,
-        None => return,
-    };
-
-    if glyph.width == 0 || glyph.height == 0 {
-        return;
-    }
-
-    let cell_w = CELL_WIDTH as f32;
-    let cell_h = CELL_HEIGHT as f32;
-    let mut base_x = ctx.origin_x as f32;
-    if glyph.advance_width > 0.0 {
-        let padding = (cell_w - glyph.advance_width).max(0.0) * 0.5;
-        base_x += padding;
+    origin_y: i32,
+    thickness: u32,
+) -> Rect {
+    match shape {
+        CursorShape::Block => Rect {
+            x: origin_x,
+            y: origin_y,
+            width: cell.width,
+            height: cell.height,
+        },
+        CursorShape::Underline => Rect {
+            x: origin_x,
+            y: origin_y + cell.height.saturating_sub(thickness) as i32,
+            width: cell.width,
+            height: thickness,
+        },
+        CursorShape::Bar => Rect {
+            x: origin_x + 1,
+            y: origin_y + 2,
+            width: thickness,
+            height: cell.height.saturating_sub(4),
+        },
     }
+}
 
-    let base_y = if let Some(metrics) = line_metrics {
-        let line_height = metrics.ascent - metrics.descent;
-        let padding = (cell_h - line_height).max(0.0) * 0.5;
-        ctx.origin_y as f32 + padding + metrics.ascent
-    } else {
-        ctx.origin_y as f32 + cell_h * 0.8
-    };
-
-    let base_x = (base_x + glyph.xmin as f32).round() as i32;
-    let base_y = (base_y - (glyph.ymin as f32 + glyph.height as f32)).round() as i32;
+// Outline of the cursor cell as top, bottom, left and right edges; the
+// shape is ignored so every style reads the same when unfocused.
+fn hollow_cursor_rects(cell: CellMetrics, origin_x: u32, origin_y: i32) -> [Rect; 4] {
+    let thickness = HOLLOW_CURSOR_THICKNESS.min(cell.width / 2).max(1);
+    let inner = cell.height.saturating_sub(thickness * 2);
+    [
+        Rect {
+            x: origin_x,
+            y: origin_y,
+            width: cell.width,
+            height: thickness,
+        },
+        Rect {
+            x: origin_x,
+            y: origin_y + cell.height.saturating_sub(thickness) as i32,
+            width: cell.width,
+            height: thickness,
+        },
+        Rect {
+            x: origin_x,
+            y: origin_y + thickness as i32,
+            width: thickness,
+            height: inner,
+        },
+        Rect {
+            x: origin_x + cell.width.saturating_sub(thickness),
+            y: origin_y + thickness as i32,
+            width: thickness,
+            height: inner,
+        },
+    ]
+}
 
-    for y in 0..glyph.height {
-        for x in 0..glyph.width {
-            let alpha = glyph.data[(y * glyph.width + x) as usize];
-            if alpha == 0 {
-                continue;
-            }
-            let px = base_x + x as i32;
-            let py = base_y + y as i32;
-            if px < 0 || py < 0 {
-                continue;
-            }
-            let px = px as usize;
-            let py = py as usize;
-            if px >= ctx.width || py >= ctx.height {
-                continue;
-            }
-            let idx = py * ctx.stride + px * 4;
-            if idx + 4 <= ctx.buffer.len() {
-                blend_pixel(&mut ctx.buffer[idx..idx + 4], COLOR_FG, alpha);
-            }
-        }
-    }
+// sRGB formats get correct blending for free. Otherwise an 8-bit UNORM
+// format is used and colours are written already encoded; wider or float
+// formats would need a different encoding, so they are not accepted.
+fn preferred_surface_format(formats: &[wgpu::TextureFormat]) -> Option<wgpu::TextureFormat> {
+    let srgb = formats
+        .iter()
+        .copied()
+        .find(|format| format.is_srgb() && format.block_copy_size(None) == Some(4));
+    srgb.or_else(|| {
+        formats.iter().copied().find(|format| {
+            matches!(
+                format,
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Rgba8Unorm
+            )
+        })
+    })
 }
 
-fn draw_cursor_bar(
-    origin_x: u32,
-    origin_y: u32,
-    width: usize,
-    height: usize,
-    stride: usize,
-    buffer: &mut [u8],
-) {
-    let bar_width = 2u32;
-    let bar_height = CELL_HEIGHT.saturating_sub(4);
-    let start_x = origin_x + 1;
-    let start_y = origin_y + 2;
-
-    for y in 0..bar_height {
-        let py = start_y + y;
-        if py as usize >= height {
-            continue;
-        }
-        for x in 0..bar_width {
-            let px = start_x + x;
-            if px as usize >= width {
-                continue;
-            }
-            let idx = py as usize * stride + px as usize * 4;
-            if idx + 4 <= buffer.len() {
-                buffer[idx..idx + 4].copy_from_slice(&COLOR_CURSOR);
-            }
-        }
-    }
+fn rgba(color: Rgb) -> [u8; 4] {
+    [color.r, color.g, color.b, 255]
 }
 
-fn blend_pixel(dst: &mut [u8], fg: [u8; 4], alpha: u8) {
-    let a = alpha as u32;
-    let inv = 255 - alpha as u32;
-    dst[0] = ((fg[0] as u32 * a + dst[0] as u32 * inv) / 255) as u8;
-    dst[1] = ((fg[1] as u32 * a + dst[1] as u32 * inv) / 255) as u8;
-    dst[2] = ((fg[2] as u32 * a + dst[2] as u32 * inv) / 255) as u8;
-    dst[3] = 255;
+fn supports_transparency(mode: wgpu::CompositeAlphaMode) -> bool {
+    matches!(
+        mode,
+        wgpu::CompositeAlphaMode::PreMultiplied | wgpu::CompositeAlphaMode::PostMultiplied
+    )
 }
 
-fn color_to_wgpu(color: [u8; 4]) -> wgpu::Color {
+fn color_to_wgpu(color: [u8; 4], srgb: bool, premultiplied: bool) -> wgpu::Color {
+    let [mut r, mut g, mut b, a] = linear_color(color, srgb);
+    if premultiplied {
+        r *= a;
+        g *= a;
+        b *= a;
+    }
     wgpu::Color {
-        r: color[0] as f64 / 255.0,
-        g: color[1] as f64 / 255.0,
-        b: color[2] as f64 / 255.0,
-        a: color[3] as f64 / 255.0,
+        r: r as f64,
+        g: g as f64,
+        b: b as f64,
+        a: a as f64,
     }
 }
 
-struct FontRasterizer {
-    font: Font,
-    size: f32,
-    cache: HashMap<char, GlyphBitmap>,
-    line_metrics: Option<fontdue::LineMetrics>,
+fn linear_color(color: [u8; 4], srgb: bool) -> [f32; 4] {
+    let channel = |value: u8| {
+        let value = value as f32 / 255.0;
+        if !srgb {
+            value
+        } else if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [
+        channel(color[0]),
+        channel(color[1]),
+        channel(color[2]),
+        color[3] as f32 / 255.0,
+    ]
 }
 
-impl FontRasterizer {
-    fn new(spec: FontSpec) -> Result<Self, RenderError> {
-        let font = Font::from_bytes(spec.bytes, fontdue::FontSettings::default())
-            .map_err(|err| RenderError::Font(err.to_string()))?;
-        let line_metrics = font.horizontal_line_metrics(spec.size);
-        Ok(Self {
-            font,
-            size: spec.size,
-            cache: HashMap::new(),
-            line_metrics,
-        })
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn rasterize(&mut self, ch: char) -> Option<&GlyphBitmap> {
-        if !self.cache.contains_key(&ch) {
-            let (metrics, bitmap) = self.font.rasterize(ch, self.size);
-            let glyph = GlyphBitmap {
-                width: metrics.width as u32,
-                height: metrics.height as u32,
-                xmin: metrics.xmin,
-                ymin: metrics.ymin,
-                advance_width: metrics.advance_width,
-                data: bitmap,
-            };
-            self.cache.insert(ch, glyph);
-        }
-        self.cache.get(&ch)
+    fn size(width: u32, height: u32) -> RenderSize {
+        RenderSize { width, height }
     }
-}
 
-struct GlyphBitmap {
-    width: u32,
-    height: u32,
-    xmin: i32,
-    ymin: i32,
-    advance_width: f32,
-    data: Vec<u8>,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 2],
-    uv: [f32; 2],
-}
-
-impl Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-            ],
+    #[test]
+    fn sizes_within_the_limit_are_kept() {
+        for (width, height) in [(800, 600), (8192, 8192), (8192, 1), (1, 8192)] {
+            assert_eq!(fit_to_limit(size(width, height), 8192), size(width, height));
         }
     }
-}
-
-const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [-1.0, -1.0],
-        uv: [0.0, 1.0],
-    },
-    Vertex {
-        position: [1.0, -1.0],
-        uv: [1.0, 1.0],
-    },
-    Vertex {
-        position: [1.0, 1.0],
-        uv: [1.0, 0.0],
-    },
-    Vertex {
-        position: [-1.0, -1.0],
-        uv: [0.0, 1.0],
-    },
-    Vertex {
-        position: [1.0, 1.0],
-        uv: [1.0, 0.0],
-    },
-    Vertex {
-        position: [-1.0, 1.0],
-        uv: [0.0, 0.0],
-    },
-];
 
-const RENDER_SHADER: &str = r#"
-struct VertexOutput {
-    @builtin(position) position: vec4<f32>,
-    @location(0) uv: vec2<f32>,
-};
+    #[test]
+    fn oversized_windows_keep_their_aspect_ratio() {
+        assert_eq!(fit_to_limit(size(10000, 5000), 8192), size(8192, 4096));
+        assert_eq!(fit_to_limit(size(3000, 12000), 8192), size(2048, 8192));
+    }
 
-@vertex
-fn vs_main(@location(0) position: vec2<f32>, @location(1) uv: vec2<f32>) -> VertexOutput {
-    var out: VertexOutput;
-    out.position = vec4<f32>(position, 0.0, 1.0);
-    out.uv = uv;
-    return out;
-}
+    #[test]
+    fn thin_windows_keep_at_least_one_pixel() {
+        assert_eq!(fit_to_limit(size(100_000, 1), 8192), size(8192, 1));
+        assert_eq!(fit_to_limit(size(2, 100_000), 8192), size(1, 8192));
+    }
 
-@group(0) @binding(0)
-var screen_texture: texture_2d<f32>;
-@group(0) @binding(1)
-var screen_sampler: sampler;
+    #[test]
+    fn the_grid_is_centered_at_every_window_size() {
+        let cell = CellMetrics {
+            width: 9,
+            height: 19,
+        };
+        for width in 40..400 {
+            for height in (40..300).step_by(7) {
+                let layout = GridLayout::new(size(width, height), cell);
+                let right = width - layout.origin_x - layout.cols * cell.width;
+                let bottom = height - layout.origin_y - layout.rows * cell.height;
+                assert!(layout.origin_x.abs_diff(right) <= 1, "{width}x{height}");
+                assert!(layout.origin_y.abs_diff(bottom) <= 1, "{width}x{height}");
+                assert!(layout.origin_x >= PADDING_X && layout.origin_y >= PADDING_Y);
+            }
+        }
+    }
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(screen_texture, screen_sampler, in.uv);
+    #[test]
+    fn cell_at_uses_the_centered_origin() {
+        let layout = GridLayout::new(
+            size(105, 70),
+            CellMetrics {
+                width: 10,
+                height: 20,
+            },
+        );
+        let (x, y) = (layout.origin_x as f64, layout.origin_y as f64);
+        let at = |x: f64, y: f64| layout.cell_at(x, y).map(|cell| (cell.col, cell.row));
+        assert_eq!(at(x, y), Some((0, 0)));
+        assert_eq!(at(x + 19.5, y + 20.0), Some((1, 1)));
+        assert_eq!(at(x - 0.5, y), None);
+        assert_eq!(at(x, y - 0.5), None);
+        assert_eq!(at(x + (layout.cols * 10) as f64, y), None);
+    }
 }
-"#;
@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use crate::frame::FrameRenderer;
+use crate::{
+    CellMetrics, CursorBlink, FontSpec, FrameStats, GlyphCacheStats, GridLayout, PostEffect,
+    RenderBackend, RenderError, RenderGrid, RenderSize, RowSource, TextAntialias, Theme,
+};
+
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+pub struct OffscreenRenderer {
+    frame: FrameRenderer,
+    frame_time: Duration,
+}
+
+impl OffscreenRenderer {
+    pub fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        size: RenderSize,
+        font: FontSpec,
+    ) -> Result<Self, RenderError> {
+        validate_size(size, device.limits().max_texture_dimension_2d)?;
+        let frame = FrameRenderer::new(
+            device,
+            queue,
+            OFFSCREEN_FORMAT,
+            wgpu::CompositeAlphaMode::PreMultiplied,
+            size,
+            font,
+        )?;
+        Ok(Self {
+            frame,
+            frame_time: Duration::ZERO,
+        })
+    }
+
+    pub fn resize(&mut self, size: RenderSize) -> Result<(), RenderError> {
+        validate_size(size, self.frame.device().limits().max_texture_dimension_2d)?;
+        self.frame.resize(size)
+    }
+
+    pub fn set_font(&mut self, font: FontSpec) -> Result<bool, RenderError> {
+        self.frame.set_font(font)
+    }
+
+    pub fn set_font_size(&mut self, size: f32) -> Result<CellMetrics, RenderError> {
+        self.frame.set_font_size(size)
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.frame.scale_factor()
+    }
+
+    pub fn set_scale_factor(&mut self, scale: f64) -> Result<CellMetrics, RenderError> {
+        self.frame.set_scale_factor(scale)
+    }
+
+    pub fn font_size(&self) -> f32 {
+        self.frame.font_size()
+    }
+
+    pub fn line_height_scale(&self) -> f32 {
+        self.frame.line_height_scale()
+    }
+
+    pub fn set_line_height_scale(&mut self, scale: f32) -> Result<CellMetrics, RenderError> {
+        self.frame.set_line_height_scale(scale)
+    }
+
+    pub fn cell_width_scale(&self) -> f32 {
+        self.frame.cell_width_scale()
+    }
+
+    pub fn set_cell_width_scale(&mut self, scale: f32) -> Result<CellMetrics, RenderError> {
+        self.frame.set_cell_width_scale(scale)
+    }
+
+    pub fn cell_metrics(&self) -> CellMetrics {
+        self.frame.cell_metrics()
+    }
+
+    pub fn grid_layout(&self) -> GridLayout {
+        self.frame.grid_layout()
+    }
+
+    pub fn backend(&self) -> RenderBackend {
+        self.frame.backend()
+    }
+
+    pub fn set_backend(&mut self, backend: RenderBackend) {
+        self.frame.set_backend(backend);
+    }
+
+    pub fn text_antialias(&self) -> TextAntialias {
+        self.frame.text_antialias()
+    }
+
+    pub fn set_text_antialias(&mut self, antialias: TextAntialias) {
+        self.frame.set_text_antialias(antialias);
+    }
+
+    pub fn set_ligatures(&mut self, enabled: bool) {
+        self.frame.set_ligatures(enabled);
+    }
+
+    pub fn set_text_blink(&mut self, enabled: bool) {
+        self.frame.set_text_blink(enabled);
+    }
+
+    pub fn cursor_blink(&self) -> CursorBlink {
+        self.frame.cursor_blink()
+    }
+
+    pub fn set_cursor_blink(&mut self, blink: CursorBlink) {
+        self.frame.set_cursor_blink(blink);
+    }
+
+    pub fn reset_cursor_blink(&mut self) {
+        self.frame.reset_cursor_blink();
+    }
+
+    pub fn next_blink_deadline(&self) -> Option<Instant> {
+        self.frame.next_blink_deadline()
+    }
+
+    pub fn post_effect(&self) -> Option<&PostEffect> {
+        self.frame.post_effect()
+    }
+
+    pub fn set_post_effect(&mut self, effect: Option<PostEffect>) -> Result<(), RenderError> {
+        self.frame.set_post_effect(effect)
+    }
+
+    pub fn minimum_contrast(&self) -> Option<f32> {
+        self.frame.minimum_contrast()
+    }
+
+    pub fn set_minimum_contrast(&mut self, ratio: Option<f32>) {
+        self.frame.set_minimum_contrast(ratio);
+    }
+
+    pub fn theme(&self) -> &Theme {
+        self.frame.theme()
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.frame.set_theme(theme);
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.frame.opacity()
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) -> Result<(), RenderError> {
+        self.frame.set_opacity(opacity)
+    }
+
+    pub fn last_frame_stats(&self) -> FrameStats {
+        FrameStats {
+            frame_time: self.frame_time,
+            ..self.frame.last_frame_stats()
+        }
+    }
+
+    pub fn glyph_cache_stats(&self) -> GlyphCacheStats {
+        self.frame.glyph_cache_stats()
+    }
+
+    pub fn set_glyph_cache_budget(&mut self, bytes: usize) {
+        self.frame.set_glyph_cache_budget(bytes);
+    }
+
+    // Builds the frame and hands it to the GPU without reading it back, as
+    // the windowed renderer does before presenting. False when the frame is
+    // unchanged.
+    pub fn prepare<R: RowSource + ?Sized>(
+        &mut self,
+        grid: &RenderGrid<'_, R>,
+    ) -> Result<bool, RenderError> {
+        self.frame.prepare(grid)
+    }
+
+    pub fn render_to_rgba<R: RowSource + ?Sized>(
+        &mut self,
+        grid: &RenderGrid<'_, R>,
+    ) -> Result<Vec<u8>, RenderError> {
+        let started = Instant::now();
+        self.frame.prepare(grid)?;
+        let rgba = self.frame.capture_frame()?.rgba;
+        self.frame_time = started.elapsed();
+        Ok(rgba)
+    }
+}
+
+fn validate_size(size: RenderSize, max: u32) -> Result<(), RenderError> {
+    if size.width == 0 || size.height == 0 {
+        return Err(RenderError::InvalidSize {
+            width: size.width,
+            height: size.height,
+        });
+    }
+    if size.width > max || size.height > max {
+        return Err(RenderError::SizeExceedsLimit {
+            width: size.width,
+            height: size.height,
+            max,
+        });
+    }
+    Ok(())
+}
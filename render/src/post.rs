@@ -0,0 +1,314 @@
+use crate::{RenderError, RenderSize};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostEffect {
+    Scanlines,
+    Crt,
+    // WGSL defining `fs_main`; it is appended to POST_PRELUDE, which declares
+    // the bindings and the full-screen vertex stage.
+    #[cfg(feature = "custom-post-effects")]
+    Custom(String),
+}
+
+pub(crate) struct PostProcess {
+    effect: PostEffect,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    view: wgpu::TextureView,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniforms {
+    resolution: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl PostProcess {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: RenderSize,
+        effect: PostEffect,
+    ) -> Result<Self, RenderError> {
+        let source = match &effect {
+            PostEffect::Scanlines => format!("{POST_PRELUDE}{SCANLINES_SHADER}"),
+            PostEffect::Crt => format!("{POST_PRELUDE}{CRT_SHADER}"),
+            #[cfg(feature = "custom-post-effects")]
+            PostEffect::Custom(source) => format!("{POST_PRELUDE}{source}"),
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("post_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        // Custom shaders may not compile, so surface that as an error rather
+        // than letting wgpu's uncaptured error handler panic.
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("post_shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("post_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        if let Some(err) = crate::frame::pop_error_scope(device) {
+            return Err(RenderError::PostEffect(err.to_string()));
+        }
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("post_uniforms"),
+            size: std::mem::size_of::<PostUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (view, bind_group) = create_target(
+            device,
+            format,
+            size,
+            &bind_group_layout,
+            &sampler,
+            &uniform_buffer,
+        );
+
+        Ok(Self {
+            effect,
+            pipeline,
+            bind_group_layout,
+            sampler,
+            uniform_buffer,
+            bind_group,
+            view,
+        })
+    }
+
+    pub(crate) fn effect(&self) -> &PostEffect {
+        &self.effect
+    }
+
+    // The frame is rendered here first, then resolved onto the real target.
+    pub(crate) fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub(crate) fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: RenderSize,
+    ) {
+        let (view, bind_group) = create_target(
+            device,
+            format,
+            size,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+        self.view = view;
+        self.bind_group = bind_group;
+    }
+
+    pub(crate) fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        size: RenderSize,
+    ) {
+        let uniforms = PostUniforms {
+            resolution: [size.width as f32, size.height as f32],
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("post_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    size: RenderSize,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    uniforms: &wgpu::Buffer,
+) -> (wgpu::TextureView, wgpu::BindGroup) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("post_source_texture"),
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("post_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniforms.as_entire_binding(),
+            },
+        ],
+    });
+    (view, bind_group)
+}
+
+pub(crate) const POST_PRELUDE: &str = r#"
+struct PostUniforms {
+    resolution: vec2<f32>,
+    _padding: vec2<f32>,
+};
+
+@group(0) @binding(0) var t_frame: texture_2d<f32>;
+@group(0) @binding(1) var s_frame: sampler;
+@group(0) @binding(2) var<uniform> post: PostUniforms;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+"#;
+
+pub(crate) const SCANLINES_SHADER: &str = r#"
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_frame, s_frame, in.uv);
+    let line = floor(in.position.y) % 2.0;
+    let shade = select(1.0, 0.7, line >= 1.0);
+    return vec4<f32>(color.rgb * shade, color.a);
+}
+"#;
+
+pub(crate) const CRT_SHADER: &str = r#"
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Barrel distortion around the centre of the screen.
+    let centered = in.uv * 2.0 - 1.0;
+    let warped = centered * (1.0 + 0.04 * dot(centered, centered));
+    let uv = warped * 0.5 + 0.5;
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    let texel = 1.0 / post.resolution;
+    let color = textureSample(t_frame, s_frame, uv);
+    var glow = vec3<f32>(0.0);
+    glow += textureSample(t_frame, s_frame, uv + vec2<f32>(texel.x * 2.0, 0.0)).rgb;
+    glow += textureSample(t_frame, s_frame, uv - vec2<f32>(texel.x * 2.0, 0.0)).rgb;
+    glow += textureSample(t_frame, s_frame, uv + vec2<f32>(0.0, texel.y * 2.0)).rgb;
+    glow += textureSample(t_frame, s_frame, uv - vec2<f32>(0.0, texel.y * 2.0)).rgb;
+
+    let line = floor(uv.y * post.resolution.y) % 2.0;
+    let shade = select(1.0, 0.75, line >= 1.0);
+    let vignette = clamp(1.0 - 0.35 * dot(centered, centered), 0.0, 1.0);
+    let rgb = (color.rgb + glow * 0.08) * shade * vignette;
+    return vec4<f32>(min(rgb, vec3<f32>(color.a)), color.a);
+}
+"#;
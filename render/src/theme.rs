@@ -0,0 +1,122 @@
+use screen::{Palette, Rgb};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub foreground: Rgb,
+    pub background: Rgb,
+    // None draws the cursor in inverse video using the colours of the cell
+    // underneath it.
+    pub cursor: Option<Rgb>,
+    pub selection: Rgb,
+    pub scrollbar_track: Rgb,
+    pub scrollbar_thumb: Rgb,
+    pub ansi: [Rgb; 16],
+    pub bold_is_bright: bool,
+    // How far faint (SGR 2) text is blended toward its background, 0.0-1.0.
+    pub faint_factor: f32,
+    // Width of the bar cursor and height of the underline cursor. Below 1.0
+    // it is a fraction of the cell, otherwise logical pixels.
+    pub cursor_thickness: f32,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        let palette = Palette::default();
+        let mut ansi = [rgb(0); 16];
+        ansi.copy_from_slice(&palette.colors[..16]);
+        Self {
+            foreground: palette.foreground,
+            background: palette.background,
+            cursor: palette.cursor,
+            selection: rgb(0x264f78),
+            scrollbar_track: rgb(0x2a2d33),
+            scrollbar_thumb: rgb(0x5c6370),
+            ansi,
+            bold_is_bright: true,
+            faint_factor: 0.5,
+            cursor_thickness: 2.0,
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            foreground: rgb(0x24292f),
+            background: rgb(0xffffff),
+            cursor: Some(rgb(0x0969da)),
+            selection: rgb(0xb6d7ff),
+            scrollbar_track: rgb(0xeaeef2),
+            scrollbar_thumb: rgb(0x8c959f),
+            ansi: [
+                rgb(0x24292f),
+                rgb(0xcf222e),
+                rgb(0x116329),
+                rgb(0x4d2d00),
+                rgb(0x0969da),
+                rgb(0x8250df),
+                rgb(0x1b7c83),
+                rgb(0x6e7781),
+                rgb(0x57606a),
+                rgb(0xa40e26),
+                rgb(0x1a7f37),
+                rgb(0x633c01),
+                rgb(0x218bff),
+                rgb(0xa475f9),
+                rgb(0x3192aa),
+                rgb(0x8c959f),
+            ],
+            bold_is_bright: true,
+            faint_factor: 0.5,
+            cursor_thickness: 2.0,
+        }
+    }
+
+    pub fn gruvbox() -> Self {
+        Self {
+            foreground: rgb(0xebdbb2),
+            background: rgb(0x282828),
+            cursor: Some(rgb(0xebdbb2)),
+            selection: rgb(0x504945),
+            scrollbar_track: rgb(0x3c3836),
+            scrollbar_thumb: rgb(0x7c6f64),
+            ansi: [
+                rgb(0x282828),
+                rgb(0xcc241d),
+                rgb(0x98971a),
+                rgb(0xd79921),
+                rgb(0x458588),
+                rgb(0xb16286),
+                rgb(0x689d6a),
+                rgb(0xa89984),
+                rgb(0x928374),
+                rgb(0xfb4934),
+                rgb(0xb8bb26),
+                rgb(0xfabd2f),
+                rgb(0x83a598),
+                rgb(0xd3869b),
+                rgb(0x8ec07c),
+                rgb(0xebdbb2),
+            ],
+            bold_is_bright: true,
+            faint_factor: 0.5,
+            cursor_thickness: 2.0,
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        Palette::from_ansi(self.ansi, self.foreground, self.background, self.cursor)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+const fn rgb(value: u32) -> Rgb {
+    Rgb {
+        r: (value >> 16) as u8,
+        g: (value >> 8) as u8,
+        b: value as u8,
+    }
+}
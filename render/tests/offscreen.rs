@@ -0,0 +1,653 @@
+// Renders small grids without a window and checks the pixels. Runs on the
+// fallback (software) adapter; each test returns early on machines without
+// one, or without a monospace font to draw with.
+
+use render::{
+    CursorPosition, FontSpec, GridLayout, GridSelection, OffscreenRenderer, RenderBackend,
+    RenderGrid, RenderSize, Theme, MAX_CELL_SCALE, MAX_FONT_SIZE, MIN_CELL_SCALE, MIN_FONT_SIZE,
+};
+use screen::{Cell, CellFlags, Color, CursorShape, Palette, Rgb, UnderlineStyle};
+
+const FONTS: [&str; 3] = [
+    r"C:\Windows\Fonts\consola.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+    "/System/Library/Fonts/Menlo.ttc",
+];
+const SIZE: RenderSize = RenderSize {
+    width: 160,
+    height: 96,
+};
+const RED: Rgb = Rgb { r: 255, g: 0, b: 0 };
+const GREEN: Rgb = Rgb { r: 0, g: 255, b: 0 };
+
+fn renderer() -> Option<OffscreenRenderer> {
+    let bytes = FONTS.iter().find_map(|path| std::fs::read(path).ok())?;
+    let instance = wgpu::Instance::default();
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        force_fallback_adapter: true,
+        compatible_surface: None,
+    }))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            required_limits: adapter.limits(),
+        },
+        None,
+    ))
+    .ok()?;
+    let font = FontSpec {
+        bytes,
+        size: 16.0,
+        collection_index: 0,
+        weight: None,
+    };
+    Some(OffscreenRenderer::new(device, queue, SIZE, font).expect("offscreen renderer"))
+}
+
+fn blank(renderer: &OffscreenRenderer) -> Vec<Cell> {
+    let layout = renderer.grid_layout();
+    vec![Cell::default(); (layout.cols * layout.rows) as usize]
+}
+
+fn grid<'a>(
+    renderer: &OffscreenRenderer,
+    cells: &'a [Cell],
+    palette: &'a Palette,
+) -> RenderGrid<'a> {
+    let layout = renderer.grid_layout();
+    RenderGrid {
+        cols: layout.cols as u16,
+        rows: layout.rows as u16,
+        cells,
+        palette,
+        cursor: None,
+        cursor_visible: false,
+        cursor_shape: CursorShape::Block,
+        cursor_blinking: false,
+        scroll_indicator: None,
+        scroll_pixel_offset: 0.0,
+        blink_on: true,
+        focused: true,
+        preedit: None,
+        selection: None,
+        link: None,
+        notice: None,
+    }
+}
+
+struct Frame {
+    rgba: Vec<u8>,
+    layout: GridLayout,
+}
+
+impl Frame {
+    fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        let at = ((y * SIZE.width + x) * 4) as usize;
+        [self.rgba[at], self.rgba[at + 1], self.rgba[at + 2]]
+    }
+
+    // Every pixel of the cell, row by row.
+    fn cell(&self, col: u32, row: u32) -> Vec<[u8; 3]> {
+        let cell = self.layout.cell;
+        let left = self.layout.origin_x + col * cell.width;
+        let top = self.layout.origin_y + row * cell.height;
+        (top..top + cell.height)
+            .flat_map(|y| (left..left + cell.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.pixel(x, y))
+            .collect()
+    }
+
+    // Pixel rows of the cell, from its top, drawn fully in `color`.
+    fn lines(&self, col: u32, row: u32, color: Rgb) -> Vec<u32> {
+        let width = self.layout.cell.width as usize;
+        self.cell(col, row)
+            .chunks(width)
+            .enumerate()
+            .filter(|(_, line)| line.iter().all(|pixel| *pixel == rgb(color)))
+            .map(|(y, _)| y as u32)
+            .collect()
+    }
+
+    // Pixel rows of the cell, from its top, with anything other than
+    // `color` in them.
+    fn ink_rows(&self, col: u32, row: u32, color: Rgb) -> Vec<u32> {
+        let width = self.layout.cell.width as usize;
+        self.cell(col, row)
+            .chunks(width)
+            .enumerate()
+            .filter(|(_, line)| line.iter().any(|pixel| *pixel != rgb(color)))
+            .map(|(y, _)| y as u32)
+            .collect()
+    }
+
+    // Pixel columns of the cell, from its left, with anything other than
+    // `color` in them.
+    fn ink_cols(&self, col: u32, row: u32, color: Rgb) -> Vec<u32> {
+        let width = self.layout.cell.width as usize;
+        let pixels = self.cell(col, row);
+        (0..width)
+            .filter(|x| {
+                pixels
+                    .iter()
+                    .skip(*x)
+                    .step_by(width)
+                    .any(|pixel| *pixel != rgb(color))
+            })
+            .map(|x| x as u32)
+            .collect()
+    }
+
+    // Pixels of the cell that differ from `color`.
+    fn inked(&self, col: u32, row: u32, color: Rgb) -> usize {
+        self.cell(col, row)
+            .into_iter()
+            .filter(|pixel| *pixel != rgb(color))
+            .count()
+    }
+}
+
+fn render(renderer: &mut OffscreenRenderer, cells: &[Cell], palette: &Palette) -> Frame {
+    render_with(renderer, cells, palette, |_| {})
+}
+
+// Renders after `edit` has set the grid fields the test is about.
+fn render_with(
+    renderer: &mut OffscreenRenderer,
+    cells: &[Cell],
+    palette: &Palette,
+    edit: impl FnOnce(&mut RenderGrid<'_>),
+) -> Frame {
+    let mut grid = grid(renderer, cells, palette);
+    edit(&mut grid);
+    let rgba = renderer.render_to_rgba(&grid).expect("render");
+    assert_eq!(rgba.len(), (SIZE.width * SIZE.height * 4) as usize);
+    Frame {
+        rgba,
+        layout: renderer.grid_layout(),
+    }
+}
+
+fn rgb(color: Rgb) -> [u8; 3] {
+    [color.r, color.g, color.b]
+}
+
+#[test]
+fn a_blank_grid_is_the_background_colour() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let cells = blank(&renderer);
+    let palette = Palette::default();
+    let frame = render(&mut renderer, &cells, &palette);
+    for (x, y) in [(0, 0), (SIZE.width - 1, SIZE.height - 1), (80, 48)] {
+        assert_eq!(frame.pixel(x, y), rgb(palette.background));
+    }
+}
+
+#[test]
+fn cell_backgrounds_fill_their_cell() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let mut cells = blank(&renderer);
+    cells[1].bg = Color::Rgb(RED);
+    let palette = Palette::default();
+    let frame = render(&mut renderer, &cells, &palette);
+    assert!(frame.cell(1, 0).iter().all(|pixel| *pixel == rgb(RED)));
+    assert_eq!(frame.inked(0, 0, palette.background), 0);
+    assert_eq!(frame.inked(2, 0, palette.background), 0);
+}
+
+#[test]
+fn glyphs_are_drawn_inside_their_cell() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let mut cells = blank(&renderer);
+    cells[0].ch = 'M';
+    let palette = Palette::default();
+    let frame = render(&mut renderer, &cells, &palette);
+    assert!(frame.inked(0, 0, palette.background) > 0);
+    assert_eq!(frame.inked(1, 0, palette.background), 0);
+}
+
+#[test]
+fn glyphs_take_the_cell_foreground() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let mut cells = blank(&renderer);
+    cells[0] = Cell {
+        ch: '█',
+        fg: Color::Rgb(GREEN),
+        ..Cell::default()
+    };
+    cells[1] = Cell {
+        ch: '█',
+        fg: Color::Indexed(4),
+        ..Cell::default()
+    };
+    cells[2] = Cell {
+        ch: 'M',
+        fg: Color::Rgb(GREEN),
+        bg: Color::Rgb(RED),
+        ..Cell::default()
+    };
+    let palette = Palette::default();
+    let frame = render(&mut renderer, &cells, &palette);
+    assert_eq!(frame.inked(0, 0, GREEN), 0);
+    assert_eq!(frame.inked(1, 0, palette.colors[4]), 0);
+    let lettered = frame.cell(2, 0);
+    assert!(lettered.contains(&rgb(GREEN)));
+    assert!(lettered.contains(&rgb(RED)));
+    // Only red, green and blends of the two; none of the default background.
+    assert!(lettered.iter().all(|pixel| pixel[2] == 0));
+}
+
+#[test]
+fn decorations_are_lines_across_the_cell() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let decorated = |underline, flags| Cell {
+        fg: Color::Rgb(RED),
+        underline,
+        flags,
+        ..Cell::default()
+    };
+    let mut cells = blank(&renderer);
+    cells[0] = decorated(Some(UnderlineStyle::Single), CellFlags::empty());
+    cells[1] = decorated(None, CellFlags::STRIKETHROUGH);
+    cells[2] = decorated(None, CellFlags::OVERLINE);
+    cells[3] = Cell {
+        underline_color: Color::Rgb(GREEN),
+        ..decorated(Some(UnderlineStyle::Double), CellFlags::empty())
+    };
+    let palette = Palette::default();
+    let frame = render(&mut renderer, &cells, &palette);
+    let height = frame.layout.cell.height;
+
+    let underline = frame.lines(0, 0, RED);
+    assert!(!underline.is_empty());
+    assert!(underline.iter().all(|y| *y > height / 2));
+    let strikethrough = frame.lines(1, 0, RED);
+    assert!(!strikethrough.is_empty());
+    assert!(strikethrough
+        .iter()
+        .all(|y| *y > height / 4 && *y < height * 3 / 4));
+    assert_eq!(frame.lines(2, 0, RED).first(), Some(&0));
+    // A double underline is two separate lines in the underline colour.
+    let double = frame.lines(3, 0, GREEN);
+    assert!(double.windows(2).any(|pair| pair[1] > pair[0] + 1));
+    assert_eq!(
+        frame.inked(3, 0, palette.background),
+        double.len() * frame.layout.cell.width as usize
+    );
+}
+
+#[test]
+fn font_size_changes_resize_cells_and_glyphs() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let palette = Palette::default();
+    let letter = |renderer: &OffscreenRenderer| {
+        let mut cells = blank(renderer);
+        cells[0].ch = 'M';
+        cells
+    };
+    let before = renderer.cell_metrics();
+    let cells = letter(&renderer);
+    let small = render(&mut renderer, &cells, &palette);
+
+    let after = renderer.set_font_size(32.0).expect("font size");
+    assert_eq!(renderer.font_size(), 32.0);
+    assert!(after.width > before.width && after.height > before.height);
+    assert_eq!(renderer.grid_layout().cell, after);
+    let cells = letter(&renderer);
+    let large = render(&mut renderer, &cells, &palette);
+    let height = |frame: &Frame| frame.ink_rows(0, 0, palette.background).len();
+    assert!(height(&large) > height(&small));
+
+    renderer.set_font_size(500.0).expect("font size");
+    assert_eq!(renderer.font_size(), MAX_FONT_SIZE);
+    renderer.set_font_size(1.0).expect("font size");
+    assert_eq!(renderer.font_size(), MIN_FONT_SIZE);
+}
+
+// Text cells for the atlas and backend tests.
+fn text(renderer: &OffscreenRenderer, line: &str) -> Vec<Cell> {
+    let mut cells = blank(renderer);
+    for (cell, ch) in cells.iter_mut().zip(line.chars()) {
+        cell.ch = ch;
+    }
+    cells
+}
+
+// The CPU backend is the reference the atlas is checked against. Glyph
+// edges blend slightly differently on the two, so text is compared by the
+// pixels it covers while solid fills must match exactly.
+#[test]
+fn the_atlas_draws_what_the_cpu_backend_draws() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let palette = Palette::default();
+    let mut cells = text(&renderer, "Hello, world! {gjpqy}@#");
+    let cols = renderer.grid_layout().cols as usize;
+    cells[cols].bg = Color::Rgb(RED);
+    cells[cols + 1] = Cell {
+        ch: '█',
+        fg: Color::Rgb(GREEN),
+        ..Cell::default()
+    };
+    cells[cols + 2] = Cell {
+        ch: '╬',
+        fg: Color::Indexed(3),
+        bg: Color::Indexed(4),
+        underline: Some(UnderlineStyle::Single),
+        ..Cell::default()
+    };
+    let gpu = render(&mut renderer, &cells, &palette);
+    renderer.set_backend(RenderBackend::Cpu);
+    let cpu = render(&mut renderer, &cells, &palette);
+
+    for col in 0..3 {
+        assert_eq!(gpu.cell(col, 1), cpu.cell(col, 1), "cell {col}");
+    }
+    let background = rgb(palette.background);
+    let covered = |frame: &Frame| -> Vec<bool> {
+        (0..SIZE.height)
+            .flat_map(|y| (0..SIZE.width).map(move |x| (x, y)))
+            .map(|(x, y)| frame.pixel(x, y) != background)
+            .collect()
+    };
+    let (gpu, cpu) = (covered(&gpu), covered(&cpu));
+    let inked = cpu.iter().filter(|covered| **covered).count();
+    let differing = gpu.iter().zip(&cpu).filter(|(a, b)| a != b).count();
+    assert!(inked > 0);
+    assert!(
+        differing * 20 < inked,
+        "{differing} of {inked} pixels differ"
+    );
+}
+
+#[test]
+fn glyphs_already_in_the_atlas_are_not_uploaded_again() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let palette = Palette::default();
+    let cells = text(&renderer, "Hello, world!");
+    render(&mut renderer, &cells, &palette);
+    let first = renderer.last_frame_stats().bytes_uploaded;
+    // The same glyphs a cell to the right: new instances, no new bitmaps.
+    let shifted = text(&renderer, " Hello, world!");
+    render(&mut renderer, &shifted, &palette);
+    let moved = renderer.last_frame_stats().bytes_uploaded;
+    assert!(moved > 0 && moved < first, "{moved} after {first}");
+    render(&mut renderer, &shifted, &palette);
+    assert_eq!(renderer.last_frame_stats().bytes_uploaded, 0);
+    assert!(renderer.glyph_cache_stats().entries > 0);
+}
+
+#[test]
+fn the_cpu_backend_uploads_only_damaged_rows() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    renderer.set_backend(RenderBackend::Cpu);
+    let palette = Palette::default();
+    let mut cells = text(&renderer, "Hello, world!");
+    render(&mut renderer, &cells, &palette);
+    let full = renderer.last_frame_stats().bytes_uploaded;
+    // Rows padded to the copy alignment, for the whole surface.
+    let row_bytes = (SIZE.width * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    assert_eq!(full, (row_bytes * SIZE.height) as u64);
+
+    let cols = renderer.grid_layout().cols as usize;
+    cells[cols + 2].bg = Color::Rgb(RED);
+    let frame = render(&mut renderer, &cells, &palette);
+    let band = renderer.last_frame_stats().bytes_uploaded;
+    assert_eq!(band, (row_bytes * frame.layout.cell.height) as u64);
+    assert!(frame.cell(2, 1).iter().all(|pixel| *pixel == rgb(RED)));
+
+    render(&mut renderer, &cells, &palette);
+    assert_eq!(renderer.last_frame_stats().bytes_uploaded, 0);
+    renderer.resize(SIZE).expect("resize");
+    render(&mut renderer, &cells, &palette);
+    assert_eq!(renderer.last_frame_stats().bytes_uploaded, full);
+}
+
+#[test]
+fn bold_ansi_colours_can_be_drawn_bright() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let block = |index: u8, flags: CellFlags| Cell {
+        ch: '█',
+        fg: Color::Indexed(index),
+        flags,
+        ..Cell::default()
+    };
+    let mut cells = blank(&renderer);
+    cells[0] = block(1, CellFlags::BOLD);
+    cells[1] = block(1, CellFlags::empty());
+    cells[2] = block(9, CellFlags::BOLD);
+    let palette = Palette::default();
+
+    let frame = render(&mut renderer, &cells, &palette);
+    assert_eq!(frame.inked(0, 0, palette.colors[9]), 0);
+    assert_eq!(frame.inked(1, 0, palette.colors[1]), 0);
+    assert_eq!(frame.inked(2, 0, palette.colors[9]), 0);
+
+    renderer.set_theme(Theme {
+        bold_is_bright: false,
+        ..Theme::dark()
+    });
+    let frame = render(&mut renderer, &cells, &palette);
+    assert_eq!(frame.inked(0, 0, palette.colors[1]), 0);
+}
+
+#[test]
+fn a_block_cursor_keeps_the_character_under_it_legible() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let mut cells = blank(&renderer);
+    cells[0] = Cell {
+        ch: 'M',
+        fg: Color::Rgb(GREEN),
+        bg: Color::Rgb(RED),
+        ..Cell::default()
+    };
+    let cursor = |grid: &mut RenderGrid<'_>| {
+        grid.cursor = Some(CursorPosition { col: 0, row: 0 });
+        grid.cursor_visible = true;
+    };
+
+    // Without a cursor colour the cell is drawn in inverse video.
+    let palette = Palette {
+        cursor: None,
+        ..Palette::default()
+    };
+    let frame = render_with(&mut renderer, &cells, &palette, cursor);
+    let pixels = frame.cell(0, 0);
+    assert!(pixels.contains(&rgb(GREEN)));
+    assert!(pixels.contains(&rgb(RED)));
+    assert!(pixels.iter().all(|pixel| pixel[2] == 0));
+    let inverse = pixels.iter().filter(|pixel| **pixel == rgb(GREEN)).count();
+    assert!(inverse * 2 > pixels.len());
+
+    let blue = Rgb { r: 0, g: 0, b: 255 };
+    let palette = Palette {
+        cursor: Some(blue),
+        ..Palette::default()
+    };
+    let frame = render_with(&mut renderer, &cells, &palette, cursor);
+    let pixels = frame.cell(0, 0);
+    assert!(pixels.contains(&rgb(blue)));
+    assert!(frame.inked(0, 0, blue) > 0);
+}
+
+#[test]
+fn scaled_cells_fit_fewer_rows_with_glyphs_centered() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let palette = Palette::default();
+    let window = RenderSize {
+        width: 800,
+        height: 600,
+    };
+    let normal = renderer.cell_metrics();
+    let cells = text(&renderer, "M");
+    let before = render(&mut renderer, &cells, &palette);
+
+    let taller = renderer.set_line_height_scale(1.2).expect("line height");
+    assert_eq!(taller.width, normal.width);
+    assert_eq!(taller.height, (normal.height as f32 * 1.2).round() as u32);
+    let rows = GridLayout::new(window, normal).rows as f32;
+    let scaled_rows = GridLayout::new(window, taller).rows as f32;
+    assert!(
+        (scaled_rows - rows / 1.2).abs() <= 1.0,
+        "{scaled_rows} rows for {rows}"
+    );
+
+    // The extra height is split above and below the glyph.
+    let tall = renderer.set_line_height_scale(2.0).expect("line height");
+    let cells = text(&renderer, "M");
+    let after = render(&mut renderer, &cells, &palette);
+    let top = |frame: &Frame| frame.ink_rows(0, 0, palette.background)[0] as i32;
+    let shift = top(&after) - top(&before);
+    assert!((shift - (tall.height - normal.height) as i32 / 2).abs() <= 1);
+
+    renderer.set_line_height_scale(1.0).expect("line height");
+    let wide = renderer.set_cell_width_scale(2.0).expect("cell width");
+    assert_eq!(wide.width, normal.width * 2);
+    let cells = text(&renderer, "M");
+    let frame = render(&mut renderer, &cells, &palette);
+    let ink = frame.ink_cols(0, 0, palette.background);
+    let left = ink[0] as i32;
+    let right = (wide.width - 1 - ink[ink.len() - 1]) as i32;
+    assert!(
+        (left - right).abs() <= 1,
+        "{left} left of the glyph, {right} right"
+    );
+
+    renderer.set_line_height_scale(5.0).expect("line height");
+    assert_eq!(renderer.line_height_scale(), MAX_CELL_SCALE);
+    renderer.set_cell_width_scale(0.1).expect("cell width");
+    assert_eq!(renderer.cell_width_scale(), MIN_CELL_SCALE);
+}
+
+#[test]
+fn faint_text_is_blended_toward_the_background() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let block = |flags: CellFlags| Cell {
+        ch: '█',
+        fg: Color::Rgb(RED),
+        bg: Color::Rgb(GREEN),
+        flags,
+        ..Cell::default()
+    };
+    let mut cells = blank(&renderer);
+    cells[0] = block(CellFlags::FAINT);
+    cells[1] = block(CellFlags::empty());
+    let palette = Palette::default();
+    let halfway = Rgb {
+        r: 128,
+        g: 128,
+        b: 0,
+    };
+    let frame = render(&mut renderer, &cells, &palette);
+    assert_eq!(frame.inked(0, 0, halfway), 0);
+    assert_eq!(frame.inked(1, 0, RED), 0);
+
+    renderer.set_theme(Theme {
+        faint_factor: 0.25,
+        ..Theme::dark()
+    });
+    let quarter = Rgb {
+        r: 191,
+        g: 64,
+        b: 0,
+    };
+    let frame = render(&mut renderer, &cells, &palette);
+    assert_eq!(frame.inked(0, 0, quarter), 0);
+}
+
+#[test]
+fn inverse_cells_swap_foreground_and_background() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let inverse = |ch: char, fg: Color, bg: Color| Cell {
+        ch,
+        fg,
+        bg,
+        flags: CellFlags::INVERSE,
+        ..Cell::default()
+    };
+    let mut cells = blank(&renderer);
+    cells[0] = inverse('M', Color::Default, Color::Default);
+    cells[1] = inverse('█', Color::Default, Color::Rgb(GREEN));
+    cells[2] = inverse(' ', Color::Rgb(RED), Color::Default);
+    cells[3] = inverse(' ', Color::Rgb(RED), Color::Default);
+    let palette = Palette::default();
+    let selection = renderer.theme().selection;
+    let frame = render_with(&mut renderer, &cells, &palette, |grid| {
+        let cell = CursorPosition { col: 3, row: 0 };
+        grid.selection = Some(GridSelection {
+            start: cell,
+            end: cell,
+        });
+    });
+
+    // Background-coloured text on a foreground-coloured rectangle.
+    let pixels = frame.cell(0, 0);
+    let rectangle = pixels
+        .iter()
+        .filter(|pixel| **pixel == rgb(palette.foreground))
+        .count();
+    assert!(rectangle * 2 > pixels.len());
+    assert!(pixels.contains(&rgb(palette.background)));
+    assert_eq!(frame.inked(1, 0, GREEN), 0);
+    assert_eq!(frame.inked(2, 0, RED), 0);
+    // The selection colour wins over the swapped background.
+    assert_eq!(frame.inked(3, 0, selection), 0);
+}
+
+#[test]
+fn doubling_the_scale_factor_doubles_cells_and_glyphs() {
+    let Some(mut renderer) = renderer() else {
+        return;
+    };
+    let palette = Palette::default();
+    let normal = renderer.cell_metrics();
+    let cells = text(&renderer, "M");
+    let before = render(&mut renderer, &cells, &palette);
+
+    let doubled = renderer.set_scale_factor(2.0).expect("scale factor");
+    assert_eq!(renderer.scale_factor(), 2.0);
+    assert_eq!(renderer.font_size(), 16.0);
+    assert!(doubled.width.abs_diff(normal.width * 2) <= 1);
+    assert!(doubled.height.abs_diff(normal.height * 2) <= 1);
+    let cells = text(&renderer, "M");
+    let after = render(&mut renderer, &cells, &palette);
+    let height = |frame: &Frame| frame.ink_rows(0, 0, palette.background).len();
+    assert!(height(&after).abs_diff(height(&before) * 2) <= 2);
+
+    for invalid in [0.0, -1.0, f64::NAN] {
+        assert_eq!(renderer.set_scale_factor(invalid).expect("scale"), doubled);
+    }
+    assert_eq!(
+        renderer.set_scale_factor(1.0).expect("scale factor"),
+        normal
+    );
+}
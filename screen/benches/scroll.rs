@@ -0,0 +1,74 @@
+// Full-screen scrolling through the parse -> screen path at a 4K grid
+// (3840x2160 with 8x16 cells). Run with `cargo bench -p screen`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use screen::{Screen, ScreenSize};
+use vt::{VtEvent, VtParser};
+
+const GRID: ScreenSize = ScreenSize {
+    cols: 480,
+    rows: 135,
+};
+
+// One screenful of colored lines, so every row scrolls once per pass.
+fn screenful() -> Vec<u8> {
+    let mut output = String::new();
+    for line in 0..GRID.rows as usize {
+        let color = 31 + line % 7;
+        output.push_str(&format!("\x1b[{color}m{line:>6} \x1b[0m"));
+        let text = "the quick brown fox jumps over the lazy dog ";
+        output.extend(text.chars().cycle().take(GRID.cols as usize - 8));
+        output.push_str("\r\n");
+    }
+    output.into_bytes()
+}
+
+fn parse(c: &mut Criterion) {
+    let input = screenful();
+    let mut group = c.benchmark_group("scroll");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+
+    group.bench_function("parse", |b| {
+        let mut parser = VtParser::new();
+        let mut events: Vec<VtEvent> = Vec::new();
+        b.iter(|| {
+            events.clear();
+            parser.advance(black_box(&input), &mut events);
+            black_box(events.len())
+        });
+    });
+
+    group.bench_function("parse_and_apply", |b| {
+        let mut parser = VtParser::new();
+        let mut events = Vec::new();
+        let mut screen = Screen::new(GRID).expect("screen");
+        b.iter(|| {
+            events.clear();
+            parser.advance(black_box(&input), &mut events);
+            screen.apply_events(&events);
+        });
+    });
+
+    // What the renderer reads back each frame after the output above.
+    group.bench_function("read_visible_rows", |b| {
+        let mut parser = VtParser::new();
+        let mut events = Vec::new();
+        let mut screen = Screen::new(GRID).expect("screen");
+        parser.advance(&input, &mut events);
+        screen.apply_events(&events);
+        b.iter(|| {
+            let mut printed = 0;
+            for row in 0..GRID.rows as usize {
+                if let Some(cells) = screen.view_row(0, row) {
+                    printed += cells.iter().filter(|cell| cell.ch != ' ').count();
+                }
+            }
+            black_box(printed)
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -1,4 +1,9 @@
-use vt::VtEvent;
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use unicode_width::UnicodeWidthChar;
+use vt::{CursorStyle, DynamicColor, EraseMode, Mode, PromptMark, SgrAttribute, VtEvent};
+
+pub use vt::{Color, CursorShape, Rgb, UnderlineStyle};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct ScreenSize {
@@ -12,17 +17,262 @@ pub struct Cursor {
     pub row: u16,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScrollPosition {
+    pub offset: usize,
+    pub scrollback: usize,
+    pub rows: u16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CursorState {
+    pub visible: bool,
+    pub style: CursorShape,
+    pub blink: bool,
+}
+
+impl Default for CursorState {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            style: CursorShape::Bar,
+            blink: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 256],
+    pub foreground: Rgb,
+    pub background: Rgb,
+    pub cursor: Option<Rgb>,
+}
+
+impl Palette {
+    pub fn from_ansi(
+        ansi: [Rgb; 16],
+        foreground: Rgb,
+        background: Rgb,
+        cursor: Option<Rgb>,
+    ) -> Self {
+        let mut colors = [Rgb { r: 0, g: 0, b: 0 }; 256];
+        colors[..16].copy_from_slice(&ansi);
+        let levels = [0u8, 95, 135, 175, 215, 255];
+        for index in 0..216 {
+            colors[16 + index] = Rgb {
+                r: levels[index / 36],
+                g: levels[(index / 6) % 6],
+                b: levels[index % 6],
+            };
+        }
+        for step in 0..24 {
+            let level = 8 + step as u8 * 10;
+            colors[232 + step] = Rgb {
+                r: level,
+                g: level,
+                b: level,
+            };
+        }
+        Self {
+            colors,
+            foreground,
+            background,
+            cursor,
+        }
+    }
+
+    pub fn resolve(&self, color: Color, default: Rgb) -> Rgb {
+        match color {
+            Color::Default => default,
+            Color::Indexed(index) => self.colors[index as usize],
+            Color::Rgb(rgb) => rgb,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::from_ansi(
+            DEFAULT_ANSI_COLORS.map(|[r, g, b]| Rgb { r, g, b }),
+            Rgb {
+                r: 230,
+                g: 237,
+                b: 243,
+            },
+            Rgb {
+                r: 10,
+                g: 14,
+                b: 20,
+            },
+            Some(Rgb {
+                r: 88,
+                g: 168,
+                b: 255,
+            }),
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CellFlags(u16);
+
+impl CellFlags {
+    pub const BOLD: Self = Self(1 << 0);
+    pub const FAINT: Self = Self(1 << 1);
+    pub const ITALIC: Self = Self(1 << 2);
+    pub const BLINK: Self = Self(1 << 4);
+    pub const INVERSE: Self = Self(1 << 5);
+    pub const HIDDEN: Self = Self(1 << 6);
+    pub const STRIKETHROUGH: Self = Self(1 << 7);
+    pub const OVERLINE: Self = Self(1 << 8);
+    pub const WIDE: Self = Self(1 << 9);
+    pub const WIDE_SPACER: Self = Self(1 << 10);
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Cell {
     pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub underline: Option<UnderlineStyle>,
+    pub underline_color: Color,
+    pub flags: CellFlags,
 }
 
 impl Default for Cell {
     fn default() -> Self {
-        Self { ch: ' ' }
+        Self {
+            ch: ' ',
+            fg: Color::Default,
+            bg: Color::Default,
+            underline: None,
+            underline_color: Color::Default,
+            flags: CellFlags::empty(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RowView<'a> {
+    pub cells: &'a [Cell],
+    pub line: usize,
+    pub wrapped: bool,
+    pub timestamp: Option<SystemTime>,
+}
+
+struct ScrollbackLine {
+    cells: Vec<Cell>,
+    wrapped: bool,
+    archived_at: u64,
+}
+
+impl ScrollbackLine {
+    fn new(cells: Vec<Cell>, wrapped: bool, time: SystemTime) -> Self {
+        let archived_at = time
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Self {
+            cells,
+            wrapped,
+            archived_at,
+        }
+    }
+
+    fn archived_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.archived_at)
+    }
+}
+
+struct LogicalLine {
+    cells: Vec<Cell>,
+    timestamp: Option<SystemTime>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct LinePosition {
+    logical: usize,
+    offset: usize,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct Pen {
+    fg: Color,
+    bg: Color,
+    underline: Option<UnderlineStyle>,
+    underline_color: Color,
+    flags: CellFlags,
+}
+
+// A cell on an absolute line, numbered like RowView::line: scrollback
+// first, then the grid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SelectionPoint {
+    pub line: usize,
+    pub col: u16,
+}
+
+// Both ends are inclusive. The anchor is where the selection started and
+// the head where it currently ends, which may be before the anchor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: SelectionPoint,
+    pub head: SelectionPoint,
+}
+
+impl Selection {
+    pub fn ordered(self) -> (SelectionPoint, SelectionPoint) {
+        if self.head < self.anchor {
+            (self.head, self.anchor)
+        } else {
+            (self.anchor, self.head)
+        }
     }
 }
 
+// A URL in the text, from `start` to `end` inclusive. It may run across
+// soft-wrapped rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlMatch {
+    pub url: String,
+    pub start: SelectionPoint,
+    pub end: SelectionPoint,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRecord {
+    pub prompt_line: usize,
+    pub output: Range<usize>,
+    pub exit_code: Option<i32>,
+}
+
+// Which mouse events the application asked to be sent.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum MouseTracking {
+    #[default]
+    Off,
+    Clicks,
+    Drag,
+    Motion,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ScreenError {
     #[error("invalid screen size: cols={cols}, rows={rows}")]
@@ -32,9 +282,35 @@ pub enum ScreenError {
 pub struct Screen {
     size: ScreenSize,
     cursor: Cursor,
+    cursor_state: CursorState,
+    // What a reset or DECSCUSR 0 returns the cursor to.
+    default_cursor: CursorState,
     cells: Vec<Cell>,
-    scrollback: Vec<Vec<Cell>>,
+    wrapped: Vec<bool>,
+    scrollback: Vec<ScrollbackLine>,
+    scrollback_limit: usize,
     scroll_offset: usize,
+    pen: Pen,
+    commands: Vec<CommandRecord>,
+    pending_prompt: Option<usize>,
+    pending_output: Option<usize>,
+    title: String,
+    icon_title: String,
+    title_stack: Vec<(String, String)>,
+    title_changed: bool,
+    // Last reported by the shell with OSC 7.
+    working_directory: Option<String>,
+    bell: bool,
+    palette: Palette,
+    base_palette: Palette,
+    palette_changed: bool,
+    // Answers to queries, waiting to be written back to the pty.
+    replies: Vec<u8>,
+    application_cursor_keys: bool,
+    bracketed_paste: bool,
+    mouse_tracking: MouseTracking,
+    sgr_mouse: bool,
+    selection: Option<Selection>,
 }
 
 impl Screen {
@@ -44,9 +320,32 @@ impl Screen {
         Ok(Self {
             size,
             cursor: Cursor { col: 0, row: 0 },
+            cursor_state: CursorState::default(),
+            default_cursor: CursorState::default(),
             cells,
+            wrapped: vec![false; size.rows as usize],
             scrollback: Vec::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK_LINES,
             scroll_offset: 0,
+            pen: Pen::default(),
+            commands: Vec::new(),
+            pending_prompt: None,
+            pending_output: None,
+            title: String::new(),
+            icon_title: String::new(),
+            title_stack: Vec::new(),
+            title_changed: false,
+            working_directory: None,
+            bell: false,
+            palette: Palette::default(),
+            base_palette: Palette::default(),
+            palette_changed: false,
+            replies: Vec::new(),
+            application_cursor_keys: false,
+            bracketed_paste: false,
+            mouse_tracking: MouseTracking::Off,
+            sgr_mouse: false,
+            selection: None,
         })
     }
 
@@ -58,16 +357,48 @@ impl Screen {
         self.cursor
     }
 
+    pub fn cursor_state(&self) -> CursorState {
+        self.cursor_state
+    }
+
+    pub fn set_default_cursor_style(&mut self, style: CursorShape) {
+        if self.cursor_state.style == self.default_cursor.style {
+            self.cursor_state.style = style;
+        }
+        self.default_cursor.style = style;
+    }
+
+    pub fn scrollback_limit(&self) -> usize {
+        self.scrollback_limit
+    }
+
+    // Lowering the limit drops the oldest lines straight away.
+    pub fn set_scrollback_limit(&mut self, lines: usize) {
+        self.scrollback_limit = lines;
+        let evicted = self.scrollback.len().saturating_sub(lines);
+        if evicted > 0 {
+            self.scrollback.drain(..evicted);
+            self.discard_leading_lines(evicted);
+            self.scroll_offset = self.scroll_offset.min(self.scrollback.len());
+        }
+    }
+
     pub fn cells(&self) -> &[Cell] {
         &self.cells
     }
 
     pub fn clear(&mut self) {
-        for cell in &mut self.cells {
-            *cell = Cell::default();
-        }
+        let blank = self.blank_cell();
+        self.cells.fill(blank);
+        self.wrapped.fill(false);
         self.cursor = Cursor { col: 0, row: 0 };
         self.scroll_offset = 0;
+        let first_grid_line = self.scrollback.len();
+        self.commands
+            .retain(|record| record.prompt_line < first_grid_line);
+        self.pending_prompt = None;
+        self.pending_output = None;
+        self.selection = None;
     }
 
     pub fn scroll_to_bottom(&mut self) {
@@ -85,31 +416,290 @@ impl Screen {
         false
     }
 
-    pub fn render_chars(&self, out: &mut Vec<char>) {
+    pub fn visible(&self) -> impl Iterator<Item = RowView<'_>> + '_ {
+        let start = self.top_visible_line();
+        (start..start + self.size.rows as usize).filter_map(move |line| self.row(line))
+    }
+
+    // Row `index` of the view scrolled `scroll_offset` lines back, borrowed
+    // from the grid or scrollback without copying.
+    pub fn view_row(&self, scroll_offset: usize, index: usize) -> Option<&[Cell]> {
+        self.row(self.view_line(scroll_offset, index))
+            .map(|row| row.cells)
+    }
+
+    // The absolute line shown at row `index` of that view, numbered like
+    // RowView::line.
+    pub fn view_line(&self, scroll_offset: usize, index: usize) -> usize {
+        self.scrollback.len() - scroll_offset.min(self.scrollback.len()) + index
+    }
+
+    #[deprecated(note = "borrow rows with Screen::view_row instead of copying")]
+    pub fn render_cells(&self, out: &mut Vec<Cell>) {
         out.clear();
         out.reserve(self.cells.len());
+        for row in self.visible() {
+            out.extend_from_slice(row.cells);
+        }
+    }
 
-        let total_lines = self.scrollback.len() + self.size.rows as usize;
-        let rows = self.size.rows as usize;
+    pub fn has_blinking_cells(&self) -> bool {
+        self.visible().any(|row| {
+            row.cells
+                .iter()
+                .any(|cell| cell.flags.contains(CellFlags::BLINK))
+        })
+    }
+
+    #[deprecated(note = "borrow rows with Screen::view_row instead of copying")]
+    pub fn render_cells_at(&self, scroll_offset: usize, rows: usize, out: &mut Vec<Cell>) {
         let cols = self.size.cols as usize;
-        let offset = self.scroll_offset.min(self.scrollback.len());
-        let start_line = total_lines.saturating_sub(rows + offset);
-
-        for row in 0..rows {
-            let line_index = start_line + row;
-            if line_index < self.scrollback.len() {
-                let line = &self.scrollback[line_index];
-                for cell in line.iter().take(cols) {
-                    out.push(cell.ch);
-                }
+        let start = self.scrollback.len() - scroll_offset.min(self.scrollback.len());
+        out.clear();
+        out.reserve(rows * cols);
+        for line in start..start + rows {
+            match self.row(line) {
+                Some(row) => out.extend_from_slice(row.cells),
+                None => out.resize(out.len() + cols, Cell::default()),
+            }
+        }
+    }
+
+    pub fn line_timestamp(&self, line_index: usize) -> Option<SystemTime> {
+        self.scrollback
+            .get(line_index)
+            .map(ScrollbackLine::archived_at)
+    }
+
+    pub fn lines_since(&self, time: SystemTime) -> Range<usize> {
+        let start = self
+            .scrollback
+            .partition_point(|line| line.archived_at() < time);
+        start..self.scrollback.len() + self.size.rows as usize
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn working_directory(&self) -> Option<&str> {
+        self.working_directory.as_deref()
+    }
+
+    pub fn icon_title(&self) -> &str {
+        &self.icon_title
+    }
+
+    pub fn take_title_changed(&mut self) -> bool {
+        std::mem::take(&mut self.title_changed)
+    }
+
+    // True once after the program rang the bell, however many times.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_base_palette(&mut self, palette: Palette) {
+        self.base_palette = palette.clone();
+        self.palette = palette;
+        self.palette_changed = true;
+    }
+
+    pub fn take_palette_changed(&mut self) -> bool {
+        std::mem::take(&mut self.palette_changed)
+    }
+
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    // DECCKM, set by full-screen programs that want the cursor keys sent
+    // as ESC O sequences.
+    pub fn application_cursor_keys(&self) -> bool {
+        self.application_cursor_keys
+    }
+
+    // Whether the application asked for pastes to be wrapped in
+    // ESC [200~ ... ESC [201~ (mode 2004).
+    pub fn bracketed_paste(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    pub fn mouse_tracking(&self) -> MouseTracking {
+        self.mouse_tracking
+    }
+
+    pub fn sgr_mouse(&self) -> bool {
+        self.sgr_mouse
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    pub fn select(&mut self, anchor: SelectionPoint, head: SelectionPoint) {
+        self.selection = Some(Selection { anchor, head });
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    // Everything from the oldest scrollback line to the bottom of the grid.
+    pub fn select_all(&mut self) {
+        let last = self.scrollback.len() + self.size.rows as usize - 1;
+        self.select(
+            SelectionPoint { line: 0, col: 0 },
+            SelectionPoint {
+                line: last,
+                col: self.size.cols - 1,
+            },
+        );
+    }
+
+    // Soft-wrapped rows are joined without a line break, and trailing
+    // blanks are dropped from the end of each line.
+    pub fn selection_text(&self) -> Option<String> {
+        let (start, end) = self.selection?.ordered();
+        let mut text = String::new();
+        for line in start.line..=end.line {
+            let Some(row) = self.row(line) else {
+                break;
+            };
+            let first = if line == start.line {
+                start.col as usize
             } else {
-                let screen_row = line_index - self.scrollback.len();
-                let start = screen_row * cols;
-                let end = start + cols;
-                for cell in self.cells[start..end].iter() {
-                    out.push(cell.ch);
+                0
+            };
+            let last = if line == end.line {
+                end.col as usize + 1
+            } else {
+                row.cells.len()
+            };
+            let last = last.min(row.cells.len());
+            let segment: String = row.cells[first.min(last)..last]
+                .iter()
+                .filter(|cell| !cell.flags.contains(CellFlags::WIDE_SPACER))
+                .map(|cell| cell.ch)
+                .collect();
+            if row.wrapped && line < end.line {
+                text.push_str(&segment);
+                continue;
+            }
+            text.push_str(segment.trim_end());
+            if line < end.line {
+                text.push('\n');
+            }
+        }
+        (!text.is_empty()).then_some(text)
+    }
+
+    // The URL written at `point`, looked for in the whole logical line
+    // around it so links wrapped by the shell still match.
+    pub fn url_at(&self, point: SelectionPoint) -> Option<UrlMatch> {
+        let mut line = point.line;
+        while line > 0 && self.row(line - 1).is_some_and(|row| row.wrapped) {
+            line -= 1;
+        }
+        let mut chars = Vec::new();
+        while let Some(row) = self.row(line) {
+            for (col, cell) in row.cells.iter().enumerate() {
+                if !cell.flags.contains(CellFlags::WIDE_SPACER) {
+                    chars.push((
+                        cell.ch,
+                        SelectionPoint {
+                            line,
+                            col: col as u16,
+                        },
+                    ));
                 }
             }
+            if !row.wrapped {
+                break;
+            }
+            line += 1;
+        }
+
+        // A point on the right half of a wide character lands on its spacer,
+        // which belongs to the character before it.
+        let index = chars.iter().rposition(|(_, at)| *at <= point)?;
+        if !is_url_char(chars[index].0) {
+            return None;
+        }
+        let mut start = index;
+        while start > 0 && is_url_char(chars[start - 1].0) {
+            start -= 1;
+        }
+        let mut end = index + 1;
+        while end < chars.len() && is_url_char(chars[end].0) {
+            end += 1;
+        }
+        let word: String = chars[start..end].iter().map(|(ch, _)| *ch).collect();
+        // Schemes are case-insensitive. ASCII folding keeps every byte
+        // offset, so a match in the folded word is one in the word itself.
+        let folded = word.to_ascii_lowercase();
+        let (at, scheme) = URL_SCHEMES
+            .iter()
+            .filter_map(|scheme| folded.find(scheme).map(|at| (at, scheme)))
+            .min()?;
+        let mut url: Vec<char> = word[at..].chars().collect();
+        trim_url_end(&mut url);
+        if url.len() <= scheme.len() {
+            return None;
+        }
+        let first = start + word[..at].chars().count();
+        let last = first + url.len() - 1;
+        if index < first || index > last {
+            return None;
+        }
+        Some(UrlMatch {
+            url: url.into_iter().collect(),
+            start: chars[first].1,
+            end: chars[last].1,
+        })
+    }
+
+    pub fn commands(&self) -> &[CommandRecord] {
+        &self.commands
+    }
+
+    pub fn last_command_output(&self) -> Option<String> {
+        let record = self.commands.last()?;
+        let mut text = String::new();
+        for line_index in record.output.clone() {
+            let row = self.row(line_index)?;
+            let line: String = row.cells.iter().map(|cell| cell.ch).collect();
+            if line_index > record.output.start {
+                text.push('\n');
+            }
+            text.push_str(line.trim_end());
+        }
+        Some(text)
+    }
+
+    pub fn scroll_to_previous_prompt(&mut self) -> bool {
+        let top = self.top_visible_line();
+        let target = self.prompt_lines().filter(|line| *line < top).max();
+        match target {
+            Some(line) => self.scroll_line_to_top(line),
+            None => false,
+        }
+    }
+
+    pub fn scroll_to_next_prompt(&mut self) -> bool {
+        let top = self.top_visible_line();
+        let target = self.prompt_lines().filter(|line| *line > top).min();
+        match target {
+            Some(line) => self.scroll_line_to_top(line),
+            None => {
+                let scrolled = self.is_scrolled();
+                self.scroll_to_bottom();
+                scrolled
+            }
         }
     }
 
@@ -117,37 +707,147 @@ impl Screen {
         self.scroll_offset > 0
     }
 
+    pub fn scroll_position(&self) -> ScrollPosition {
+        ScrollPosition {
+            offset: self.scroll_offset,
+            scrollback: self.scrollback.len(),
+            rows: self.size.rows,
+        }
+    }
+
     pub fn resize(&mut self, size: ScreenSize) -> Result<(), ScreenError> {
         validate_size(size)?;
-        let mut new_cells = vec![Cell::default(); size.cols as usize * size.rows as usize];
-        let min_cols = self.size.cols.min(size.cols) as usize;
-        let min_rows = self.size.rows.min(size.rows) as usize;
+        if size == self.size {
+            return Ok(());
+        }
+
+        // Rewrapping moves cells between rows, so a selection can't follow.
+        self.selection = None;
+        let anchor = (self.scroll_offset > 0).then(|| self.top_visible_line());
+        let cursor_line = self.cursor_line();
+        let used_lines = self.scrollback.len() + self.used_grid_rows();
 
-        for row in 0..min_rows {
-            let old_start = row * self.size.cols as usize;
-            let new_start = row * size.cols as usize;
-            new_cells[new_start..new_start + min_cols]
-                .copy_from_slice(&self.cells[old_start..old_start + min_cols]);
+        let mut logical: Vec<LogicalLine> = Vec::new();
+        let mut positions = Vec::with_capacity(used_lines);
+        let mut current: Option<LogicalLine> = None;
+        for line in 0..used_lines {
+            let Some(row) = self.row(line) else {
+                break;
+            };
+            let entry = current.get_or_insert_with(|| LogicalLine {
+                cells: Vec::new(),
+                timestamp: row.timestamp,
+            });
+            positions.push(LinePosition {
+                logical: logical.len(),
+                offset: entry.cells.len(),
+            });
+            entry.cells.extend_from_slice(row.cells);
+            if !row.wrapped {
+                logical.extend(current.take());
+            }
         }
+        logical.extend(current.take());
 
-        self.size = size;
-        self.cells = new_cells;
-        for line in &mut self.scrollback {
-            if line.len() < size.cols as usize {
-                line.resize(size.cols as usize, Cell::default());
-            } else {
-                line.truncate(size.cols as usize);
+        let cursor = positions.get(cursor_line).map(|position| LinePosition {
+            logical: position.logical,
+            offset: position.offset + self.cursor.col as usize,
+        });
+        // Rows are trimmed and padded with the same blank, so erased cells
+        // keep the current background across a resize.
+        let blank = self.blank_cell();
+        for (index, line) in logical.iter_mut().enumerate() {
+            while line.cells.last() == Some(&blank) {
+                line.cells.pop();
+            }
+            if let Some(cursor) = cursor.filter(|cursor| cursor.logical == index) {
+                if line.cells.len() <= cursor.offset {
+                    line.cells.resize(cursor.offset + 1, blank);
+                }
+            }
+        }
+
+        let cols = size.cols as usize;
+        let now = SystemTime::now();
+        let mut rows: Vec<ScrollbackLine> = Vec::new();
+        let mut line_starts = Vec::with_capacity(logical.len());
+        for line in logical {
+            line_starts.push(rows.len());
+            let timestamp = line.timestamp.unwrap_or(now);
+            if line.cells.is_empty() {
+                rows.push(ScrollbackLine::new(vec![blank; cols], false, timestamp));
+                continue;
+            }
+            let chunks = line.cells.len().div_ceil(cols);
+            for (index, chunk) in line.cells.chunks(cols).enumerate() {
+                let mut cells = chunk.to_vec();
+                cells.resize(cols, blank);
+                rows.push(ScrollbackLine::new(cells, index + 1 < chunks, timestamp));
             }
         }
-        if self.scroll_offset > self.scrollback.len() {
-            self.scroll_offset = self.scrollback.len();
+
+        let map_line = |line: usize| -> usize {
+            match positions.get(line.min(positions.len().saturating_sub(1))) {
+                Some(position) => line_starts[position.logical] + position.offset / cols,
+                None => 0,
+            }
+        };
+
+        let (cursor_row, cursor_col) = match cursor {
+            Some(cursor) => (
+                line_starts[cursor.logical] + cursor.offset / cols,
+                cursor.offset % cols,
+            ),
+            None => (rows.len(), 0),
+        };
+        let grid_start = rows
+            .len()
+            .saturating_sub(size.rows as usize)
+            .min(cursor_row);
+
+        let mut grid_rows = rows.split_off(grid_start);
+        grid_rows.truncate(size.rows as usize);
+        let mut cells = Vec::with_capacity(cols * size.rows as usize);
+        let mut wrapped = Vec::with_capacity(size.rows as usize);
+        for row in &grid_rows {
+            cells.extend_from_slice(&row.cells);
+            wrapped.push(row.wrapped);
         }
-        if self.cursor.col >= size.cols {
-            self.cursor.col = size.cols.saturating_sub(1);
+        cells.resize(cols * size.rows as usize, blank);
+        wrapped.resize(size.rows as usize, false);
+
+        for record in &mut self.commands {
+            record.prompt_line = map_line(record.prompt_line);
+            record.output = if record.output.is_empty() {
+                let start = map_line(record.output.start);
+                start..start
+            } else {
+                map_line(record.output.start)..map_line(record.output.end - 1) + 1
+            };
         }
-        if self.cursor.row >= size.rows {
-            self.cursor.row = size.rows.saturating_sub(1);
+        self.pending_prompt = self.pending_prompt.map(map_line);
+        self.pending_output = self.pending_output.map(map_line);
+
+        self.size = size;
+        self.cells = cells;
+        self.wrapped = wrapped;
+        self.scrollback = rows;
+        self.cursor = Cursor {
+            col: cursor_col as u16,
+            row: (cursor_row - grid_start).min(size.rows as usize - 1) as u16,
+        };
+
+        let evicted = self.scrollback.len().saturating_sub(self.scrollback_limit);
+        if evicted > 0 {
+            self.scrollback.drain(..evicted);
+            self.discard_leading_lines(evicted);
         }
+
+        self.scroll_offset = match anchor.map(map_line) {
+            Some(top) if top >= evicted => self.scrollback.len().saturating_sub(top - evicted),
+            Some(_) => self.scrollback.len(),
+            None => 0,
+        };
         Ok(())
     }
 
@@ -157,21 +857,400 @@ impl Screen {
             VtEvent::Newline => self.newline(),
             VtEvent::CarriageReturn => self.carriage_return(),
             VtEvent::Backspace => self.backspace(),
+            VtEvent::SetAttribute(attribute) => self.set_attribute(attribute),
+            VtEvent::CursorPosition { col, row } => self.move_cursor_to(col, row),
+            VtEvent::EraseInDisplay(mode) => self.erase_in_display(mode),
+            VtEvent::EraseInLine(mode) => self.erase_in_line(mode),
+            VtEvent::EraseChars(count) => self.erase_chars(count),
+            VtEvent::ClearScrollback => self.clear_scrollback(),
+            VtEvent::PromptMark(mark) => self.prompt_mark(mark),
+            VtEvent::SetMode(mode, enabled) => self.set_mode(mode, enabled),
+            VtEvent::SetCursorStyle(style) => self.set_cursor_style(style),
+            VtEvent::FullReset => self.full_reset(),
+            VtEvent::SoftReset => self.soft_reset(),
+            VtEvent::SetTitle(title) => self.set_title(title),
+            VtEvent::SetIconTitle(title) => self.icon_title = capped_title(title),
+            VtEvent::Bell => self.bell = true,
+            VtEvent::PushTitle => self.push_title(),
+            VtEvent::PopTitle => self.pop_title(),
+            VtEvent::SetWorkingDirectory(path) => self.working_directory = Some(path),
+            VtEvent::SetPaletteColor(index, color) => {
+                self.palette.colors[index as usize] = color;
+                self.palette_changed = true;
+            }
+            VtEvent::QueryPaletteColor(index) => {
+                let Rgb { r, g, b } = self.palette.colors[index as usize];
+                // Channels are scaled to 16 bits the way xterm reports them.
+                let reply = format!(
+                    "\x1b]4;{index};rgb:{:04x}/{:04x}/{:04x}\x1b\\",
+                    r as u16 * 0x101,
+                    g as u16 * 0x101,
+                    b as u16 * 0x101
+                );
+                self.replies.extend_from_slice(reply.as_bytes());
+            }
+            VtEvent::ResetPaletteColor(Some(index)) => {
+                self.palette.colors[index as usize] = self.base_palette.colors[index as usize];
+                self.palette_changed = true;
+            }
+            VtEvent::ResetPaletteColor(None) => {
+                self.palette.colors = self.base_palette.colors;
+                self.palette_changed = true;
+            }
+            VtEvent::SetDynamicColor(target, color) => self.set_dynamic_color(target, color),
+            VtEvent::ResetDynamicColor(target) => {
+                match target {
+                    DynamicColor::Foreground => {
+                        self.palette.foreground = self.base_palette.foreground
+                    }
+                    DynamicColor::Background => {
+                        self.palette.background = self.base_palette.background
+                    }
+                    DynamicColor::Cursor => self.palette.cursor = self.base_palette.cursor,
+                }
+                self.palette_changed = true;
+            }
         }
     }
 
     pub fn apply_events(&mut self, events: &[VtEvent]) {
         for event in events {
-            self.apply_event(*event);
+            self.apply_event(event.clone());
         }
     }
 
     fn print_char(&mut self, ch: char) {
-        let idx = self.index(self.cursor.col, self.cursor.row);
-        if let Some(cell) = self.cells.get_mut(idx) {
-            cell.ch = ch;
+        let wide = ch.width() == Some(2) && self.size.cols >= 2;
+        if wide && self.cursor.col + 1 >= self.size.cols {
+            self.advance_cursor();
         }
+
+        let pen = self.pen;
+        let mut flags = pen.flags;
+        if wide {
+            flags.insert(CellFlags::WIDE);
+        }
+        self.write_cell(Cell {
+            ch,
+            fg: pen.fg,
+            bg: pen.bg,
+            underline: pen.underline,
+            underline_color: pen.underline_color,
+            flags,
+        });
         self.advance_cursor();
+
+        if wide {
+            let mut spacer = self.blank_cell();
+            spacer.flags.insert(CellFlags::WIDE_SPACER);
+            self.write_cell(spacer);
+            self.advance_cursor();
+        }
+    }
+
+    fn write_cell(&mut self, cell: Cell) {
+        let (col, row) = (self.cursor.col, self.cursor.row);
+        let idx = self.index(col, row);
+        let Some(previous) = self.cells.get(idx).copied() else {
+            return;
+        };
+        // Overwriting either half of a wide character leaves the other half blank.
+        let partner = if previous.flags.contains(CellFlags::WIDE) && col + 1 < self.size.cols {
+            Some(idx + 1)
+        } else if previous.flags.contains(CellFlags::WIDE_SPACER) && col > 0 {
+            Some(idx - 1)
+        } else {
+            None
+        };
+        if let Some(partner) = partner {
+            self.cells[partner] = self.blank_cell();
+        }
+        self.cells[idx] = cell;
+    }
+
+    fn set_attribute(&mut self, attribute: SgrAttribute) {
+        let flags = &mut self.pen.flags;
+        match attribute {
+            SgrAttribute::Reset => self.pen = Pen::default(),
+            SgrAttribute::Bold => flags.insert(CellFlags::BOLD),
+            SgrAttribute::Faint => flags.insert(CellFlags::FAINT),
+            SgrAttribute::NormalIntensity => {
+                flags.remove(CellFlags::BOLD);
+                flags.remove(CellFlags::FAINT);
+            }
+            SgrAttribute::Italic => flags.insert(CellFlags::ITALIC),
+            SgrAttribute::NoItalic => flags.remove(CellFlags::ITALIC),
+            SgrAttribute::Underline(style) => self.pen.underline = Some(style),
+            SgrAttribute::NoUnderline => self.pen.underline = None,
+            SgrAttribute::UnderlineColor(color) => self.pen.underline_color = color,
+            SgrAttribute::Overline => flags.insert(CellFlags::OVERLINE),
+            SgrAttribute::NoOverline => flags.remove(CellFlags::OVERLINE),
+            SgrAttribute::Blink => flags.insert(CellFlags::BLINK),
+            SgrAttribute::NoBlink => flags.remove(CellFlags::BLINK),
+            SgrAttribute::Inverse => flags.insert(CellFlags::INVERSE),
+            SgrAttribute::NoInverse => flags.remove(CellFlags::INVERSE),
+            SgrAttribute::Hidden => flags.insert(CellFlags::HIDDEN),
+            SgrAttribute::NoHidden => flags.remove(CellFlags::HIDDEN),
+            SgrAttribute::Strikethrough => flags.insert(CellFlags::STRIKETHROUGH),
+            SgrAttribute::NoStrikethrough => flags.remove(CellFlags::STRIKETHROUGH),
+            SgrAttribute::Foreground(color) => self.pen.fg = color,
+            SgrAttribute::Background(color) => self.pen.bg = color,
+        }
+    }
+
+    fn move_cursor_to(&mut self, col: u16, row: u16) {
+        self.cursor.col = col.min(self.size.cols.saturating_sub(1));
+        self.cursor.row = row.min(self.size.rows.saturating_sub(1));
+    }
+
+    fn erase_in_display(&mut self, mode: EraseMode) {
+        let cursor = self.index(self.cursor.col, self.cursor.row);
+        let range = match mode {
+            EraseMode::ToEnd => cursor..self.cells.len(),
+            EraseMode::ToStart => 0..cursor + 1,
+            EraseMode::All => {
+                self.wrapped.fill(false);
+                0..self.cells.len()
+            }
+        };
+        self.erase_range(range);
+    }
+
+    fn erase_in_line(&mut self, mode: EraseMode) {
+        let row_start = self.index(0, self.cursor.row);
+        let row_end = row_start + self.size.cols as usize;
+        let cursor = self.index(self.cursor.col, self.cursor.row);
+        let range = match mode {
+            EraseMode::ToEnd => cursor..row_end,
+            EraseMode::ToStart => row_start..cursor + 1,
+            EraseMode::All => row_start..row_end,
+        };
+        self.erase_range(range);
+    }
+
+    fn erase_chars(&mut self, count: u16) {
+        let start = self.index(self.cursor.col, self.cursor.row);
+        let remaining = self.size.cols.saturating_sub(self.cursor.col);
+        let end = start + count.min(remaining) as usize;
+        self.erase_range(start..end);
+    }
+
+    fn erase_range(&mut self, range: std::ops::Range<usize>) {
+        let blank = self.blank_cell();
+        let end = range.end.min(self.cells.len());
+        let start = range.start.min(end);
+        self.cells[start..end].fill(blank);
+    }
+
+    fn set_mode(&mut self, mode: Mode, enabled: bool) {
+        match mode {
+            Mode::ShowCursor => self.cursor_state.visible = enabled,
+            Mode::ApplicationCursorKeys => self.application_cursor_keys = enabled,
+            Mode::BracketedPaste => self.bracketed_paste = enabled,
+            Mode::MouseClicks => self.set_mouse_tracking(MouseTracking::Clicks, enabled),
+            Mode::MouseDrag => self.set_mouse_tracking(MouseTracking::Drag, enabled),
+            Mode::MouseMotion => self.set_mouse_tracking(MouseTracking::Motion, enabled),
+            Mode::SgrMouse => self.sgr_mouse = enabled,
+        }
+    }
+
+    // The tracking modes replace each other; resetting the one in effect
+    // turns tracking off.
+    fn set_mouse_tracking(&mut self, tracking: MouseTracking, enabled: bool) {
+        if enabled {
+            self.mouse_tracking = tracking;
+        } else if self.mouse_tracking == tracking {
+            self.mouse_tracking = MouseTracking::Off;
+        }
+    }
+
+    fn set_cursor_style(&mut self, style: Option<CursorStyle>) {
+        let default = self.default_cursor;
+        let style = style.unwrap_or(CursorStyle {
+            shape: default.style,
+            blinking: default.blink,
+        });
+        self.cursor_state.style = style.shape;
+        self.cursor_state.blink = style.blinking;
+    }
+
+    fn set_dynamic_color(&mut self, target: DynamicColor, color: Rgb) {
+        match target {
+            DynamicColor::Foreground => self.palette.foreground = color,
+            DynamicColor::Background => self.palette.background = color,
+            DynamicColor::Cursor => self.palette.cursor = Some(color),
+        }
+        self.palette_changed = true;
+    }
+
+    fn full_reset(&mut self) {
+        self.soft_reset();
+        self.palette = self.base_palette.clone();
+        self.palette_changed = true;
+        self.bracketed_paste = false;
+        self.mouse_tracking = MouseTracking::Off;
+        self.sgr_mouse = false;
+        self.clear_scrollback();
+        self.clear();
+    }
+
+    fn soft_reset(&mut self) {
+        self.pen = Pen::default();
+        self.cursor_state = self.default_cursor;
+        self.application_cursor_keys = false;
+    }
+
+    fn set_title(&mut self, title: String) {
+        let title = capped_title(title);
+        if title != self.title {
+            self.title = title;
+            self.title_changed = true;
+        }
+    }
+
+    fn push_title(&mut self) {
+        if self.title_stack.len() >= MAX_TITLE_STACK {
+            self.title_stack.remove(0);
+        }
+        self.title_stack
+            .push((self.title.clone(), self.icon_title.clone()));
+    }
+
+    fn pop_title(&mut self) {
+        if let Some((title, icon_title)) = self.title_stack.pop() {
+            self.set_title(title);
+            self.icon_title = icon_title;
+        }
+    }
+
+    pub fn clear_scrollback(&mut self) {
+        let removed = self.scrollback.len();
+        self.scrollback.clear();
+        self.scroll_offset = 0;
+        self.discard_leading_lines(removed);
+    }
+
+    fn prompt_mark(&mut self, mark: PromptMark) {
+        let line = self.cursor_line();
+        match mark {
+            PromptMark::PromptStart => {
+                self.pending_prompt = Some(line);
+                self.pending_output = None;
+            }
+            PromptMark::CommandStart => {}
+            PromptMark::OutputStart => self.pending_output = Some(line),
+            PromptMark::CommandFinished(exit_code) => {
+                let (Some(prompt_line), Some(start)) =
+                    (self.pending_prompt.take(), self.pending_output.take())
+                else {
+                    return;
+                };
+                let end = if self.cursor.col == 0 { line } else { line + 1 };
+                self.commands.push(CommandRecord {
+                    prompt_line,
+                    output: start..end.max(start),
+                    exit_code,
+                });
+            }
+        }
+    }
+
+    fn discard_leading_lines(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.commands.retain_mut(|record| {
+            if record.prompt_line < count {
+                return false;
+            }
+            record.prompt_line -= count;
+            record.output =
+                record.output.start.saturating_sub(count)..record.output.end.saturating_sub(count);
+            true
+        });
+        self.pending_prompt = self.pending_prompt.and_then(|line| line.checked_sub(count));
+        self.pending_output = self.pending_output.and_then(|line| line.checked_sub(count));
+        // A selection partly in the discarded lines keeps what is left of it.
+        self.selection = self.selection.and_then(|selection| {
+            let shift = |point: SelectionPoint| {
+                point
+                    .line
+                    .checked_sub(count)
+                    .map(|line| SelectionPoint { line, ..point })
+            };
+            let top = SelectionPoint { line: 0, col: 0 };
+            match (shift(selection.anchor), shift(selection.head)) {
+                (None, None) => None,
+                (anchor, head) => Some(Selection {
+                    anchor: anchor.unwrap_or(top),
+                    head: head.unwrap_or(top),
+                }),
+            }
+        });
+    }
+
+    fn prompt_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.commands
+            .iter()
+            .map(|record| record.prompt_line)
+            .chain(self.pending_prompt)
+    }
+
+    fn used_grid_rows(&self) -> usize {
+        let cols = self.size.cols as usize;
+        let last_content = self
+            .cells
+            .chunks(cols)
+            .rposition(|row| row.iter().any(|cell| *cell != Cell::default()))
+            .map_or(0, |row| row + 1);
+        last_content.max(self.cursor.row as usize + 1)
+    }
+
+    fn cursor_line(&self) -> usize {
+        self.scrollback.len() + self.cursor.row as usize
+    }
+
+    fn top_visible_line(&self) -> usize {
+        let offset = self.scroll_offset.min(self.scrollback.len());
+        self.scrollback.len() - offset
+    }
+
+    fn scroll_line_to_top(&mut self, line: usize) -> bool {
+        let next = self.scrollback.len().saturating_sub(line);
+        if next == self.scroll_offset {
+            return false;
+        }
+        self.scroll_offset = next;
+        true
+    }
+
+    fn row(&self, line: usize) -> Option<RowView<'_>> {
+        if let Some(scrollback) = self.scrollback.get(line) {
+            return Some(RowView {
+                cells: &scrollback.cells,
+                line,
+                wrapped: scrollback.wrapped,
+                timestamp: Some(scrollback.archived_at()),
+            });
+        }
+        let row = line - self.scrollback.len();
+        if row >= self.size.rows as usize {
+            return None;
+        }
+        let cols = self.size.cols as usize;
+        Some(RowView {
+            cells: &self.cells[row * cols..(row + 1) * cols],
+            line,
+            wrapped: self.wrapped[row],
+            timestamp: None,
+        })
+    }
+
+    fn blank_cell(&self) -> Cell {
+        Cell {
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
     }
 
     fn newline(&mut self) {
@@ -190,8 +1269,9 @@ impl Screen {
         if self.cursor.col > 0 {
             self.cursor.col -= 1;
             let idx = self.index(self.cursor.col, self.cursor.row);
+            let blank = self.blank_cell();
             if let Some(cell) = self.cells.get_mut(idx) {
-                cell.ch = ' ';
+                *cell = blank;
             }
         }
     }
@@ -199,6 +1279,9 @@ impl Screen {
     fn advance_cursor(&mut self) {
         self.cursor.col = self.cursor.col.saturating_add(1);
         if self.cursor.col >= self.size.cols {
+            if let Some(wrapped) = self.wrapped.get_mut(self.cursor.row as usize) {
+                *wrapped = true;
+            }
             self.cursor.col = 0;
             self.newline();
         }
@@ -212,9 +1295,14 @@ impl Screen {
         }
 
         let top_line = self.cells[0..cols].to_vec();
-        self.scrollback.push(top_line);
-        if self.scrollback.len() > MAX_SCROLLBACK_LINES {
+        self.scrollback.push(ScrollbackLine::new(
+            top_line,
+            self.wrapped[0],
+            SystemTime::now(),
+        ));
+        if self.scrollback.len() > self.scrollback_limit {
             self.scrollback.remove(0);
+            self.discard_leading_lines(1);
             if self.scroll_offset > 0 {
                 self.scroll_offset -= 1;
             }
@@ -229,10 +1317,12 @@ impl Screen {
             self.cells.copy_within(range, dst);
         }
 
+        self.wrapped.copy_within(1..rows, 0);
+        self.wrapped[rows - 1] = false;
+
         let last_row_start = (rows - 1) * cols;
-        for cell in &mut self.cells[last_row_start..last_row_start + cols] {
-            *cell = Cell::default();
-        }
+        let blank = self.blank_cell();
+        self.cells[last_row_start..last_row_start + cols].fill(blank);
     }
 
     fn index(&self, col: u16, row: u16) -> usize {
@@ -240,7 +1330,69 @@ impl Screen {
     }
 }
 
-const MAX_SCROLLBACK_LINES: usize = 1000;
+pub const DEFAULT_SCROLLBACK_LINES: usize = 1000;
+const MAX_TITLE_CHARS: usize = 1024;
+const MAX_TITLE_STACK: usize = 10;
+
+const DEFAULT_ANSI_COLORS: [[u8; 3]; 16] = [
+    [28, 33, 40],
+    [224, 108, 117],
+    [152, 195, 121],
+    [229, 192, 123],
+    [97, 175, 239],
+    [198, 120, 221],
+    [86, 182, 194],
+    [171, 178, 191],
+    [92, 99, 112],
+    [240, 135, 143],
+    [176, 214, 147],
+    [240, 210, 150],
+    [130, 195, 250],
+    [215, 150, 235],
+    [120, 205, 215],
+    [230, 237, 243],
+];
+
+fn capped_title(title: String) -> String {
+    match title.char_indices().nth(MAX_TITLE_CHARS) {
+        Some((end, _)) => title[..end].to_string(),
+        None => title,
+    }
+}
+
+// Schemes recognised in plain text.
+const URL_SCHEMES: &[&str] = &["https://", "http://", "ftp://", "file://", "mailto:"];
+
+// Box-drawing characters end a URL, since full-screen programs draw their
+// frames right next to the text.
+fn is_url_char(ch: char) -> bool {
+    !ch.is_whitespace()
+        && !ch.is_control()
+        && !matches!(ch, '<' | '>' | '"' | '`' | '{' | '}' | '|' | '\\' | '^')
+        && !('\u{2500}'..='\u{259f}').contains(&ch)
+}
+
+// Sentence punctuation after a URL isn't part of it, and neither is a
+// closing bracket without a matching opening one, as in "(see https://x)".
+fn trim_url_end(url: &mut Vec<char>) {
+    while let Some(&last) = url.last() {
+        let open = match last {
+            '.' | ',' | ':' | ';' | '!' | '?' | '\'' => {
+                url.pop();
+                continue;
+            }
+            ')' => '(',
+            ']' => '[',
+            _ => break,
+        };
+        let opened = url.iter().filter(|ch| **ch == open).count();
+        let closed = url.iter().filter(|ch| **ch == last).count();
+        if closed <= opened {
+            break;
+        }
+        url.pop();
+    }
+}
 
 fn validate_size(size: ScreenSize) -> Result<(), ScreenError> {
     if size.cols == 0 || size.rows == 0 {
@@ -0,0 +1,58 @@
+// Box-drawing, block and Powerline characters arriving from the pty as UTF-8
+// land in single cells so the renderer can draw them edge to edge.
+
+mod common;
+
+use common::feed;
+use screen::{CellFlags, Screen, ScreenSize};
+use vt::VtParser;
+
+fn text(screen: &Screen, row: usize, cols: usize) -> String {
+    let cells = screen.view_row(0, row).expect("row");
+    cells[..cols].iter().map(|cell| cell.ch).collect()
+}
+
+#[test]
+fn box_outline_fills_cells() {
+    let mut screen = Screen::new(ScreenSize { cols: 8, rows: 4 }).expect("screen");
+    feed(&mut screen, "┌─┐\r\n│ │\r\n└─┘");
+    assert_eq!(text(&screen, 0, 3), "┌─┐");
+    assert_eq!(text(&screen, 1, 3), "│ │");
+    assert_eq!(text(&screen, 2, 3), "└─┘");
+    let cells = screen.view_row(0, 0).expect("row");
+    assert!(cells[..3]
+        .iter()
+        .all(|cell| !cell.flags.contains(CellFlags::WIDE)));
+    assert_eq!(screen.cursor().col, 3);
+}
+
+#[test]
+fn block_elements_take_one_cell_each() {
+    let mut screen = Screen::new(ScreenSize { cols: 8, rows: 2 }).expect("screen");
+    feed(&mut screen, "█▀▄░");
+    assert_eq!(text(&screen, 0, 4), "█▀▄░");
+    assert_eq!(screen.cursor().col, 4);
+}
+
+#[test]
+fn split_box_drawing_bytes_are_joined() {
+    let mut screen = Screen::new(ScreenSize { cols: 8, rows: 2 }).expect("screen");
+    let bytes = "═╬".as_bytes();
+    let mut parser = VtParser::new();
+    let mut events = Vec::new();
+    for byte in bytes {
+        parser.advance(std::slice::from_ref(byte), &mut events);
+    }
+    screen.apply_events(&events);
+    assert_eq!(text(&screen, 0, 2), "═╬");
+}
+
+#[test]
+fn powerline_separators_take_one_cell_each() {
+    let mut screen = Screen::new(ScreenSize { cols: 8, rows: 2 }).expect("screen");
+    feed(&mut screen, "a\u{e0b0}b\u{e0b2}");
+    assert_eq!(text(&screen, 0, 4), "a\u{e0b0}b\u{e0b2}");
+    let cells = screen.view_row(0, 0).expect("row");
+    assert!(!cells[1].flags.contains(CellFlags::WIDE));
+    assert_eq!(screen.cursor().col, 4);
+}
@@ -0,0 +1,18 @@
+// Helpers shared by the screen integration tests. Not every test file uses
+// all of them.
+#![allow(dead_code)]
+
+use screen::Screen;
+use vt::VtParser;
+
+// Feeds `input` through a fresh parser, as one write from the shell.
+pub fn feed(screen: &mut Screen, input: &str) {
+    feed_with(screen, &mut VtParser::new(), input);
+}
+
+// Keeps the parser's state across calls, like the app does between reads.
+pub fn feed_with(screen: &mut Screen, parser: &mut VtParser, input: &str) {
+    let mut events = Vec::new();
+    parser.advance(input.as_bytes(), &mut events);
+    screen.apply_events(&events);
+}
@@ -0,0 +1,99 @@
+// Erasing fills cells with the current background color (back-color erase),
+// so full-screen programs that clear with a colored background, like vim,
+// paint the whole area.
+
+mod common;
+
+use common::feed;
+use screen::{Color, Screen, ScreenSize};
+
+fn screen() -> Screen {
+    let mut screen = Screen::new(ScreenSize { cols: 6, rows: 3 }).expect("screen");
+    feed(&mut screen, "abcde\r\nghijk\r\nmnopq");
+    screen
+}
+
+fn backgrounds(screen: &Screen, row: usize) -> Vec<Color> {
+    let cells = screen.view_row(0, row).expect("row");
+    cells.iter().map(|cell| cell.bg).collect()
+}
+
+fn text(screen: &Screen, row: usize) -> String {
+    let cells = screen.view_row(0, row).expect("row");
+    cells.iter().map(|cell| cell.ch).collect()
+}
+
+const BLUE: Color = Color::Indexed(4);
+
+#[test]
+fn erase_display_uses_the_background() {
+    let mut screen = screen();
+    feed(&mut screen, "\x1b[44m\x1b[2J");
+    for row in 0..3 {
+        assert_eq!(text(&screen, row), "      ");
+        assert_eq!(backgrounds(&screen, row), [BLUE; 6]);
+    }
+}
+
+#[test]
+fn erase_below_leaves_rows_above_alone() {
+    let mut screen = screen();
+    feed(&mut screen, "\x1b[2;3H\x1b[44m\x1b[J");
+    assert_eq!(backgrounds(&screen, 0), [Color::Default; 6]);
+    assert_eq!(text(&screen, 1), "gh    ");
+    assert_eq!(
+        backgrounds(&screen, 1),
+        [Color::Default, Color::Default, BLUE, BLUE, BLUE, BLUE]
+    );
+    assert_eq!(backgrounds(&screen, 2), [BLUE; 6]);
+}
+
+#[test]
+fn erase_line_uses_the_background() {
+    let mut screen = screen();
+    feed(&mut screen, "\x1b[1;4H\x1b[48;2;10;20;30m\x1b[K");
+    let rgb = Color::Rgb(screen::Rgb {
+        r: 10,
+        g: 20,
+        b: 30,
+    });
+    assert_eq!(text(&screen, 0), "abc   ");
+    assert_eq!(
+        backgrounds(&screen, 0),
+        [
+            Color::Default,
+            Color::Default,
+            Color::Default,
+            rgb,
+            rgb,
+            rgb
+        ]
+    );
+    feed(&mut screen, "\x1b[2;1H\x1b[2K");
+    assert_eq!(backgrounds(&screen, 1), [rgb; 6]);
+}
+
+#[test]
+fn erase_chars_uses_the_background() {
+    let mut screen = screen();
+    feed(&mut screen, "\x1b[3;2H\x1b[44m\x1b[3X");
+    assert_eq!(text(&screen, 2), "m   q ");
+    assert_eq!(
+        backgrounds(&screen, 2),
+        [
+            Color::Default,
+            BLUE,
+            BLUE,
+            BLUE,
+            Color::Default,
+            Color::Default
+        ]
+    );
+}
+
+#[test]
+fn reset_background_erases_with_the_default() {
+    let mut screen = screen();
+    feed(&mut screen, "\x1b[44m\x1b[49m\x1b[2J");
+    assert_eq!(backgrounds(&screen, 0), [Color::Default; 6]);
+}
@@ -0,0 +1,81 @@
+// Finding the URL under a cell, as the app does for ctrl+click.
+
+mod common;
+
+use common::feed;
+use screen::{Screen, ScreenSize, SelectionPoint};
+
+fn at(col: u16) -> SelectionPoint {
+    SelectionPoint { line: 0, col }
+}
+
+fn url_at(input: &str, col: u16) -> Option<String> {
+    let mut screen = Screen::new(ScreenSize { cols: 40, rows: 3 }).expect("screen");
+    feed(&mut screen, input);
+    screen.url_at(at(col)).map(|found| found.url)
+}
+
+#[test]
+fn lower_case_schemes_match() {
+    assert_eq!(
+        url_at("see https://example.com/a now", 8).as_deref(),
+        Some("https://example.com/a")
+    );
+}
+
+#[test]
+fn upper_case_schemes_match() {
+    assert_eq!(
+        url_at("HTTPS://EXAMPLE.COM/PATH", 3).as_deref(),
+        Some("HTTPS://EXAMPLE.COM/PATH")
+    );
+    assert_eq!(
+        url_at("MAILTO:me@example.com", 10).as_deref(),
+        Some("MAILTO:me@example.com")
+    );
+}
+
+#[test]
+fn mixed_case_schemes_match() {
+    assert_eq!(
+        url_at("go HtTp://x.org", 5).as_deref(),
+        Some("HtTp://x.org")
+    );
+    assert_eq!(
+        url_at("x:File:///tmp/a", 4).as_deref(),
+        Some("File:///tmp/a")
+    );
+}
+
+#[test]
+fn trailing_punctuation_is_not_part_of_the_url() {
+    assert_eq!(
+        url_at("see https://x.org/a.", 6).as_deref(),
+        Some("https://x.org/a")
+    );
+    assert_eq!(
+        url_at("(see HTTPS://x.org/a)!", 8).as_deref(),
+        Some("HTTPS://x.org/a")
+    );
+    assert_eq!(
+        url_at("https://x.org/wiki/A_(b)", 3).as_deref(),
+        Some("https://x.org/wiki/A_(b)")
+    );
+}
+
+#[test]
+fn the_match_spans_the_url_cells() {
+    let mut screen = Screen::new(ScreenSize { cols: 40, rows: 3 }).expect("screen");
+    feed(&mut screen, "see Http://x.org, ok");
+    let found = screen.url_at(at(6)).expect("url");
+    assert_eq!(found.url, "Http://x.org");
+    assert_eq!(found.start, at(4));
+    assert_eq!(found.end, at(15));
+    assert_eq!(screen.url_at(at(16)), None);
+}
+
+#[test]
+fn a_bare_scheme_is_not_a_url() {
+    assert_eq!(url_at("HTTPS:// nothing", 2), None);
+    assert_eq!(url_at("plain words", 2), None);
+}
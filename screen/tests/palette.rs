@@ -0,0 +1,75 @@
+// Programs change the palette with OSC 4 and read it back with a `?` query;
+// the answer is queued for the app to write to the pty.
+
+mod common;
+
+use common::feed;
+use screen::{Rgb, Screen, ScreenSize};
+
+fn screen() -> Screen {
+    Screen::new(ScreenSize { cols: 10, rows: 3 }).expect("screen")
+}
+
+fn replies(screen: &mut Screen) -> String {
+    String::from_utf8(screen.take_replies()).expect("utf-8 reply")
+}
+
+#[test]
+fn query_reports_the_current_color() {
+    let mut screen = screen();
+    let Rgb { r, g, b } = screen.palette().colors[2];
+    feed(&mut screen, "\x1b]4;2;?\x07");
+    assert_eq!(
+        replies(&mut screen),
+        format!(
+            "\x1b]4;2;rgb:{:04x}/{:04x}/{:04x}\x1b\\",
+            r as u16 * 0x101,
+            g as u16 * 0x101,
+            b as u16 * 0x101
+        )
+    );
+    assert!(screen.take_replies().is_empty());
+}
+
+#[test]
+fn set_then_query_reports_the_new_color() {
+    let mut screen = screen();
+    feed(&mut screen, "\x1b]4;17;rgb:12/34/ab\x07");
+    assert_eq!(
+        screen.palette().colors[17],
+        Rgb {
+            r: 0x12,
+            g: 0x34,
+            b: 0xab
+        }
+    );
+    assert!(screen.take_palette_changed());
+    assert!(screen.take_replies().is_empty());
+    feed(&mut screen, "\x1b]4;17;?\x1b\\");
+    assert_eq!(replies(&mut screen), "\x1b]4;17;rgb:1212/3434/abab\x1b\\");
+}
+
+#[test]
+fn several_queries_reply_in_order() {
+    let mut screen = screen();
+    feed(
+        &mut screen,
+        "\x1b]4;0;#000000;255;#ffffff\x07\x1b]4;0;?;255;?\x07",
+    );
+    assert_eq!(
+        replies(&mut screen),
+        "\x1b]4;0;rgb:0000/0000/0000\x1b\\\x1b]4;255;rgb:ffff/ffff/ffff\x1b\\"
+    );
+}
+
+#[test]
+fn reset_restores_the_base_color() {
+    let mut screen = screen();
+    let base = screen.palette().colors[4];
+    feed(&mut screen, "\x1b]4;4;#010203;5;#040506\x07");
+    feed(&mut screen, "\x1b]104;4\x07");
+    assert_eq!(screen.palette().colors[4], base);
+    assert_eq!(screen.palette().colors[5], Rgb { r: 4, g: 5, b: 6 });
+    feed(&mut screen, "\x1b]104\x07");
+    assert_eq!(screen.palette(), &screen::Palette::default());
+}
@@ -0,0 +1,125 @@
+// Resizing rewraps soft-wrapped lines to the new width, keeps the cursor on
+// the character it was on and, when scrolled back, keeps the same line at
+// the top of the view.
+
+mod common;
+
+use common::feed;
+use screen::{Color, Screen, ScreenSize};
+
+fn size(cols: u16, rows: u16) -> ScreenSize {
+    ScreenSize { cols, rows }
+}
+
+// Row `index` of the current view with trailing blanks trimmed.
+fn view_text(screen: &Screen, index: usize) -> String {
+    let offset = screen.scroll_position().offset;
+    let cells = screen.view_row(offset, index).expect("row");
+    let text: String = cells.iter().map(|cell| cell.ch).collect();
+    text.trim_end().to_string()
+}
+
+fn numbered_lines(screen: &mut Screen, count: usize) {
+    let lines: Vec<String> = (0..count).map(|line| format!("line{line}")).collect();
+    feed(screen, &lines.join("\r\n"));
+}
+
+#[test]
+fn narrowing_wraps_long_lines() {
+    let mut screen = Screen::new(size(10, 4)).expect("screen");
+    feed(&mut screen, "abcdefgh\r\nxy");
+    screen.resize(size(4, 4)).expect("resize");
+    assert_eq!(view_text(&screen, 0), "abcd");
+    assert_eq!(view_text(&screen, 1), "efgh");
+    assert_eq!(view_text(&screen, 2), "xy");
+    assert_eq!(screen.cursor().row, 2);
+    assert_eq!(screen.cursor().col, 2);
+}
+
+#[test]
+fn widening_joins_wrapped_lines() {
+    let mut screen = Screen::new(size(4, 4)).expect("screen");
+    feed(&mut screen, "abcdefg\r\nxy");
+    assert_eq!(view_text(&screen, 0), "abcd");
+    assert_eq!(view_text(&screen, 1), "efg");
+    screen.resize(size(10, 4)).expect("resize");
+    assert_eq!(view_text(&screen, 0), "abcdefg");
+    assert_eq!(view_text(&screen, 1), "xy");
+    assert_eq!(view_text(&screen, 2), "");
+    assert_eq!(screen.cursor().row, 1);
+    assert_eq!(screen.cursor().col, 2);
+}
+
+#[test]
+fn hard_line_breaks_are_not_joined() {
+    let mut screen = Screen::new(size(4, 4)).expect("screen");
+    feed(&mut screen, "ab\r\ncd");
+    screen.resize(size(10, 4)).expect("resize");
+    assert_eq!(view_text(&screen, 0), "ab");
+    assert_eq!(view_text(&screen, 1), "cd");
+}
+
+#[test]
+fn cursor_follows_its_character_through_a_round_trip() {
+    let mut screen = Screen::new(size(10, 3)).expect("screen");
+    feed(&mut screen, "hello world\x1b[1;8H");
+    let before = screen.cursor();
+    assert_eq!((before.row, before.col), (0, 7));
+    screen.resize(size(5, 3)).expect("resize");
+    assert_eq!((screen.cursor().row, screen.cursor().col), (1, 2));
+    screen.resize(size(10, 3)).expect("resize");
+    assert_eq!(screen.cursor(), before);
+    assert_eq!(view_text(&screen, 0), "hello worl");
+    assert_eq!(view_text(&screen, 1), "d");
+}
+
+#[test]
+fn scrolled_back_view_keeps_its_top_line() {
+    let mut screen = Screen::new(size(10, 3)).expect("screen");
+    numbered_lines(&mut screen, 12);
+    assert!(screen.scroll_view(5));
+    assert_eq!(view_text(&screen, 0), "line4");
+
+    screen.resize(size(20, 3)).expect("resize");
+    assert!(screen.is_scrolled());
+    assert_eq!(view_text(&screen, 0), "line4");
+
+    screen.resize(size(3, 3)).expect("resize");
+    assert!(screen.is_scrolled());
+    assert_eq!(view_text(&screen, 0), "lin");
+    assert_eq!(view_text(&screen, 1), "e4");
+
+    screen.resize(size(10, 5)).expect("resize");
+    assert_eq!(view_text(&screen, 0), "line4");
+}
+
+#[test]
+fn view_at_the_bottom_stays_at_the_bottom() {
+    let mut screen = Screen::new(size(10, 3)).expect("screen");
+    numbered_lines(&mut screen, 12);
+    screen.resize(size(3, 3)).expect("resize");
+    assert!(!screen.is_scrolled());
+    // The cursor sat just past "line11", so it moves to a row of its own.
+    assert_eq!((screen.cursor().row, screen.cursor().col), (2, 0));
+    assert_eq!(view_text(&screen, 0), "lin");
+    assert_eq!(view_text(&screen, 1), "e11");
+}
+
+#[test]
+fn rewrapped_rows_keep_the_erase_background() {
+    let blue = Color::Indexed(4);
+    let mut screen = Screen::new(size(10, 4)).expect("screen");
+    feed(&mut screen, "\x1b[44m\x1b[2J\x1b[Habcdefgh\r\nxy");
+    for cols in [4, 12] {
+        screen.resize(size(cols, 4)).expect("resize");
+        for row in 0..4 {
+            let cells = screen.view_row(0, row).expect("row");
+            assert!(
+                cells.iter().all(|cell| cell.bg == blue),
+                "row {row} at {cols} cols"
+            );
+        }
+    }
+    assert_eq!(view_text(&screen, 0), "abcdefgh");
+    assert_eq!(view_text(&screen, 1), "xy");
+}
@@ -0,0 +1,38 @@
+// Drives the screen through the parser the way the app does, checking the
+// title state a shell leaves behind.
+
+mod common;
+
+use common::feed_with;
+use screen::{Screen, ScreenSize};
+use vt::VtParser;
+
+#[test]
+fn title_follows_osc_and_the_title_stack() {
+    let mut screen = Screen::new(ScreenSize { cols: 80, rows: 24 }).expect("screen");
+    let mut parser = VtParser::new();
+    assert_eq!(screen.title(), "");
+    assert!(!screen.take_title_changed());
+
+    feed_with(&mut screen, &mut parser, "\x1b]2;first\x07prompt> ");
+    assert_eq!(screen.title(), "first");
+    assert!(screen.take_title_changed());
+    assert!(!screen.take_title_changed());
+
+    // Setting the same title again isn't a change.
+    feed_with(&mut screen, &mut parser, "\x1b]2;first\x1b\\");
+    assert!(!screen.take_title_changed());
+
+    // An editor saves the title, sets its own and restores it on exit.
+    feed_with(&mut screen, &mut parser, "\x1b[22;0t\x1b]0;editor\x07");
+    assert_eq!(screen.title(), "editor");
+    assert_eq!(screen.icon_title(), "editor");
+    feed_with(&mut screen, &mut parser, "\x1b[23;0t");
+    assert_eq!(screen.title(), "first");
+    assert!(screen.take_title_changed());
+
+    // Popping an empty stack leaves the title alone.
+    feed_with(&mut screen, &mut parser, "\x1b[23;0t");
+    assert_eq!(screen.title(), "first");
+    assert!(!screen.take_title_changed());
+}
@@ -0,0 +1,62 @@
+// Wide characters arriving from the pty as UTF-8 take two cells: the
+// character and a spacer after it.
+
+mod common;
+
+use common::feed;
+use screen::{Cell, CellFlags, Screen, ScreenSize, SelectionPoint};
+
+fn row(screen: &Screen, row: usize) -> &[Cell] {
+    screen.view_row(0, row).expect("row")
+}
+
+#[test]
+fn cjk_characters_take_two_cells() {
+    let mut screen = Screen::new(ScreenSize { cols: 10, rows: 3 }).expect("screen");
+    feed(&mut screen, "a日本b");
+    let cells = row(&screen, 0);
+    let chars: Vec<char> = cells[..6].iter().map(|cell| cell.ch).collect();
+    assert_eq!(chars, ['a', '日', ' ', '本', ' ', 'b']);
+    assert!(cells[1].flags.contains(CellFlags::WIDE));
+    assert!(cells[2].flags.contains(CellFlags::WIDE_SPACER));
+    assert!(cells[3].flags.contains(CellFlags::WIDE));
+    assert!(cells[4].flags.contains(CellFlags::WIDE_SPACER));
+    assert!(!cells[5].flags.contains(CellFlags::WIDE));
+    assert_eq!(screen.cursor().col, 6);
+}
+
+#[test]
+fn a_wide_character_in_the_last_column_wraps_whole() {
+    let mut screen = Screen::new(ScreenSize { cols: 4, rows: 3 }).expect("screen");
+    feed(&mut screen, "abc語");
+    let first = row(&screen, 0);
+    assert_eq!(first[2].ch, 'c');
+    assert!(!first[3].flags.contains(CellFlags::WIDE));
+    let second = row(&screen, 1);
+    assert_eq!(second[0].ch, '語');
+    assert!(second[0].flags.contains(CellFlags::WIDE));
+    assert!(second[1].flags.contains(CellFlags::WIDE_SPACER));
+    assert_eq!((screen.cursor().col, screen.cursor().row), (2, 1));
+}
+
+#[test]
+fn overwriting_half_of_a_wide_character_blanks_the_other_half() {
+    let mut screen = Screen::new(ScreenSize { cols: 6, rows: 2 }).expect("screen");
+    feed(&mut screen, "日本\x1b[1;2Hx");
+    let cells = row(&screen, 0);
+    assert_eq!(cells[0].ch, ' ');
+    assert!(!cells[0].flags.contains(CellFlags::WIDE));
+    assert_eq!(cells[1].ch, 'x');
+    assert!(cells[2].flags.contains(CellFlags::WIDE));
+}
+
+#[test]
+fn selected_text_skips_the_spacers() {
+    let mut screen = Screen::new(ScreenSize { cols: 10, rows: 2 }).expect("screen");
+    feed(&mut screen, "日本語 ok");
+    screen.select(
+        SelectionPoint { line: 0, col: 0 },
+        SelectionPoint { line: 0, col: 9 },
+    );
+    assert_eq!(screen.selection_text().as_deref(), Some("日本語 ok"));
+}
@@ -1,31 +1,639 @@
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Color {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(Rgb),
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnderlineStyle {
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SgrAttribute {
+    Reset,
+    Bold,
+    Faint,
+    NormalIntensity,
+    Italic,
+    NoItalic,
+    Underline(UnderlineStyle),
+    NoUnderline,
+    UnderlineColor(Color),
+    Overline,
+    NoOverline,
+    Blink,
+    NoBlink,
+    Inverse,
+    NoInverse,
+    Hidden,
+    NoHidden,
+    Strikethrough,
+    NoStrikethrough,
+    Foreground(Color),
+    Background(Color),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EraseMode {
+    ToEnd,
+    ToStart,
+    All,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blinking: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    ShowCursor,
+    ApplicationCursorKeys,
+    BracketedPaste,
+    // Mouse tracking: button presses only (1000), also drags (1002), or
+    // all motion (1003).
+    MouseClicks,
+    MouseDrag,
+    MouseMotion,
+    // Mouse reports in the ESC [< ... M/m form (1006).
+    SgrMouse,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DynamicColor {
+    Foreground,
+    Background,
+    Cursor,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PromptMark {
+    PromptStart,
+    CommandStart,
+    OutputStart,
+    CommandFinished(Option<i32>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VtEvent {
     Print(char),
     Newline,
     CarriageReturn,
     Backspace,
+    Bell,
+    SetAttribute(SgrAttribute),
+    CursorPosition { col: u16, row: u16 },
+    EraseInDisplay(EraseMode),
+    EraseInLine(EraseMode),
+    EraseChars(u16),
+    ClearScrollback,
+    PromptMark(PromptMark),
+    SetMode(Mode, bool),
+    SetCursorStyle(Option<CursorStyle>),
+    FullReset,
+    SoftReset,
+    SetTitle(String),
+    SetIconTitle(String),
+    PushTitle,
+    PopTitle,
+    // OSC 7, the path of a file:// URL with any escapes decoded.
+    SetWorkingDirectory(String),
+    SetPaletteColor(u8, Rgb),
+    // OSC 4 with `?` in place of a color, answered with the current value.
+    QueryPaletteColor(u8),
+    ResetPaletteColor(Option<u8>),
+    SetDynamicColor(DynamicColor, Rgb),
+    ResetDynamicColor(DynamicColor),
+}
+
+const MAX_PARAMS: usize = 16;
+const REPLACEMENT: char = '\u{fffd}';
+const MAX_OSC_LEN: usize = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    CsiIgnore,
+    Osc,
+    OscEscape,
 }
 
-pub struct VtParser;
+pub struct VtParser {
+    state: State,
+    params: Vec<u16>,
+    subparams: Vec<bool>,
+    current: Option<u16>,
+    in_subparam: bool,
+    private: Option<u8>,
+    intermediate: Option<u8>,
+    osc: Vec<u8>,
+    // A UTF-8 sequence in progress: the bits decoded so far, how many
+    // continuation bytes are still to come, and the smallest code point the
+    // sequence's length may encode, to reject overlong forms.
+    utf8_code: u32,
+    utf8_remaining: u8,
+    utf8_min: u32,
+}
 
 impl VtParser {
     pub fn new() -> Self {
-        Self
+        Self {
+            state: State::Ground,
+            params: Vec::with_capacity(MAX_PARAMS),
+            subparams: Vec::with_capacity(MAX_PARAMS),
+            current: None,
+            in_subparam: false,
+            private: None,
+            intermediate: None,
+            osc: Vec::new(),
+            utf8_code: 0,
+            utf8_remaining: 0,
+            utf8_min: 0,
+        }
     }
 
     pub fn advance(&mut self, input: &[u8], events: &mut Vec<VtEvent>) {
         for byte in input {
-            match byte {
-                b'\n' => events.push(VtEvent::Newline),
-                b'\r' => events.push(VtEvent::CarriageReturn),
-                0x08 => events.push(VtEvent::Backspace),
-                0x20..=0x7E => events.push(VtEvent::Print(*byte as char)),
-                _ => {}
+            match self.state {
+                State::Ground => self.ground(*byte, events),
+                State::Escape => self.escape(*byte, events),
+                State::Csi => self.csi(*byte, events),
+                State::CsiIgnore => {
+                    if (0x40..=0x7E).contains(byte) {
+                        self.state = State::Ground;
+                    }
+                }
+                State::Osc => self.osc(*byte, events),
+                State::OscEscape => {
+                    if *byte == b'\\' {
+                        self.dispatch_osc(events);
+                        self.state = State::Ground;
+                    } else {
+                        self.state = State::Escape;
+                        self.escape(*byte, events);
+                    }
+                }
+            }
+        }
+    }
+
+    // Text is UTF-8. A malformed sequence prints U+FFFD, and a sequence cut
+    // short by a control byte prints it before the control takes effect.
+    fn ground(&mut self, byte: u8, events: &mut Vec<VtEvent>) {
+        if self.utf8_remaining > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.utf8_code = (self.utf8_code << 6) | (byte & 0x3F) as u32;
+                self.utf8_remaining -= 1;
+                if self.utf8_remaining == 0 {
+                    let ch = char::from_u32(self.utf8_code)
+                        .filter(|_| self.utf8_code >= self.utf8_min)
+                        .unwrap_or(REPLACEMENT);
+                    events.push(VtEvent::Print(ch));
+                }
+                return;
+            }
+            self.utf8_remaining = 0;
+            events.push(VtEvent::Print(REPLACEMENT));
+        }
+        match byte {
+            b'\n' => events.push(VtEvent::Newline),
+            b'\r' => events.push(VtEvent::CarriageReturn),
+            0x07 => events.push(VtEvent::Bell),
+            0x08 => events.push(VtEvent::Backspace),
+            0x1B => self.state = State::Escape,
+            0x20..=0x7E => events.push(VtEvent::Print(byte as char)),
+            0xC2..=0xDF => self.start_utf8(byte & 0x1F, 1, 0x80),
+            0xE0..=0xEF => self.start_utf8(byte & 0x0F, 2, 0x800),
+            0xF0..=0xF4 => self.start_utf8(byte & 0x07, 3, 0x10000),
+            0x80..=0xFF => events.push(VtEvent::Print(REPLACEMENT)),
+            _ => {}
+        }
+    }
+
+    fn start_utf8(&mut self, bits: u8, remaining: u8, min: u32) {
+        self.utf8_code = bits as u32;
+        self.utf8_remaining = remaining;
+        self.utf8_min = min;
+    }
+
+    fn escape(&mut self, byte: u8, events: &mut Vec<VtEvent>) {
+        match byte {
+            b'[' => {
+                self.params.clear();
+                self.subparams.clear();
+                self.current = None;
+                self.in_subparam = false;
+                self.private = None;
+                self.intermediate = None;
+                self.state = State::Csi;
+            }
+            b']' => {
+                self.osc.clear();
+                self.state = State::Osc;
+            }
+            b'c' => {
+                events.push(VtEvent::FullReset);
+                self.state = State::Ground;
+            }
+            0x1B => {}
+            _ => self.state = State::Ground,
+        }
+    }
+
+    fn csi(&mut self, byte: u8, events: &mut Vec<VtEvent>) {
+        match byte {
+            b'0'..=b'9' => {
+                let digit = (byte - b'0') as u16;
+                let value = self.current.unwrap_or(0);
+                self.current = Some(value.saturating_mul(10).saturating_add(digit));
+            }
+            b';' => self.push_param(),
+            b':' => {
+                self.push_param();
+                self.in_subparam = true;
+            }
+            b'<'..=b'?' => {
+                if self.params.is_empty() && self.current.is_none() && self.private.is_none() {
+                    self.private = Some(byte);
+                } else {
+                    self.state = State::CsiIgnore;
+                }
+            }
+            0x20..=0x2F => self.intermediate = Some(byte),
+            0x40..=0x7E => {
+                self.push_param();
+                self.dispatch_csi(byte, events);
+                self.state = State::Ground;
+            }
+            0x1B => self.state = State::Escape,
+            _ => self.state = State::CsiIgnore,
+        }
+    }
+
+    fn osc(&mut self, byte: u8, events: &mut Vec<VtEvent>) {
+        match byte {
+            0x07 => {
+                self.dispatch_osc(events);
+                self.state = State::Ground;
             }
+            0x1B => self.state = State::OscEscape,
+            _ => {
+                if self.osc.len() < MAX_OSC_LEN {
+                    self.osc.push(byte);
+                }
+            }
+        }
+    }
+
+    fn dispatch_osc(&mut self, events: &mut Vec<VtEvent>) {
+        let Ok(text) = std::str::from_utf8(&self.osc) else {
+            return;
+        };
+        let (command, rest) = text.split_once(';').unwrap_or((text, ""));
+        match command {
+            "0" => {
+                events.push(VtEvent::SetTitle(rest.to_string()));
+                events.push(VtEvent::SetIconTitle(rest.to_string()));
+            }
+            "1" => events.push(VtEvent::SetIconTitle(rest.to_string())),
+            "2" => events.push(VtEvent::SetTitle(rest.to_string())),
+            "7" => {
+                if let Some(path) = file_url_path(rest) {
+                    events.push(VtEvent::SetWorkingDirectory(path));
+                }
+            }
+            "4" => {
+                let mut parts = rest.split(';');
+                while let (Some(index), Some(spec)) = (parts.next(), parts.next()) {
+                    let Ok(index) = index.parse() else {
+                        continue;
+                    };
+                    if spec == "?" {
+                        events.push(VtEvent::QueryPaletteColor(index));
+                    } else if let Some(color) = parse_color_spec(spec) {
+                        events.push(VtEvent::SetPaletteColor(index, color));
+                    }
+                }
+            }
+            "10" | "11" | "12" => {
+                let target = dynamic_color(command);
+                if let Some(color) = parse_color_spec(rest) {
+                    events.push(VtEvent::SetDynamicColor(target, color));
+                }
+            }
+            "104" => {
+                if rest.is_empty() {
+                    events.push(VtEvent::ResetPaletteColor(None));
+                }
+                for index in rest.split(';').filter_map(|index| index.parse().ok()) {
+                    events.push(VtEvent::ResetPaletteColor(Some(index)));
+                }
+            }
+            "110" | "111" | "112" => {
+                events.push(VtEvent::ResetDynamicColor(dynamic_color(&command[1..])));
+            }
+            "133" => {
+                if let Some(mark) = prompt_mark(rest) {
+                    events.push(VtEvent::PromptMark(mark));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push_param(&mut self) {
+        if self.params.len() < MAX_PARAMS {
+            self.params.push(self.current.take().unwrap_or(0));
+            self.subparams.push(self.in_subparam);
+        } else {
+            self.current = None;
+        }
+        self.in_subparam = false;
+    }
+
+    fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params.get(index) {
+            Some(0) | None => default,
+            Some(value) => *value,
+        }
+    }
+
+    fn dispatch_csi(&mut self, action: u8, events: &mut Vec<VtEvent>) {
+        match (self.private, self.intermediate) {
+            (None, None) => {}
+            (Some(b'?'), None) => {
+                if let b'h' | b'l' = action {
+                    self.dispatch_private_modes(action == b'h', events);
+                }
+                return;
+            }
+            (None, Some(b' ')) => {
+                if action == b'q' {
+                    events.push(VtEvent::SetCursorStyle(cursor_style(self.param(0, 0))));
+                }
+                return;
+            }
+            (None, Some(b'!')) => {
+                if action == b'p' {
+                    events.push(VtEvent::SoftReset);
+                }
+                return;
+            }
+            _ => return,
+        }
+        match action {
+            b'H' | b'f' => events.push(VtEvent::CursorPosition {
+                row: self.param(0, 1) - 1,
+                col: self.param(1, 1) - 1,
+            }),
+            b'J' => match self.params.first().copied().unwrap_or(0) {
+                0 => events.push(VtEvent::EraseInDisplay(EraseMode::ToEnd)),
+                1 => events.push(VtEvent::EraseInDisplay(EraseMode::ToStart)),
+                2 => events.push(VtEvent::EraseInDisplay(EraseMode::All)),
+                3 => events.push(VtEvent::ClearScrollback),
+                _ => {}
+            },
+            b'K' => match self.params.first().copied().unwrap_or(0) {
+                0 => events.push(VtEvent::EraseInLine(EraseMode::ToEnd)),
+                1 => events.push(VtEvent::EraseInLine(EraseMode::ToStart)),
+                2 => events.push(VtEvent::EraseInLine(EraseMode::All)),
+                _ => {}
+            },
+            b'X' => events.push(VtEvent::EraseChars(self.param(0, 1))),
+            b'm' => self.dispatch_sgr(events),
+            b't' => match self.params.first().copied().unwrap_or(0) {
+                22 => events.push(VtEvent::PushTitle),
+                23 => events.push(VtEvent::PopTitle),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn dispatch_private_modes(&self, enabled: bool, events: &mut Vec<VtEvent>) {
+        for param in &self.params {
+            let mode = match param {
+                1 => Mode::ApplicationCursorKeys,
+                25 => Mode::ShowCursor,
+                1000 => Mode::MouseClicks,
+                1002 => Mode::MouseDrag,
+                1003 => Mode::MouseMotion,
+                1006 => Mode::SgrMouse,
+                2004 => Mode::BracketedPaste,
+                _ => continue,
+            };
+            events.push(VtEvent::SetMode(mode, enabled));
+        }
+    }
+
+    fn dispatch_sgr(&self, events: &mut Vec<VtEvent>) {
+        let mut index = 0;
+        while index < self.params.len() {
+            let param = self.params[index];
+            let group_end = index
+                + 1
+                + self.subparams[index + 1..]
+                    .iter()
+                    .take_while(|subparam| **subparam)
+                    .count();
+            let subparams = &self.params[index + 1..group_end];
+            index = group_end;
+            let attribute = match param {
+                0 => SgrAttribute::Reset,
+                1 => SgrAttribute::Bold,
+                2 => SgrAttribute::Faint,
+                3 => SgrAttribute::Italic,
+                4 => match subparams.first() {
+                    None | Some(1) => SgrAttribute::Underline(UnderlineStyle::Single),
+                    Some(0) => SgrAttribute::NoUnderline,
+                    Some(2) => SgrAttribute::Underline(UnderlineStyle::Double),
+                    Some(3) => SgrAttribute::Underline(UnderlineStyle::Curly),
+                    Some(4) => SgrAttribute::Underline(UnderlineStyle::Dotted),
+                    Some(5) => SgrAttribute::Underline(UnderlineStyle::Dashed),
+                    Some(_) => continue,
+                },
+                5 | 6 => SgrAttribute::Blink,
+                7 => SgrAttribute::Inverse,
+                8 => SgrAttribute::Hidden,
+                9 => SgrAttribute::Strikethrough,
+                21 => SgrAttribute::Underline(UnderlineStyle::Double),
+                22 => SgrAttribute::NormalIntensity,
+                23 => SgrAttribute::NoItalic,
+                24 => SgrAttribute::NoUnderline,
+                25 => SgrAttribute::NoBlink,
+                27 => SgrAttribute::NoInverse,
+                28 => SgrAttribute::NoHidden,
+                29 => SgrAttribute::NoStrikethrough,
+                30..=37 => SgrAttribute::Foreground(Color::Indexed((param - 30) as u8)),
+                38 | 48 | 58 => {
+                    let color = if subparams.is_empty() {
+                        let mut rest = self.params[index..].iter().copied();
+                        let color = extended_color(&mut rest);
+                        index = self.params.len() - rest.len();
+                        color
+                    } else {
+                        extended_color_subparams(subparams)
+                    };
+                    let color = match color {
+                        Some(color) => color,
+                        None if subparams.is_empty() => break,
+                        None => continue,
+                    };
+                    match param {
+                        38 => SgrAttribute::Foreground(color),
+                        48 => SgrAttribute::Background(color),
+                        _ => SgrAttribute::UnderlineColor(color),
+                    }
+                }
+                39 => SgrAttribute::Foreground(Color::Default),
+                40..=47 => SgrAttribute::Background(Color::Indexed((param - 40) as u8)),
+                49 => SgrAttribute::Background(Color::Default),
+                53 => SgrAttribute::Overline,
+                55 => SgrAttribute::NoOverline,
+                59 => SgrAttribute::UnderlineColor(Color::Default),
+                90..=97 => SgrAttribute::Foreground(Color::Indexed((param - 90 + 8) as u8)),
+                100..=107 => SgrAttribute::Background(Color::Indexed((param - 100 + 8) as u8)),
+                _ => continue,
+            };
+            events.push(VtEvent::SetAttribute(attribute));
         }
     }
 }
 
+fn dynamic_color(command: &str) -> DynamicColor {
+    match command {
+        "10" => DynamicColor::Foreground,
+        "11" => DynamicColor::Background,
+        _ => DynamicColor::Cursor,
+    }
+}
+
+fn parse_color_spec(spec: &str) -> Option<Rgb> {
+    if let Some(channels) = spec.strip_prefix("rgb:") {
+        let mut channels = channels.split('/').map(scale_hex_channel);
+        let r = channels.next()??;
+        let g = channels.next()??;
+        let b = channels.next()??;
+        if channels.next().is_some() {
+            return None;
+        }
+        return Some(Rgb { r, g, b });
+    }
+    let hex = spec.strip_prefix('#')?;
+    let width = match hex.len() {
+        3 | 6 | 9 | 12 => hex.len() / 3,
+        _ => return None,
+    };
+    let channel = |index: usize| scale_hex_channel(hex.get(index * width..(index + 1) * width)?);
+    Some(Rgb {
+        r: channel(0)?,
+        g: channel(1)?,
+        b: channel(2)?,
+    })
+}
+
+fn scale_hex_channel(digits: &str) -> Option<u8> {
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+    let value = u32::from_str_radix(digits, 16).ok()?;
+    let max = (1u32 << (digits.len() * 4)) - 1;
+    Some((value * 255 / max) as u8)
+}
+
+// The host is left out: shells send their own host name, so the path is
+// always local.
+fn file_url_path(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut input = path.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn prompt_mark(args: &str) -> Option<PromptMark> {
+    let mut parts = args.split(';');
+    let mark = match parts.next()? {
+        "A" => PromptMark::PromptStart,
+        "B" => PromptMark::CommandStart,
+        "C" => PromptMark::OutputStart,
+        "D" => PromptMark::CommandFinished(parts.next().and_then(|code| code.parse().ok())),
+        _ => return None,
+    };
+    Some(mark)
+}
+
+fn cursor_style(param: u16) -> Option<CursorStyle> {
+    let (shape, blinking) = match param {
+        1 => (CursorShape::Block, true),
+        2 => (CursorShape::Block, false),
+        3 => (CursorShape::Underline, true),
+        4 => (CursorShape::Underline, false),
+        5 => (CursorShape::Bar, true),
+        6 => (CursorShape::Bar, false),
+        _ => return None,
+    };
+    Some(CursorStyle { shape, blinking })
+}
+
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    match params.next()? {
+        5 => Some(Color::Indexed(params.next()?.min(255) as u8)),
+        2 => {
+            let r = params.next()?.min(255) as u8;
+            let g = params.next()?.min(255) as u8;
+            let b = params.next()?.min(255) as u8;
+            Some(Color::Rgb(Rgb { r, g, b }))
+        }
+        _ => None,
+    }
+}
+
+fn extended_color_subparams(subparams: &[u16]) -> Option<Color> {
+    match subparams {
+        [5, index, ..] => Some(Color::Indexed((*index).min(255) as u8)),
+        [2, r, g, b] | [2, _, r, g, b, ..] => Some(Color::Rgb(Rgb {
+            r: (*r).min(255) as u8,
+            g: (*g).min(255) as u8,
+            b: (*b).min(255) as u8,
+        })),
+        _ => None,
+    }
+}
+
 impl Default for VtParser {
     fn default() -> Self {
         Self::new()
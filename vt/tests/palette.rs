@@ -0,0 +1,67 @@
+// OSC 4 sets, queries and OSC 104 resets palette entries.
+
+use vt::{Rgb, VtEvent, VtParser};
+
+fn events(input: &str) -> Vec<VtEvent> {
+    let mut parser = VtParser::new();
+    let mut events = Vec::new();
+    parser.advance(input.as_bytes(), &mut events);
+    events
+}
+
+#[test]
+fn set_parses_each_index_and_color() {
+    assert_eq!(
+        events("\x1b]4;1;rgb:ff/00/00;12;#00ff80\x07"),
+        [
+            VtEvent::SetPaletteColor(1, Rgb { r: 255, g: 0, b: 0 }),
+            VtEvent::SetPaletteColor(
+                12,
+                Rgb {
+                    r: 0,
+                    g: 255,
+                    b: 128
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn query_is_reported_for_each_index() {
+    assert_eq!(
+        events("\x1b]4;1;?;200;?\x1b\\"),
+        [
+            VtEvent::QueryPaletteColor(1),
+            VtEvent::QueryPaletteColor(200),
+        ]
+    );
+}
+
+#[test]
+fn set_and_query_can_be_mixed() {
+    assert_eq!(
+        events("\x1b]4;3;rgb:01/02/03;3;?\x07"),
+        [
+            VtEvent::SetPaletteColor(3, Rgb { r: 1, g: 2, b: 3 }),
+            VtEvent::QueryPaletteColor(3),
+        ]
+    );
+}
+
+#[test]
+fn invalid_index_is_ignored() {
+    assert_eq!(events("\x1b]4;256;?;x;?\x07"), []);
+}
+
+#[test]
+fn reset_with_and_without_indexes() {
+    assert_eq!(
+        events("\x1b]104;1;5\x07"),
+        [
+            VtEvent::ResetPaletteColor(Some(1)),
+            VtEvent::ResetPaletteColor(Some(5)),
+        ]
+    );
+    assert_eq!(events("\x1b]104\x07"), [VtEvent::ResetPaletteColor(None)]);
+}
@@ -0,0 +1,68 @@
+// Text arrives from the pty as UTF-8, possibly split across reads.
+
+use vt::{VtEvent, VtParser};
+
+fn printed(chunks: &[&[u8]]) -> String {
+    let mut parser = VtParser::new();
+    let mut events = Vec::new();
+    for chunk in chunks {
+        parser.advance(chunk, &mut events);
+    }
+    events
+        .into_iter()
+        .map(|event| match event {
+            VtEvent::Print(ch) => ch,
+            VtEvent::Newline => '\n',
+            VtEvent::CarriageReturn => '\r',
+            other => panic!("unexpected event {other:?}"),
+        })
+        .collect()
+}
+
+#[test]
+fn multibyte_characters_print() {
+    let text = "é ü → ─┐ 日本語 \u{e0b0} 🦀";
+    assert_eq!(printed(&[text.as_bytes()]), text);
+}
+
+#[test]
+fn sequences_split_across_reads_print_once() {
+    let text = "a日🦀b";
+    let bytes = text.as_bytes();
+    for split in 0..=bytes.len() {
+        let (first, second) = bytes.split_at(split);
+        assert_eq!(printed(&[first, second]), text, "split at {split}");
+    }
+    let single_bytes: Vec<&[u8]> = bytes.chunks(1).collect();
+    assert_eq!(printed(&single_bytes), text);
+}
+
+#[test]
+fn malformed_input_prints_replacement_characters() {
+    // A stray continuation byte and bytes that never start a sequence.
+    assert_eq!(
+        printed(&[b"a\x80b\xffc\xc0d"]),
+        "a\u{fffd}b\u{fffd}c\u{fffd}d"
+    );
+    // Overlong forms and UTF-16 surrogates are rejected.
+    assert_eq!(printed(&[b"\xe0\x80\xaf"]), "\u{fffd}");
+    assert_eq!(printed(&[b"\xed\xa0\x80"]), "\u{fffd}");
+    // A sequence cut short by plain text or a line break.
+    assert_eq!(printed(&[b"\xe6\x97x"]), "\u{fffd}x");
+    assert_eq!(printed(&[b"\xc3\r\n"]), "\u{fffd}\r\n");
+}
+
+#[test]
+fn an_escape_interrupts_a_sequence() {
+    let mut parser = VtParser::new();
+    let mut events = Vec::new();
+    parser.advance(b"\xe2\x94\x1b[0m\xe2\x94\x80", &mut events);
+    assert_eq!(
+        events,
+        vec![
+            VtEvent::Print('\u{fffd}'),
+            VtEvent::SetAttribute(vt::SgrAttribute::Reset),
+            VtEvent::Print('─'),
+        ]
+    );
+}